@@ -5,6 +5,7 @@ Provides HTTP API for TTS, RVC, ASR, Agent, and VAD services
 
 from fastapi import FastAPI, HTTPException
 from fastapi.middleware.cors import CORSMiddleware
+from fastapi.responses import StreamingResponse
 from pydantic import BaseModel
 from typing import List, Optional, Dict, Any
 import os
@@ -64,6 +65,7 @@ class TTSRequest(BaseModel):
     voice: Optional[str] = None
     language: Optional[str] = None
     config: Optional[Dict[str, Any]] = None  # Full TTS config for engine initialization
+    file_name_no_ext: Optional[str] = None  # Cache file name chosen by the caller
 
 
 class TTSResponse(BaseModel):
@@ -84,6 +86,10 @@ class RVCResponse(BaseModel):
 
 class ASRRequest(BaseModel):
     audio_data: List[float]
+    sample_rate: int = 16000  # Sample rate of audio_data
+    channels: int = 1  # Interleaved channels in audio_data
+    language: Optional[str] = None
+    config: Optional[Dict[str, Any]] = None  # Active character's ASR config
 
 
 class ASRResponse(BaseModel):
@@ -91,6 +97,17 @@ class ASRResponse(BaseModel):
     success: bool
 
 
+class VADRequest(BaseModel):
+    audio_data: List[float]
+    config: Optional[Dict[str, Any]] = None  # Active character's VAD config
+
+
+class VADResponse(BaseModel):
+    speech_detected: bool
+    audio_segments: List[List[float]]
+    success: bool
+
+
 class Message(BaseModel):
     role: str
     content: str
@@ -153,7 +170,7 @@ async def synthesize_tts(request: TTSRequest):
                 )
         
         # Generate audio
-        audio_path = tts_service.synthesize(request.text)
+        audio_path = tts_service.synthesize(request.text, request.file_name_no_ext)
         
         if audio_path:
             return TTSResponse(audio_path=audio_path, success=True)
@@ -173,6 +190,100 @@ async def synthesize_tts(request: TTSRequest):
         )
 
 
+# Sample rate of the PCM produced by /tts/stream
+STREAM_SAMPLE_RATE = 24000
+
+
+@app.post("/tts/stream")
+async def stream_tts(request: TTSRequest):
+    """Stream synthesized speech as 16-bit mono PCM (edge_tts and azure_tts only)"""
+    config = request.config or {}
+    tts_model = config.get("tts_model")
+
+    if tts_model == "edge_tts":
+        chunks = _edge_tts_pcm_chunks(request, config.get("edge_tts") or {})
+    elif tts_model == "azure_tts":
+        chunks = _azure_tts_pcm_chunks(request, config.get("azure_tts") or {})
+    else:
+        raise HTTPException(
+            status_code=501,
+            detail=f"Streaming is not supported for {tts_model}",
+        )
+
+    return StreamingResponse(
+        chunks,
+        media_type="application/octet-stream",
+        headers={"X-Sample-Rate": str(STREAM_SAMPLE_RATE)},
+    )
+
+
+async def _edge_tts_pcm_chunks(request: TTSRequest, edge_config: Dict[str, Any]):
+    """Decode edge_tts MP3 output to PCM as it arrives"""
+    import asyncio
+    import edge_tts
+
+    voice = request.voice or edge_config.get("voice", "en-US-AvaMultilingualNeural")
+    communicate = edge_tts.Communicate(request.text, voice)
+
+    # One ffmpeg process decodes the MP3 stream, so each frame is decoded once
+    decoder = await asyncio.create_subprocess_exec(
+        "ffmpeg", "-loglevel", "error",
+        "-f", "mp3", "-i", "pipe:0",
+        "-f", "s16le", "-ac", "1", "-ar", str(STREAM_SAMPLE_RATE), "pipe:1",
+        stdin=asyncio.subprocess.PIPE,
+        stdout=asyncio.subprocess.PIPE,
+    )
+
+    async def feed():
+        try:
+            async for chunk in communicate.stream():
+                if chunk["type"] != "audio":
+                    continue
+                decoder.stdin.write(chunk["data"])
+                await decoder.stdin.drain()
+        finally:
+            decoder.stdin.close()
+
+    feeder = asyncio.create_task(feed())
+    try:
+        while True:
+            pcm = await decoder.stdout.read(8192)
+            if not pcm:
+                break
+            yield pcm
+        # Surface a failed edge_tts request
+        await feeder
+    finally:
+        feeder.cancel()
+        if decoder.returncode is None:
+            decoder.kill()
+        await decoder.wait()
+
+
+async def _azure_tts_pcm_chunks(request: TTSRequest, azure_config: Dict[str, Any]):
+    """Read Azure synthesis output while the service is still speaking"""
+    import asyncio
+    import azure.cognitiveservices.speech as speechsdk
+
+    speech_config = speechsdk.SpeechConfig(
+        subscription=azure_config.get("api_key"), region=azure_config.get("region")
+    )
+    speech_config.speech_synthesis_voice_name = request.voice or azure_config.get("voice")
+    speech_config.set_speech_synthesis_output_format(
+        speechsdk.SpeechSynthesisOutputFormat.Raw24Khz16BitMonoPcm
+    )
+    synthesizer = speechsdk.SpeechSynthesizer(speech_config=speech_config, audio_config=None)
+
+    result = await asyncio.to_thread(lambda: synthesizer.start_speaking_text_async(request.text).get())
+    stream = speechsdk.AudioDataStream(result)
+    buffer = bytes(16000)
+    while True:
+        filled = await asyncio.to_thread(stream.read_data, buffer)
+        if filled == 0:
+            break
+        yield buffer[:filled]
+
+
 # RVC endpoints
 @app.post("/rvc/convert", response_model=RVCResponse)
 async def convert_voice(request: RVCRequest):
@@ -186,27 +297,96 @@ async def convert_voice(request: RVCRequest):
         raise HTTPException(status_code=500, detail=str(e))
 
 
+# Engines built from configs sent with requests, keyed by the config
+_asr_engines: Dict[str, Any] = {}
+_vad_engines: Dict[str, Any] = {}
+
+
+def _engine_for(config: Optional[Dict[str, Any]], model_key: str, cache: Dict[str, Any], build):
+    """Engine for a character's config, or None to use the service context's"""
+    if not config or not config.get(model_key):
+        return None
+    import json
+    key = json.dumps(config, sort_keys=True, default=str)
+    if key not in cache:
+        model = config[model_key]
+        cache[key] = build(model, **(config.get(model) or {}))
+    return cache[key]
+
+
 # ASR endpoints
 @app.post("/asr/transcribe", response_model=ASRResponse)
 async def transcribe_audio(request: ASRRequest):
     """Transcribe audio to text"""
     try:
-        context = get_service_context()
-        if not context.asr_engine:
+        asr_engine = _engine_for(
+            request.config, "asr_model", _asr_engines, ASRFactory.get_asr_system
+        )
+        if asr_engine is None:
+            asr_engine = get_service_context().asr_engine
+        if not asr_engine:
             raise HTTPException(status_code=500, detail="ASR engine not initialized")
         
         # Convert float array to numpy array
         import numpy as np
         audio_array = np.array(request.audio_data, dtype=np.float32)
+
+        # The engines expect 16 kHz mono
+        if request.channels > 1:
+            frames = len(audio_array) // request.channels
+            audio_array = audio_array[: frames * request.channels]
+            audio_array = audio_array.reshape(frames, request.channels).mean(axis=1)
+        if request.sample_rate != 16000 and len(audio_array) > 0:
+            duration = len(audio_array) / request.sample_rate
+            target = np.linspace(0, duration, int(duration * 16000), endpoint=False)
+            source = np.arange(len(audio_array)) / request.sample_rate
+            audio_array = np.interp(target, source, audio_array).astype(np.float32)
         
         # Transcribe
-        text = context.asr_engine.transcribe(audio_array)
+        text = asr_engine.transcribe(audio_array)
         
         return ASRResponse(text=text, success=True)
     except Exception as e:
         raise HTTPException(status_code=500, detail=str(e))
 
 
+# VAD endpoints
+@app.post("/vad/detect", response_model=VADResponse)
+async def detect_speech(request: VADRequest):
+    """Run VAD over a chunk of 16 kHz mono audio and return finished utterances"""
+    try:
+        vad_engine = _engine_for(
+            request.config, "vad_model", _vad_engines, VADFactory.get_vad_engine
+        )
+        if vad_engine is None:
+            vad_engine = get_service_context().vad_engine
+        if not vad_engine:
+            raise HTTPException(status_code=500, detail="VAD engine not initialized")
+
+        import numpy as np
+        speech_detected = False
+        segments = []
+        for audio_bytes in vad_engine.detect_speech(request.audio_data):
+            if audio_bytes == b"<|PAUSE|>":
+                speech_detected = True
+            elif audio_bytes == b"<|RESUME|>":
+                pass
+            elif len(audio_bytes) > 1024:
+                # The engine yields 16-bit PCM
+                samples = np.frombuffer(audio_bytes, dtype=np.int16).astype(np.float32) / 32768.0
+                segments.append(samples.tolist())
+
+        return VADResponse(
+            speech_detected=speech_detected or bool(segments),
+            audio_segments=segments,
+            success=True,
+        )
+    except HTTPException:
+        raise
+    except Exception as e:
+        raise HTTPException(status_code=500, detail=str(e))
+
+
 # Agent endpoints
 @app.post("/agent/chat", response_model=AgentResponse)
 async def chat(request: AgentRequest):
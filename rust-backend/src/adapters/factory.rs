@@ -0,0 +1,43 @@
+use std::sync::Arc;
+use anyhow::Result;
+use crate::conversations::WebSocketSend;
+
+use super::base_adapter::BackendAdapter;
+use super::orphiq_adapter::OrphiqAdapter;
+use crate::python_service::PythonService;
+use crate::state::ClientContext;
+
+/// Adapters that can be named in `system_config.backend_adapter`
+pub const AVAILABLE_ADAPTERS: [&str; 1] = ["orphiq"];
+
+/// Factory for creating backend adapters
+pub struct AdapterFactory;
+
+impl AdapterFactory {
+    /// Create the adapter a client's commands go through
+    ///
+    /// # Arguments
+    /// * `adapter_type` - Adapter name from the system config
+    /// * `client_context` - Client the adapter acts for
+    /// * `python_service` - Python service client
+    /// * `websocket_sender` - Outbound channel of the client
+    pub fn create_adapter(
+        adapter_type: &str,
+        client_context: Arc<ClientContext>,
+        python_service: Arc<dyn PythonService>,
+        websocket_sender: WebSocketSend,
+    ) -> Result<Box<dyn BackendAdapter>> {
+        match adapter_type {
+            "orphiq" => Ok(Box::new(OrphiqAdapter::new(
+                client_context,
+                python_service,
+                websocket_sender,
+            ))),
+            other => anyhow::bail!(
+                "Unknown backend adapter: {} (available: {})",
+                other,
+                AVAILABLE_ADAPTERS.join(", ")
+            ),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub mod base_adapter;
+pub mod factory;
 pub mod orphiq_adapter;
 
-// BackendAdapter is used internally by OrphiqAdapter but not exported
+pub use base_adapter::BackendAdapter;
+pub use factory::{AdapterFactory, AVAILABLE_ADAPTERS};
 pub use orphiq_adapter::OrphiqAdapter;
-
@@ -2,17 +2,17 @@ use async_trait::async_trait;
 use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use crate::conversations::WebSocketSend;
 
 use super::base_adapter::BackendAdapter;
 use crate::state::ClientContext;
-use crate::python_service::PythonServiceClient;
+use crate::python_service::PythonService;
 
 /// Adapter for existing orphiq backend
 pub struct OrphiqAdapter {
     client_context: Arc<ClientContext>,
-    python_service: Arc<PythonServiceClient>,
-    websocket_sender: mpsc::UnboundedSender<String>,
+    python_service: Arc<dyn PythonService>,
+    websocket_sender: WebSocketSend,
     current_expression: Option<i32>,
     current_motion: Option<HashMap<String, Value>>,
 }
@@ -20,8 +20,8 @@ pub struct OrphiqAdapter {
 impl OrphiqAdapter {
     pub fn new(
         client_context: Arc<ClientContext>,
-        python_service: Arc<PythonServiceClient>,
-        websocket_sender: mpsc::UnboundedSender<String>,
+        python_service: Arc<dyn PythonService>,
+        websocket_sender: WebSocketSend,
     ) -> Self {
         Self {
             client_context,
@@ -62,18 +62,10 @@ impl BackendAdapter for OrphiqAdapter {
         priority: i32,
     ) -> Result<HashMap<String, Value>, anyhow::Error> {
         let payload = json!({
-            "type": "audio",
-            "audio": null,
-            "volumes": [],
-            "slice_length": 20,
-            "display_text": {
-                "text": format!("Expression {}", expression_id),
-                "name": "Character", // TODO: Get from context
-            },
-            "actions": {
-                "expressions": [expression_id]
-            },
-            "forwarded": false
+            "type": "expression",
+            "expression_id": expression_id,
+            "duration": duration,
+            "priority": priority
         });
 
         self.websocket_sender.send(payload.to_string())?;
@@ -3,11 +3,15 @@ use tracing::info;
 use anyhow::Result;
 
 use crate::agent::agents::AgentInterface;
-use crate::agent::agents::basic_memory_agent::BasicMemoryAgent;
+use crate::agent::agents::basic_memory_agent::{BasicMemoryAgent, FileLimits, ImageLimits};
 use crate::agent::agents::hume_ai::HumeAIAgent;
 use crate::agent::agents::mem0_llm::Mem0LLM;
+use crate::agent::agents::proxy_agent::ProxyAgent;
+use crate::agent::prompt_guard::PromptGuard;
+use crate::agent::stateless_llm::fallback_llm::FallbackLLM;
 use crate::agent::stateless_llm_factory::StatelessLLMFactory;
-use crate::python_service::PythonServiceClient;
+use crate::config_manager::agent::{AgentConfig, PromptGuardConfig};
+use crate::python_service::PythonService;
 
 /// Factory for creating agent instances
 pub struct AgentFactory;
@@ -16,22 +20,19 @@ impl AgentFactory {
     /// Create an agent based on the configuration.
     ///
     /// # Arguments
-    /// * `conversation_agent_choice` - The type of agent to create
-    /// * `agent_settings` - Settings for different types of agents
-    /// * `llm_configs` - Pool of LLM configurations
+    /// * `agent_config` - The agent to create, its settings, the pool of LLM
+    ///   configurations and the fallback providers
     /// * `system_prompt` - The system prompt to use
     /// * `python_service` - Python service client for ML operations
-    /// * `live2d_model` - Optional Live2D model instance for expression extraction
-    /// * `tts_preprocessor_config` - Optional configuration for TTS preprocessing
     pub fn create_agent(
-        conversation_agent_choice: &str,
-        agent_settings: &serde_json::Value,
-        llm_configs: &serde_json::Value,
+        agent_config: &AgentConfig,
         system_prompt: &str,
-        python_service: Arc<PythonServiceClient>,
-        _live2d_model: Option<Arc<dyn std::any::Any + Send + Sync>>, // TODO: Proper Live2D model type
-        _tts_preprocessor_config: Option<serde_json::Value>, // TODO: Proper TTS preprocessor config type
+        python_service: Arc<dyn PythonService>,
     ) -> Result<Box<dyn AgentInterface>> {
+        let conversation_agent_choice = agent_config.conversation_agent_choice.as_str();
+        let agent_settings = &serde_json::to_value(&agent_config.agent_settings)?;
+        let llm_configs = &serde_json::to_value(&agent_config.llm_configs)?;
+        let fallback_llm_providers = &agent_config.fallback_llm_providers;
         info!("Initializing agent: {}", conversation_agent_choice);
 
         match conversation_agent_choice {
@@ -60,33 +61,90 @@ impl AgentFactory {
                     .to_string();
 
                 // Create the stateless LLM
-                let llm = StatelessLLMFactory::create_llm(
+                let mut llm = StatelessLLMFactory::create_llm(
                     llm_provider,
                     python_service.clone(),
                     Some(system_prompt),
                     &llm_config,
                 )?;
 
+                // A fallback may take over mid-conversation, so all must go on
+                // from a prefill for it to be sent
+                let prefill_supported = std::iter::once(llm_provider)
+                    .chain(fallback_llm_providers.iter().map(String::as_str))
+                    .all(StatelessLLMFactory::supports_prefill);
+                let documents_supported = std::iter::once(llm_provider)
+                    .chain(fallback_llm_providers.iter().map(String::as_str))
+                    .all(StatelessLLMFactory::supports_documents);
+                if !fallback_llm_providers.is_empty() {
+                    let mut providers = vec![(llm_provider.to_string(), llm)];
+                    for provider in fallback_llm_providers {
+                        let config = llm_configs
+                            .get(provider)
+                            .filter(|c| !c.is_null())
+                            .ok_or_else(|| anyhow::anyhow!("Configuration not found for fallback LLM provider: {}", provider))?;
+                        let fallback = StatelessLLMFactory::create_llm(
+                            provider,
+                            python_service.clone(),
+                            Some(system_prompt),
+                            config,
+                        )?;
+                        providers.push((provider.clone(), fallback));
+                    }
+                    llm = Arc::new(FallbackLLM::new(providers));
+                }
+
                 // Create the agent with the LLM
-                let faster_first_response = basic_settings
-                    .get("faster_first_response")
-                    .and_then(|v| v.as_bool())
-                    .unwrap_or(true);
-                
-                let segment_method = basic_settings
-                    .get("segment_method")
+                let prefill = basic_settings
+                    .get("prefill")
                     .and_then(|v| v.as_str())
-                    .unwrap_or("pysbd")
-                    .to_string();
+                    .filter(|p| !p.trim().is_empty())
+                    .map(|p| p.to_string());
 
-                let agent = BasicMemoryAgent::new(
+                let stream_retries = basic_settings
+                    .get("stream_retries")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(1) as u32;
+
+                let defaults = ImageLimits::default();
+                let limit = |key: &str, default: usize| {
+                    basic_settings
+                        .get(key)
+                        .and_then(|v| v.as_u64())
+                        .map_or(default, |v| v as usize)
+                };
+                let image_limits = ImageLimits {
+                    max_images: limit("max_images", defaults.max_images),
+                    max_image_bytes: limit("max_image_bytes", defaults.max_image_bytes),
+                    max_total_bytes: limit("max_total_image_bytes", defaults.max_total_bytes),
+                };
+                let defaults = FileLimits::default();
+                let file_limits = FileLimits {
+                    max_files: limit("max_files", defaults.max_files),
+                    max_file_bytes: limit("max_file_bytes", defaults.max_file_bytes),
+                    max_total_bytes: limit("max_total_file_bytes", defaults.max_total_bytes),
+                };
+
+                let prompt_guard_config: PromptGuardConfig = basic_settings
+                    .get("prompt_guard")
+                    .cloned()
+                    .map(serde_json::from_value)
+                    .transpose()?
+                    .unwrap_or_default();
+
+                let mut agent = BasicMemoryAgent::new(
                     llm,
                     system_prompt.to_string(),
-                    python_service,
-                    faster_first_response,
-                    segment_method,
                     interrupt_method,
-                );
+                )
+                .with_image_limits(image_limits)
+                .with_file_limits(file_limits)
+                .with_documents(documents_supported)
+                .with_prefill(prefill, prefill_supported)
+                .with_stream_retries(stream_retries);
+                if let Some(guard) = PromptGuard::from_config(&prompt_guard_config)? {
+                    agent = agent.with_prompt_guard(guard);
+                }
 
                 Ok(Box::new(agent))
             }
@@ -134,6 +192,28 @@ impl AgentFactory {
                         .unwrap_or(15) as u32,
                 )))
             }
+            "proxy_agent" => {
+                let settings = agent_settings
+                    .get("proxy_agent")
+                    .filter(|s| !s.is_null())
+                    .ok_or_else(|| anyhow::anyhow!("Proxy agent settings not found"))?;
+                let url = settings
+                    .get("url")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Missing required field 'url' in proxy_agent settings"))?;
+
+                Ok(Box::new(ProxyAgent::new(
+                    url.to_string(),
+                    settings.get("auth_token").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                    settings.get("idle_timeout")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or(60) as u32,
+                    settings.get("cache_dir")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("cache")
+                        .to_string(),
+                )))
+            }
             _ => Err(anyhow::anyhow!(
                 "Unsupported agent type: {}",
                 conversation_agent_choice
@@ -1,10 +1,39 @@
 use async_trait::async_trait;
 use futures::Stream;
+use serde::Serialize;
+use std::collections::HashMap;
 use tracing::warn;
 
 use crate::agent::input_types::BatchInput;
 use crate::agent::output_types::BaseOutput;
 
+/// An agent's working memory as its LLM would see it, for debugging
+#[derive(Debug, Clone, Serialize)]
+pub struct MemorySnapshot {
+    /// System prompt, including notes the agent adds itself
+    pub system: String,
+    pub messages: Vec<HashMap<String, serde_json::Value>>,
+}
+
+/// Error ending an agent's output stream when the LLM's stream broke off
+/// part way through a reply
+///
+/// The agent has already put out and remembered the whole sentences it got,
+/// so the turn can end as if the reply were complete.
+#[derive(Debug)]
+pub struct GenerationInterrupted {
+    /// Why the stream broke off
+    pub cause: anyhow::Error,
+}
+
+impl std::fmt::Display for GenerationInterrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Generation interrupted: {}", self.cause)
+    }
+}
+
+impl std::error::Error for GenerationInterrupted {}
+
 /// Base interface for all agent implementations
 #[async_trait]
 pub trait AgentInterface: Send + Sync {
@@ -39,6 +68,29 @@ pub trait AgentInterface: Send + Sync {
         );
     }
 
+    /// Reset the interrupt handled flag before a new conversation turn
+    fn reset_interrupt(&mut self) {
+        // Default implementation does nothing
+    }
+
+    /// Forget the most recent user turn and everything after it, so the
+    /// turn can be answered again
+    fn rewind_last_turn(&mut self) {
+        // Default implementation does nothing
+    }
+
+    /// Forget the conversation, keeping only the system prompt; chat history
+    /// is left as it is
+    fn clear_memory(&mut self) {
+        // Default implementation does nothing
+    }
+
+    /// Copy of the agent's working memory; `None` for agents whose memory
+    /// lives elsewhere (e.g. with a hosted service)
+    fn memory_snapshot(&self) -> Option<MemorySnapshot> {
+        None
+    }
+
     /// Load the agent's working memory from chat history
     ///
     /// # Arguments
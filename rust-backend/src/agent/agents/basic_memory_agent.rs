@@ -1,27 +1,162 @@
 use async_trait::async_trait;
 use futures::Stream;
 use std::collections::HashMap;
-use tracing::{info, debug};
+use tracing::{info, debug, warn};
 
-use super::agent_interface::AgentInterface;
-use crate::agent::input_types::{BatchInput, TextSource, ImageSource};
+use super::agent_interface::{AgentInterface, GenerationInterrupted, MemorySnapshot};
+use crate::agent::input_types::{BatchInput, FileData, ImageData, TextSource, ImageSource, CONTINUE_PROMPT};
 use crate::agent::output_types::{BaseOutput, SentenceOutput, DisplayText, Actions};
+use crate::agent::prompt_guard::PromptGuard;
+use crate::agent::transformers::join_continuation;
 use crate::agent::stateless_llm::StatelessLLMInterface;
-use crate::python_service::PythonServiceClient;
 use crate::chat_history;
+use crate::utils::sentence_divider::{detect_language, segment_text};
 use std::sync::Arc;
 
+/// Limits on the images a single user message may carry
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLimits {
+    pub max_images: usize,
+    /// Largest decoded size of one image, in bytes
+    pub max_image_bytes: usize,
+    /// Largest decoded size of all images together, in bytes
+    pub max_total_bytes: usize,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_images: 4,
+            max_image_bytes: 10 * 1024 * 1024,
+            max_total_bytes: 20 * 1024 * 1024,
+        }
+    }
+}
+
+impl ImageLimits {
+    /// Check a message's images against the limits
+    pub fn check(&self, images: &[ImageData]) -> anyhow::Result<()> {
+        if images.len() > self.max_images {
+            anyhow::bail!(
+                "Too many images: {} sent, at most {} allowed",
+                images.len(),
+                self.max_images
+            );
+        }
+
+        let mut total = 0;
+        for (i, image) in images.iter().enumerate() {
+            let size = decoded_len(&image.data);
+            if size > self.max_image_bytes {
+                anyhow::bail!(
+                    "Image {} is {} bytes, at most {} allowed",
+                    i + 1,
+                    size,
+                    self.max_image_bytes
+                );
+            }
+            total += size;
+        }
+        if total > self.max_total_bytes {
+            anyhow::bail!(
+                "Images total {} bytes, at most {} allowed",
+                total,
+                self.max_total_bytes
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Limits on the files a single user message may carry
+#[derive(Debug, Clone, Copy)]
+pub struct FileLimits {
+    pub max_files: usize,
+    /// Largest decoded size of one file, in bytes
+    pub max_file_bytes: usize,
+    /// Largest decoded size of all files together, in bytes
+    pub max_total_bytes: usize,
+}
+
+impl Default for FileLimits {
+    fn default() -> Self {
+        Self {
+            max_files: 4,
+            max_file_bytes: 5 * 1024 * 1024,
+            max_total_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl FileLimits {
+    /// Check a message's files against the limits
+    pub fn check(&self, files: &[FileData]) -> anyhow::Result<()> {
+        if files.len() > self.max_files {
+            anyhow::bail!(
+                "Too many files: {} sent, at most {} allowed",
+                files.len(),
+                self.max_files
+            );
+        }
+
+        let mut total = 0;
+        for file in files {
+            let size = decoded_len(&file.data);
+            if size > self.max_file_bytes {
+                anyhow::bail!(
+                    "File {} is {} bytes, at most {} allowed",
+                    file.name,
+                    size,
+                    self.max_file_bytes
+                );
+            }
+            total += size;
+        }
+        if total > self.max_total_bytes {
+            anyhow::bail!(
+                "Files total {} bytes, at most {} allowed",
+                total,
+                self.max_total_bytes
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Characters of a text file put in the prompt before it is cut off
+const MAX_FILE_TEXT_CHARS: usize = 20_000;
+
+/// Decoded size of base64 data, with or without a data URI prefix
+fn decoded_len(data: &str) -> usize {
+    let encoded = data.split_once("base64,").map_or(data, |(_, d)| d).trim_end();
+    let padding = encoded.chars().rev().take_while(|&c| c == '=').count();
+    (encoded.len() * 3 / 4).saturating_sub(padding)
+}
+
 /// Agent with basic chat memory using a list to store messages.
 /// Implements text-based responses with sentence processing pipeline.
 pub struct BasicMemoryAgent {
     memory: Vec<HashMap<String, serde_json::Value>>,
     llm: Arc<dyn StatelessLLMInterface>,
     system: String,
-    python_service: Arc<PythonServiceClient>,
+    /// System prompt as given, before the guard and interrupt notes
+    base_system: String,
     interrupt_handled: bool,
     interrupt_method: String, // "system" or "user"
-    faster_first_response: bool,
-    segment_method: String,
+    image_limits: ImageLimits,
+    file_limits: FileLimits,
+    /// Whether the LLM reads documents (PDFs) sent as they are
+    documents_supported: bool,
+    prompt_guard: Option<PromptGuard>,
+    /// The reply being continued this turn, as it was before
+    continued_reply: Option<String>,
+    /// Start given to replies unless the turn brings its own
+    prefill: Option<String>,
+    /// Whether the LLM goes on from a trailing assistant message; if not,
+    /// the prefill is only put in front of its reply
+    prefill_supported: bool,
+    /// Times a reply whose stream broke off is resumed before giving up
+    stream_retries: u32,
 }
 
 impl BasicMemoryAgent {
@@ -30,27 +165,27 @@ impl BasicMemoryAgent {
     /// # Arguments
     /// * `llm` - The LLM to use
     /// * `system` - System prompt
-    /// * `python_service` - Python service client
-    /// * `faster_first_response` - Whether to enable faster first response
-    /// * `segment_method` - Method for sentence segmentation ("regex" or "pysbd")
     /// * `interrupt_method` - Methods for writing interruptions signal in chat history ("system" or "user")
     pub fn new(
         llm: Arc<dyn StatelessLLMInterface>,
         system: String,
-        python_service: Arc<PythonServiceClient>,
-        faster_first_response: bool,
-        segment_method: String,
         interrupt_method: String,
     ) -> Self {
         let mut agent = Self {
             memory: Vec::new(),
             llm,
             system: String::new(),
-            python_service,
+            base_system: String::new(),
             interrupt_handled: false,
             interrupt_method,
-            faster_first_response,
-            segment_method,
+            image_limits: ImageLimits::default(),
+            file_limits: FileLimits::default(),
+            documents_supported: false,
+            prompt_guard: None,
+            continued_reply: None,
+            prefill: None,
+            prefill_supported: false,
+            stream_retries: 0,
         };
 
         agent.set_system(system);
@@ -58,15 +193,60 @@ impl BasicMemoryAgent {
         agent
     }
 
+    /// Limit the images accepted in a user message
+    pub fn with_image_limits(mut self, image_limits: ImageLimits) -> Self {
+        self.image_limits = image_limits;
+        self
+    }
+
+    /// Limit the files accepted in a user message
+    pub fn with_file_limits(mut self, file_limits: FileLimits) -> Self {
+        self.file_limits = file_limits;
+        self
+    }
+
+    /// Send documents to the LLM as they are rather than only describing
+    /// them; `supported` says whether it can read them
+    pub fn with_documents(mut self, supported: bool) -> Self {
+        self.documents_supported = supported;
+        self
+    }
+
+    /// Start every reply with `prefill`; `supported` says whether the LLM
+    /// can be asked to go on from it
+    pub fn with_prefill(mut self, prefill: Option<String>, supported: bool) -> Self {
+        self.prefill = prefill;
+        self.prefill_supported = supported;
+        self
+    }
+
+    /// Resume a reply whose stream breaks off up to `stream_retries` times
+    pub fn with_stream_retries(mut self, stream_retries: u32) -> Self {
+        self.stream_retries = stream_retries;
+        self
+    }
+
+    /// Delimit user messages so they can't pass for instructions
+    pub fn with_prompt_guard(mut self, prompt_guard: PromptGuard) -> Self {
+        self.prompt_guard = Some(prompt_guard);
+        self.set_system(self.base_system.clone());
+        self
+    }
+
     /// Set the system prompt
     pub fn set_system(&mut self, system: String) {
         debug!("Memory Agent: Setting system prompt: '''{}'''", system);
+        self.base_system = system.clone();
 
-        let system_prompt = if self.interrupt_method == "user" {
-            format!("{}\n\nIf you received `[interrupted by user]` signal, you were interrupted.", system)
-        } else {
-            system
-        };
+        let mut system_prompt = system;
+        if let Some(guard) = &self.prompt_guard {
+            system_prompt = format!("{}\n\n{}", system_prompt, guard.system_notice());
+        }
+        // The interrupt instruction goes after everything else, including any
+        // operator suffix, so it stays the last thing the model reads
+        if self.interrupt_method == "user" {
+            system_prompt.push_str("\n\nIf you received `[interrupted by user]` signal, you were interrupted.");
+        }
 
         self.system = system_prompt;
     }
@@ -146,15 +326,64 @@ impl BasicMemoryAgent {
             }
         }
 
-        message_parts.join("\n")
+        // Process files
+        if let Some(files) = &input_data.files {
+            message_parts.push("\nFiles in this message:".to_string());
+            for file in files {
+                message_parts.push(self.describe_file(file));
+            }
+        }
+
+        let text = message_parts.join("\n");
+        match &self.prompt_guard {
+            Some(guard) => guard.wrap(&text),
+            None => text,
+        }
     }
 
-    /// Prepare messages list with image support
+    /// A file as it appears in the text prompt: text files in full (up to
+    /// [`MAX_FILE_TEXT_CHARS`]), anything else by name, type and size
+    fn describe_file(&self, file: &FileData) -> String {
+        let size = decoded_len(&file.data);
+        let heading = format!("- {} ({}, {} bytes)", file.name, file.mime_type, size);
+
+        if file.is_text() {
+            if let Some(bytes) = file.decode() {
+                let text = String::from_utf8_lossy(&bytes);
+                let mut content: String = text.chars().take(MAX_FILE_TEXT_CHARS).collect();
+                if content.len() < text.len() {
+                    content.push_str("\n[... truncated]");
+                }
+                return format!("{}:\n```\n{}\n```", heading, content);
+            }
+        }
+        if file.is_document() && self.documents_supported {
+            return format!("{}: attached as a document", heading);
+        }
+        format!("{}: this file type can't be read, only its name and type are known", heading)
+    }
+
+    /// Files to send the LLM as content parts rather than as text
+    fn document_files<'a>(&self, input_data: &'a BatchInput) -> Vec<&'a FileData> {
+        if !self.documents_supported {
+            return Vec::new();
+        }
+        input_data
+            .files
+            .iter()
+            .flatten()
+            .filter(|f| f.is_document())
+            .collect()
+    }
+
+    /// Prepare messages list with image and document support
     fn to_messages(&mut self, input_data: &BatchInput) -> Vec<HashMap<String, serde_json::Value>> {
         let mut messages = self.memory.clone();
 
-        let user_message = if let Some(images) = &input_data.images {
-            // Multi-modal message with images
+        let images = input_data.images.as_deref().unwrap_or_default();
+        let documents = self.document_files(input_data);
+        let user_message = if !images.is_empty() || !documents.is_empty() {
+            // Multi-modal message with images or documents
             let mut content = Vec::new();
             let text_content = self.to_text_prompt(input_data);
             content.push(serde_json::json!({
@@ -172,6 +401,16 @@ impl BasicMemoryAgent {
                 }));
             }
 
+            for file in documents {
+                content.push(serde_json::json!({
+                    "type": "file",
+                    "file": {
+                        "filename": file.name,
+                        "file_data": file.data
+                    }
+                }));
+            }
+
             let mut msg = HashMap::new();
             msg.insert("role".to_string(), serde_json::json!("user"));
             msg.insert("content".to_string(), serde_json::json!(content));
@@ -194,6 +433,75 @@ impl BasicMemoryAgent {
 
         messages
     }
+
+    /// `messages` with the reply so far for the LLM to go on from: as the
+    /// start of its own message where it can take that, otherwise, when
+    /// `resuming` a broken off reply, followed by a request to go on
+    fn primed_messages(
+        &self,
+        messages: &[HashMap<String, serde_json::Value>],
+        so_far: &str,
+        resuming: bool,
+    ) -> Vec<HashMap<String, serde_json::Value>> {
+        let mut messages = messages.to_vec();
+        if so_far.trim().is_empty() || !(self.prefill_supported || resuming) {
+            return messages;
+        }
+        let mut reply = HashMap::new();
+        reply.insert("role".to_string(), serde_json::json!("assistant"));
+        // Some APIs refuse a final assistant message ending in whitespace
+        reply.insert("content".to_string(), serde_json::json!(so_far.trim_end()));
+        messages.push(reply);
+        if !self.prefill_supported {
+            let mut request = HashMap::new();
+            request.insert("role".to_string(), serde_json::json!("user"));
+            request.insert("content".to_string(), serde_json::json!(CONTINUE_PROMPT));
+            messages.push(request);
+        }
+        messages
+    }
+
+    /// Join what the LLM `generated` onto the reply it was primed with
+    fn stitch(&self, so_far: &str, generated: &str) -> String {
+        if !self.prefill_supported {
+            // The LLM answered anew rather than going on
+            return join_continuation(so_far, generated.trim_start());
+        }
+        // The LLM may or may not bring back the whitespace it wasn't sent
+        if generated.starts_with(char::is_whitespace) {
+            format!("{}{}", so_far.trim_end(), generated)
+        } else {
+            format!("{}{}", so_far, generated)
+        }
+    }
+
+    /// Content of the last message if it is the assistant's
+    fn last_reply(&self) -> Option<String> {
+        let last = self.memory.last()?;
+        if last.get("role").and_then(|v| v.as_str()) != Some("assistant") {
+            return None;
+        }
+        Some(last.get("content").and_then(|v| v.as_str()).unwrap_or_default().to_string())
+    }
+
+    /// Whether the last message is the user's and still unanswered
+    fn awaiting_reply(&self) -> bool {
+        self.memory.last().is_some_and(|msg| {
+            msg.get("role").and_then(|v| v.as_str()) == Some("user")
+                && msg.get("content").and_then(|v| v.as_str()) != Some("[Interrupted by user]")
+        })
+    }
+
+    /// Messages for continuing the last reply: memory as it is, followed by
+    /// the request to go on, which isn't remembered
+    fn to_continuation_messages(&self, input_data: &BatchInput) -> Vec<HashMap<String, serde_json::Value>> {
+        let mut messages = self.memory.clone();
+        let mut request = HashMap::new();
+        request.insert("role".to_string(), serde_json::json!("user"));
+        request.insert("content".to_string(), serde_json::json!(self.to_text_prompt(input_data)));
+        messages.push(request);
+        messages
+    }
 }
 
 #[async_trait]
@@ -202,39 +510,113 @@ impl AgentInterface for BasicMemoryAgent {
         &mut self,
         input_data: BatchInput,
     ) -> Box<dyn Stream<Item = Result<Box<dyn BaseOutput>, anyhow::Error>> + Send + Unpin> {
-        let messages = self.to_messages(&input_data);
+        // Reject before the message reaches memory or the LLM
+        if let Some(images) = &input_data.images {
+            if let Err(e) = self.image_limits.check(images) {
+                return Box::new(futures::stream::iter(vec![Err(e)]));
+            }
+        }
+        if let Some(files) = &input_data.files {
+            if let Err(e) = self.file_limits.check(files) {
+                return Box::new(futures::stream::iter(vec![Err(e)]));
+            }
+        }
+
+        self.continued_reply = None;
+        let messages = if !input_data.continuation {
+            self.to_messages(&input_data)
+        } else if let Some(reply) = self.last_reply() {
+            self.continued_reply = Some(reply);
+            self.to_continuation_messages(&input_data)
+        } else if self.awaiting_reply() {
+            // The last turn got no reply; answer it now
+            self.memory.clone()
+        } else {
+            self.to_messages(&input_data)
+        };
+        // A continued reply already has its start
+        let prefill = input_data
+            .prefill
+            .as_deref()
+            .or(self.prefill.as_deref())
+            .filter(|p| !p.trim().is_empty() && self.continued_reply.is_none())
+            .map(str::to_string);
         let system = Some(self.system.as_str());
 
         // Call LLM through stateless LLM interface
-        let token_stream = match self.llm.chat_completion(messages, system).await {
-            Ok(stream) => stream,
-            Err(e) => {
-                let error = anyhow::anyhow!("LLM error: {}", e);
-                return Box::new(futures::stream::iter(vec![Err(error)]));
-            }
-        };
+        let options = input_data
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get("sampling"))
+            .cloned()
+            .unwrap_or_else(|| serde_json::json!({}));
 
         // Collect tokens into complete response
         // TODO: Implement proper sentence-by-sentence streaming with transformers
         // For now, collect all tokens and create a single sentence output
-        let mut complete_response = String::new();
-        let token_stream = token_stream;
-        
         use futures::StreamExt;
-        let tokens: Vec<Result<String, anyhow::Error>> = token_stream.collect().await;
-        
-        for token_result in tokens {
-            match token_result {
-                Ok(token) => complete_response.push_str(&token),
-                Err(e) => {
-                    let error = anyhow::anyhow!("Token stream error: {}", e);
+        let mut complete_response = prefill.clone().unwrap_or_default();
+        let mut retries = 0;
+        let interruption = loop {
+            let request = self.primed_messages(&messages, &complete_response, retries > 0);
+            let mut token_stream = match self.llm.chat_completion_with_options(request, system, &options).await {
+                Ok(stream) => stream,
+                Err(e) if retries == 0 => {
+                    let error = anyhow::anyhow!("LLM error: {}", e);
                     return Box::new(futures::stream::iter(vec![Err(error)]));
                 }
+                Err(e) => break Some(e),
+            };
+
+            let mut generated = String::new();
+            let mut error = None;
+            while let Some(token_result) = token_stream.next().await {
+                match token_result {
+                    Ok(token) => generated.push_str(&token),
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                }
+            }
+            complete_response = self.stitch(&complete_response, &generated);
+
+            match error {
+                Some(e) if retries < self.stream_retries => {
+                    retries += 1;
+                    warn!(
+                        "LLM stream broke off after {} characters, resuming (retry {} of {}): {}",
+                        complete_response.chars().count(),
+                        retries,
+                        self.stream_retries,
+                        e
+                    );
+                }
+                error => break error,
+            }
+        };
+
+        if let Some(e) = &interruption {
+            // Only whole sentences of a broken off reply are kept
+            let (_, unfinished) = segment_text(&complete_response, detect_language(&complete_response));
+            let said = complete_response.trim_end();
+            complete_response = said.strip_suffix(unfinished.as_str()).unwrap_or(said).trim_end().to_string();
+            if complete_response.is_empty() {
+                let error = anyhow::anyhow!("Token stream error: {}", e);
+                return Box::new(futures::stream::iter(vec![Err(error)]));
             }
         }
 
         // Store complete response in memory
-        self.add_message(serde_json::json!(complete_response.clone()), "assistant", None);
+        match &self.continued_reply {
+            Some(reply) => {
+                let extended = join_continuation(reply, &complete_response);
+                if let Some(last_msg) = self.memory.last_mut() {
+                    last_msg.insert("content".to_string(), serde_json::json!(extended));
+                }
+            }
+            None => self.add_message(serde_json::json!(complete_response.clone()), "assistant", None),
+        }
 
         // Create sentence output
         // TODO: Apply transformers (sentence_divider, actions_extractor, display_processor, tts_filter)
@@ -244,7 +626,11 @@ impl AgentInterface for BasicMemoryAgent {
             actions: Actions::new(),
         };
 
-        Box::new(futures::stream::iter(vec![Ok(Box::new(output) as Box<dyn BaseOutput>)]))
+        let mut outputs = vec![Ok(Box::new(output) as Box<dyn BaseOutput>)];
+        if let Some(cause) = interruption {
+            outputs.push(Err(anyhow::Error::new(GenerationInterrupted { cause })));
+        }
+        Box::new(futures::stream::iter(outputs))
     }
 
     /// Handle an interruption by the user.
@@ -258,6 +644,12 @@ impl AgentInterface for BasicMemoryAgent {
 
         self.interrupt_handled = true;
 
+        // A continued reply keeps what was said before the continuation
+        let heard_response = match &self.continued_reply {
+            Some(reply) => join_continuation(reply, heard_response),
+            None => heard_response.to_string(),
+        };
+
         // Update last assistant message if exists
         if let Some(last_msg) = self.memory.last_mut() {
             if last_msg.get("role").and_then(|v| v.as_str()) == Some("assistant") {
@@ -289,6 +681,35 @@ impl AgentInterface for BasicMemoryAgent {
         );
     }
 
+    /// Reset the interrupt handled flag for a new conversation.
+    fn reset_interrupt(&mut self) {
+        self.interrupt_handled = false;
+    }
+
+    /// Drop the last user message and the replies after it. Interrupt
+    /// markers are part of the turn they interrupted, not a turn of their own.
+    fn rewind_last_turn(&mut self) {
+        let last_turn = self.memory.iter().rposition(|msg| {
+            msg.get("role").and_then(|v| v.as_str()) == Some("user")
+                && msg.get("content").and_then(|v| v.as_str()) != Some("[Interrupted by user]")
+        });
+        if let Some(index) = last_turn {
+            self.memory.truncate(index);
+        }
+    }
+
+    fn clear_memory(&mut self) {
+        self.memory.clear();
+        self.add_message(serde_json::json!(self.system.clone()), "system", None);
+    }
+
+    fn memory_snapshot(&self) -> Option<MemorySnapshot> {
+        Some(MemorySnapshot {
+            system: self.system.clone(),
+            messages: self.memory.clone(),
+        })
+    }
+
     /// Load the memory from chat history
     fn set_memory_from_history(&mut self, conf_uid: &str, history_uid: &str) {
         // Load history from file system
@@ -303,17 +724,29 @@ impl AgentInterface for BasicMemoryAgent {
                     None,
                 );
 
-                // Add history messages
+                // Add history messages, keeping who said each one
                 for msg in messages {
-                    let role = if msg.role == "human" {
-                        "user"
-                    } else {
-                        "assistant"
+                    let role = match msg.role.as_str() {
+                        "human" => "user",
+                        // Interrupt markers go back in as handle_interrupt wrote them
+                        "system" if self.interrupt_method == "system" => "system",
+                        "system" => "user",
+                        _ => "assistant",
+                    };
+                    let speaker = DisplayText {
+                        text: String::new(),
+                        name: msg.name.clone(),
+                        avatar: msg.avatar.clone(),
+                    };
+                    // Past user turns look the same as new ones
+                    let content = match &self.prompt_guard {
+                        Some(guard) if role == "user" && msg.role == "human" => guard.wrap(&msg.content),
+                        _ => msg.content,
                     };
                     self.add_message(
-                        serde_json::json!(msg.content),
+                        serde_json::json!(content),
                         role,
-                        None,
+                        Some(&speaker),
                     );
                 }
             }
@@ -333,11 +766,6 @@ impl AgentInterface for BasicMemoryAgent {
 
 // Additional methods not part of the trait
 impl BasicMemoryAgent {
-    /// Reset the interrupt handled flag for a new conversation.
-    pub fn reset_interrupt(&mut self) {
-        self.interrupt_handled = false;
-    }
-
     /// Start a group conversation by adding a system message that informs the AI about
     /// the conversation participants.
     ///
@@ -358,3 +786,129 @@ impl BasicMemoryAgent {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// LLM answering every request with one reply, counting the requests
+    #[derive(Default)]
+    struct FakeLLM {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StatelessLLMInterface for FakeLLM {
+        async fn chat_completion(
+            &self,
+            _messages: Vec<HashMap<String, serde_json::Value>>,
+            _system: Option<&str>,
+        ) -> anyhow::Result<Box<dyn Stream<Item = anyhow::Result<String>> + Send + Unpin>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(futures::stream::iter(vec![Ok("Hello there.".to_string())])))
+        }
+    }
+
+    fn agent(llm: Arc<FakeLLM>) -> BasicMemoryAgent {
+        BasicMemoryAgent::new(
+            llm,
+            "You are a test.".to_string(),
+            "user".to_string(),
+        )
+    }
+
+    /// An image whose base64 data decodes to `bytes` bytes
+    fn image(bytes: usize) -> ImageData {
+        use base64::Engine;
+        ImageData {
+            source: ImageSource::Upload,
+            data: base64::engine::general_purpose::STANDARD.encode(vec![0u8; bytes]),
+            mime_type: "image/png".to_string(),
+        }
+    }
+
+    fn limits() -> ImageLimits {
+        ImageLimits {
+            max_images: 2,
+            max_image_bytes: 100,
+            max_total_bytes: 150,
+        }
+    }
+
+    #[test]
+    fn decoded_len_ignores_padding_and_data_uri_prefix() {
+        for bytes in [0, 1, 2, 3, 100] {
+            let data = image(bytes).data;
+            assert_eq!(decoded_len(&data), bytes);
+            assert_eq!(decoded_len(&format!("data:image/png;base64,{}", data)), bytes);
+        }
+    }
+
+    #[test]
+    fn image_count_limit_is_inclusive() {
+        assert!(limits().check(&[image(10), image(10)]).is_ok());
+        let err = limits().check(&[image(10), image(10), image(10)]).unwrap_err();
+        assert!(err.to_string().contains("Too many images"), "{err}");
+    }
+
+    #[test]
+    fn image_size_limit_is_inclusive() {
+        assert!(limits().check(&[image(100)]).is_ok());
+        let err = limits().check(&[image(10), image(101)]).unwrap_err();
+        assert!(err.to_string().contains("Image 2 is 101 bytes"), "{err}");
+    }
+
+    #[test]
+    fn total_size_limit_is_inclusive() {
+        assert!(limits().check(&[image(100), image(50)]).is_ok());
+        let err = limits().check(&[image(100), image(51)]).unwrap_err();
+        assert!(err.to_string().contains("Images total 151 bytes"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn images_over_the_limit_fail_the_stream_before_the_llm() {
+        use futures::StreamExt;
+        let llm = Arc::new(FakeLLM::default());
+        let mut agent = agent(llm.clone()).with_image_limits(limits());
+        let mut input = BatchInput::new(Vec::new());
+        input.images = Some(vec![image(1); 3]);
+
+        let outputs: Vec<_> = agent.chat(input).await.collect().await;
+
+        assert_eq!(outputs.len(), 1);
+        assert!(outputs[0].is_err());
+        assert_eq!(llm.calls.load(Ordering::SeqCst), 0);
+        assert!(agent.memory_snapshot().unwrap().messages.is_empty());
+    }
+
+    #[test]
+    fn group_history_reloads_with_each_speaker() {
+        let conf_uid = format!("test-{}", uuid::Uuid::new_v4().as_simple());
+        let history_uid = chat_history::create_new_history(&conf_uid, false).unwrap();
+        for (role, content, name, avatar) in [
+            ("human", "Hi both", "Alice", None),
+            ("ai", "Hello Alice", "Mio", Some("mio.png")),
+            ("ai", "Hey", "Rin", Some("rin.png")),
+        ] {
+            chat_history::store_message(&conf_uid, &history_uid, role, content, Some(name), avatar, false).unwrap();
+        }
+
+        let mut agent = agent(Arc::new(FakeLLM::default()));
+        agent.set_memory_from_history(&conf_uid, &history_uid);
+        let _ = std::fs::remove_dir_all(std::path::Path::new("chat_history").join(&conf_uid));
+
+        let speakers: Vec<_> = agent.memory[1..]
+            .iter()
+            .map(|m| (m["role"].as_str(), m["name"].as_str(), m.get("avatar").and_then(|a| a.as_str())))
+            .collect();
+        assert_eq!(
+            speakers,
+            [
+                (Some("user"), Some("Alice"), None),
+                (Some("assistant"), Some("Mio"), Some("mio.png")),
+                (Some("assistant"), Some("Rin"), Some("rin.png")),
+            ]
+        );
+    }
+}
@@ -17,6 +17,8 @@ pub struct HumeAIAgent {
     host: String,
     config_id: Option<String>,
     idle_timeout: u32,
+    /// Chat group of the loaded history, if it was a group conversation
+    group_id: Option<String>,
 }
 
 impl HumeAIAgent {
@@ -38,6 +40,7 @@ impl HumeAIAgent {
             host,
             config_id,
             idle_timeout,
+            group_id: None,
         }
     }
 }
@@ -57,18 +60,19 @@ impl AgentInterface for HumeAIAgent {
         // Handle user interruption (not implemented for Hume AI)
     }
 
-    fn set_memory_from_history(&mut self, _conf_uid: &str, _history_uid: &str) {
-        // TODO: Set chat group ID based on history metadata
-        // For now, this is a stub
+    fn set_memory_from_history(&mut self, conf_uid: &str, history_uid: &str) {
+        self.group_id = match crate::chat_history::get_metadata(conf_uid, history_uid) {
+            Ok(metadata) => metadata.group_id,
+            Err(e) => {
+                warn!("Could not read metadata of history {}: {}", history_uid, e);
+                None
+            }
+        };
     }
 }
 
 // Additional methods not part of the trait
 impl HumeAIAgent {
-    pub fn reset_interrupt(&mut self) {
-        // Stub
-    }
-
     pub fn start_group_conversation(&mut self, _human_name: &str, _ai_participants: &[String]) {
         // Stub
     }
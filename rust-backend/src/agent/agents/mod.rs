@@ -2,6 +2,7 @@ pub mod agent_interface;
 pub mod basic_memory_agent;
 pub mod hume_ai;
 pub mod mem0_llm;
+pub mod proxy_agent;
 
 pub use agent_interface::*;
 pub use basic_memory_agent::*;
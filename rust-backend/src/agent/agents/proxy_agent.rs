@@ -0,0 +1,242 @@
+use async_trait::async_trait;
+use base64::Engine;
+use futures::{SinkExt, Stream, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+
+use super::agent_interface::AgentInterface;
+use crate::agent::input_types::{BatchInput, TextSource};
+use crate::agent::output_types::{Actions, AudioOutput, BaseOutput, DisplayText, SentenceOutput};
+
+type Upstream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Agent that forwards each turn to an upstream Open-LLM-VTuber compatible
+/// WebSocket and relays the replies
+///
+/// The upstream keeps its own memory, so history and interrupts are not
+/// replayed to it. The connection is reused across turns; if it drops, or a
+/// turn is cancelled while reading, it is reopened on the next turn.
+pub struct ProxyAgent {
+    url: String,
+    auth_token: Option<String>,
+    idle_timeout: Duration,
+    cache_dir: String,
+    connection: Arc<Mutex<Option<Upstream>>>,
+}
+
+impl ProxyAgent {
+    /// # Arguments
+    /// * `url` - Upstream WebSocket endpoint
+    /// * `auth_token` - Optional bearer token sent when connecting
+    /// * `idle_timeout` - Seconds to wait for each upstream message
+    /// * `cache_dir` - Directory upstream audio is written to
+    pub fn new(url: String, auth_token: Option<String>, idle_timeout: u32, cache_dir: String) -> Self {
+        info!("Initialized ProxyAgent: upstream={}", url);
+        Self {
+            url,
+            auth_token,
+            idle_timeout: Duration::from_secs(idle_timeout.max(1) as u64),
+            cache_dir,
+            connection: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Take the open connection, or open a new one
+    async fn take_connection(&self) -> anyhow::Result<Upstream> {
+        if let Some(upstream) = self.connection.lock().await.take() {
+            return Ok(upstream);
+        }
+
+        let mut request = self.url.as_str().into_client_request()?;
+        if let Some(token) = &self.auth_token {
+            request
+                .headers_mut()
+                .insert("Authorization", format!("Bearer {}", token).parse()?);
+        }
+        let (upstream, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to upstream {}: {}", self.url, e))?;
+        info!("ProxyAgent connected to {}", self.url);
+        Ok(upstream)
+    }
+
+    /// The `text-input` message for a turn
+    fn to_upstream_input(input_data: &BatchInput) -> serde_json::Value {
+        let text = input_data
+            .texts
+            .iter()
+            .filter(|t| t.source == TextSource::Input)
+            .map(|t| t.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let images: Vec<&str> = input_data
+            .images
+            .iter()
+            .flatten()
+            .map(|image| image.data.as_str())
+            .collect();
+
+        serde_json::json!({
+            "type": "text-input",
+            "text": text,
+            "images": images
+        })
+    }
+}
+
+/// What an upstream message means for the turn in progress
+enum Relay {
+    Output(Box<dyn BaseOutput>),
+    /// Message to send back upstream
+    Reply(serde_json::Value),
+    Done,
+    Skip,
+}
+
+/// Turn an upstream `audio` payload into an output, saving its audio into
+/// the cache so it can be served like locally synthesized speech
+fn relay_audio(payload: &serde_json::Value, cache_dir: &str) -> anyhow::Result<Box<dyn BaseOutput>> {
+    let display = payload.get("display_text");
+    let display_text = DisplayText {
+        text: display
+            .and_then(|d| d.get("text"))
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        name: display
+            .and_then(|d| d.get("name"))
+            .and_then(|n| n.as_str())
+            .map(|n| n.to_string()),
+        avatar: display
+            .and_then(|d| d.get("avatar"))
+            .and_then(|a| a.as_str())
+            .map(|a| a.to_string()),
+    };
+    let actions: Actions = payload
+        .get("actions")
+        .filter(|a| !a.is_null())
+        .map(|a| serde_json::from_value(a.clone()))
+        .transpose()?
+        .unwrap_or_default();
+
+    let Some(audio) = payload.get("audio").and_then(|a| a.as_str()) else {
+        return Ok(Box::new(SentenceOutput {
+            tts_text: display_text.text.clone(),
+            display_text,
+            actions,
+        }));
+    };
+
+    let bytes = base64::engine::general_purpose::STANDARD.decode(audio)?;
+    std::fs::create_dir_all(cache_dir)?;
+    let audio_path = std::path::Path::new(cache_dir)
+        .join(format!("proxy_{}.wav", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    std::fs::write(&audio_path, bytes)?;
+
+    Ok(Box::new(AudioOutput {
+        audio_path,
+        transcript: display_text.text.clone(),
+        display_text,
+        actions,
+    }))
+}
+
+/// Interpret one upstream message
+fn relay(message: Message, cache_dir: &str) -> anyhow::Result<Relay> {
+    let text = match message {
+        Message::Text(text) => text,
+        Message::Close(_) => anyhow::bail!("Upstream closed the connection"),
+        _ => return Ok(Relay::Skip),
+    };
+    let payload: serde_json::Value = serde_json::from_str(&text)?;
+
+    match payload.get("type").and_then(|t| t.as_str()) {
+        Some("audio") => Ok(Relay::Output(relay_audio(&payload, cache_dir)?)),
+        // Upstream waits for playback before ending the turn; nothing plays here
+        Some("backend-synth-complete") => Ok(Relay::Reply(serde_json::json!({
+            "type": "frontend-playback-complete"
+        }))),
+        Some("control") if payload.get("text").and_then(|t| t.as_str()) == Some("conversation-chain-end") => {
+            Ok(Relay::Done)
+        }
+        Some("error") => anyhow::bail!(
+            "Upstream error: {}",
+            payload.get("message").and_then(|m| m.as_str()).unwrap_or("unknown error")
+        ),
+        _ => Ok(Relay::Skip),
+    }
+}
+
+#[async_trait]
+impl AgentInterface for ProxyAgent {
+    async fn chat(
+        &mut self,
+        input_data: BatchInput,
+    ) -> Box<dyn Stream<Item = Result<Box<dyn BaseOutput>, anyhow::Error>> + Send + Unpin> {
+        let mut upstream = match self.take_connection().await {
+            Ok(upstream) => upstream,
+            Err(e) => return Box::new(futures::stream::iter(vec![Err(e)])),
+        };
+        let input = Self::to_upstream_input(&input_data).to_string();
+        if let Err(e) = upstream.send(Message::Text(input)).await {
+            let error = anyhow::anyhow!("Failed to send to upstream: {}", e);
+            return Box::new(futures::stream::iter(vec![Err(error)]));
+        }
+
+        let slot = self.connection.clone();
+        let cache_dir = self.cache_dir.clone();
+        let idle_timeout = self.idle_timeout;
+
+        // The stream owns the connection while the turn runs and hands it
+        // back only once the turn ends cleanly
+        let outputs = futures::stream::unfold(Some(upstream), move |upstream| {
+            let slot = slot.clone();
+            let cache_dir = cache_dir.clone();
+            async move {
+                let mut upstream = upstream?;
+                loop {
+                    let message = match tokio::time::timeout(idle_timeout, upstream.next()).await {
+                        Ok(Some(Ok(message))) => message,
+                        Ok(Some(Err(e))) => {
+                            return Some((Err(anyhow::anyhow!("Upstream connection failed: {}", e)), None))
+                        }
+                        Ok(None) => return Some((Err(anyhow::anyhow!("Upstream disconnected")), None)),
+                        Err(_) => {
+                            return Some((Err(anyhow::anyhow!("Upstream did not respond in time")), None))
+                        }
+                    };
+
+                    match relay(message, &cache_dir) {
+                        Ok(Relay::Output(output)) => return Some((Ok(output), Some(upstream))),
+                        Ok(Relay::Reply(reply)) => {
+                            if let Err(e) = upstream.send(Message::Text(reply.to_string())).await {
+                                warn!("Failed to reply to upstream: {}", e);
+                            }
+                        }
+                        Ok(Relay::Done) => {
+                            *slot.lock().await = Some(upstream);
+                            return None;
+                        }
+                        Ok(Relay::Skip) => {}
+                        Err(e) => return Some((Err(e), None)),
+                    }
+                }
+            }
+        });
+
+        Box::new(outputs.boxed())
+    }
+
+    fn handle_interrupt(&mut self, _heard_response: &str) {
+        // The interrupted turn dropped its connection, which stops the
+        // upstream reply; the next turn reconnects
+    }
+}
@@ -46,6 +46,35 @@ pub struct FileData {
     pub mime_type: String,
 }
 
+/// Types besides `text/*` whose contents are text
+const TEXT_MIME_TYPES: [&str; 6] = [
+    "application/json",
+    "application/xml",
+    "application/yaml",
+    "application/x-yaml",
+    "application/toml",
+    "application/javascript",
+];
+
+impl FileData {
+    /// Whether the file's contents can be put in the prompt as they are
+    pub fn is_text(&self) -> bool {
+        self.mime_type.starts_with("text/") || TEXT_MIME_TYPES.contains(&self.mime_type.as_str())
+    }
+
+    /// Whether the file is a document some LLMs read natively
+    pub fn is_document(&self) -> bool {
+        self.mime_type == "application/pdf"
+    }
+
+    /// The file's bytes, or None if `data` isn't valid base64
+    pub fn decode(&self) -> Option<Vec<u8>> {
+        use base64::Engine;
+        let encoded = self.data.split_once(";base64,").map_or(self.data.as_str(), |(_, d)| d);
+        base64::engine::general_purpose::STANDARD.decode(encoded.trim_end()).ok()
+    }
+}
+
 /// Represents text data from various sources
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextData {
@@ -61,6 +90,10 @@ pub struct TextData {
 /// Base trait for all input types
 pub trait BaseInput: Send + Sync {}
 
+/// What an agent is asked when it should go on with its last reply
+pub const CONTINUE_PROMPT: &str =
+    "Continue your last reply from where it stopped, without repeating what you already said.";
+
 /// Input type for batch processing, containing complete transcription and optional media
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BatchInput {
@@ -72,6 +105,17 @@ pub struct BatchInput {
     /// Optional list of files
     #[serde(skip_serializing_if = "Option::is_none")]
     pub files: Option<Vec<FileData>>,
+    /// Optional per-turn settings (e.g. `sampling` overrides for the LLM)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Extend the agent's last reply rather than answer `texts` as a new
+    /// turn; agents that can't treat `texts` as the user asking for more
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub continuation: bool,
+    /// Start of the agent's reply, which it goes on from; an empty string
+    /// turns off the configured one for this turn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefill: Option<String>,
 }
 
 impl BaseInput for BatchInput {}
@@ -82,6 +126,9 @@ impl BatchInput {
             texts,
             images: None,
             files: None,
+            metadata: None,
+            continuation: false,
+            prefill: None,
         }
     }
 }
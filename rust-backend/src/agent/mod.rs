@@ -3,6 +3,7 @@ pub mod output_types;
 pub mod agent_factory;
 pub mod stateless_llm_factory;
 pub mod transformers;
+pub mod prompt_guard;
 
 pub mod agents;
 pub mod stateless_llm;
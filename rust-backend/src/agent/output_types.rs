@@ -12,6 +12,16 @@ pub struct Actions {
     /// List of sound paths/URLs
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sounds: Option<Vec<String>>,
+    /// Expressions to switch to at points during playback
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timed_expressions: Option<Vec<TimedExpression>>,
+}
+
+/// An expression change scheduled at an offset into a sentence's audio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedExpression {
+    pub offset_ms: u64,
+    pub expression: serde_json::Value,
 }
 
 impl Actions {
@@ -20,6 +30,7 @@ impl Actions {
             expressions: None,
             pictures: None,
             sounds: None,
+            timed_expressions: None,
         }
     }
 
@@ -35,6 +46,9 @@ impl Actions {
         if let Some(ref snds) = self.sounds {
             result.insert("sounds".to_string(), serde_json::to_value(snds).unwrap());
         }
+        if let Some(ref timed) = self.timed_expressions {
+            result.insert("timed_expressions".to_string(), serde_json::to_value(timed).unwrap());
+        }
         serde_json::Value::Object(result)
     }
 }
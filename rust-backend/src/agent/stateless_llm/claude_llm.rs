@@ -1,51 +1,212 @@
 use async_trait::async_trait;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::info;
+use tracing::{debug, info};
 
 use super::stateless_llm_interface::StatelessLLMInterface;
-use crate::python_service::PythonServiceClient;
+use crate::utils::http_status::HttpStatusError;
+use crate::utils::utf8_decoder::Utf8Decoder;
+use crate::config_manager::stateless_llm::ClaudeConfig;
+use crate::python_service::PythonService;
 
 /// Claude LLM implementation
+///
+/// Streams from Anthropic's Messages API (`/v1/messages`) by default. When
+/// `native` is false the request goes through the Python service instead.
 pub struct ClaudeLLM {
+    client: reqwest::Client,
     model: String,
     base_url: String,
     api_key: String,
     system: String,
-    python_service: Arc<PythonServiceClient>,
+    max_tokens: u32,
+    anthropic_version: String,
+    native: bool,
+    python_service: Arc<dyn PythonService>,
 }
 
 impl ClaudeLLM {
-    pub fn new(
-        system: String,
-        base_url: String,
-        model: String,
-        api_key: String,
-        python_service: Arc<PythonServiceClient>,
-    ) -> Self {
-        info!("Initialized ClaudeLLM: model={}, base_url={}", model, base_url);
+    pub fn new(system: String, config: &ClaudeConfig, python_service: Arc<dyn PythonService>) -> Self {
+        info!(
+            "Initialized ClaudeLLM: model={}, base_url={}, native={}",
+            config.model, config.base_url, config.native
+        );
         Self {
-            model,
-            base_url,
-            api_key,
+            client: reqwest::Client::new(),
+            model: config.model.clone(),
+            base_url: config.base_url.clone(),
+            api_key: config.llm_api_key.clone(),
             system,
+            max_tokens: config.max_tokens,
+            anthropic_version: config.anthropic_version.clone(),
+            native: config.native,
             python_service,
         }
     }
-}
 
-#[async_trait]
-impl StatelessLLMInterface for ClaudeLLM {
-    async fn chat_completion(
+    /// Convert OpenAI-style messages into Claude's format
+    ///
+    /// System messages are dropped, since Claude takes the system prompt as a
+    /// separate field. Data URI images become base64 image blocks.
+    fn to_claude_messages(messages: Vec<HashMap<String, serde_json::Value>>) -> Vec<serde_json::Value> {
+        let mut result = Vec::new();
+
+        for msg in messages {
+            let role = msg.get("role").and_then(|v| v.as_str()).unwrap_or("user");
+            if role == "system" {
+                continue;
+            }
+            let Some(content) = msg.get("content") else {
+                continue;
+            };
+
+            let content = match content.as_array() {
+                Some(parts) => {
+                    let blocks: Vec<serde_json::Value> = parts
+                        .iter()
+                        .filter_map(|part| match part.get("type").and_then(|v| v.as_str()) {
+                            Some("text") => Some(serde_json::json!({
+                                "type": "text",
+                                "text": part.get("text").and_then(|v| v.as_str()).unwrap_or("")
+                            })),
+                            Some("image_url") => {
+                                let url = part.pointer("/image_url/url").and_then(|v| v.as_str())?;
+                                let (header, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+                                Some(serde_json::json!({
+                                    "type": "image",
+                                    "source": {
+                                        "type": "base64",
+                                        "media_type": header,
+                                        "data": data
+                                    }
+                                }))
+                            }
+                            Some("file") => {
+                                let url = part.pointer("/file/file_data").and_then(|v| v.as_str())?;
+                                let (header, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+                                Some(serde_json::json!({
+                                    "type": "document",
+                                    "source": {
+                                        "type": "base64",
+                                        "media_type": header,
+                                        "data": data
+                                    },
+                                    "title": part.pointer("/file/filename").and_then(|v| v.as_str()).unwrap_or("")
+                                }))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    serde_json::Value::Array(blocks)
+                }
+                None => serde_json::Value::String(
+                    content
+                        .as_str()
+                        .map(|s| s.to_string())
+                        .unwrap_or_else(|| content.to_string()),
+                ),
+            };
+
+            result.push(serde_json::json!({ "role": role, "content": content }));
+        }
+
+        result
+    }
+
+    /// Stream a completion from `/v1/messages`, which answers with
+    /// server-sent events
+    async fn native_chat(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: &str,
+        options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        let mut body = serde_json::json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "messages": Self::to_claude_messages(messages),
+            "stream": true
+        });
+        if !system.is_empty() {
+            body["system"] = serde_json::json!(system);
+        }
+        if let (Some(b), Some(opts)) = (body.as_object_mut(), options.as_object()) {
+            // The Messages API calls the OpenAI-style `stop` option `stop_sequences`
+            b.extend(opts.iter().map(|(k, v)| {
+                let key = if k == "stop" { "stop_sequences" } else { k.as_str() };
+                (key.to_string(), v.clone())
+            }));
+        }
+
+        let base_url = self.base_url.trim_end_matches('/').trim_end_matches("/v1");
+        let url = format!("{}/v1/messages", base_url);
+        debug!("Claude: POST {}", url);
+        let response = self
+            .client
+            .post(&url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", &self.anthropic_version)
+            .json(&body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(HttpStatusError::new(status, format!("Claude returned {}: {}", status, text)).into());
+        }
+
+        let bytes = response.bytes_stream();
+        let tokens = futures::stream::unfold(
+            (bytes, Utf8Decoder::new(), String::new(), false),
+            |(mut bytes, mut decoder, mut buffer, mut done)| async move {
+                loop {
+                    if done {
+                        return None;
+                    }
+
+                    // Handle the next complete line if one is buffered
+                    if let Some(pos) = buffer.find('\n') {
+                        let line: String = buffer.drain(..=pos).collect();
+                        match parse_event_line(&line) {
+                            Ok(Some(ClaudeEvent::Text(token))) => {
+                                return Some((Ok(token), (bytes, decoder, buffer, done)));
+                            }
+                            Ok(Some(ClaudeEvent::Stop)) => done = true,
+                            Ok(None) => {}
+                            Err(e) => return Some((Err(e), (bytes, decoder, buffer, true))),
+                        }
+                        continue;
+                    }
+
+                    match bytes.next().await {
+                        Some(Ok(chunk)) => buffer.push_str(&decoder.push(&chunk)),
+                        Some(Err(e)) => return Some((Err(e.into()), (bytes, decoder, buffer, true))),
+                        None => {
+                            // Flush a trailing line without a newline
+                            done = true;
+                            buffer.push_str(&decoder.finish());
+                            if let Ok(Some(ClaudeEvent::Text(token))) = parse_event_line(&buffer) {
+                                return Some((Ok(token), (bytes, decoder, buffer, done)));
+                            }
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(Box::new(tokens.boxed()))
+    }
+
+    /// Send the conversation through the Python service
+    async fn python_chat(
         &self,
         messages: Vec<HashMap<String, serde_json::Value>>,
-        _system: Option<&str>,
+        system: &str,
     ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
-        // Claude uses system prompt from constructor
         let mut service_messages = vec![crate::python_service::Message {
             role: "system".to_string(),
-            content: self.system.clone(),
+            content: system.to_string(),
         }];
 
         for msg in messages {
@@ -75,10 +236,73 @@ impl StatelessLLMInterface for ClaudeLLM {
         let service = self.python_service.clone();
         let response = service.chat(request).await?;
         let text = response.text;
-        
+
         // Split into words as tokens (simplified)
         let tokens: Vec<String> = text.split_whitespace().map(|s| s.to_string()).collect();
         Ok(Box::new(futures::stream::iter(tokens.into_iter().map(Ok))))
     }
 }
 
+/// Event from the Messages API stream that the caller cares about
+enum ClaudeEvent {
+    Text(String),
+    Stop,
+}
+
+/// Parse one server-sent event line; only `data:` lines carry anything
+fn parse_event_line(line: &str) -> Result<Option<ClaudeEvent>, anyhow::Error> {
+    let line = line.trim();
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(None);
+    };
+
+    let value: serde_json::Value = serde_json::from_str(data.trim())?;
+    match value.get("type").and_then(|t| t.as_str()) {
+        Some("content_block_delta") => {
+            let text = value
+                .pointer("/delta/text")
+                .and_then(|t| t.as_str())
+                .unwrap_or("");
+            if text.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(ClaudeEvent::Text(text.to_string())))
+            }
+        }
+        Some("message_stop") => Ok(Some(ClaudeEvent::Stop)),
+        Some("error") => {
+            let message = value
+                .pointer("/error/message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown error");
+            Err(anyhow::anyhow!("Claude error: {}", message))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[async_trait]
+impl StatelessLLMInterface for ClaudeLLM {
+    async fn chat_completion(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        self.chat_completion_with_options(messages, system, &serde_json::json!({})).await
+    }
+
+    async fn chat_completion_with_options(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        // Fall back to the prompt given at construction
+        let system = system.unwrap_or(&self.system);
+        if self.native {
+            self.native_chat(messages, system, options).await
+        } else {
+            self.python_chat(messages, system).await
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+use super::stateless_llm_interface::StatelessLLMInterface;
+
+/// Shortest repeat, in non-whitespace characters, taken for a replay
+///
+/// Shorter repeats ("very very", "no, no") are as likely meant as not, so
+/// they are left alone.
+const MIN_REPEAT_CHARS: usize = 12;
+
+/// Chunk boundaries remembered for matching replays against
+const MAX_BOUNDARIES: usize = 256;
+
+/// LLM wrapper that drops content a provider sends twice
+///
+/// Some providers replay the last chunks of a stream after reconnecting or
+/// retrying internally, which the user hears as a stutter ("I think that I
+/// think that it works"). See [`collapse_replays`] for what counts as one.
+pub struct DedupeLLM {
+    inner: Arc<dyn StatelessLLMInterface>,
+}
+
+impl DedupeLLM {
+    pub fn new(inner: Arc<dyn StatelessLLMInterface>) -> Self {
+        info!("Initialized DedupeLLM: min_repeat_chars={}", MIN_REPEAT_CHARS);
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl StatelessLLMInterface for DedupeLLM {
+    async fn chat_completion(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        self.chat_completion_with_options(messages, system, &serde_json::json!({})).await
+    }
+
+    async fn chat_completion_with_options(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        let stream = self
+            .inner
+            .chat_completion_with_options(messages, system, options)
+            .await?;
+        Ok(collapse_replays(stream))
+    }
+}
+
+/// Where a chunk started in the stream
+struct Boundary {
+    /// Byte offset from the start of the stream
+    offset: usize,
+    /// Characters besides whitespace emitted before it
+    solid_before: usize,
+}
+
+/// The end of what has gone out of the stream so far
+#[derive(Default)]
+struct Emitted {
+    /// Text since the oldest remembered boundary
+    text: String,
+    /// Stream offset of the start of `text`
+    start: usize,
+    /// Characters besides whitespace emitted in all
+    solid: usize,
+    /// The last [`MAX_BOUNDARIES`] chunk boundaries, oldest first
+    boundaries: VecDeque<Boundary>,
+}
+
+impl Emitted {
+    /// Tails of the text starting at a chunk boundary that are long enough
+    /// to count as a replay, longest first
+    fn tails(&self) -> impl Iterator<Item = &str> {
+        self.boundaries
+            .iter()
+            .take_while(|b| self.solid - b.solid_before >= MIN_REPEAT_CHARS)
+            .map(|b| &self.text[b.offset - self.start..])
+    }
+
+    fn push(&mut self, chunk: &str) {
+        self.boundaries.push_back(Boundary {
+            offset: self.start + self.text.len(),
+            solid_before: self.solid,
+        });
+        self.text.push_str(chunk);
+        self.solid += chunk.chars().filter(|c| !c.is_whitespace()).count();
+
+        if self.boundaries.len() > MAX_BOUNDARIES {
+            self.boundaries.pop_front();
+            let oldest = self.boundaries[0].offset;
+            self.text.drain(..oldest - self.start);
+            self.start = oldest;
+        }
+    }
+}
+
+/// Drop exact repeats of the stream's tail that arrive at a chunk boundary
+///
+/// A repeat is collapsed when the text after a boundary starts with
+/// everything emitted since an earlier boundary, leading whitespace aside,
+/// and that span has at least
+/// [`MIN_REPEAT_CHARS`] characters besides whitespace. Text that could still
+/// turn out to be such a repeat is held back until the next chunk decides
+/// it, so replays split over several chunks are caught too.
+pub fn collapse_replays(
+    stream: Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>,
+) -> Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin> {
+    let emitted = Emitted::default();
+    let tokens = futures::stream::unfold(
+        (stream, emitted, String::new(), false),
+        |(mut stream, mut emitted, mut pending, done)| async move {
+            if done {
+                return None;
+            }
+
+            loop {
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        pending.push_str(&chunk);
+
+                        // A replay may come with or without the whitespace
+                        // that led the original
+                        let text = pending.trim_start();
+                        let leading = pending.len() - text.len();
+                        let replayed = emitted
+                            .tails()
+                            .map(str::trim_start)
+                            .find(|tail| text.starts_with(tail))
+                            .map(|tail| leading + tail.len());
+                        if let Some(len) = replayed {
+                            debug!("Dropping {} replayed bytes from the LLM stream", len);
+                            pending.drain(..len);
+                        } else if emitted.tails().any(|tail| tail.trim_start().starts_with(text)) {
+                            // May be the start of a replay; wait for more
+                            continue;
+                        }
+
+                        if !pending.is_empty() {
+                            emitted.push(&pending);
+                            let chunk = std::mem::take(&mut pending);
+                            return Some((Ok(chunk), (stream, emitted, pending, false)));
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), (stream, emitted, pending, false))),
+                    None => {
+                        if pending.is_empty() {
+                            return None;
+                        }
+                        return Some((Ok(pending), (stream, emitted, String::new(), true)));
+                    }
+                }
+            }
+        },
+    );
+
+    Box::new(tokens.boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collapse(chunks: &[&str]) -> String {
+        let chunks: Vec<_> = chunks.iter().map(|c| Ok(c.to_string())).collect();
+        collapse_replays(Box::new(futures::stream::iter(chunks)))
+            .map(|t| t.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn replayed_tail_is_dropped() {
+        let out = collapse(&["The weather ", "today is sunny", "today is sunny", " and warm."]).await;
+        assert_eq!(out, "The weather today is sunny and warm.");
+    }
+
+    #[tokio::test]
+    async fn replay_of_several_chunks_split_differently_is_dropped() {
+        let out = collapse(&["I think ", "that it ", "works", " I think that", " it works", ", yes."]).await;
+        assert_eq!(out, "I think that it works, yes.");
+    }
+
+    #[tokio::test]
+    async fn replay_without_the_leading_space_is_dropped() {
+        let out = collapse(&["Hello", " wonderful world", "wonderful world", "!"]).await;
+        assert_eq!(out, "Hello wonderful world!");
+    }
+
+    #[tokio::test]
+    async fn short_repetition_is_kept() {
+        assert_eq!(collapse(&["very ", "very ", "good"]).await, "very very good");
+        assert_eq!(collapse(&["Hello ", "Hello ", "world"]).await, "Hello Hello world");
+    }
+
+    #[tokio::test]
+    async fn long_repetition_that_is_not_the_tail_is_kept() {
+        let chunks = ["I said: ", "hello there my friend", ". ", "hello there my friend"];
+        assert_eq!(collapse(&chunks).await, chunks.concat());
+        let chunks = ["Row, row, row your boat, ", "gently down the stream. ", "Row, row, row your boat"];
+        assert_eq!(collapse(&chunks).await, chunks.concat());
+    }
+
+    #[test]
+    fn only_the_remembered_chunks_are_kept() {
+        let mut emitted = Emitted::default();
+        for i in 0..MAX_BOUNDARIES + 10 {
+            emitted.push(&format!("chunk {} ", i % 10));
+        }
+
+        assert_eq!(emitted.boundaries.len(), MAX_BOUNDARIES);
+        assert_eq!(emitted.text.len(), MAX_BOUNDARIES * "chunk 0 ".len());
+        assert!(emitted.text.starts_with("chunk 0 "));
+        // The last chunk alone falls under MIN_REPEAT_CHARS
+        assert_eq!(emitted.tails().count(), MAX_BOUNDARIES - 1);
+        assert_eq!(emitted.tails().last(), Some("chunk 4 chunk 5 "));
+    }
+
+    #[tokio::test]
+    async fn errors_pass_through() {
+        let chunks: Vec<Result<String, anyhow::Error>> =
+            vec![Ok("Hi ".to_string()), Err(anyhow::anyhow!("broken")), Ok("there".to_string())];
+        let out: Vec<_> = collapse_replays(Box::new(futures::stream::iter(chunks))).collect().await;
+        assert_eq!(out.len(), 3);
+        assert!(out[1].is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::stateless_llm_interface::StatelessLLMInterface;
+use crate::utils::http_status::HttpStatusError;
+
+/// LLM that tries an ordered list of providers, moving on to the next one
+/// when a provider times out, is rate-limited or returns a server error
+///
+/// Failover only happens before the first token. Once a provider has started
+/// streaming, its errors are passed through unchanged so the client never
+/// sees a reply that restarts halfway.
+pub struct FallbackLLM {
+    providers: Vec<(String, Arc<dyn StatelessLLMInterface>)>,
+}
+
+impl FallbackLLM {
+    /// # Arguments
+    /// * `providers` - (provider name, LLM) pairs, primary first
+    pub fn new(providers: Vec<(String, Arc<dyn StatelessLLMInterface>)>) -> Self {
+        let names: Vec<&str> = providers.iter().map(|(name, _)| name.as_str()).collect();
+        info!("Initialized FallbackLLM: {}", names.join(" -> "));
+        Self { providers }
+    }
+}
+
+/// Whether an error is worth retrying with another provider: a timeout,
+/// a failed connection, or an HTTP 429 or 5xx status
+fn is_retryable(error: &anyhow::Error) -> bool {
+    let retryable = |status: StatusCode| status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<HttpStatusError>() {
+            return retryable(e.status);
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if e.is_timeout() || e.is_connect() {
+                return true;
+            }
+            if let Some(status) = e.status() {
+                return retryable(status);
+            }
+        }
+    }
+    false
+}
+
+#[async_trait]
+impl StatelessLLMInterface for FallbackLLM {
+    async fn chat_completion(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        self.chat_completion_with_options(messages, system, &serde_json::json!({})).await
+    }
+
+    async fn chat_completion_with_options(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        let mut last_error = None;
+
+        for (index, (name, llm)) in self.providers.iter().enumerate() {
+            if index > 0 {
+                warn!("LLM failover: trying {}", name);
+            }
+
+            let error = match llm
+                .chat_completion_with_options(messages.clone(), system, options)
+                .await
+            {
+                Ok(mut stream) => match stream.next().await {
+                    // Hand back the first token in front of the rest of the stream
+                    Some(Ok(first)) => {
+                        let stream = futures::stream::once(async move { Ok(first) }).chain(stream);
+                        return Ok(Box::new(stream.boxed()));
+                    }
+                    Some(Err(e)) => e,
+                    None => return Ok(Box::new(futures::stream::empty().boxed())),
+                },
+                Err(e) => e,
+            };
+
+            if !is_retryable(&error) {
+                return Err(error);
+            }
+            warn!("LLM provider {} failed: {}", name, error);
+            last_error = Some(error);
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| anyhow::anyhow!("No LLM providers configured"))
+            .context("All LLM providers failed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn status_error(status: StatusCode) -> anyhow::Error {
+        anyhow::Error::new(HttpStatusError::new(status, format!("Ollama returned {}", status)))
+    }
+
+    #[test]
+    fn rate_limits_and_server_errors_are_retried() {
+        assert!(is_retryable(&status_error(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(is_retryable(&status_error(StatusCode::SERVICE_UNAVAILABLE)));
+        // The status is found under added context
+        let wrapped = Err::<(), _>(status_error(StatusCode::BAD_GATEWAY)).context("chat failed").unwrap_err();
+        assert!(is_retryable(&wrapped));
+    }
+
+    #[test]
+    fn client_errors_and_status_like_text_are_not_retried() {
+        assert!(!is_retryable(&status_error(StatusCode::BAD_REQUEST)));
+        assert!(!is_retryable(&status_error(StatusCode::UNAUTHORIZED)));
+        assert!(!is_retryable(&anyhow::anyhow!("Model answered with 503 words")));
+    }
+}
@@ -3,6 +3,9 @@ pub mod openai_compatible_llm;
 pub mod ollama_llm;
 pub mod claude_llm;
 pub mod llama_cpp_llm;
+pub mod fallback_llm;
+pub mod stop_sequence_llm;
+pub mod dedupe_llm;
 
 pub use stateless_llm_interface::*;
 pub use openai_compatible_llm::*;
@@ -1,52 +1,230 @@
 use async_trait::async_trait;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::info;
+use tracing::{debug, info, warn};
 
 use super::stateless_llm_interface::StatelessLLMInterface;
+use crate::utils::http_status::HttpStatusError;
+use crate::utils::utf8_decoder::Utf8Decoder;
 use super::openai_compatible_llm::OpenAICompatibleLLM;
+use crate::config_manager::stateless_llm::OllamaConfig;
 
 /// Ollama LLM implementation
-/// Extends OpenAICompatibleLLM since Ollama uses OpenAI-compatible API
+///
+/// Talks to Ollama's native `/api/chat` endpoint by default. When `native`
+/// is false it delegates to OpenAICompatibleLLM, which relies on Ollama's
+/// OpenAI-compatible shim.
 pub struct OllamaLLM {
     inner: OpenAICompatibleLLM,
+    client: reqwest::Client,
+    model: String,
+    api_base: String,
+    temperature: f32,
     keep_alive: f32,
     unload_at_exit: bool,
+    native: bool,
 }
 
 impl OllamaLLM {
-    pub fn new(
-        model: String,
-        base_url: String,
-        api_key: String,
-        organization_id: Option<String>,
-        project_id: Option<String>,
-        temperature: f32,
-        keep_alive: f32,
-        unload_at_exit: bool,
-        python_service: Arc<crate::python_service::PythonServiceClient>,
-    ) -> Self {
-        info!("Initialized OllamaLLM: model={}, base_url={}", model, base_url);
-        
-        let inner = OpenAICompatibleLLM::new(
-            model,
-            base_url,
-            api_key,
-            organization_id,
-            project_id,
-            temperature,
-            python_service,
+    pub fn new(config: &OllamaConfig, python_service: Arc<dyn crate::python_service::PythonService>) -> Self {
+        let openai_compatible = &config.openai_compatible;
+        info!(
+            "Initialized OllamaLLM: model={}, base_url={}, native={}",
+            openai_compatible.model, openai_compatible.base_url, config.native
         );
 
+        // Config usually points at the OpenAI shim (`.../v1`); the native API lives at the root
+        let api_base = openai_compatible
+            .base_url
+            .trim_end_matches('/')
+            .trim_end_matches("/v1")
+            .to_string();
+
+        let inner = OpenAICompatibleLLM::new(openai_compatible, python_service);
+
         Self {
+            client: Self::build_client(&inner),
             inner,
-            keep_alive,
-            unload_at_exit,
+            model: openai_compatible.model.clone(),
+            api_base,
+            temperature: openai_compatible.temperature,
+            keep_alive: config.keep_alive,
+            unload_at_exit: config.unload_at_exit,
+            native: config.native,
+        }
+    }
+
+    /// Client for the native API, sending the headers `inner` is configured
+    /// with (extra headers, organization and project) on every request
+    fn build_client(inner: &OpenAICompatibleLLM) -> reqwest::Client {
+        match inner
+            .headers()
+            .and_then(|map| Ok(reqwest::Client::builder().default_headers(map).build()?))
+        {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Ollama: ignoring configured headers: {}", e);
+                reqwest::Client::new()
+            }
+        }
+    }
+
+    /// Convert OpenAI-style messages into Ollama's format, where images are
+    /// passed as a list of base64 strings next to the text content
+    fn to_ollama_messages(
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+    ) -> Vec<serde_json::Value> {
+        let mut result = Vec::new();
+        if let Some(sys) = system {
+            result.push(serde_json::json!({ "role": "system", "content": sys }));
+        }
+
+        for msg in messages {
+            let role = msg.get("role").and_then(|v| v.as_str()).unwrap_or("user");
+            let Some(content) = msg.get("content") else {
+                continue;
+            };
+
+            match content.as_array() {
+                Some(parts) => {
+                    let mut text = String::new();
+                    let mut images = Vec::new();
+                    for part in parts {
+                        match part.get("type").and_then(|v| v.as_str()) {
+                            Some("text") => {
+                                let part_text = part.get("text").and_then(|v| v.as_str());
+                                text.push_str(part_text.unwrap_or(""));
+                            }
+                            Some("image_url") => {
+                                let url = part.pointer("/image_url/url").and_then(|v| v.as_str());
+                                if let Some(url) = url {
+                                    // Strip the data URI prefix, Ollama wants raw base64
+                                    let data = url.split_once("base64,").map_or(url, |(_, d)| d);
+                                    images.push(data.to_string());
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    result.push(serde_json::json!({
+                        "role": role,
+                        "content": text,
+                        "images": images
+                    }));
+                }
+                None => {
+                    let text = content
+                        .as_str()
+                        .map(|s| s.to_string())
+                        .unwrap_or_else(|| content.to_string());
+                    result.push(serde_json::json!({ "role": role, "content": text }));
+                }
+            }
         }
+
+        result
+    }
+
+    /// Stream a completion from `/api/chat`, which answers with one JSON
+    /// object per line
+    async fn native_chat(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        let mut sampling = serde_json::json!({ "temperature": self.temperature });
+        if let (Some(s), Some(opts)) = (sampling.as_object_mut(), options.as_object()) {
+            s.extend(opts.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+
+        let body = serde_json::json!({
+            "model": self.model,
+            "messages": Self::to_ollama_messages(messages, system),
+            "stream": true,
+            "keep_alive": self.keep_alive,
+            "options": sampling
+        });
+
+        let url = format!("{}/api/chat", self.api_base);
+        debug!("Ollama: POST {}", url);
+        let response = self.client.post(&url).json(&body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(HttpStatusError::new(status, format!("Ollama returned {}: {}", status, text)).into());
+        }
+
+        let bytes = response.bytes_stream();
+        let tokens = futures::stream::unfold(
+            (bytes, Utf8Decoder::new(), String::new(), false),
+            |(mut bytes, mut decoder, mut buffer, mut done)| async move {
+                loop {
+                    if done {
+                        return None;
+                    }
+
+                    // Emit the next complete line if one is buffered
+                    if let Some(pos) = buffer.find('\n') {
+                        let line: String = buffer.drain(..=pos).collect();
+                        match parse_chat_line(&line) {
+                            Ok(Some((token, finished))) => {
+                                done = finished;
+                                if !token.is_empty() {
+                                    return Some((Ok(token), (bytes, decoder, buffer, done)));
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => return Some((Err(e), (bytes, decoder, buffer, true))),
+                        }
+                        continue;
+                    }
+
+                    match bytes.next().await {
+                        Some(Ok(chunk)) => buffer.push_str(&decoder.push(&chunk)),
+                        Some(Err(e)) => return Some((Err(e.into()), (bytes, decoder, buffer, true))),
+                        None => {
+                            // Flush a trailing line without a newline
+                            done = true;
+                            buffer.push_str(&decoder.finish());
+                            if let Ok(Some((token, _))) = parse_chat_line(&buffer) {
+                                if !token.is_empty() {
+                                    return Some((Ok(token), (bytes, decoder, buffer, done)));
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(Box::new(tokens.boxed()))
     }
 }
 
+/// Parse one NDJSON line from `/api/chat` into (token, done)
+fn parse_chat_line(line: &str) -> Result<Option<(String, bool)>, anyhow::Error> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    let value: serde_json::Value = serde_json::from_str(line)?;
+    if let Some(error) = value.get("error").and_then(|e| e.as_str()) {
+        return Err(anyhow::anyhow!("Ollama error: {}", error));
+    }
+
+    let token = value
+        .pointer("/message/content")
+        .and_then(|c| c.as_str())
+        .unwrap_or("")
+        .to_string();
+    let done = value.get("done").and_then(|d| d.as_bool()).unwrap_or(false);
+    Ok(Some((token, done)))
+}
+
 #[async_trait]
 impl StatelessLLMInterface for OllamaLLM {
     async fn chat_completion(
@@ -54,7 +232,20 @@ impl StatelessLLMInterface for OllamaLLM {
         messages: Vec<HashMap<String, serde_json::Value>>,
         system: Option<&str>,
     ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
-        self.inner.chat_completion(messages, system).await
+        self.chat_completion_with_options(messages, system, &serde_json::json!({})).await
+    }
+
+    async fn chat_completion_with_options(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        if self.native {
+            self.native_chat(messages, system, options).await
+        } else {
+            self.inner.chat_completion_with_options(messages, system, options).await
+        }
     }
 }
 
@@ -66,4 +257,3 @@ impl Drop for OllamaLLM {
         }
     }
 }
-
@@ -1,14 +1,21 @@
 use async_trait::async_trait;
 use futures::Stream;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::info;
 
 use super::stateless_llm_interface::StatelessLLMInterface;
-use crate::python_service::PythonServiceClient;
+use crate::config_manager::stateless_llm::OpenAICompatibleConfig;
+use crate::python_service::PythonService;
 
 /// OpenAI compatible LLM implementation
 /// Calls Python service for actual LLM interaction
+///
+/// It has no HTTP client of its own: the organization and project ids and
+/// `extra_headers` go to the Python service in the request context, which
+/// sends them on. [`OpenAICompatibleLLM::headers`] is what clients that do
+/// call the provider themselves, such as Ollama's native one, send.
 pub struct OpenAICompatibleLLM {
     model: String,
     base_url: String,
@@ -16,33 +23,65 @@ pub struct OpenAICompatibleLLM {
     organization_id: Option<String>,
     project_id: Option<String>,
     temperature: f32,
-    python_service: Arc<PythonServiceClient>,
+    extra_headers: HashMap<String, String>,
+    python_service: Arc<dyn PythonService>,
 }
 
 impl OpenAICompatibleLLM {
-    pub fn new(
-        model: String,
-        base_url: String,
-        api_key: String,
-        organization_id: Option<String>,
-        project_id: Option<String>,
-        temperature: f32,
-        python_service: Arc<PythonServiceClient>,
-    ) -> Self {
+    pub fn new(config: &OpenAICompatibleConfig, python_service: Arc<dyn PythonService>) -> Self {
         info!(
             "Initialized OpenAICompatibleLLM: model={}, base_url={}",
-            model, base_url
+            config.model, config.base_url
         );
         Self {
-            model,
-            base_url,
-            api_key,
-            organization_id,
-            project_id,
-            temperature,
+            model: config.model.clone(),
+            base_url: config.base_url.clone(),
+            api_key: config.llm_api_key.clone(),
+            organization_id: config.organization_id.clone(),
+            project_id: config.project_id.clone(),
+            temperature: config.temperature,
+            extra_headers: config.extra_headers.clone(),
             python_service,
         }
     }
+
+    /// Headers every request carries: `extra_headers`, then
+    /// `OpenAI-Organization` and `OpenAI-Project` when they are configured
+    pub fn headers(&self) -> anyhow::Result<HeaderMap> {
+        let mut map = header_map(&self.extra_headers)?;
+        let ids = [
+            ("openai-organization", &self.organization_id),
+            ("openai-project", &self.project_id),
+        ];
+        for (name, id) in ids {
+            if let Some(id) = id.as_deref().filter(|id| !id.trim().is_empty()) {
+                let value = HeaderValue::from_str(id.trim())
+                    .map_err(|_| anyhow::anyhow!("invalid value for header {}", name))?;
+                map.insert(HeaderName::from_static(name), value);
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// Check and convert configured `extra_headers`
+///
+/// Fails on names or values that can't be sent, and on a `${VAR}` left in a
+/// value because the variable wasn't set when the config was read.
+pub fn header_map(headers: &HashMap<String, String>) -> anyhow::Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| anyhow::anyhow!("invalid header name '{}'", name))?;
+        if let Some(start) = value.find("${") {
+            let end = value[start..].find('}').map_or(value.len(), |end| start + end + 1);
+            anyhow::bail!("header {} uses {}, which is not set", name, &value[start..end]);
+        }
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|_| anyhow::anyhow!("invalid value for header {}", name))?;
+        map.insert(header_name, header_value);
+    }
+    Ok(map)
 }
 
 #[async_trait]
@@ -51,6 +90,15 @@ impl StatelessLLMInterface for OpenAICompatibleLLM {
         &self,
         messages: Vec<HashMap<String, serde_json::Value>>,
         system: Option<&str>,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        self.chat_completion_with_options(messages, system, &serde_json::json!({})).await
+    }
+
+    async fn chat_completion_with_options(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        options: &serde_json::Value,
     ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
         // Convert messages to Python service format
         let mut service_messages = Vec::new();
@@ -79,13 +127,31 @@ impl StatelessLLMInterface for OpenAICompatibleLLM {
             }
         }
 
+        // Per-request options override the configured sampling values
+        let mut context = serde_json::json!({
+            "model": self.model,
+            "base_url": self.base_url,
+            "temperature": self.temperature
+        });
+        if !self.extra_headers.is_empty() {
+            context["extra_headers"] = serde_json::json!(self.extra_headers);
+        }
+        let ids = [
+            ("organization_id", &self.organization_id),
+            ("project_id", &self.project_id),
+        ];
+        for (field, id) in ids {
+            if let Some(id) = id.as_deref().filter(|id| !id.trim().is_empty()) {
+                context[field] = serde_json::json!(id.trim());
+            }
+        }
+        if let (Some(ctx), Some(opts)) = (context.as_object_mut(), options.as_object()) {
+            ctx.extend(opts.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+
         let request = crate::python_service::AgentRequest {
             messages: service_messages,
-            context: Some(serde_json::json!({
-                "model": self.model,
-                "base_url": self.base_url,
-                "temperature": self.temperature
-            })),
+            context: Some(context),
         };
 
         let service = self.python_service.clone();
@@ -101,3 +167,80 @@ impl StatelessLLMInterface for OpenAICompatibleLLM {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python_service::mock::MockPythonService;
+    use futures::StreamExt;
+
+    fn llm(config: serde_json::Value, python_service: Arc<MockPythonService>) -> OpenAICompatibleLLM {
+        let mut config_json = serde_json::json!({ "model": "gpt-4o-mini" });
+        config_json.as_object_mut().unwrap().extend(config.as_object().unwrap().clone());
+        OpenAICompatibleLLM::new(&serde_json::from_value(config_json).unwrap(), python_service)
+    }
+
+    #[test]
+    fn headers_carry_configured_ids() {
+        let llm = llm(
+            serde_json::json!({
+                "organization_id": "org-123",
+                "project_id": " proj_456 ",
+                "extra_headers": { "X-Title": "vaidol" }
+            }),
+            Arc::new(MockPythonService::new()),
+        );
+        let headers = llm.headers().unwrap();
+        assert_eq!(headers["openai-organization"], "org-123");
+        assert_eq!(headers["openai-project"], "proj_456");
+        assert_eq!(headers["x-title"], "vaidol");
+    }
+
+    #[test]
+    fn headers_leave_out_unset_ids() {
+        let llm = llm(serde_json::json!({}), Arc::new(MockPythonService::new()));
+        let headers = llm.headers().unwrap();
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn headers_leave_out_blank_ids() {
+        let llm = llm(
+            serde_json::json!({ "organization_id": "  ", "project_id": "" }),
+            Arc::new(MockPythonService::new()),
+        );
+        let headers = llm.headers().unwrap();
+        assert!(!headers.contains_key("openai-organization"));
+        assert!(!headers.contains_key("openai-project"));
+    }
+
+    #[test]
+    fn headers_reject_unset_variables() {
+        let llm = llm(
+            serde_json::json!({ "extra_headers": { "Authorization": "Bearer ${GATEWAY_KEY}" } }),
+            Arc::new(MockPythonService::new()),
+        );
+        let error = llm.headers().unwrap_err().to_string();
+        assert!(error.contains("${GATEWAY_KEY}"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn python_service_gets_configured_ids() {
+        let python_service = Arc::new(MockPythonService::new().with_chat("Hi there"));
+        let llm = llm(
+            serde_json::json!({ "organization_id": "org-123", "project_id": " " }),
+            python_service.clone(),
+        );
+        let messages = vec![HashMap::from([
+            ("role".to_string(), serde_json::json!("user")),
+            ("content".to_string(), serde_json::json!("Hello")),
+        ])];
+        let reply: Vec<_> = llm.chat_completion(messages, None).await.unwrap().collect().await;
+        assert_eq!(reply.len(), 2);
+
+        let requests = python_service.requests("/agent/chat");
+        let context = &requests[0]["context"];
+        assert_eq!(context["organization_id"], "org-123");
+        assert!(context.get("project_id").is_none());
+    }
+}
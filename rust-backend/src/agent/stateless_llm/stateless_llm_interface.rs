@@ -13,5 +13,16 @@ pub trait StatelessLLMInterface: Send + Sync {
         messages: Vec<HashMap<String, serde_json::Value>>,
         system: Option<&str>,
     ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error>;
+
+    /// Generate a chat completion with per-request sampling options such as
+    /// `temperature` or `top_p`. Providers without support ignore the options.
+    async fn chat_completion_with_options(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        _options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        self.chat_completion(messages, system).await
+    }
 }
 
@@ -0,0 +1,250 @@
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+use super::stateless_llm_interface::StatelessLLMInterface;
+
+/// LLM wrapper that applies configured stop sequences
+///
+/// The sequences are forwarded to the provider as the `stop` option, and the
+/// returned stream is also cut client-side as a safety net for providers or
+/// proxies that ignore the option.
+pub struct StopSequenceLLM {
+    inner: Arc<dyn StatelessLLMInterface>,
+    stop: Vec<String>,
+}
+
+impl StopSequenceLLM {
+    /// # Arguments
+    /// * `inner` - The LLM to wrap
+    /// * `stop` - Stop sequences; empty strings are ignored
+    pub fn new(inner: Arc<dyn StatelessLLMInterface>, stop: Vec<String>) -> Self {
+        let stop: Vec<String> = stop.into_iter().filter(|s| !s.is_empty()).collect();
+        info!("Initialized StopSequenceLLM: stop={:?}", stop);
+        Self { inner, stop }
+    }
+}
+
+#[async_trait]
+impl StatelessLLMInterface for StopSequenceLLM {
+    async fn chat_completion(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        self.chat_completion_with_options(messages, system, &serde_json::json!({})).await
+    }
+
+    async fn chat_completion_with_options(
+        &self,
+        messages: Vec<HashMap<String, serde_json::Value>>,
+        system: Option<&str>,
+        options: &serde_json::Value,
+    ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+        // A per-request `stop` option takes precedence over the configured one
+        let mut options = options.clone();
+        let stop = match options.get("stop").and_then(|v| v.as_array()) {
+            Some(requested) => requested
+                .iter()
+                .filter_map(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+            None => self.stop.clone(),
+        };
+        if !stop.is_empty() {
+            match options.as_object_mut() {
+                Some(opts) => {
+                    opts.insert("stop".to_string(), serde_json::json!(stop));
+                }
+                None => options = serde_json::json!({ "stop": stop }),
+            }
+        }
+
+        let stream = self
+            .inner
+            .chat_completion_with_options(messages, system, &options)
+            .await?;
+        Ok(cut_at_stop_sequences(stream, stop))
+    }
+}
+
+/// Length in bytes of the longest suffix of `text` that could still grow
+/// into one of the stop sequences
+fn partial_stop_len(text: &str, stop: &[String]) -> usize {
+    let longest = stop.iter().map(|s| s.len()).max().unwrap_or(0);
+    let max_len = longest.saturating_sub(1).min(text.len());
+    (1..=max_len)
+        .rev()
+        .filter(|&len| text.is_char_boundary(text.len() - len))
+        .find(|&len| {
+            let suffix = &text[text.len() - len..];
+            stop.iter().any(|s| s.starts_with(suffix))
+        })
+        .unwrap_or(0)
+}
+
+/// End a token stream at the first stop sequence, dropping the sequence and
+/// everything after it
+///
+/// Text that might be the start of a stop sequence is held back until the
+/// next chunk decides it, so sequences split across chunk boundaries are
+/// still caught. Held-back text is flushed when the stream ends.
+pub fn cut_at_stop_sequences(
+    stream: Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>,
+    stop: Vec<String>,
+) -> Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin> {
+    if stop.is_empty() {
+        return stream;
+    }
+
+    let tokens = futures::stream::unfold(
+        (stream, String::new(), false),
+        move |(mut stream, mut pending, done)| {
+            let stop = stop.clone();
+            async move {
+                if done {
+                    return None;
+                }
+
+                loop {
+                    match stream.next().await {
+                        Some(Ok(chunk)) => {
+                            pending.push_str(&chunk);
+
+                            let hit = stop
+                                .iter()
+                                .filter_map(|s| pending.find(s.as_str()))
+                                .min();
+                            if let Some(index) = hit {
+                                debug!("Stop sequence reached, ending LLM stream");
+                                pending.truncate(index);
+                                if pending.is_empty() {
+                                    return None;
+                                }
+                                return Some((Ok(pending), (stream, String::new(), true)));
+                            }
+
+                            let held = partial_stop_len(&pending, &stop);
+                            if pending.len() > held {
+                                let rest = pending.split_off(pending.len() - held);
+                                return Some((Ok(pending), (stream, rest, false)));
+                            }
+                        }
+                        Some(Err(e)) => return Some((Err(e), (stream, pending, false))),
+                        None => {
+                            if pending.is_empty() {
+                                return None;
+                            }
+                            return Some((Ok(pending), (stream, String::new(), true)));
+                        }
+                    }
+                }
+            }
+        },
+    );
+
+    Box::new(tokens.boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// LLM streaming fixed chunks and recording the options it was given
+    struct FakeLLM {
+        chunks: Vec<&'static str>,
+        options: Mutex<Option<serde_json::Value>>,
+    }
+
+    #[async_trait]
+    impl StatelessLLMInterface for FakeLLM {
+        async fn chat_completion(
+            &self,
+            messages: Vec<HashMap<String, serde_json::Value>>,
+            system: Option<&str>,
+        ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+            self.chat_completion_with_options(messages, system, &serde_json::json!({})).await
+        }
+
+        async fn chat_completion_with_options(
+            &self,
+            _messages: Vec<HashMap<String, serde_json::Value>>,
+            _system: Option<&str>,
+            options: &serde_json::Value,
+        ) -> Result<Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin>, anyhow::Error> {
+            *self.options.lock().unwrap() = Some(options.clone());
+            Ok(chunks(&self.chunks))
+        }
+    }
+
+    fn chunks(chunks: &[&str]) -> Box<dyn Stream<Item = Result<String, anyhow::Error>> + Send + Unpin> {
+        let chunks: Vec<_> = chunks.iter().map(|c| Ok(c.to_string())).collect();
+        Box::new(futures::stream::iter(chunks))
+    }
+
+    async fn cut(input: &[&str], stop: &[&str]) -> Vec<String> {
+        let stop = stop.iter().map(|s| s.to_string()).collect();
+        cut_at_stop_sequences(chunks(input), stop)
+            .map(|t| t.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn stop_sequence_split_across_two_chunks() {
+        assert_eq!(cut(&["Sure thing!\nUs", "er: and then"], &["\nUser:"]).await, ["Sure thing!"]);
+    }
+
+    #[tokio::test]
+    async fn stop_sequence_split_across_many_chunks() {
+        let out = cut(&["Bye", "\n", "U", "se", "r:", " more"], &["\nUser:"]).await;
+        assert_eq!(out.concat(), "Bye");
+    }
+
+    #[tokio::test]
+    async fn stop_sequence_split_in_cjk_text() {
+        let out = cut(&["好的。用", "户：你好"], &["用户："]).await;
+        assert_eq!(out.concat(), "好的。");
+    }
+
+    #[tokio::test]
+    async fn held_back_text_is_flushed_when_no_stop_follows() {
+        let out = cut(&["Hello\nUs", "ually fine"], &["\nUser:"]).await;
+        assert_eq!(out.concat(), "Hello\nUsually fine");
+        let out = cut(&["Ends with \nUs"], &["\nUser:"]).await;
+        assert_eq!(out.concat(), "Ends with \nUs");
+    }
+
+    #[tokio::test]
+    async fn earliest_stop_sequence_wins() {
+        let out = cut(&["a END b STOP c"], &["STOP", "END"]).await;
+        assert_eq!(out.concat(), "a ");
+    }
+
+    #[tokio::test]
+    async fn stop_is_forwarded_and_overridden_per_request() {
+        let inner = Arc::new(FakeLLM {
+            chunks: vec!["Hi###", "after"],
+            options: Mutex::new(None),
+        });
+        let llm = StopSequenceLLM::new(inner.clone(), vec!["###".to_string(), String::new()]);
+
+        let out: Vec<_> = llm.chat_completion(Vec::new(), None).await.unwrap().collect().await;
+        assert_eq!(out.into_iter().map(|t| t.unwrap()).collect::<String>(), "Hi");
+        assert_eq!(inner.options.lock().unwrap().clone().unwrap(), serde_json::json!({"stop": ["###"]}));
+
+        let options = serde_json::json!({"temperature": 0.5, "stop": ["after"]});
+        let out: Vec<_> = llm
+            .chat_completion_with_options(Vec::new(), None, &options)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(out.into_iter().map(|t| t.unwrap()).collect::<String>(), "Hi###");
+        assert_eq!(inner.options.lock().unwrap().clone().unwrap(), options);
+    }
+}
@@ -1,13 +1,16 @@
 use std::sync::Arc;
 use tracing::info;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use crate::agent::stateless_llm::StatelessLLMInterface;
 use crate::agent::stateless_llm::openai_compatible_llm::OpenAICompatibleLLM;
 use crate::agent::stateless_llm::ollama_llm::OllamaLLM;
 use crate::agent::stateless_llm::claude_llm::ClaudeLLM;
 use crate::agent::stateless_llm::llama_cpp_llm::LlamaCppLLM;
-use crate::python_service::PythonServiceClient;
+use crate::agent::stateless_llm::stop_sequence_llm::StopSequenceLLM;
+use crate::agent::stateless_llm::dedupe_llm::DedupeLLM;
+use crate::config_manager::stateless_llm::{ClaudeConfig, OllamaConfig, OpenAICompatibleConfig};
+use crate::python_service::PythonService;
 
 /// Factory for creating stateless LLM instances
 pub struct StatelessLLMFactory;
@@ -22,44 +25,85 @@ impl StatelessLLMFactory {
     /// * `config` - LLM configuration dictionary
     pub fn create_llm(
         llm_provider: &str,
-        python_service: Arc<PythonServiceClient>,
+        python_service: Arc<dyn PythonService>,
         system_prompt: Option<&str>,
         config: &serde_json::Value,
     ) -> Result<Arc<dyn StatelessLLMInterface>> {
         info!("Initializing LLM: {}", llm_provider);
 
+        let mut llm = Self::create_provider_llm(llm_provider, python_service, system_prompt, config)?;
+
+        // Replays are dropped before stop sequences are looked for
+        if config.get("dedupe_stream").and_then(|v| v.as_bool()).unwrap_or(false) {
+            llm = Arc::new(DedupeLLM::new(llm));
+        }
+
+        // Optional `stop` list, shared by every provider
+        let stop: Vec<String> = config
+            .get("stop")
+            .and_then(|v| v.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if stop.is_empty() {
+            Ok(llm)
+        } else {
+            Ok(Arc::new(StopSequenceLLM::new(llm, stop)))
+        }
+    }
+
+    /// Whether the provider's client passes images on to the model
+    ///
+    /// This says nothing about the model itself; a text-only model behind a
+    /// provider listed here will still refuse images.
+    pub fn supports_images(llm_provider: &str) -> bool {
+        matches!(
+            llm_provider,
+            "openai_compatible_llm" | "openai_llm" | "gemini_llm" | "zhipu_llm" | "mistral_llm"
+                | "groq_llm" | "ollama_llm" | "claude_llm"
+        )
+    }
+
+    /// Whether the provider's API reads PDFs sent as content parts
+    pub fn supports_documents(llm_provider: &str) -> bool {
+        matches!(llm_provider, "openai_llm" | "claude_llm")
+    }
+
+    /// Whether the provider's API goes on from a trailing assistant message
+    /// instead of starting a new one
+    pub fn supports_prefill(llm_provider: &str) -> bool {
+        matches!(llm_provider, "openai_compatible_llm" | "claude_llm" | "ollama_llm")
+    }
+
+    /// Create the provider-specific LLM without any wrappers
+    fn create_provider_llm(
+        llm_provider: &str,
+        python_service: Arc<dyn PythonService>,
+        system_prompt: Option<&str>,
+        config: &serde_json::Value,
+    ) -> Result<Arc<dyn StatelessLLMInterface>> {
+
         match llm_provider {
             "openai_compatible_llm" | "openai_llm" | "gemini_llm" | "zhipu_llm" 
             | "deepseek_llm" | "groq_llm" | "mistral_llm" => {
-                Ok(Arc::new(OpenAICompatibleLLM::new(
-                    config.get("model").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    config.get("base_url").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    config.get("llm_api_key").and_then(|v| v.as_str()).unwrap_or("z").to_string(),
-                    config.get("organization_id").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    config.get("project_id").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    config.get("temperature").and_then(|v| v.as_f64()).unwrap_or(1.0) as f32,
-                    python_service,
-                )))
+                let config: OpenAICompatibleConfig = parse_config(llm_provider, config)?;
+                Ok(Arc::new(OpenAICompatibleLLM::new(&config, python_service)))
             }
             "ollama_llm" => {
-                Ok(Arc::new(OllamaLLM::new(
-                    config.get("model").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    config.get("base_url").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    config.get("llm_api_key").and_then(|v| v.as_str()).unwrap_or("z").to_string(),
-                    config.get("organization_id").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    config.get("project_id").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    config.get("temperature").and_then(|v| v.as_f64()).unwrap_or(1.0) as f32,
-                    config.get("keep_alive").and_then(|v| v.as_f64()).unwrap_or(-1.0) as f32,
-                    config.get("unload_at_exit").and_then(|v| v.as_bool()).unwrap_or(true),
-                    python_service,
-                )))
+                let config: OllamaConfig = parse_config(llm_provider, config)?;
+                Ok(Arc::new(OllamaLLM::new(&config, python_service)))
             }
             "claude_llm" => {
+                let config: ClaudeConfig = parse_config(llm_provider, config)?;
                 Ok(Arc::new(ClaudeLLM::new(
                     system_prompt.unwrap_or("").to_string(),
-                    config.get("base_url").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    config.get("model").and_then(|v| v.as_str()).unwrap_or("claude-3-haiku-20240307").to_string(),
-                    config.get("llm_api_key").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                    &config,
                     python_service,
                 )))
             }
@@ -73,3 +117,9 @@ impl StatelessLLMFactory {
     }
 }
 
+
+/// The provider's `extra_headers`, already checked when the config was loaded
+fn parse_config<T: serde::de::DeserializeOwned>(llm_provider: &str, config: &serde_json::Value) -> Result<T> {
+    serde_json::from_value(config.clone())
+        .map_err(|e| anyhow!("llm_configs.{}: {}", llm_provider, e))
+}
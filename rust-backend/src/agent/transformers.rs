@@ -3,8 +3,11 @@
 // Note: Full implementation would require sentence divider and Live2D model integration
 // For now, these are simplified versions that match the Python structure
 
-use crate::agent::output_types::{SentenceOutput, DisplayText, Actions};
+use crate::agent::output_types::{DisplayText, Actions, TimedExpression};
+use crate::live2d_model::Live2DModel;
+use crate::config::RewriteRule;
 use crate::config_manager::tts_preprocessor::TTSPreprocessorConfig;
+use crate::utils::number_reading::NumberLocale;
 
 /// Sentence divider transformer
 /// Transforms token stream into sentences with tags
@@ -25,24 +28,194 @@ pub fn sentence_divider(
 }
 
 /// Actions extractor transformer
-/// Extracts actions from sentences using Live2D model
+/// Extracts actions from a sentence using the Live2D model
+///
+/// Expressions keep the order their keywords appear in, so the avatar moves
+/// through them; a keyword repeated back to back counts once.
 /// 
 /// # Arguments
 /// * `live2d_model` - Live2D model instance for expression extraction
+/// * `sentence` - Sentence text to scan for emotion keywords
+pub fn actions_extractor(live2d_model: Option<&Live2DModel>, sentence: &str) -> Actions {
+    let mut actions = Actions::new();
+    if let Some(model) = live2d_model {
+        let mut expressions = model.extract_emotion(sentence);
+        expressions.dedup();
+        if !expressions.is_empty() {
+            actions.expressions = Some(expressions.into_iter().map(serde_json::Value::from).collect());
+        }
+    }
+    actions
+}
+
+/// Timed actions extractor transformer
+/// Like `actions_extractor`, and also schedules each expression at the point
+/// its keyword appeared, estimated from the speaking rate
 /// 
-/// Note: Full implementation would require Live2D model integration
-pub fn actions_extractor(_live2d_model: Option<&dyn std::any::Any>) {
-    // TODO: Extract expressions from text using Live2D model
-    // This would process SentenceWithTags and yield (SentenceWithTags, Actions)
+/// # Arguments
+/// * `live2d_model` - Live2D model instance for expression extraction
+/// * `sentence` - Sentence text to scan for emotion keywords
+/// * `chars_per_second` - Estimated speaking rate of the TTS voice
+pub fn timed_actions_extractor(
+    live2d_model: Option<&Live2DModel>,
+    sentence: &str,
+    chars_per_second: f32,
+) -> Actions {
+    let mut actions = actions_extractor(live2d_model, sentence);
+    let Some(model) = live2d_model else {
+        return actions;
+    };
+
+    // Offsets count from the start of the spoken text, which is trimmed
+    let leading = model
+        .remove_emotion_keywords(sentence)
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .count();
+    let mut offsets = model.extract_emotion_offsets(sentence);
+    offsets.dedup_by_key(|(_, expression)| *expression);
+    let timed: Vec<TimedExpression> = offsets
+        .into_iter()
+        .map(|(offset, expression)| TimedExpression {
+            offset_ms: (offset.saturating_sub(leading) as f32 / chars_per_second.max(1.0) * 1000.0) as u64,
+            expression: serde_json::Value::from(expression),
+        })
+        .collect();
+    if !timed.is_empty() {
+        actions.timed_expressions = Some(timed);
+    }
+    actions
+}
+
+/// Text of a reply chunk split around `<think>` tags
+#[derive(Debug, Default)]
+pub struct ThinkSplit {
+    /// Text meant for display and TTS
+    pub visible: String,
+    /// Reasoning inside think tags
+    pub thinking: String,
+    /// Whether a think block closed within this chunk
+    pub thinking_done: bool,
+}
+
+/// Think tag transformer
+/// Separates `<think>...</think>` reasoning from the reply as chunks stream
+/// in. Tags may be split across chunks, so a trailing fragment that could
+/// start a tag is held back until the next chunk or [`ThinkTagParser::finish`].
+#[derive(Debug, Default)]
+pub struct ThinkTagParser {
+    in_think: bool,
+    pending: String,
+}
+
+impl ThinkTagParser {
+    const OPEN: &'static str = "<think>";
+    const CLOSE: &'static str = "</think>";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Split the next chunk of the reply
+    pub fn push(&mut self, chunk: &str) -> ThinkSplit {
+        let mut input = std::mem::take(&mut self.pending);
+        input.push_str(chunk);
+        let mut split = ThinkSplit::default();
+
+        let mut rest = input.as_str();
+        loop {
+            let tag = if self.in_think { Self::CLOSE } else { Self::OPEN };
+            if let Some(pos) = rest.find(tag) {
+                self.target(&mut split).push_str(&rest[..pos]);
+                if self.in_think {
+                    split.thinking_done = true;
+                }
+                self.in_think = !self.in_think;
+                rest = &rest[pos + tag.len()..];
+                continue;
+            }
+
+            let held = partial_tag_len(rest, tag);
+            self.target(&mut split).push_str(&rest[..rest.len() - held]);
+            self.pending = rest[rest.len() - held..].to_string();
+            return split;
+        }
+    }
+
+    /// Flush text held back at the end of the reply; an unclosed think
+    /// block counts as finished
+    pub fn finish(&mut self) -> ThinkSplit {
+        let mut split = ThinkSplit::default();
+        let pending = std::mem::take(&mut self.pending);
+        self.target(&mut split).push_str(&pending);
+        if self.in_think {
+            split.thinking_done = true;
+            self.in_think = false;
+        }
+        split
+    }
+
+    fn target<'a>(&self, split: &'a mut ThinkSplit) -> &'a mut String {
+        if self.in_think {
+            &mut split.thinking
+        } else {
+            &mut split.visible
+        }
+    }
+}
+
+/// Length of the longest suffix of `text` that is the start of `tag`
+fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len().min(text.len() + 1))
+        .rev()
+        .find(|&len| {
+            let start = text.len() - len;
+            text.is_char_boundary(start) && tag.starts_with(&text[start..])
+        })
+        .unwrap_or(0)
 }
 
 /// Display processor transformer
-/// Processes text for display, handling think tags
+/// Processes text for display, stripping emotion keywords
 /// 
-/// Note: Full implementation would handle tag states
-pub fn display_processor() {
-    // TODO: Process sentences for display, handling think tag states
-    // This would process (SentenceWithTags, Actions) and yield (SentenceWithTags, DisplayText, Actions)
+/// Think tags are separated beforehand by [`ThinkTagParser`].
+pub fn display_processor(live2d_model: Option<&Live2DModel>, sentence: &str) -> DisplayText {
+    let text = match live2d_model {
+        Some(model) => model.remove_emotion_keywords(sentence),
+        None => sentence.to_string(),
+    };
+    DisplayText::new(text.trim().to_string())
+}
+
+/// Output rewrite transformer
+/// Applies the character's `output_rewrites` rules to text, in order
+pub fn rewrite(text: &str, rules: &[RewriteRule]) -> String {
+    rules.iter().fold(text.to_string(), |text, rule| {
+        rule.pattern
+            .replace_all(&text, rule.replacement.as_str())
+            .into_owned()
+    })
+}
+
+/// Continuation joiner
+/// Appends the continuation of a reply to what was already said, adding a
+/// space between words unless either side already has one or the text
+/// doesn't separate words (e.g. Chinese and Japanese)
+pub fn join_continuation(reply: &str, continuation: &str) -> String {
+    let (Some(last), Some(first)) = (reply.chars().last(), continuation.chars().next()) else {
+        return format!("{}{}", reply, continuation);
+    };
+    let unspaced = |c: char| c >= '\u{2E80}';
+    let joined = last.is_whitespace()
+        || first.is_whitespace()
+        || (first.is_ascii_punctuation() && !matches!(first, '(' | '[' | '*' | '"'))
+        || unspaced(last)
+        || unspaced(first);
+    if joined {
+        format!("{}{}", reply, continuation)
+    } else {
+        format!("{} {}", reply, continuation)
+    }
 }
 
 /// TTS filter transformer
@@ -50,11 +223,14 @@ pub fn display_processor() {
 /// 
 /// # Arguments
 /// * `tts_preprocessor_config` - Configuration for TTS preprocessing
+/// * `tts_language` - Language of the TTS engine, for `locale_numbers`;
+///   guessed from the text when None
 /// 
 /// Note: Full implementation would use TTS preprocessor
 pub fn tts_filter(
     text: &str,
     tts_preprocessor_config: Option<&TTSPreprocessorConfig>,
+    tts_language: Option<&str>,
 ) -> String {
     let config = if let Some(cfg) = tts_preprocessor_config {
         cfg.clone()
@@ -67,6 +243,9 @@ pub fn tts_filter(
             ignore_parentheses: false,
             ignore_asterisks: false,
             ignore_angle_brackets: false,
+            normalize_numbers: false,
+            locale_numbers: false,
+            emoji_handling: "keep".to_string(),
             translator_config: TranslatorConfig {
                 translate_audio: false,
                 translate_provider: String::new(),
@@ -76,9 +255,32 @@ pub fn tts_filter(
         }
     };
 
+    // Rewrite numbers and emoji first, before special characters such as
+    // `$` and `%` are stripped
+    let mut text = text.to_string();
+    let locale = if config.locale_numbers {
+        // Engines set to `auto` detect the language themselves
+        match tts_language.filter(|l| !l.to_ascii_lowercase().starts_with("auto")) {
+            Some(language) => NumberLocale::from_language(language),
+            None => NumberLocale::detect(&text),
+        }
+    } else {
+        None
+    };
+    if let Some(locale) = locale {
+        text = crate::utils::number_reading::read_numbers(&text, locale);
+    } else if config.normalize_numbers {
+        text = crate::utils::tts_preprocessor::normalize_numbers(&text);
+    }
+    match config.emoji_handling.as_str() {
+        "remove" => text = crate::utils::tts_preprocessor::filter_emoji(&text, false),
+        "describe" => text = crate::utils::tts_preprocessor::filter_emoji(&text, true),
+        _ => {}
+    }
+
     // Use TTS preprocessor utility
     crate::utils::tts_preprocessor::tts_filter(
-        text,
+        &text,
         config.remove_special_char,
         config.ignore_brackets,
         config.ignore_parentheses,
@@ -87,3 +289,118 @@ pub fn tts_filter(
     )
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A model with joy=3, sadness=1 and surprise=2
+    fn model() -> Live2DModel {
+        let path = std::env::temp_dir().join(format!("model_dict-{}.json", uuid::Uuid::new_v4().as_simple()));
+        let dict = json!([{
+            "name": "test",
+            "emotionMap": {"joy": 3, "sadness": 1, "surprise": 2}
+        }]);
+        std::fs::write(&path, dict.to_string()).unwrap();
+        let model = Live2DModel::new("test", path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        model
+    }
+
+    fn expressions(model: &Live2DModel, sentence: &str) -> Option<Vec<serde_json::Value>> {
+        actions_extractor(Some(model), sentence).expressions
+    }
+
+    fn display(model: &Live2DModel, sentence: &str) -> String {
+        display_processor(Some(model), sentence).text
+    }
+
+    #[test]
+    fn expressions_keep_text_order() {
+        let model = model();
+        assert_eq!(
+            expressions(&model, "[surprise] Oh! [Joy] That's great, [SADNESS] but sad."),
+            Some(vec![json!(2), json!(3), json!(1)])
+        );
+    }
+
+    #[test]
+    fn only_consecutive_duplicates_are_dropped() {
+        let model = model();
+        assert_eq!(expressions(&model, "[joy][joy] Hi [joy]"), Some(vec![json!(3)]));
+        assert_eq!(
+            expressions(&model, "[joy] Hi [sadness] bye [joy]"),
+            Some(vec![json!(3), json!(1), json!(3)])
+        );
+    }
+
+    #[test]
+    fn unknown_tags_are_not_expressions() {
+        let model = model();
+        assert_eq!(expressions(&model, "Hello [wink] there"), None);
+        assert_eq!(display(&model, "Hello [wink] there"), "Hello [wink] there");
+        assert_eq!(actions_extractor(None, "[joy] Hi").expressions, None);
+    }
+
+    #[test]
+    fn adjacent_tags_are_removed_cleanly() {
+        let model = model();
+        assert_eq!(display(&model, "Hi [joy][surprise] there"), "Hi there");
+        assert_eq!(display(&model, "Hi[joy][surprise]there"), "Hithere");
+        assert_eq!(display(&model, "Wow [joy] [surprise]!"), "Wow!");
+    }
+
+    #[test]
+    fn tags_at_the_string_boundaries_are_removed() {
+        let model = model();
+        assert_eq!(display(&model, "[joy][surprise]Hello!"), "Hello!");
+        assert_eq!(display(&model, "Hello! [sadness]"), "Hello!");
+        assert_eq!(display(&model, "[joy]"), "");
+        assert_eq!(expressions(&model, "[joy]"), Some(vec![json!(3)]));
+    }
+
+    #[test]
+    fn timed_expressions_follow_their_keywords() {
+        let model = model();
+        let actions = timed_actions_extractor(Some(&model), "[joy] Hello [sadness] world", 10.0);
+        let timed: Vec<_> = actions
+            .timed_expressions
+            .unwrap()
+            .into_iter()
+            .map(|t| (t.offset_ms, t.expression))
+            .collect();
+        assert_eq!(timed, [(0, json!(3)), (500, json!(1))]);
+    }
+
+    fn preprocessor(locale_numbers: bool) -> TTSPreprocessorConfig {
+        use crate::config_manager::tts_preprocessor::TranslatorConfig;
+        TTSPreprocessorConfig {
+            remove_special_char: false,
+            ignore_brackets: false,
+            ignore_parentheses: false,
+            ignore_asterisks: false,
+            ignore_angle_brackets: false,
+            normalize_numbers: false,
+            locale_numbers,
+            emoji_handling: "keep".to_string(),
+            translator_config: TranslatorConfig {
+                translate_audio: false,
+                translate_provider: String::new(),
+                deeplx: None,
+                tencent: None,
+            },
+        }
+    }
+
+    #[test]
+    fn numbers_are_read_in_the_tts_language() {
+        let config = preprocessor(true);
+        assert_eq!(tts_filter("3/4/2024", Some(&config), Some("zh")), "二零二四年三月四日");
+        assert_eq!(tts_filter("3/4/2024", Some(&config), Some("en-GB")), "the third of April, twenty twenty-four");
+        // Guessed from the text when the engine detects the language itself
+        assert_eq!(tts_filter("共1,000人", Some(&config), Some("auto")), "共一千人");
+        assert_eq!(tts_filter("1,000 people", Some(&config), None), "one thousand people");
+        assert_eq!(tts_filter("1,000 people", Some(&preprocessor(false)), Some("en")), "1,000 people");
+    }
+}
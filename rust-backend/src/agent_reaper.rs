@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::state::AppState;
+
+/// How often clients are checked for idle agents
+const SCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Drop the agents of clients idle beyond `agent_idle_ttl_secs`, checking
+/// every [`SCAN_INTERVAL`]
+///
+/// The TTL is read on every scan, so a config reload applies to the next
+/// one; a null TTL turns reaping off.
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SCAN_INTERVAL);
+        // The first tick fires immediately, when nobody can be idle yet
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(ttl) = state.config().system_config.agent_idle_ttl_secs else {
+                continue;
+            };
+            reap(&state, Duration::from_secs(ttl));
+        }
+    });
+}
+
+/// Drop the agents of every client idle for `ttl`
+///
+/// # Returns
+/// Number of clients whose agents were dropped
+pub fn reap(state: &AppState, ttl: Duration) -> usize {
+    let clients: Vec<String> = state
+        .client_contexts
+        .iter()
+        .map(|context| context.key().clone())
+        .collect();
+    let mut reaped = 0;
+    for client_uid in clients {
+        if state.reap_idle_agents(&client_uid, ttl) {
+            info!(
+                "Dropped agent of {} after {} s without a turn; its history is kept",
+                client_uid,
+                ttl.as_secs()
+            );
+            reaped += 1;
+        }
+    }
+    reaped
+}
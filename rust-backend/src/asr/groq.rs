@@ -0,0 +1,65 @@
+use anyhow::Result;
+use tracing::debug;
+
+use crate::config_manager::asr::GroqWhisperASRConfig;
+use crate::utils::audio::{encode_wav, ASR_SAMPLE_RATE};
+
+const GROQ_TRANSCRIPTIONS_URL: &str = "https://api.groq.com/openai/v1/audio/transcriptions";
+
+/// Groq's hosted Whisper, called directly rather than through the Python service
+pub struct GroqWhisperASR {
+    client: reqwest::Client,
+    api_key: String,
+    model: String,
+}
+
+impl GroqWhisperASR {
+    pub fn new(config: &GroqWhisperASRConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_key: config.api_key.clone(),
+            model: config.model.clone(),
+        }
+    }
+
+    /// Transcribe mono audio at [`ASR_SAMPLE_RATE`]
+    ///
+    /// # Arguments
+    /// * `audio` - Samples in -1.0..=1.0
+    /// * `language` - Language hint; `None` or "auto" lets Whisper detect it
+    pub async fn transcribe(&self, audio: &[f32], language: Option<&str>) -> Result<String> {
+        let wav = encode_wav(audio, ASR_SAMPLE_RATE);
+        let file = reqwest::multipart::Part::bytes(wav)
+            .file_name("audio.wav")
+            .mime_str("audio/wav")?;
+        let mut form = reqwest::multipart::Form::new()
+            .part("file", file)
+            .text("model", self.model.clone())
+            .text("response_format", "json");
+        if let Some(language) = language.filter(|l| *l != "auto") {
+            form = form.text("language", language.to_string());
+        }
+
+        debug!("Groq ASR: {} samples", audio.len());
+        let response = self
+            .client
+            .post(GROQ_TRANSCRIPTIONS_URL)
+            .bearer_auth(&self.api_key)
+            .multipart(form)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            anyhow::bail!("Groq ASR returned {}: {}", status, text);
+        }
+
+        let body: serde_json::Value = response.json().await?;
+        Ok(body
+            .get("text")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .trim()
+            .to_string())
+    }
+}
@@ -5,6 +5,13 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ASRRequest {
     pub audio_data: Vec<f32>,
+    /// Sample rate of `audio_data`
+    pub sample_rate: u32,
+    /// Interleaved channels in `audio_data`
+    pub channels: u8,
+    /// Language hint; "auto" asks for detection, `None` uses the service default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1,5 +1,71 @@
 // ASR module - interfaces for Python service integration
 pub mod interface;
+pub mod groq;
 
 pub use interface::*;
+pub use groq::GroqWhisperASR;
 
+use crate::state::AppState;
+use crate::utils::audio::ASR_SAMPLE_RATE;
+
+/// Language to transcribe a client's speech in: the one it chose with
+/// `set-asr-language`, else the ASR model's configured language
+pub fn client_language(state: &AppState, client_uid: &str) -> Option<String> {
+    let asr_language = state
+        .client_contexts
+        .get(client_uid)
+        .and_then(|c| c.value().asr_language.clone());
+    asr_language.or_else(|| state.config().character_config.asr_config.as_ref()?.language())
+}
+
+/// Transcribe mono audio at the ASR sample rate with the configured engine
+///
+/// Groq Whisper is called directly; every other engine runs in the Python
+/// service. Mic audio is already downmixed and resampled from the client's
+/// `mic-config` when buffered, so that is the format sent.
+pub async fn transcribe(
+    state: &AppState,
+    audio: Vec<f32>,
+    language: Option<String>,
+) -> anyhow::Result<String> {
+    let config = state.config();
+    let asr_config = config.character_config.asr_config.as_ref();
+    if let Some(groq) = asr_config
+        .filter(|c| c.asr_model == "groq_whisper_asr")
+        .and_then(|c| c.groq_whisper_asr.as_ref())
+    {
+        return GroqWhisperASR::new(groq).transcribe(&audio, language.as_deref()).await;
+    }
+
+    let request = crate::python_service::ASRRequest {
+        audio_data: audio,
+        sample_rate: ASR_SAMPLE_RATE,
+        channels: 1,
+        language,
+        config: asr_config.and_then(|c| serde_json::to_value(c).ok()),
+    };
+    Ok(state.python_service.transcribe(request).await?.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python_service::mock::MockPythonService;
+    use crate::test_support;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn python_engines_get_mono_audio_at_the_asr_rate() {
+        let service = Arc::new(MockPythonService::new().with_transcription("hello there"));
+        let state = test_support::state_with(test_support::config(), service.clone()).await;
+
+        let text = transcribe(&state, vec![0.0; 160], Some("en".to_string())).await.unwrap();
+
+        assert_eq!(text, "hello there");
+        let request = &service.requests("/asr/transcribe")[0];
+        assert_eq!(request["sample_rate"], ASR_SAMPLE_RATE);
+        assert_eq!(request["channels"], 1);
+        assert_eq!(request["language"], "en");
+        assert_eq!(request["audio_data"].as_array().unwrap().len(), 160);
+    }
+}
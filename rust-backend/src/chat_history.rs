@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use dashmap::DashMap;
 use std::time::SystemTime;
 use anyhow::Result;
 use uuid::Uuid;
@@ -17,6 +20,133 @@ pub struct HistoryMessage {
     pub avatar: Option<String>,
 }
 
+/// The `"role": "metadata"` row at the start of a history file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryMetadata {
+    /// When the history was created
+    pub timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Client settings restored when the history is reopened; absent in
+    /// histories saved before settings were persisted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<ConversationSettings>,
+    /// Chat group the history was last spoken in, restored when it is
+    /// reopened; absent for single conversations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    /// Client uids of the group's members at the time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participants: Option<Vec<String>>,
+}
+
+/// Per-conversation client settings kept in the history's metadata row
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConversationSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tts_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asr_language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub barge_in: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tts_engine: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tts_rate: Option<f64>,
+}
+
+/// Summary of a history for listing
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryInfo {
+    pub uid: String,
+    pub title: Option<String>,
+    pub created_at: Option<String>,
+    /// Time of the latest message, or of creation for an empty history
+    pub timestamp: Option<String>,
+    pub latest_message: Option<HistoryMessage>,
+}
+
+/// Long-term notes about a character's past conversations, kept in
+/// `summary.json` next to its histories
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MemorySummary {
+    pub summary: String,
+    pub updated_at: Option<String>,
+    /// How many messages of each history are already in the summary
+    #[serde(default)]
+    pub summarized: std::collections::HashMap<String, usize>,
+}
+
+/// File stem of the summary; never a history uid
+const SUMMARY_STEM: &str = "summary";
+
+/// One lock per history file, held across each read-modify-write so
+/// concurrent updates to a history don't drop each other's changes
+static HISTORY_LOCKS: LazyLock<DashMap<PathBuf, Arc<Mutex<()>>>> = LazyLock::new(DashMap::new);
+
+/// Run `update`, a read-modify-write of the history at `path`, under that
+/// history's lock
+///
+/// The lock is dropped from `HISTORY_LOCKS` once no other writer holds or
+/// waits on it, so the map only holds histories being written.
+fn with_history_lock<T>(path: &Path, update: impl FnOnce() -> Result<T>) -> Result<T> {
+    let lock = HISTORY_LOCKS.entry(path.to_path_buf()).or_default().clone();
+    let result = {
+        // Writes are atomic, so a writer that panicked left the file whole
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        update()
+    };
+    drop(lock);
+    // Clones are only taken under the map's lock, so a count of one means
+    // no other writer has it
+    HISTORY_LOCKS.remove_if(path, |_, lock| Arc::strong_count(lock) == 1);
+    result
+}
+
+/// Replace `path` with `contents` so that readers, and the file left after
+/// a crash, see either the old or the new contents, never part of either
+///
+/// The contents go to a temporary file in the same directory, renamed over
+/// `path`. With `fsync` (the `fsync_history` setting) the file and then the
+/// directory entry are flushed too, so the write also survives a power cut.
+fn write_atomic(path: &Path, contents: &str, fsync: bool) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("No directory for {:?}", path))?;
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid path: {:?}", path))?;
+    // Unique per write so concurrent writers don't share one; the `.tmp`
+    // extension keeps it out of history listings
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, Uuid::new_v4().as_simple()));
+
+    let written = (|| {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        if fsync {
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, path)
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(anyhow::anyhow!("Failed to write {:?}: {}", path, e));
+    }
+
+    // The rename is only durable once the directory is flushed; directories
+    // can't be opened for this on Windows
+    #[cfg(unix)]
+    if fsync {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
 fn is_safe_filename(filename: &str) -> bool {
     if filename.is_empty() || filename.len() > 255 {
         return false;
@@ -54,6 +184,9 @@ fn ensure_conf_dir(conf_uid: &str) -> Result<PathBuf> {
 fn get_safe_history_path(conf_uid: &str, history_uid: &str) -> Result<PathBuf> {
     let safe_conf_uid = sanitize_path_component(conf_uid)?;
     let safe_history_uid = sanitize_path_component(history_uid)?;
+    if safe_history_uid == SUMMARY_STEM {
+        return Err(anyhow::anyhow!("Invalid history uid: {}", history_uid));
+    }
     let base_dir = PathBuf::from("chat_history").join(&safe_conf_uid);
     let full_path = base_dir.join(format!("{}.json", safe_history_uid));
     
@@ -65,7 +198,7 @@ fn get_safe_history_path(conf_uid: &str, history_uid: &str) -> Result<PathBuf> {
     Ok(full_path)
 }
 
-pub fn create_new_history(conf_uid: &str) -> Result<String> {
+pub fn create_new_history(conf_uid: &str, fsync: bool) -> Result<String> {
     if conf_uid.is_empty() {
         tracing::warn!("No conf_uid provided");
         return Ok(String::new());
@@ -77,7 +210,7 @@ pub fn create_new_history(conf_uid: &str) -> Result<String> {
         .unwrap()
         .as_secs();
     let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(now as i64, 0)
-        .unwrap_or_else(|| chrono::Utc::now());
+        .unwrap_or_else(chrono::Utc::now);
     let timestamp = datetime.format("%Y-%m-%d_%H-%M-%S").to_string();
     let uuid_hex = Uuid::new_v4().as_simple().to_string();
     let history_uid = format!("{}_{}", timestamp, uuid_hex);
@@ -91,13 +224,13 @@ pub fn create_new_history(conf_uid: &str) -> Result<String> {
         .unwrap()
         .as_secs();
     let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(now as i64, 0)
-        .unwrap_or_else(|| chrono::Utc::now());
+        .unwrap_or_else(chrono::Utc::now);
     let initial_data = vec![serde_json::json!({
         "role": "metadata",
         "timestamp": datetime.to_rfc3339()
     })];
     
-    fs::write(&filepath, serde_json::to_string_pretty(&initial_data)?)?;
+    write_atomic(&filepath, &serde_json::to_string_pretty(&initial_data)?, fsync)?;
     tracing::debug!("Created new history file: {:?}", filepath);
     
     Ok(history_uid)
@@ -110,41 +243,117 @@ pub fn store_message(
     content: &str,
     name: Option<&str>,
     avatar: Option<&str>,
+    fsync: bool,
+) -> Result<()> {
+    store_message_at(conf_uid, history_uid, role, content, name, avatar, None, fsync)
+}
+
+/// Store a message with an explicit timestamp; `None` stamps it now
+#[allow(clippy::too_many_arguments)]
+pub fn store_message_at(
+    conf_uid: &str,
+    history_uid: &str,
+    role: &str,
+    content: &str,
+    name: Option<&str>,
+    avatar: Option<&str>,
+    timestamp: Option<&str>,
+    fsync: bool,
 ) -> Result<()> {
     let filepath = get_safe_history_path(conf_uid, history_uid)?;
-    
-    // Read existing history
-    let mut messages: Vec<serde_json::Value> = if filepath.exists() {
+
+    with_history_lock(&filepath, || {
+        // Read existing history
+        let mut messages: Vec<serde_json::Value> = if filepath.exists() {
+            let content = fs::read_to_string(&filepath)?;
+            serde_json::from_str(&content)?
+        } else {
+            Vec::new()
+        };
+
+        // Add new message
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(now as i64, 0)
+            .unwrap_or_else(chrono::Utc::now);
+        let timestamp = timestamp.map_or_else(|| datetime.to_rfc3339(), str::to_string);
+        let message = serde_json::json!({
+            "role": role,
+            "timestamp": timestamp,
+            "content": content,
+            "name": name,
+            "avatar": avatar
+        });
+
+        messages.push(message);
+
+        // Write back
+        write_atomic(&filepath, &serde_json::to_string_pretty(&messages)?, fsync)?;
+
+        Ok(())
+    })
+}
+
+/// Append `continuation` to the last message if it is the AI's, keeping
+/// its timestamp
+///
+/// # Returns
+/// Whether there was such a message to extend
+pub fn extend_last_reply(conf_uid: &str, history_uid: &str, continuation: &str, fsync: bool) -> Result<bool> {
+    let filepath = get_safe_history_path(conf_uid, history_uid)?;
+    with_history_lock(&filepath, || {
+        if !filepath.exists() {
+            return Ok(false);
+        }
+
         let content = fs::read_to_string(&filepath)?;
-        serde_json::from_str(&content)?
-    } else {
-        Vec::new()
-    };
-    
-    // Add new message
-    let now = SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(now as i64, 0)
-        .unwrap_or_else(|| chrono::Utc::now());
-    let message = serde_json::json!({
-        "role": role,
-        "timestamp": datetime.to_rfc3339(),
-        "content": content,
-        "name": name,
-        "avatar": avatar
-    });
-    
-    messages.push(message);
-    
-    // Write back
-    fs::write(&filepath, serde_json::to_string_pretty(&messages)?)?;
-    
-    Ok(())
+        let mut messages: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+        let Some(last) = messages
+            .last_mut()
+            .filter(|m| m.get("role").and_then(|r| r.as_str()) == Some("ai"))
+        else {
+            return Ok(false);
+        };
+        let reply = last.get("content").and_then(|c| c.as_str()).unwrap_or_default();
+        last["content"] = serde_json::json!(crate::agent::transformers::join_continuation(reply, continuation));
+
+        write_atomic(&filepath, &serde_json::to_string_pretty(&messages)?, fsync)?;
+        Ok(true)
+    })
+}
+
+/// Remove the last human message and everything stored after it
+///
+/// # Returns
+/// The removed human message, or `None` if the history has no human turn
+pub fn truncate_last_turn(conf_uid: &str, history_uid: &str, fsync: bool) -> Result<Option<HistoryMessage>> {
+    let filepath = get_safe_history_path(conf_uid, history_uid)?;
+    with_history_lock(&filepath, || {
+        if !filepath.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&filepath)?;
+        let mut messages: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+
+        let Some(index) = messages
+            .iter()
+            .rposition(|m| m.get("role").and_then(|r| r.as_str()) == Some("human"))
+        else {
+            return Ok(None);
+        };
+        let removed = serde_json::from_value(messages[index].clone()).ok();
+        messages.truncate(index);
+
+        write_atomic(&filepath, &serde_json::to_string_pretty(&messages)?, fsync)?;
+
+        Ok(removed)
+    })
 }
 
-pub fn get_history_list(conf_uid: &str) -> Result<Vec<String>> {
+pub fn get_history_list(conf_uid: &str) -> Result<Vec<HistoryInfo>> {
     let conf_dir = ensure_conf_dir(conf_uid)?;
     let mut history_list = Vec::new();
     
@@ -153,20 +362,184 @@ pub fn get_history_list(conf_uid: &str) -> Result<Vec<String>> {
             let entry = entry?;
             let path = entry.path();
             if path.is_file() && path.extension() == Some(std::ffi::OsStr::new("json")) {
-                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
-                    history_list.push(stem.to_string());
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()).filter(|s| *s != SUMMARY_STEM) {
+                    let metadata = match get_metadata(conf_uid, stem) {
+                        Ok(metadata) => metadata,
+                        Err(e) => {
+                            tracing::error!("Error reading history file {:?}: {}", path, e);
+                            continue;
+                        }
+                    };
+                    let latest_message = get_history(conf_uid, stem)?.pop();
+                    history_list.push(HistoryInfo {
+                        uid: stem.to_string(),
+                        title: metadata.title,
+                        timestamp: latest_message
+                            .as_ref()
+                            .map(|m| m.timestamp.clone())
+                            .or_else(|| metadata.timestamp.clone()),
+                        created_at: metadata.timestamp,
+                        latest_message,
+                    });
                 }
             }
         }
     }
     
-    // Sort by filename (which includes timestamp)
-    history_list.sort();
+    // Sort by uid (which starts with the creation timestamp)
+    history_list.sort_by(|a, b| a.uid.cmp(&b.uid));
     history_list.reverse(); // Most recent first
     
     Ok(history_list)
 }
 
+/// Read the metadata row of a history; histories without one get defaults
+pub fn get_metadata(conf_uid: &str, history_uid: &str) -> Result<HistoryMetadata> {
+    let filepath = get_safe_history_path(conf_uid, history_uid)?;
+    let content = fs::read_to_string(&filepath)?;
+    let messages: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+    
+    let metadata = messages
+        .into_iter()
+        .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("metadata"))
+        .map(serde_json::from_value)
+        .transpose()?
+        .unwrap_or_default();
+    
+    Ok(metadata)
+}
+
+/// Store a human-friendly title in the history's metadata row
+pub fn rename_history(conf_uid: &str, history_uid: &str, title: &str, fsync: bool) -> Result<()> {
+    set_metadata_fields(conf_uid, history_uid, &[("title", serde_json::json!(title))], fsync)
+}
+
+/// Store the client's settings in the history's metadata row
+pub fn save_settings(
+    conf_uid: &str,
+    history_uid: &str,
+    settings: &ConversationSettings,
+    fsync: bool,
+) -> Result<()> {
+    set_metadata_fields(conf_uid, history_uid, &[("settings", serde_json::to_value(settings)?)], fsync)
+}
+
+/// Record the chat group a history is spoken in, in its metadata row
+///
+/// Nothing is written when the row already names the same group and
+/// participants.
+pub fn save_group(
+    conf_uid: &str,
+    history_uid: &str,
+    group_id: &str,
+    participants: &[String],
+    fsync: bool,
+) -> Result<()> {
+    let metadata = get_metadata(conf_uid, history_uid)?;
+    if metadata.group_id.as_deref() == Some(group_id)
+        && metadata.participants.as_deref() == Some(participants)
+    {
+        return Ok(());
+    }
+    set_metadata_fields(
+        conf_uid,
+        history_uid,
+        &[
+            ("group_id", serde_json::json!(group_id)),
+            ("participants", serde_json::json!(participants)),
+        ],
+        fsync,
+    )
+}
+
+/// Set fields of the metadata row in one write, adding the row if the file
+/// predates it
+fn set_metadata_fields(
+    conf_uid: &str,
+    history_uid: &str,
+    fields: &[(&str, serde_json::Value)],
+    fsync: bool,
+) -> Result<()> {
+    let filepath = get_safe_history_path(conf_uid, history_uid)?;
+    with_history_lock(&filepath, || {
+        if !filepath.exists() {
+            return Err(anyhow::anyhow!("History not found: {}", history_uid));
+        }
+
+        let content = fs::read_to_string(&filepath)?;
+        let mut messages: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+
+        let existing = messages
+            .iter_mut()
+            .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("metadata"));
+        match existing.and_then(|m| m.as_object_mut()) {
+            Some(metadata) => {
+                for (field, value) in fields {
+                    metadata.insert(field.to_string(), value.clone());
+                }
+            }
+            None => {
+                // Older files may lack the row; creation time is unknown
+                let mut metadata = serde_json::json!({
+                    "role": "metadata",
+                    "timestamp": null
+                });
+                for (field, value) in fields {
+                    metadata[*field] = value.clone();
+                }
+                messages.insert(0, metadata);
+            }
+        }
+
+        write_atomic(&filepath, &serde_json::to_string_pretty(&messages)?, fsync)?;
+
+        Ok(())
+    })
+}
+
+/// Whether a history file exists; fails if either uid is not a safe name
+pub fn history_exists(conf_uid: &str, history_uid: &str) -> Result<bool> {
+    Ok(get_safe_history_path(conf_uid, history_uid)?.is_file())
+}
+
+/// Render a history as a Markdown transcript headed by the participants
+///
+/// Messages without a stored name are attributed by role, using
+/// `character_name` and `human_name`.
+pub fn export_markdown(
+    conf_uid: &str,
+    history_uid: &str,
+    character_name: &str,
+    human_name: &str,
+) -> Result<String> {
+    let metadata = get_metadata(conf_uid, history_uid)?;
+    let messages = get_history(conf_uid, history_uid)?;
+
+    let title = metadata.title.as_deref().unwrap_or(history_uid);
+    let mut markdown = format!("# {}\n\n", title);
+    markdown.push_str(&format!("- **Character:** {}\n", character_name));
+    markdown.push_str(&format!("- **User:** {}\n", human_name));
+    if let Some(created) = &metadata.timestamp {
+        markdown.push_str(&format!("- **Started:** {}\n", created));
+    }
+
+    for message in &messages {
+        let speaker = message.name.as_deref().unwrap_or(match message.role.as_str() {
+            "human" => human_name,
+            "ai" => character_name,
+            _ => "System",
+        });
+        markdown.push_str(&format!(
+            "\n### {} — {}\n\n{}\n",
+            speaker,
+            message.timestamp,
+            message.content.trim()
+        ));
+    }
+
+    Ok(markdown)
+}
+
 pub fn get_history(conf_uid: &str, history_uid: &str) -> Result<Vec<HistoryMessage>> {
     let filepath = get_safe_history_path(conf_uid, history_uid)?;
     
@@ -193,6 +566,22 @@ pub fn get_history(conf_uid: &str, history_uid: &str) -> Result<Vec<HistoryMessa
     Ok(history)
 }
 
+/// Read the character's memory summary; empty if none has been written
+pub fn load_summary(conf_uid: &str) -> Result<MemorySummary> {
+    let filepath = ensure_conf_dir(conf_uid)?.join(format!("{}.json", SUMMARY_STEM));
+    if !filepath.exists() {
+        return Ok(MemorySummary::default());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(&filepath)?)?)
+}
+
+pub fn save_summary(conf_uid: &str, summary: &MemorySummary, fsync: bool) -> Result<()> {
+    let filepath = ensure_conf_dir(conf_uid)?.join(format!("{}.json", SUMMARY_STEM));
+    write_atomic(&filepath, &serde_json::to_string_pretty(summary)?, fsync)?;
+    tracing::debug!("Saved memory summary: {:?}", filepath);
+    Ok(())
+}
+
 pub fn delete_history(conf_uid: &str, history_uid: &str) -> Result<()> {
     let filepath = get_safe_history_path(conf_uid, history_uid)?;
     
@@ -204,3 +593,113 @@ pub fn delete_history(conf_uid: &str, history_uid: &str) -> Result<()> {
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A character's history directory, removed when the test ends
+    struct TestConf(String);
+
+    impl TestConf {
+        fn new() -> Self {
+            Self(format!("test-{}", Uuid::new_v4().as_simple()))
+        }
+    }
+
+    impl Drop for TestConf {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(PathBuf::from("chat_history").join(&self.0));
+        }
+    }
+
+    #[test]
+    fn concurrent_stores_keep_every_message() {
+        let conf = TestConf::new();
+        let history_uid = create_new_history(&conf.0, false).unwrap();
+
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                let (conf_uid, history_uid) = (&conf.0, &history_uid);
+                scope.spawn(move || {
+                    for i in 0..10 {
+                        let content = format!("{}-{}", writer, i);
+                        store_message(conf_uid, history_uid, "human", &content, None, None, false).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(get_history(&conf.0, &history_uid).unwrap().len(), 80);
+    }
+
+    #[test]
+    fn metadata_updates_keep_concurrent_messages() {
+        let conf = TestConf::new();
+        let history_uid = create_new_history(&conf.0, false).unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..20 {
+                    store_message(&conf.0, &history_uid, "ai", &i.to_string(), None, None, false).unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for i in 0..20 {
+                    rename_history(&conf.0, &history_uid, &format!("Title {}", i), false).unwrap();
+                }
+            });
+        });
+
+        assert_eq!(get_history(&conf.0, &history_uid).unwrap().len(), 20);
+        let metadata = get_metadata(&conf.0, &history_uid).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Title 19"));
+    }
+
+    #[test]
+    fn history_locks_are_dropped_after_writes() {
+        let conf = TestConf::new();
+        let history_uid = create_new_history(&conf.0, false).unwrap();
+        store_message(&conf.0, &history_uid, "human", "hi", None, None, false).unwrap();
+
+        let path = get_safe_history_path(&conf.0, &history_uid).unwrap();
+        assert!(!HISTORY_LOCKS.contains_key(&path));
+    }
+
+    /// Files in `dir` left by writes in progress
+    fn temp_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "tmp"))
+            .collect()
+    }
+
+    #[test]
+    fn atomic_write_replaces_the_file_and_leaves_no_temp_file() {
+        let conf = TestConf::new();
+        let dir = ensure_conf_dir(&conf.0).unwrap();
+        let path = dir.join("history.json");
+        fs::write(&path, "old").unwrap();
+
+        write_atomic(&path, "new", false).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(temp_files(&dir).is_empty());
+    }
+
+    #[test]
+    fn failed_atomic_write_keeps_the_old_contents() {
+        let conf = TestConf::new();
+        let dir = ensure_conf_dir(&conf.0).unwrap();
+        // A directory with something in it can't be renamed over
+        let path = dir.join("history.json");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("kept"), "old").unwrap();
+
+        assert!(write_atomic(&path, "new", false).is_err());
+
+        assert_eq!(fs::read_to_string(path.join("kept")).unwrap(), "old");
+        assert!(temp_files(&dir).is_empty());
+    }
+}
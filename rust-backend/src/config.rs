@@ -1,7 +1,12 @@
 use serde::{Deserialize, Serialize};
-use std::fs;
 use anyhow::Result;
 
+use crate::config_manager::agent::AgentConfig;
+use crate::config_manager::asr::ASRConfig;
+use crate::config_manager::tts::TTSConfig;
+use crate::config_manager::tts_preprocessor::TTSPreprocessorConfig;
+use crate::config_manager::vad::VADConfig;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub system_config: SystemConfig,
@@ -24,12 +29,145 @@ pub struct SystemConfig {
     pub avatars_dir: String,
     #[serde(default = "default_characters_dir")]
     pub characters_dir: String,
+    /// Tool prompt names to prompt files, appended to the persona in key order
+    #[serde(default)]
+    pub tool_prompts: std::collections::BTreeMap<String, String>,
+    #[serde(default = "default_model_dict_path")]
+    pub model_dict_path: String,
+    /// Directory holding the tool prompt files named in `tool_prompts`
+    #[serde(default = "default_prompts_dir")]
+    pub prompts_dir: String,
+    /// Prepended to every character's system prompt
+    #[serde(default)]
+    pub system_prompt_prefix: String,
+    /// Appended to every character's system prompt, after the tool prompts
+    #[serde(default)]
+    pub system_prompt_suffix: String,
+    /// Generated audio older than this is deleted from `cache_dir`
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// How often the cache is scanned for expired audio
+    #[serde(default = "default_cache_scan_interval_secs")]
+    pub cache_scan_interval_secs: u64,
+    /// Drop the agent (and its memory) of a connected client that hasn't
+    /// had a turn for this long; its history is kept and reloaded on the
+    /// next turn. Null, the default, keeps agents until the client
+    /// disconnects
+    #[serde(default = "default_agent_idle_ttl_secs")]
+    pub agent_idle_ttl_secs: Option<u64>,
+    /// How long after disconnecting a client can `resume` its session
+    #[serde(default = "default_resume_token_ttl_secs")]
+    pub resume_token_ttl_secs: u64,
+    /// Whether a message changed with `edit-last-message` keeps the time it
+    /// was originally sent, rather than the time of the edit
+    #[serde(default = "default_keep_edited_timestamp")]
+    pub keep_edited_timestamp: bool,
+    /// Longest typed input accepted, in characters; null for no limit
+    #[serde(default = "default_max_input_chars")]
+    pub max_input_chars: Option<usize>,
+    /// What to do with input longer than `max_input_chars`
+    #[serde(default)]
+    pub input_overflow: InputOverflow,
+    /// How many group members' replies may be generated ahead of the
+    /// member speaking; 0 generates each reply when its turn comes
+    #[serde(default = "default_group_lookahead")]
+    pub group_lookahead: usize,
+    /// Start a new chat history on a client's first input when it has none
+    /// selected; when false, nothing is stored until the client sends
+    /// `create-new-history` or picks an existing history
+    #[serde(default = "default_auto_create_history")]
+    pub auto_create_history: bool,
+    /// Flush chat history files to disk before a write counts as done.
+    /// Writes are atomic either way, so a crash never leaves a corrupt
+    /// file; without this the OS may still lose the last few turns on a
+    /// power cut, and with it every turn waits on the disk.
+    #[serde(default)]
+    pub fsync_history: bool,
+    /// Let a client's `switch-config` change the character while other
+    /// clients are connected. The config is server-wide, so the switch
+    /// moves every client to the new character and resets their agents
+    /// and histories; when false, only a client connected alone can switch
+    #[serde(default)]
+    pub shared_config_switch: bool,
+    /// Most conversations (turns being generated or spoken) running at once
+    /// across all clients; null for no limit
+    #[serde(default)]
+    pub max_active_conversations: Option<usize>,
+    /// What to do with a conversation started while
+    /// `max_active_conversations` are already running
+    #[serde(default)]
+    pub conversation_overflow: ConversationOverflow,
+    /// Most WebSocket connections (clients and observers) open at once;
+    /// upgrades over it get a 503. Null for no limit
     #[serde(default)]
-    pub tool_prompts: std::collections::HashMap<String, String>,
+    pub max_connections: Option<usize>,
+    /// Serve symlinks found in the static directories (`/cache`, `/bg`,
+    /// ...) as long as they point inside the same directory; by default any
+    /// symlink is refused
+    #[serde(default)]
+    pub follow_static_symlinks: bool,
+    /// Largest WebSocket message accepted from a client, in bytes. Larger
+    /// messages get an error instead of being handled; send big payloads as
+    /// `chunk` messages. Messages over four times this close the connection.
+    #[serde(default = "default_max_ws_message_bytes")]
+    pub max_ws_message_bytes: usize,
+    /// Largest message reassembled from `chunk` parts, in bytes
+    #[serde(default = "default_max_chunked_message_bytes")]
+    pub max_chunked_message_bytes: usize,
+    /// Serve `/api/debug/...` endpoints, which expose prompts and agent
+    /// memory; keep off in production
+    #[serde(default)]
+    pub debug_endpoints: bool,
+    /// Adapter that expression and motion commands go through; see
+    /// [`crate::adapters::AVAILABLE_ADAPTERS`]
+    #[serde(default = "default_backend_adapter")]
+    pub backend_adapter: String,
+    /// Write the upgraded config back to its file when an older
+    /// `conf_version` is migrated on load
+    #[serde(default)]
+    pub rewrite_migrated_config: bool,
+    /// Require `auth_token` on the WebSocket and REST API; off for local use
+    #[serde(default)]
+    pub auth_enabled: bool,
+    /// Token clients send as `Authorization: Bearer <token>`, an
+    /// `X-API-Key` header or a `?token=` query parameter
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Serve the static directories (`/cache`, `/live2d-models`, ...)
+    /// without the token, for frontends that load assets by plain URL
+    #[serde(default)]
+    pub auth_exempt_static: bool,
+    /// Send a throwaway chat and TTS request for the default character in
+    /// the background at startup, so the first real turn doesn't pay for
+    /// cold connections and model loading
+    #[serde(default)]
+    pub warmup_on_start: bool,
+}
+
+/// Handling of typed input over `max_input_chars`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputOverflow {
+    /// Keep the first `max_input_chars` characters and tell the client
+    #[default]
+    Truncate,
+    /// Refuse the input with an error
+    Reject,
+}
+
+/// Handling of conversations over `max_active_conversations`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversationOverflow {
+    /// Tell the client the server is busy and start once a slot frees up
+    #[default]
+    Queue,
+    /// Tell the client the server is busy and drop the conversation
+    Reject,
 }
 
 fn default_conf_version() -> Option<String> {
-    Some("v1.1.1".to_string())
+    Some(crate::config_manager::migration::CURRENT_CONF_VERSION.to_string())
 }
 
 fn default_backgrounds_dir() -> String {
@@ -44,6 +182,62 @@ fn default_characters_dir() -> String {
     "config/characters".to_string()
 }
 
+fn default_model_dict_path() -> String {
+    "model_dict.json".to_string()
+}
+
+fn default_prompts_dir() -> String {
+    "../backend/prompts/utils".to_string()
+}
+
+fn default_keep_edited_timestamp() -> bool {
+    true
+}
+
+fn default_group_lookahead() -> usize {
+    1
+}
+
+fn default_auto_create_history() -> bool {
+    true
+}
+
+fn default_max_ws_message_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_max_chunked_message_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_max_input_chars() -> Option<usize> {
+    Some(8000)
+}
+
+fn default_backend_adapter() -> String {
+    "orphiq".to_string()
+}
+
+fn default_speech_chars_per_second() -> f32 {
+    15.0
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_cache_scan_interval_secs() -> u64 {
+    300
+}
+
+fn default_agent_idle_ttl_secs() -> Option<u64> {
+    None
+}
+
+fn default_resume_token_ttl_secs() -> u64 {
+    300
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CharacterConfig {
     pub conf_name: String,
@@ -52,50 +246,336 @@ pub struct CharacterConfig {
     pub character_name: String,
     pub avatar: Option<String>,
     pub human_name: String,
+    /// Background image (file name in `backgrounds_dir`) shown on connect
+    #[serde(default)]
+    pub default_background: Option<String>,
+    #[serde(default)]
+    pub persona_prompt: String,
+    /// Send each expression with an offset into the sentence audio, so the
+    /// avatar can change expression mid-sentence
+    #[serde(default)]
+    pub timed_expressions: bool,
+    /// Speaking rate used to estimate expression offsets
+    #[serde(default = "default_speech_chars_per_second")]
+    pub speech_chars_per_second: f32,
+    /// Forward `<think>` reasoning to the client as `thinking` messages
+    /// instead of discarding it; it is never spoken either way
+    #[serde(default)]
+    pub show_thinking: bool,
+    #[serde(default)]
+    pub agent_config: Option<AgentConfig>,
+    #[serde(default)]
+    pub asr_config: Option<ASRConfig>,
+    /// Segments `raw-audio-data`; the built-in VAD is used when absent
+    #[serde(default)]
+    pub vad_config: Option<VADConfig>,
+    #[serde(default)]
+    pub tts_config: Option<TTSConfig>,
+    #[serde(default)]
+    pub tts_preprocessor_config: Option<TTSPreprocessorConfig>,
+    /// Spoken to avatar clients when they connect
+    #[serde(default)]
+    pub greeting: Option<Greeting>,
+    /// Long-term memory across sessions; off when absent
+    #[serde(default)]
+    pub summary_memory: Option<SummaryMemoryConfig>,
+    /// Pick an expression for sentences the LLM left without emotion tags;
+    /// off when absent, as it adds work to every untagged sentence
+    #[serde(default)]
+    pub emotion_inference: Option<EmotionInferenceConfig>,
+    /// Sound or expression played while the LLM works on a slow reply;
+    /// off when absent
+    #[serde(default)]
+    pub thinking_filler: Option<ThinkingFillerConfig>,
+    /// Regex find/replace rules scrubbing artifacts such as a leading
+    /// "Assistant:" from replies
+    #[serde(default)]
+    pub output_rewrites: Option<OutputRewriteConfig>,
+}
+
+/// A running LLM-written summary of the character's conversations, kept in
+/// the character's history directory and added to the system prompt of
+/// new conversations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryMemoryConfig {
+    /// Update the summary after this many turns; 0 only updates it when a
+    /// client disconnects
+    #[serde(default = "default_summary_every_n_turns")]
+    pub every_n_turns: usize,
+    /// Longest summary kept, in characters
+    #[serde(default = "default_summary_max_chars")]
+    pub max_chars: usize,
+}
+
+fn default_summary_every_n_turns() -> usize {
+    10
+}
+
+fn default_summary_max_chars() -> usize {
+    1000
+}
+
+/// Expression fallback for LLMs that don't write `[emotion]` tags
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmotionInferenceConfig {
+    #[serde(default)]
+    pub method: EmotionInferenceMethod,
+    /// LLM provider asked with the `llm` method; the character's own when
+    /// absent
+    #[serde(default)]
+    pub llm_provider: Option<String>,
+    /// Sentences whose inferred emotion is remembered
+    #[serde(default = "default_emotion_cache_size")]
+    pub cache_size: usize,
+}
+
+/// How an untagged sentence's emotion is inferred
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmotionInferenceMethod {
+    /// Built-in keyword classifier; fast, English only
+    #[default]
+    Lexicon,
+    /// A short request to an LLM, choosing from the model's emotion map
+    Llm,
+}
+
+fn default_emotion_cache_size() -> usize {
+    512
+}
+
+/// Filler sent when a turn is still waiting for its first sentence after
+/// `delay_ms`, and stopped with a `stop-filler` control once the reply
+/// starts or the turn is interrupted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThinkingFillerConfig {
+    /// Pre-rendered audio file, e.g. a "hmm", sent embedded in an `audio`
+    /// message
+    #[serde(default)]
+    pub audio: Option<String>,
+    /// Expression shown while it plays, by name or index
+    #[serde(default)]
+    pub expression: Option<serde_json::Value>,
+    /// Replies faster than this get no filler
+    #[serde(default = "default_thinking_filler_delay_ms")]
+    pub delay_ms: u64,
+}
+
+fn default_thinking_filler_delay_ms() -> u64 {
+    800
+}
+
+/// Rewrite rules for the assistant's replies, each list applied in order
+/// to every sentence
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutputRewriteConfig {
+    /// Applied before a sentence is shown, stored or spoken
+    #[serde(default)]
+    pub display: Vec<RewriteRule>,
+    /// Applied only to the text sent to TTS, before `tts_preprocessor_config`
+    #[serde(default)]
+    pub tts: Vec<RewriteRule>,
+}
+
+/// Replace every match of `pattern` with `replacement`, which may refer to
+/// capture groups as `$1` or `${name}`
+///
+/// The pattern is compiled when the config is loaded, so an invalid one is
+/// a load error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RewriteRuleConfig", into = "RewriteRuleConfig")]
+pub struct RewriteRule {
+    pub pattern: regex::Regex,
+    pub replacement: String,
+}
+
+/// [`RewriteRule`] as written in the config
+#[derive(Serialize, Deserialize)]
+struct RewriteRuleConfig {
+    pattern: String,
+    #[serde(default)]
+    replacement: String,
+}
+
+impl TryFrom<RewriteRuleConfig> for RewriteRule {
+    type Error = String;
+
+    fn try_from(rule: RewriteRuleConfig) -> Result<Self, Self::Error> {
+        let pattern = regex::Regex::new(&rule.pattern)
+            .map_err(|e| format!("invalid output_rewrites pattern {:?}: {}", rule.pattern, e))?;
+        Ok(Self {
+            pattern,
+            replacement: rule.replacement,
+        })
+    }
+}
+
+impl From<RewriteRule> for RewriteRuleConfig {
+    fn from(rule: RewriteRule) -> Self {
+        Self {
+            pattern: rule.pattern.as_str().to_string(),
+            replacement: rule.replacement,
+        }
+    }
+}
+
+/// What a character says when a client connects
+///
+/// A plain string is spoken as is; `{"prompt": ...}` asks the LLM to write
+/// the greeting, with `{character_name}` and `{human_name}` filled in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Greeting {
+    Text(String),
+    Prompt { prompt: String },
+}
+
+impl CharacterConfig {
+    /// Sentence segmentation language from the basic memory agent settings
+    pub fn segment_language(&self) -> &str {
+        self.agent_config
+            .as_ref()
+            .and_then(|a| a.agent_settings.basic_memory_agent.as_ref())
+            .map(|b| b.segment_language.as_str())
+            .unwrap_or("auto")
+    }
+
+    /// Rewrite rules for displayed (and so also spoken) text
+    pub fn display_rewrites(&self) -> &[RewriteRule] {
+        self.output_rewrites.as_ref().map_or(&[], |r| &r.display)
+    }
+
+    /// Rewrite rules for spoken text only
+    pub fn tts_rewrites(&self) -> &[RewriteRule] {
+        self.output_rewrites.as_ref().map_or(&[], |r| &r.tts)
+    }
 }
 
 impl Config {
+    /// Load configuration from a JSON-LD or YAML file
+    ///
+    /// Uses the same loader as `config_manager::Config`, so both get
+    /// encoding detection and `${VAR}` environment substitution. Configs with
+    /// an older `conf_version` are migrated; newer ones are refused.
     pub fn load(path: &str) -> Result<Self> {
-        // Try to resolve the path - if relative, try from current dir and from rust-backend dir
-        let content = if std::path::Path::new(path).exists() {
-            fs::read_to_string(path)?
-        } else {
-            // Try from rust-backend directory
-            let rust_backend_path = std::path::Path::new(".").canonicalize()
-                .ok()
-                .and_then(|p| {
-                    if p.ends_with("rust-backend") {
-                        Some(p.join(path))
-                    } else {
-                        None
-                    }
-                });
-            
-            if let Some(p) = rust_backend_path {
-                if p.exists() {
-                    fs::read_to_string(&p)?
-                } else {
-                    fs::read_to_string(path)?
+        use crate::config_manager::migration;
+
+        let mut value = crate::config_manager::utils::read_config_file(path)?;
+        let migrated_from = migration::migrate_config(&mut value)?;
+        let config: Config = serde_json::from_value(value)?;
+        if let Some(version) = migrated_from {
+            tracing::info!(
+                "Upgraded config {} from {} to {}",
+                path,
+                version,
+                migration::CURRENT_CONF_VERSION
+            );
+            if config.system_config.rewrite_migrated_config {
+                if let Err(e) = migration::rewrite_config_file(path) {
+                    tracing::warn!("Failed to rewrite migrated config {}: {}", path, e);
                 }
-            } else {
-                fs::read_to_string(path)?
             }
+        }
+        config.validate_agent_wiring()?;
+        let system_config = &config.system_config;
+        if system_config.auth_enabled
+            && system_config.auth_token.as_deref().is_none_or(|t| t.trim().is_empty())
+        {
+            anyhow::bail!("system_config.auth_enabled is set but auth_token is missing or empty");
+        }
+        let adapter = &config.system_config.backend_adapter;
+        if !crate::adapters::AVAILABLE_ADAPTERS.contains(&adapter.as_str()) {
+            anyhow::bail!(
+                "Unknown system_config.backend_adapter: {} (available: {})",
+                adapter,
+                crate::adapters::AVAILABLE_ADAPTERS.join(", ")
+            );
+        }
+        Ok(config)
+    }
+
+    /// Check that the basic memory agent's `llm_provider` points at a usable
+    /// entry in `llm_configs`
+    ///
+    /// The agent is only built on the first conversation turn, so without this
+    /// a typo in the provider name surfaces as a runtime error mid-request.
+    pub fn validate_agent_wiring(&self) -> Result<()> {
+        let Some(agent_config) = &self.character_config.agent_config else {
+            return Ok(());
         };
-        
-        // Determine file type by extension or content
-        let path_lower = path.to_lowercase();
-        if path_lower.ends_with(".jsonld") || path_lower.ends_with(".json") {
-            // Load as JSON/JSON-LD
-            let json_value: serde_json::Value = serde_json::from_str(&content)?;
-            // Remove @context if present (we don't need it for deserialization)
-            let config: Config = serde_json::from_value(json_value)?;
-            Ok(config)
-        } else {
-            // Load as YAML
-            let config: Config = serde_yaml::from_str(&content)?;
-            Ok(config)
+        if agent_config.conversation_agent_choice != "basic_memory_agent" {
+            return Ok(());
+        }
+
+        let settings = agent_config
+            .agent_settings
+            .basic_memory_agent
+            .as_ref()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "conversation_agent_choice is basic_memory_agent but agent_settings.basic_memory_agent is missing"
+                )
+            })?;
+        let llm_configs = serde_json::to_value(&agent_config.llm_configs)?;
+        validate_llm_provider(&llm_configs, "basic_memory_agent.llm_provider", &settings.llm_provider)?;
+        for provider in &agent_config.fallback_llm_providers {
+            validate_llm_provider(&llm_configs, "fallback_llm_providers", provider)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that `provider` names a non-empty `llm_configs` entry with the
+/// fields that provider needs
+///
+/// `setting` is the config key the provider came from, used in errors.
+fn validate_llm_provider(llm_configs: &serde_json::Value, setting: &str, provider: &str) -> Result<()> {
+    let llm_config = match llm_configs.get(provider) {
+        Some(serde_json::Value::Null) => anyhow::bail!(
+            "{} is '{}' but llm_configs.{} is empty",
+            setting,
+            provider,
+            provider
+        ),
+        Some(config) => config,
+        None => anyhow::bail!(
+            "{} '{}' is not a known provider; expected one of: {}",
+            setting,
+            provider,
+            llm_configs
+                .as_object()
+                .map(|o| o.keys().cloned().collect::<Vec<_>>().join(", "))
+                .unwrap_or_default()
+        ),
+    };
+
+    // Named hosted providers have a known endpoint, Ollama ignores the
+    // key, and llama.cpp runs a local model file
+    let required: &[&str] = match provider {
+        "llama_cpp_llm" => &["model_path"],
+        "ollama_llm" => &["model", "base_url"],
+        "openai_compatible_llm" => &["model", "base_url", "llm_api_key"],
+        _ => &["model", "llm_api_key"],
+    };
+    for field in required {
+        let present = llm_config
+            .get(field)
+            .and_then(|v| v.as_str())
+            .is_some_and(|v| !v.trim().is_empty());
+        if !present {
+            anyhow::bail!("llm_configs.{}.{} is missing or empty", provider, field);
         }
     }
+
+    if let Some(headers) = llm_config.get("extra_headers").filter(|v| !v.is_null()) {
+        let headers: std::collections::HashMap<String, String> = serde_json::from_value(headers.clone())
+            .map_err(|e| anyhow::anyhow!("llm_configs.{}.extra_headers: {}", provider, e))?;
+        crate::agent::stateless_llm::header_map(&headers)
+            .map_err(|e| anyhow::anyhow!("llm_configs.{}.extra_headers: {}", provider, e))?;
+    }
+
+    Ok(())
 }
 
 impl Default for SystemConfig {
@@ -111,7 +591,35 @@ impl Default for SystemConfig {
             backgrounds_dir: default_backgrounds_dir(),
             avatars_dir: default_avatars_dir(),
             characters_dir: default_characters_dir(),
-            tool_prompts: std::collections::HashMap::new(),
+            tool_prompts: std::collections::BTreeMap::new(),
+            model_dict_path: default_model_dict_path(),
+            prompts_dir: default_prompts_dir(),
+            system_prompt_prefix: String::new(),
+            system_prompt_suffix: String::new(),
+            cache_ttl_secs: default_cache_ttl_secs(),
+            cache_scan_interval_secs: default_cache_scan_interval_secs(),
+            agent_idle_ttl_secs: default_agent_idle_ttl_secs(),
+            resume_token_ttl_secs: default_resume_token_ttl_secs(),
+            keep_edited_timestamp: default_keep_edited_timestamp(),
+            max_input_chars: default_max_input_chars(),
+            input_overflow: InputOverflow::default(),
+            group_lookahead: default_group_lookahead(),
+            auto_create_history: default_auto_create_history(),
+            fsync_history: false,
+            shared_config_switch: false,
+            max_active_conversations: None,
+            conversation_overflow: ConversationOverflow::default(),
+            max_connections: None,
+            follow_static_symlinks: false,
+            max_ws_message_bytes: default_max_ws_message_bytes(),
+            max_chunked_message_bytes: default_max_chunked_message_bytes(),
+            debug_endpoints: false,
+            backend_adapter: default_backend_adapter(),
+            rewrite_migrated_config: false,
+            auth_enabled: false,
+            auth_token: None,
+            auth_exempt_static: false,
+            warmup_on_start: false,
         }
     }
 }
@@ -14,6 +14,64 @@ pub struct BasicMemoryAgentConfig {
     #[serde(rename = "segment_method")]
     #[serde(default = "default_segment_method")]
     pub segment_method: String, // "regex" or "pysbd"
+
+    /// Language used for sentence segmentation ("auto", "en", "zh", "ja", ...)
+    #[serde(rename = "segment_language")]
+    #[serde(default = "default_segment_language")]
+    pub segment_language: String,
+
+    /// Most images accepted in one user message
+    #[serde(default = "default_max_images")]
+    pub max_images: usize,
+
+    /// Largest decoded size of a single image, in bytes
+    #[serde(default = "default_max_image_bytes")]
+    pub max_image_bytes: usize,
+
+    /// Largest decoded size of all images in one message together, in bytes
+    #[serde(default = "default_max_total_image_bytes")]
+    pub max_total_image_bytes: usize,
+
+    /// Most files accepted in one user message
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+
+    /// Largest decoded size of a single file, in bytes
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: usize,
+
+    /// Largest decoded size of all files in one message together, in bytes
+    #[serde(default = "default_max_total_file_bytes")]
+    pub max_total_file_bytes: usize,
+
+    #[serde(default)]
+    pub prompt_guard: PromptGuardConfig,
+
+    /// Text every reply starts with, e.g. to keep it in character
+    #[serde(default)]
+    pub prefill: Option<String>,
+
+    /// Times a reply whose LLM stream breaks off is resumed from what was
+    /// already generated
+    #[serde(default = "default_stream_retries")]
+    pub stream_retries: u32,
+}
+
+/// Keeps user text from being mistaken for system instructions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptGuardConfig {
+    /// Enclose user messages in tagged blocks the system prompt explains
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Also replace phrases matching known injection patterns; off by
+    /// default since it can catch harmless text
+    #[serde(default)]
+    pub filter_injections: bool,
+
+    /// Extra case-insensitive regexes to filter, on top of the built-in ones
+    #[serde(default)]
+    pub extra_patterns: Vec<String>,
 }
 
 fn default_true() -> bool {
@@ -24,6 +82,38 @@ fn default_segment_method() -> String {
     "pysbd".to_string()
 }
 
+fn default_segment_language() -> String {
+    "auto".to_string()
+}
+
+fn default_max_images() -> usize {
+    4
+}
+
+fn default_max_image_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_max_total_image_bytes() -> usize {
+    20 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    4
+}
+
+fn default_max_file_bytes() -> usize {
+    5 * 1024 * 1024
+}
+
+fn default_max_total_file_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_stream_retries() -> u32 {
+    1
+}
+
 /// Configuration for Mem0 vector store
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Mem0VectorStoreConfig {
@@ -73,6 +163,33 @@ pub struct HumeAIConfig {
     pub idle_timeout: u32,
 }
 
+/// Configuration for an upstream Open-LLM-VTuber compatible backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyAgentConfig {
+    /// WebSocket endpoint, e.g. `ws://localhost:12393/client-ws`
+    pub url: String,
+
+    /// Sent as `Authorization: Bearer <token>` when connecting
+    #[serde(default)]
+    pub auth_token: Option<String>,
+
+    /// Seconds to wait for the next upstream message before giving up
+    #[serde(default = "default_proxy_idle_timeout")]
+    pub idle_timeout: u32,
+
+    /// Where upstream audio is saved so it can be served from `/cache`
+    #[serde(default = "default_proxy_cache_dir")]
+    pub cache_dir: String,
+}
+
+fn default_proxy_idle_timeout() -> u32 {
+    60
+}
+
+fn default_proxy_cache_dir() -> String {
+    "cache".to_string()
+}
+
 fn default_hume_host() -> String {
     "api.hume.ai".to_string()
 }
@@ -92,18 +209,28 @@ pub struct AgentSettings {
     
     #[serde(rename = "hume_ai_agent")]
     pub hume_ai_agent: Option<HumeAIConfig>,
+
+    #[serde(rename = "proxy_agent")]
+    #[serde(default)]
+    pub proxy_agent: Option<ProxyAgentConfig>,
 }
 
 /// Configuration for conversation agent
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
     #[serde(rename = "conversation_agent_choice")]
-    pub conversation_agent_choice: String, // "basic_memory_agent", "mem0_agent", "hume_ai_agent"
+    pub conversation_agent_choice: String, // "basic_memory_agent", "mem0_agent", "hume_ai_agent", "proxy_agent"
     
     #[serde(rename = "agent_settings")]
     pub agent_settings: AgentSettings,
     
     #[serde(rename = "llm_configs")]
     pub llm_configs: StatelessLLMConfigs,
+
+    /// Providers from `llm_configs` tried in order when the primary LLM
+    /// times out, is rate-limited or returns a server error
+    #[serde(rename = "fallback_llm_providers")]
+    #[serde(default)]
+    pub fallback_llm_providers: Vec<String>,
 }
 
@@ -222,5 +222,86 @@ pub struct ASRConfig {
     
     #[serde(rename = "sherpa_onnx_asr")]
     pub sherpa_onnx_asr: Option<SherpaOnnxASRConfig>,
+
+    /// Experimental: prepare the reply while the user is still speaking;
+    /// off when absent
+    #[serde(rename = "speculative")]
+    #[serde(default)]
+    pub speculative: Option<SpeculativeASRConfig>,
+}
+
+/// Settings for preparing replies from partial transcripts
+///
+/// The audio heard so far is transcribed again and again while the user
+/// speaks, which multiplies ASR work, and prepared replies that turn out
+/// wrong cost an LLM call each.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeculativeASRConfig {
+    /// How often the audio heard so far is transcribed
+    #[serde(default = "default_partial_interval_ms")]
+    pub partial_interval_ms: u64,
+
+    /// How long a partial transcript must stay the same before a reply is
+    /// prepared for it
+    #[serde(default = "default_stable_ms")]
+    pub stable_ms: u64,
+
+    /// Least word similarity (0 to 1) between the transcript a reply was
+    /// prepared for and the final one for the reply to be used
+    #[serde(default = "default_min_similarity")]
+    pub min_similarity: f32,
+}
+
+fn default_partial_interval_ms() -> u64 {
+    500
 }
 
+fn default_stable_ms() -> u64 {
+    600
+}
+
+fn default_min_similarity() -> f32 {
+    0.85
+}
+
+
+/// Language codes the ASR engines understand (Whisper's set), plus "auto"
+/// for detection
+pub const SUPPORTED_ASR_LANGUAGES: &[&str] = &[
+    "auto", "af", "am", "ar", "as", "az", "ba", "be", "bg", "bn", "bo", "br", "bs", "ca", "cs",
+    "cy", "da", "de", "el", "en", "es", "et", "eu", "fa", "fi", "fo", "fr", "gl", "gu", "ha",
+    "haw", "he", "hi", "hr", "ht", "hu", "hy", "id", "is", "it", "ja", "jw", "ka", "kk", "km",
+    "kn", "ko", "la", "lb", "ln", "lo", "lt", "lv", "mg", "mi", "mk", "ml", "mn", "mr", "ms",
+    "mt", "my", "ne", "nl", "nn", "no", "oc", "pa", "pl", "ps", "pt", "ro", "ru", "sa", "sd",
+    "si", "sk", "sl", "sn", "so", "sq", "sr", "su", "sv", "sw", "ta", "te", "tg", "th", "tk",
+    "tl", "tr", "tt", "uk", "ur", "uz", "vi", "yi", "yo", "yue", "zh",
+];
+
+/// Normalize a language code, or explain why it can't be used
+pub fn validate_asr_language(language: &str) -> anyhow::Result<String> {
+    let language = language.trim().to_lowercase();
+    if SUPPORTED_ASR_LANGUAGES.contains(&language.as_str()) {
+        Ok(language)
+    } else {
+        anyhow::bail!(
+            "Unsupported ASR language '{}'; use \"auto\" or a code such as \"en\", \"zh\" or \"ja\"",
+            language
+        )
+    }
+}
+
+impl ASRConfig {
+    /// Language configured for the active ASR model, if it has one
+    ///
+    /// "auto" is returned as-is so the service can detect the language.
+    pub fn language(&self) -> Option<String> {
+        match self.asr_model.as_str() {
+            "faster_whisper" => self.faster_whisper.as_ref()?.language.clone(),
+            "whisper_cpp" => Some(self.whisper_cpp.as_ref()?.language.clone()),
+            "fun_asr" => Some(self.fun_asr.as_ref()?.language.clone()),
+            "groq_whisper_asr" => self.groq_whisper_asr.as_ref()?.lang.clone(),
+            _ => None,
+        }
+        .filter(|language| !language.is_empty())
+    }
+}
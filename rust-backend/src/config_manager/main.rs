@@ -2,7 +2,10 @@ use serde::{Deserialize, Serialize};
 use crate::config_manager::system::SystemConfig;
 use crate::config_manager::character::CharacterConfig;
 
-/// Main configuration for the application using JSON-LD format
+/// Full configuration schema, loadable from JSON-LD or YAML
+///
+/// The server itself runs on `crate::config::Config`, which reads the same
+/// files through the same loader but keeps only the fields it uses.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(rename = "@context")]
@@ -17,11 +20,11 @@ pub struct Config {
 }
 
 impl Config {
-    /// Load configuration from JSON-LD file
+    /// Load configuration from a JSON-LD or YAML file
     pub fn load(path: &str) -> anyhow::Result<Self> {
-        use crate::config_manager::utils::{read_jsonld, validate_config};
-        let json_value = read_jsonld(path)?;
-        validate_config(&json_value)
+        use crate::config_manager::utils::{read_config_file, validate_config};
+        let value = read_config_file(path)?;
+        validate_config(&value)
     }
 }
 
@@ -0,0 +1,109 @@
+use anyhow::Result;
+use serde_json::Value;
+use tracing::info;
+
+/// Config version this build reads natively
+pub const CURRENT_CONF_VERSION: &str = "v1.2.0";
+
+/// Version assumed for configs that don't set `conf_version`
+const OLDEST_CONF_VERSION: &str = "v1.1.1";
+
+/// Upgrade step from the previous version to `to`
+struct Migration {
+    to: &'static str,
+    description: &'static str,
+    apply: fn(&mut Value),
+}
+
+/// Registered upgrade steps, oldest first
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: "v1.2.0",
+    description: "add backend_adapter and the built-in VAD settings",
+    apply: migrate_v1_2_0,
+}];
+
+/// Bring a raw config up to [`CURRENT_CONF_VERSION`]
+///
+/// Applies every migration newer than the config's `conf_version` and
+/// stamps the current version.
+///
+/// # Returns
+/// The version the config was upgraded from, or `None` if it was current
+pub fn migrate_config(config: &mut Value) -> Result<Option<String>> {
+    let version = config
+        .pointer("/system_config/conf_version")
+        .and_then(|v| v.as_str())
+        .unwrap_or(OLDEST_CONF_VERSION)
+        .to_string();
+    let parsed = parse_version(&version)
+        .ok_or_else(|| anyhow::anyhow!("Invalid system_config.conf_version: {}", version))?;
+    let current = parse_version(CURRENT_CONF_VERSION).expect("valid current version");
+
+    if parsed > current {
+        anyhow::bail!(
+            "Config version {} is newer than this backend supports ({}); update the backend or use an older config",
+            version,
+            CURRENT_CONF_VERSION
+        );
+    }
+    if parsed == current {
+        return Ok(None);
+    }
+
+    for migration in MIGRATIONS {
+        if parse_version(migration.to).is_some_and(|to| to > parsed) {
+            info!("Migrating config to {}: {}", migration.to, migration.description);
+            (migration.apply)(config);
+        }
+    }
+    if let Some(system_config) = config.get_mut("system_config").and_then(|s| s.as_object_mut()) {
+        system_config.insert("conf_version".to_string(), Value::from(CURRENT_CONF_VERSION));
+    }
+    Ok(Some(version))
+}
+
+/// Migrate a config file in place, keeping `${VAR}` placeholders as written
+///
+/// YAML files are left alone, since rewriting would drop their comments.
+pub fn rewrite_config_file(config_path: &str) -> Result<()> {
+    if super::utils::is_yaml_path(config_path) {
+        tracing::warn!("Not rewriting YAML config {}; update its conf_version by hand", config_path);
+        return Ok(());
+    }
+    let text = super::utils::load_text_file_with_guess_encoding(config_path)?;
+    let mut config: Value = serde_json::from_str(&text)?;
+    if migrate_config(&mut config)?.is_some() {
+        super::utils::save_config_value(config, std::path::Path::new(config_path))?;
+        info!("Rewrote {} as {}", config_path, CURRENT_CONF_VERSION);
+    }
+    Ok(())
+}
+
+/// Parse `v1.2.3` (the `v` and patch number are optional)
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().trim_start_matches('v').split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts.next().map_or(Some(0), |p| p.parse().ok())?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+/// Insert `value` at `key` in the object at `pointer` unless already set
+fn fill_default(config: &mut Value, pointer: &str, key: &str, value: Value) {
+    if let Some(object) = config.pointer_mut(pointer).and_then(|v| v.as_object_mut()) {
+        object.entry(key).or_insert(value);
+    }
+}
+
+fn migrate_v1_2_0(config: &mut Value) {
+    fill_default(config, "/system_config", "backend_adapter", Value::from("orphiq"));
+
+    // v1.1 configs could only use Silero, which now has to be chosen explicitly
+    fill_default(config, "/character_config/vad_config", "vad_model", Value::from("silero_vad"));
+    let simple_vad = serde_json::to_value(super::vad::SimpleVADConfig::default())
+        .expect("SimpleVADConfig serializes");
+    fill_default(config, "/character_config/vad_config", "simple_vad", simple_vad);
+}
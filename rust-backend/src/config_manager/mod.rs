@@ -10,6 +10,7 @@ pub mod tts_preprocessor;
 pub mod i18n;
 pub mod interfaces;
 pub mod utils;
+pub mod migration;
 
 pub use main::*;
 pub use system::*;
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 /// Base configuration for StatelessLLM
@@ -6,6 +8,16 @@ pub struct StatelessLLMBaseConfig {
     #[serde(rename = "interrupt_method")]
     #[serde(default = "default_interrupt_method")]
     pub interrupt_method: String, // "system" or "user"
+
+    /// Sequences that end generation, e.g. `"\nHuman:"` to keep the model
+    /// from writing the user's next line
+    #[serde(default)]
+    pub stop: Vec<String>,
+
+    /// Drop content the provider streams twice, as some do after
+    /// reconnecting; short repeats such as "very very" are kept
+    #[serde(default)]
+    pub dedupe_stream: bool,
 }
 
 fn default_interrupt_method() -> String {
@@ -18,10 +30,15 @@ pub struct OpenAICompatibleConfig {
     #[serde(flatten)]
     pub base: StatelessLLMBaseConfig,
     
+    /// Left empty for the provider's own endpoint, as the hosted providers'
+    /// entries are
     #[serde(rename = "base_url")]
+    #[serde(default)]
     pub base_url: String,
     
+    /// Ollama ignores the key, so it may be left out there
     #[serde(rename = "llm_api_key")]
+    #[serde(default = "default_llm_api_key")]
     pub llm_api_key: String,
     
     pub model: String,
@@ -34,6 +51,15 @@ pub struct OpenAICompatibleConfig {
     
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+
+    /// Headers added to every request, for gateways that need their own
+    /// (`HTTP-Referer`, `X-Title`, ...); values may use `${VAR}`
+    #[serde(default)]
+    pub extra_headers: HashMap<String, String>,
+}
+
+fn default_llm_api_key() -> String {
+    "z".to_string()
 }
 
 fn default_temperature() -> f32 {
@@ -53,6 +79,10 @@ pub struct OllamaConfig {
     #[serde(rename = "unload_at_exit")]
     #[serde(default = "default_true")]
     pub unload_at_exit: bool,
+
+    /// Use Ollama's native `/api/chat` endpoint instead of its OpenAI-compatible shim
+    #[serde(default = "default_true")]
+    pub native: bool,
 }
 
 fn default_keep_alive() -> f32 {
@@ -112,12 +142,37 @@ pub struct ClaudeConfig {
     pub base: StatelessLLMBaseConfig,
     
     #[serde(rename = "base_url")]
+    #[serde(default = "default_claude_base_url")]
     pub base_url: String,
     
     #[serde(rename = "llm_api_key")]
     pub llm_api_key: String,
     
     pub model: String,
+
+    /// Claude requires an explicit output limit
+    #[serde(default = "default_claude_max_tokens")]
+    pub max_tokens: u32,
+
+    /// Sent as the `anthropic-version` header
+    #[serde(default = "default_anthropic_version")]
+    pub anthropic_version: String,
+
+    /// Call the Messages API directly instead of going through the Python service
+    #[serde(default = "default_true")]
+    pub native: bool,
+}
+
+fn default_claude_base_url() -> String {
+    "https://api.anthropic.com".to_string()
+}
+
+fn default_claude_max_tokens() -> u32 {
+    1024
+}
+
+fn default_anthropic_version() -> String {
+    "2023-06-01".to_string()
 }
 
 /// Configuration for LlamaCpp
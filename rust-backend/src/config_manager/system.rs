@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crate::config_manager::interfaces::ServerPaths;
 
 /// System configuration settings
@@ -113,20 +113,25 @@ impl SystemConfig {
     }
 }
 
-fn find_model_json(dir: &PathBuf) -> Option<PathBuf> {
-    if let Ok(entries) = std::fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.ends_with(".model.json") {
-                        return Some(path);
-                    }
-                }
-            }
-        }
+/// Find a `.model.json` or `.model3.json` in `dir` or one of its
+/// immediate subdirectories (e.g. `runtime/`)
+pub fn find_model_json(dir: &Path) -> Option<PathBuf> {
+    let entries: Vec<PathBuf> = std::fs::read_dir(dir).ok()?.flatten().map(|e| e.path()).collect();
+    let is_model_json = |path: &Path| {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|name| name.ends_with(".model.json") || name.ends_with(".model3.json"))
+    };
+    if let Some(path) = entries.iter().find(|p| p.is_file() && is_model_json(p)) {
+        return Some(path.clone());
     }
-    None
+    entries.iter().filter(|p| p.is_dir()).find_map(|sub| {
+        std::fs::read_dir(sub)
+            .ok()?
+            .flatten()
+            .map(|e| e.path())
+            .find(|p| p.is_file() && is_model_json(p))
+    })
 }
 
 impl Default for SystemConfig {
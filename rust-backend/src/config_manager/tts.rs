@@ -1,5 +1,22 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+/// An engine's section of `tts_config`, naming the voice and language
+/// synthesis requests default to
+pub trait TTSEngineConfig: DeserializeOwned {
+    fn voice(&self) -> Option<String>;
+
+    fn language(&self) -> Option<String> {
+        None
+    }
+}
+
+/// `value` unless it is blank, as engines leave unused fields empty
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
 /// Configuration for Azure TTS service
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AzureTTSConfig {
@@ -41,6 +58,299 @@ pub struct MeloTTSConfig {
     pub speed: f32,
 }
 
+/// Configuration for GPT-SoVITS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GPTSoVITSConfig {
+    #[serde(default)]
+    pub api_url: String,
+
+    /// Language of the text to speak
+    #[serde(default)]
+    pub text_lang: String,
+
+    /// Reference audio that sets the voice
+    #[serde(default)]
+    pub ref_audio_path: String,
+
+    #[serde(default)]
+    pub prompt_lang: String,
+
+    /// Transcript of the reference audio
+    #[serde(default)]
+    pub prompt_text: String,
+
+    #[serde(default)]
+    pub text_split_method: String,
+
+    #[serde(default)]
+    pub media_type: String,
+}
+
+/// Configuration for CosyVoice and CosyVoice2
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CosyVoiceConfig {
+    #[serde(default)]
+    pub client_url: String,
+
+    /// Inference mode, e.g. a pretrained voice or 3-second cloning
+    #[serde(default)]
+    pub mode_checkbox_group: String,
+
+    /// Pretrained voice
+    #[serde(default)]
+    pub sft_dropdown: String,
+
+    #[serde(default)]
+    pub prompt_text: String,
+
+    #[serde(default)]
+    pub prompt_wav_upload_url: String,
+
+    #[serde(default)]
+    pub prompt_wav_record_url: String,
+
+    #[serde(default)]
+    pub instruct_text: String,
+
+    #[serde(default)]
+    pub seed: i64,
+
+    #[serde(default)]
+    pub api_name: String,
+
+    /// CosyVoice2 only
+    #[serde(default)]
+    pub stream: bool,
+
+    /// CosyVoice2 only
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+}
+
+/// Configuration for the Fish Audio API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FishAPITTSConfig {
+    #[serde(default)]
+    pub api_key: String,
+
+    /// Voice model to speak with
+    #[serde(default)]
+    pub reference_id: String,
+
+    #[serde(default = "default_fish_latency")]
+    pub latency: String,
+
+    #[serde(default = "default_fish_base_url")]
+    pub base_url: String,
+}
+
+/// Configuration for OpenAI TTS and compatible servers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAITTSConfig {
+    #[serde(default)]
+    pub model: String,
+
+    #[serde(default)]
+    pub voice: String,
+
+    #[serde(default)]
+    pub api_key: String,
+
+    #[serde(default)]
+    pub base_url: String,
+
+    #[serde(default = "default_openai_file_extension")]
+    pub file_extension: String,
+}
+
+/// Configuration for Coqui TTS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoquiTTSConfig {
+    #[serde(default)]
+    pub model_name: String,
+
+    /// Reference audio for multi-speaker models
+    #[serde(default)]
+    pub speaker_wav: String,
+
+    #[serde(default)]
+    pub language: String,
+
+    #[serde(default)]
+    pub device: String,
+}
+
+/// Configuration for an XTTS API server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XTTSConfig {
+    #[serde(default)]
+    pub api_url: String,
+
+    /// Speaker known to the server
+    #[serde(default)]
+    pub speaker_wav: String,
+
+    #[serde(default)]
+    pub language: String,
+}
+
+/// Configuration for ElevenLabs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElevenLabsTTSConfig {
+    #[serde(default)]
+    pub api_key: String,
+
+    #[serde(default)]
+    pub voice_id: String,
+
+    #[serde(default)]
+    pub model_id: String,
+
+    #[serde(default)]
+    pub output_format: String,
+}
+
+/// Configuration for MiniMax
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinimaxTTSConfig {
+    #[serde(default)]
+    pub group_id: String,
+
+    #[serde(default)]
+    pub api_key: String,
+
+    #[serde(default)]
+    pub model: String,
+
+    #[serde(default)]
+    pub voice_id: String,
+}
+
+/// Configuration for SiliconFlow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiliconFlowTTSConfig {
+    #[serde(default)]
+    pub api_url: String,
+
+    #[serde(default)]
+    pub api_key: String,
+
+    #[serde(default)]
+    pub default_model: String,
+
+    #[serde(default)]
+    pub default_voice: String,
+
+    #[serde(default)]
+    pub response_format: String,
+}
+
+impl TTSEngineConfig for AzureTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.voice)
+    }
+}
+
+impl TTSEngineConfig for BarkTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.voice)
+    }
+}
+
+impl TTSEngineConfig for EdgeTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.voice)
+    }
+}
+
+impl TTSEngineConfig for MeloTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.speaker)
+    }
+
+    fn language(&self) -> Option<String> {
+        non_empty(&self.language)
+    }
+}
+
+impl TTSEngineConfig for GPTSoVITSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.ref_audio_path)
+    }
+
+    fn language(&self) -> Option<String> {
+        non_empty(&self.text_lang)
+    }
+}
+
+impl TTSEngineConfig for CosyVoiceConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.sft_dropdown)
+    }
+}
+
+impl TTSEngineConfig for FishAPITTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.reference_id)
+    }
+}
+
+impl TTSEngineConfig for OpenAITTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.voice)
+    }
+}
+
+impl TTSEngineConfig for CoquiTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.speaker_wav)
+    }
+
+    fn language(&self) -> Option<String> {
+        non_empty(&self.language)
+    }
+}
+
+impl TTSEngineConfig for XTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.speaker_wav)
+    }
+
+    fn language(&self) -> Option<String> {
+        non_empty(&self.language)
+    }
+}
+
+impl TTSEngineConfig for ElevenLabsTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.voice_id)
+    }
+}
+
+impl TTSEngineConfig for MinimaxTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.voice_id)
+    }
+}
+
+impl TTSEngineConfig for SiliconFlowTTSConfig {
+    fn voice(&self) -> Option<String> {
+        non_empty(&self.default_voice)
+    }
+}
+
+fn default_fish_latency() -> String {
+    "balanced".to_string()
+}
+
+fn default_fish_base_url() -> String {
+    "https://api.fish.audio".to_string()
+}
+
+fn default_openai_file_extension() -> String {
+    "mp3".to_string()
+}
+
 fn default_device_auto() -> String {
     "auto".to_string()
 }
@@ -49,12 +359,56 @@ fn default_speed() -> f32 {
     1.0
 }
 
+fn default_true() -> bool {
+    true
+}
+
+fn default_tts_queue_depth() -> usize {
+    4
+}
+
 /// Configuration for Text-to-Speech
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TTSConfig {
     #[serde(rename = "tts_model")]
     pub tts_model: String,
     
+    /// When false, the TTS stage is skipped and responses are delivered as text only
+    #[serde(rename = "tts_enabled")]
+    #[serde(default = "default_true")]
+    pub tts_enabled: bool,
+
+    /// Sentences that may queue up for synthesis before the LLM output is held back
+    #[serde(rename = "tts_queue_depth")]
+    #[serde(default = "default_tts_queue_depth")]
+    pub tts_queue_depth: usize,
+
+    /// Stream audio chunks to the client while synthesizing (edge_tts, azure_tts)
+    #[serde(rename = "stream_audio")]
+    #[serde(default)]
+    pub stream_audio: bool,
+
+    /// Send synthesized audio base64-encoded inside the `audio` payload
+    /// instead of as a `/cache` path, for clients that can't reach the
+    /// static route. Costs about a third more bandwidth than the file
+    /// itself and the whole sentence arrives in one WebSocket message.
+    #[serde(rename = "embed_audio")]
+    #[serde(default)]
+    pub embed_audio: bool,
+
+    /// Syntheses allowed at once across all clients; defaults to 1 for
+    /// local engines and 4 for hosted ones
+    #[serde(rename = "max_concurrent_syntheses")]
+    #[serde(default)]
+    pub max_concurrent_syntheses: Option<usize>,
+
+    /// RVC model the synthesized audio is converted with before playback;
+    /// no conversion when unset. Streaming is turned off while it is set,
+    /// since conversion works on whole files.
+    #[serde(rename = "rvc_model")]
+    #[serde(default)]
+    pub rvc_model: Option<String>,
+    
     #[serde(rename = "azure_tts")]
     pub azure_tts: Option<serde_json::Value>,
     
@@ -67,8 +421,8 @@ pub struct TTSConfig {
     #[serde(rename = "melo_tts")]
     pub melo_tts: Option<serde_json::Value>,
     
-    // Add other TTS configs as Option<serde_json::Value> for flexibility
-    // Full implementations would have specific structs for each
+    // Other engines' sections; they are read through their structs (see
+    // `TTSEngineConfig`) and passed on to the Python service as is
     #[serde(flatten)]
     pub other_configs: Option<serde_json::Value>,
 }
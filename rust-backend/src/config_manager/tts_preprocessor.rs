@@ -65,6 +65,22 @@ pub struct TTSPreprocessorConfig {
     #[serde(rename = "ignore_angle_brackets")]
     #[serde(default = "default_true")]
     pub ignore_angle_brackets: bool,
+
+    /// Speak numbers, currency and percentages as English words ("$5" → "five dollars")
+    #[serde(default)]
+    pub normalize_numbers: bool,
+
+    /// Read numbers, dates, times and ordinals the way the TTS language
+    /// does, e.g. "3/4/2024" as "March fourth, twenty twenty-four" in English
+    /// and "二零二四年三月四日" in Chinese. Takes over from `normalize_numbers`
+    /// for English and Chinese; other languages are left to it.
+    #[serde(default)]
+    pub locale_numbers: bool,
+
+    /// What to do with emoji: "keep" passes them through, "remove" drops
+    /// them and "describe" reads common ones aloud ("👍" → "thumbs up")
+    #[serde(default = "default_emoji_handling")]
+    pub emoji_handling: String,
     
     #[serde(rename = "translator_config")]
     pub translator_config: TranslatorConfig,
@@ -74,3 +90,7 @@ fn default_true() -> bool {
     true
 }
 
+fn default_emoji_handling() -> String {
+    "keep".to_string()
+}
+
@@ -7,8 +7,65 @@ use tracing::{error, debug};
 
 use crate::config_manager::main::Config;
 
+/// Read a configuration file, choosing JSON-LD or YAML by extension
+///
+/// `.yaml` / `.yml` files are parsed as YAML; anything else as JSON-LD.
+pub fn read_config_file(config_path: &str) -> Result<Value> {
+    if is_yaml_path(config_path) {
+        read_yaml(config_path)
+    } else {
+        read_jsonld(config_path)
+    }
+}
+
+/// Recursively merge `overlay` into `base`; objects are merged key by key,
+/// anything else in `overlay` replaces the value in `base`
+pub fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Whether a path has a YAML extension
+pub fn is_yaml_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "yaml" | "yml"))
+}
+
 /// Read JSON-LD configuration file with environment variable substitution
 pub fn read_jsonld(config_path: &str) -> Result<Value> {
+    let content = read_config_text(config_path)?;
+
+    // Parse JSON-LD
+    let json_value: Value = serde_json::from_str(&content)?;
+    
+    // Extract @context if present (JSON-LD feature)
+    // For now, we'll just parse as regular JSON and ignore @context
+    Ok(json_value)
+}
+
+/// Read YAML configuration file with environment variable substitution
+pub fn read_yaml(config_path: &str) -> Result<Value> {
+    let content = read_config_text(config_path)?;
+    let value: Value = serde_yaml::from_str(&content)?;
+    Ok(value)
+}
+
+/// Read a configuration file's text and substitute `${VAR_NAME}` with the
+/// environment variable's value; unknown variables are left as written
+fn read_config_text(config_path: &str) -> Result<String> {
     if !Path::new(config_path).exists() {
         anyhow::bail!("Configuration file not found: {}", config_path);
     }
@@ -18,19 +75,12 @@ pub fn read_jsonld(config_path: &str) -> Result<Value> {
         anyhow::bail!("Failed to read configuration file: {}", config_path);
     }
 
-    // Replace environment variables: ${VAR_NAME}
     let pattern = Regex::new(r"\$\{(\w+)\}").unwrap();
     let content = pattern.replace_all(&content, |caps: &regex::Captures| {
         let var_name = caps.get(1).unwrap().as_str();
         std::env::var(var_name).unwrap_or_else(|_| caps.get(0).unwrap().as_str().to_string())
     });
-
-    // Parse JSON-LD
-    let json_value: Value = serde_json::from_str(&content)?;
-    
-    // Extract @context if present (JSON-LD feature)
-    // For now, we'll just parse as regular JSON and ignore @context
-    Ok(json_value)
+    Ok(content.into_owned())
 }
 
 /// Validate configuration data against the Config model
@@ -89,8 +139,11 @@ fn read_file_with_encoding(file_path: &str, encoding: &str) -> Result<String> {
 
 /// Save configuration to JSON-LD file
 pub fn save_config(config: &Config, config_path: &Path) -> Result<()> {
-    let mut config_data = serde_json::to_value(config)?;
-    
+    save_config_value(serde_json::to_value(config)?, config_path)
+}
+
+/// Save raw configuration data to a JSON-LD file, adding the `@context`
+pub fn save_config_value(mut config_data: Value, config_path: &Path) -> Result<()> {
     // Add @context for JSON-LD
     let mut context_obj = serde_json::Map::new();
     context_obj.insert("@vocab".to_string(), serde_json::Value::String("https://vaidol.example.org/config#".to_string()));
@@ -99,7 +152,7 @@ pub fn save_config(config: &Config, config_path: &Path) -> Result<()> {
     let context = serde_json::Value::Object(context_obj);
     
     if let serde_json::Value::Object(ref mut obj) = config_data {
-        obj.insert("@context".to_string(), context);
+        obj.entry("@context").or_insert(context);
     }
 
     let json_string = serde_json::to_string_pretty(&config_data)?;
@@ -129,8 +182,11 @@ pub fn scan_config_alts_directory(config_alts_dir: &str) -> Result<Vec<serde_jso
         for entry in fs::read_dir(config_dir)? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("jsonld") {
-                if let Ok(config) = read_jsonld(path.to_str().unwrap()) {
+            let path_str = path.to_str().unwrap_or_default();
+            let is_config = path.extension().and_then(|s| s.to_str()) == Some("jsonld")
+                || is_yaml_path(path_str);
+            if path.is_file() && is_config {
+                if let Ok(config) = read_config_file(path_str) {
                     let conf_name = config
                         .pointer("/character_config/conf_name")
                         .and_then(|v| v.as_str())
@@ -25,13 +25,84 @@ pub struct SileroVADConfig {
     pub smoothing_window: i32,
 }
 
+/// Configuration for the built-in energy VAD
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimpleVADConfig {
+    /// Frames louder than this (dBFS) count as speech
+    #[serde(default = "default_energy_threshold_db")]
+    pub energy_threshold_db: f32,
+
+    #[serde(default = "default_frame_ms")]
+    pub frame_ms: u32,
+
+    /// Consecutive loud frames needed before speech starts
+    #[serde(default = "default_required_hits")]
+    pub required_hits: u32,
+
+    /// Consecutive quiet frames that end speech
+    #[serde(default = "default_hangover_frames")]
+    pub hangover_frames: u32,
+}
+
+fn default_energy_threshold_db() -> f32 {
+    -40.0
+}
+
+fn default_frame_ms() -> u32 {
+    30
+}
+
+fn default_required_hits() -> u32 {
+    3
+}
+
+fn default_hangover_frames() -> u32 {
+    20
+}
+
+impl Default for SimpleVADConfig {
+    fn default() -> Self {
+        Self {
+            energy_threshold_db: default_energy_threshold_db(),
+            frame_ms: default_frame_ms(),
+            required_hits: default_required_hits(),
+            hangover_frames: default_hangover_frames(),
+        }
+    }
+}
+
 /// Configuration for Voice Activity Detection
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VADConfig {
     #[serde(rename = "vad_model")]
-    pub vad_model: String, // "silero_vad"
+    pub vad_model: String, // "simple_vad" (in process) or "silero_vad" (Python service)
     
     #[serde(rename = "silero_vad")]
     pub silero_vad: Option<SileroVADConfig>,
+
+    #[serde(default)]
+    pub simple_vad: Option<SimpleVADConfig>,
+
+    /// Let clients interrupt the AI by talking over it (clients can toggle
+    /// this with `set-barge-in`)
+    #[serde(default)]
+    pub barge_in: bool,
+
+    /// Level (dBFS) speech must reach to barge in; set above the speech
+    /// threshold so the AI's own voice leaking into the mic doesn't count
+    #[serde(default = "default_barge_in_threshold_db")]
+    pub barge_in_threshold_db: f32,
+
+    /// How long the user must keep talking before the AI is interrupted
+    #[serde(default = "default_barge_in_min_speech_ms")]
+    pub barge_in_min_speech_ms: u32,
+}
+
+fn default_barge_in_threshold_db() -> f32 {
+    -30.0
+}
+
+fn default_barge_in_min_speech_ms() -> u32 {
+    300
 }
 
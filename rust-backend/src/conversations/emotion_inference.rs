@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use tracing::{debug, warn};
+
+use crate::agent::output_types::{Actions, TimedExpression};
+use crate::agent::StatelessLLMInterface;
+use crate::config::{EmotionInferenceConfig, EmotionInferenceMethod};
+
+/// Cue words for the built-in classifier, with the emotion map keys each
+/// category may go by, most specific first
+const LEXICON: &[(&[&str], &[&str])] = &[
+    (
+        &["joy", "happiness", "excitement", "optimism"],
+        &[
+            "happy", "glad", "great", "love", "wonderful", "yay", "awesome", "fun", "haha",
+            "delighted", "excited", "enjoy", "fantastic", "nice", "thank you", "thanks", "can't wait",
+        ],
+    ),
+    (
+        &["sadness", "remorse"],
+        &[
+            "sad", "sorry", "unfortunately", "miss you", "lonely", "cry", "crying", "tears",
+            "upset", "hurts", "regret", "heartbroken", "disappointed",
+        ],
+    ),
+    (
+        &["anger"],
+        &[
+            "angry", "mad", "furious", "hate", "annoying", "annoyed", "how dare",
+            "unacceptable", "ridiculous", "stop it",
+        ],
+    ),
+    (
+        &["fear"],
+        &[
+            "scared", "afraid", "terrified", "worried", "nervous", "scary", "frightening",
+            "anxious", "creepy",
+        ],
+    ),
+    (
+        &["surprise", "realization"],
+        &[
+            "wow", "whoa", "no way", "unbelievable", "surprised", "surprising", "oh my",
+            "really", "what",
+        ],
+    ),
+    (&["disgust"], &["gross", "disgusting", "eww", "yuck", "nasty"]),
+    (
+        &["smirk", "amusement"],
+        &["heh", "hehe", "of course", "obviously", "just kidding", "teasing", "silly"],
+    ),
+];
+
+/// Emotions inferred for recent sentences, oldest forgotten first
+#[derive(Default)]
+pub struct EmotionCache {
+    entries: Mutex<CacheEntries>,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    expressions: HashMap<String, Option<i32>>,
+    /// Sentences in the order they were added
+    order: VecDeque<String>,
+}
+
+impl EmotionCache {
+    /// The expression cached for `sentence`; `Some(None)` when it was found
+    /// to have no particular emotion
+    pub fn get(&self, sentence: &str) -> Option<Option<i32>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.expressions.get(sentence).copied()
+    }
+
+    pub fn insert(&self, sentence: &str, expression: Option<i32>, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries
+            .expressions
+            .insert(sentence.to_string(), expression)
+            .is_none()
+        {
+            entries.order.push_back(sentence.to_string());
+        }
+        while entries.order.len() > capacity {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.expressions.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.expressions.clear();
+        entries.order.clear();
+    }
+}
+
+/// Picks an expression from the model's emotion map for sentences the LLM
+/// didn't tag
+pub struct EmotionInference {
+    config: EmotionInferenceConfig,
+    emo_map: BTreeMap<String, i32>,
+    cache: Arc<EmotionCache>,
+    llm: Option<Arc<dyn StatelessLLMInterface>>,
+    timed: bool,
+}
+
+impl EmotionInference {
+    /// # Arguments
+    /// * `config` - The character's emotion inference settings
+    /// * `emo_map` - Emotion names to expression indices, from the model
+    /// * `cache` - Shared across turns, so repeated sentences aren't
+    ///   classified again
+    pub fn new(
+        config: EmotionInferenceConfig,
+        emo_map: BTreeMap<String, i32>,
+        cache: Arc<EmotionCache>,
+    ) -> Self {
+        Self {
+            config,
+            emo_map,
+            cache,
+            llm: None,
+            timed: false,
+        }
+    }
+
+    /// LLM asked with the `llm` method; without one that method infers
+    /// nothing
+    pub fn with_llm(mut self, llm: Arc<dyn StatelessLLMInterface>) -> Self {
+        self.llm = Some(llm);
+        self
+    }
+
+    /// Also schedule the inferred expression at the start of the sentence,
+    /// for characters with `timed_expressions`
+    pub fn with_timed_expressions(mut self, timed: bool) -> Self {
+        self.timed = timed;
+        self
+    }
+
+    /// Fill in an expression for `sentence` if `actions` has none
+    pub async fn apply(&self, sentence: &str, actions: &mut Actions) {
+        if actions.expressions.is_some() || !sentence.chars().any(char::is_alphanumeric) {
+            return;
+        }
+        let Some(expression) = self.infer(sentence.trim()).await else {
+            return;
+        };
+        actions.expressions = Some(vec![serde_json::Value::from(expression)]);
+        if self.timed {
+            actions.timed_expressions = Some(vec![TimedExpression {
+                offset_ms: 0,
+                expression: serde_json::Value::from(expression),
+            }]);
+        }
+    }
+
+    /// The expression for `sentence`, from the cache when it was seen before
+    pub async fn infer(&self, sentence: &str) -> Option<i32> {
+        if let Some(expression) = self.cache.get(sentence) {
+            return expression;
+        }
+        let emotion = match self.config.method {
+            EmotionInferenceMethod::Lexicon => Some(self.classify(sentence)),
+            EmotionInferenceMethod::Llm => self.ask_llm(sentence).await,
+        };
+        // Failed LLM requests aren't cached so the sentence is tried again
+        let emotion = emotion?;
+        let expression = emotion.as_ref().and_then(|e| self.emo_map.get(e).copied());
+        debug!("Inferred emotion {:?} for: {}", emotion, sentence);
+        self.cache.insert(sentence, expression, self.config.cache_size);
+        expression
+    }
+
+    /// The emotion whose cue words appear most often, if it is in the map
+    fn classify(&self, sentence: &str) -> Option<String> {
+        let words: Vec<String> = sentence
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric() && c != '\'')
+            .filter(|w| !w.is_empty())
+            .map(str::to_string)
+            .collect();
+        let text = format!(" {} ", words.join(" "));
+
+        let mut best: Option<(&str, usize)> = None;
+        for (names, cues) in LEXICON {
+            let Some(name) = names.iter().find(|n| self.emo_map.contains_key(**n)) else {
+                continue;
+            };
+            let hits = cues
+                .iter()
+                .filter(|cue| text.contains(&format!(" {} ", cue)))
+                .count();
+            if hits > 0 && best.is_none_or(|(_, most)| hits > most) {
+                best = Some((name, hits));
+            }
+        }
+        best.map(|(name, _)| name.to_string())
+    }
+
+    /// Ask the LLM to pick from the emotion map
+    ///
+    /// # Returns
+    /// None if the request failed, otherwise the emotion named in the reply
+    async fn ask_llm(&self, sentence: &str) -> Option<Option<String>> {
+        let llm = self.llm.as_ref()?;
+        let emotions = self.emo_map.keys().cloned().collect::<Vec<_>>().join(", ");
+        let system = format!(
+            "You label the emotion of a line spoken by a character. Reply with \
+             exactly one word from this list and nothing else: {}",
+            emotions
+        );
+        let mut message = HashMap::new();
+        message.insert("role".to_string(), serde_json::json!("user"));
+        message.insert("content".to_string(), serde_json::json!(sentence));
+
+        let reply = async {
+            let mut tokens = llm.chat_completion(vec![message], Some(&system)).await?;
+            let mut reply = String::new();
+            while let Some(token) = tokens.next().await {
+                reply.push_str(&token?);
+            }
+            Ok::<_, anyhow::Error>(reply)
+        };
+        match reply.await {
+            Ok(reply) => {
+                let reply = reply.to_lowercase();
+                Some(
+                    reply
+                        .split(|c: char| !c.is_alphanumeric())
+                        .find(|word| self.emo_map.contains_key(*word))
+                        .map(str::to_string),
+                )
+            }
+            Err(e) => {
+                warn!("Emotion inference request failed: {}", e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::agent::output_types::Actions;
+use crate::conversations::{AudioPayload, WebSocketSend};
+use crate::state::AppState;
+use crate::utils::stream_audio::encode_audio_file;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Progress {
+    Waiting,
+    Playing,
+    Stopped,
+}
+
+/// A client's thinking filler, waiting out its delay or playing
+pub struct PendingFiller {
+    handle: JoinHandle<()>,
+    /// Shared with the task, so it can't send the filler after it was
+    /// stopped
+    progress: Arc<Mutex<Progress>>,
+}
+
+/// Schedule the character's `thinking_filler` for a turn that just started
+/// thinking
+pub fn start(state: &AppState, client_uid: &str, sender: &WebSocketSend) {
+    let Some(config) = state.config().character_config.thinking_filler.clone() else {
+        return;
+    };
+    // Clients with TTS off get the expression only
+    let tts_enabled = state
+        .client_contexts
+        .get(client_uid)
+        .is_some_and(|c| c.value().tts_enabled);
+    let audio_path = config.audio.filter(|_| tts_enabled);
+    if audio_path.is_none() && config.expression.is_none() {
+        return;
+    }
+
+    let progress = Arc::new(Mutex::new(Progress::Waiting));
+    let task_progress = progress.clone();
+    let task_uid = client_uid.to_string();
+    let sender = sender.clone();
+    let handle = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(config.delay_ms)).await;
+
+        let audio = audio_path.as_deref().and_then(|path| {
+            encode_audio_file(path)
+                .inspect_err(|e| warn!("Failed to read thinking filler {}: {}", path, e))
+                .ok()
+        });
+        if audio.is_none() && config.expression.is_none() {
+            return;
+        }
+        let mut actions = Actions::new();
+        actions.expressions = config.expression.map(|e| vec![e]);
+        let payload = AudioPayload {
+            actions: Some(actions),
+            filler: true,
+            ..AudioPayload::new(audio)
+        };
+
+        let mut progress = task_progress.lock().unwrap();
+        if *progress == Progress::Waiting {
+            debug!("Playing the thinking filler for {}", task_uid);
+            *progress = Progress::Playing;
+            let _ = sender.send(payload.to_string());
+        }
+    });
+
+    let filler = PendingFiller { handle, progress };
+    if let Some(previous) = state.thinking_fillers.insert(client_uid.to_string(), filler) {
+        previous.cancel();
+    }
+}
+
+/// Stop the client's filler, telling the client to cut it off if it is
+/// playing
+pub fn stop(state: &AppState, client_uid: &str, sender: &WebSocketSend) {
+    if cancel(state, client_uid) {
+        let _ = sender.send(
+            serde_json::json!({
+                "type": "control",
+                "text": "stop-filler"
+            })
+            .to_string(),
+        );
+    }
+}
+
+/// Stop the client's filler without telling the client
+///
+/// # Returns
+/// Whether it had been sent
+pub fn cancel(state: &AppState, client_uid: &str) -> bool {
+    state
+        .thinking_fillers
+        .remove(client_uid)
+        .is_some_and(|(_, filler)| filler.cancel())
+}
+
+impl PendingFiller {
+    fn cancel(self) -> bool {
+        self.handle.abort();
+        let mut progress = self.progress.lock().unwrap();
+        let playing = *progress == Progress::Playing;
+        *progress = Progress::Stopped;
+        playing
+    }
+}
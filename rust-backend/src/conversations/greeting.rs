@@ -0,0 +1,46 @@
+use tracing::info;
+
+use crate::config::Greeting;
+use crate::conversations::group_conversation::generate_reply;
+use crate::conversations::single_conversation::speak_reply;
+use crate::conversations::{TurnSignals, WebSocketSend};
+use crate::state::AppState;
+
+/// Speak the character's configured greeting to a newly connected client
+///
+/// A prompted greeting is generated by the client's agent and then rewound,
+/// so the prompt doesn't linger in its memory. The greeting is not stored
+/// in chat history.
+pub async fn process_greeting(
+    state: &AppState,
+    client_uid: &str,
+    signals: TurnSignals,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let config = state.config();
+    let character_config = &config.character_config;
+    let Some(greeting) = &character_config.greeting else {
+        return Ok(());
+    };
+
+    let text = match greeting {
+        Greeting::Text(text) => text.clone(),
+        Greeting::Prompt { prompt } => {
+            let prompt = prompt
+                .replace("{character_name}", &character_config.character_name)
+                .replace("{human_name}", &character_config.human_name);
+            let reply = generate_reply(state, client_uid, prompt).await;
+            if let Some(context) = state.client_contexts.get(client_uid).map(|c| c.value().clone()) {
+                state.get_or_create_agent(&context)?.lock().await.rewind_last_turn();
+            }
+            reply?
+        }
+    };
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    info!("Greeting {}", client_uid);
+
+    speak_reply(state, client_uid, &text, signals, sender).await;
+    Ok(())
+}
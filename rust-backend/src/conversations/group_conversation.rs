@@ -1,36 +1,457 @@
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{info, warn};
+
+use crate::agent::agents::GenerationInterrupted;
+use crate::agent::input_types::{BatchInput, ImageData, TextData, TextSource};
+use crate::agent::transformers::{display_processor, rewrite, ThinkTagParser};
+use crate::chat_history;
+use crate::conversations::single_conversation::{reply_tts_manager, sentence_jobs, store_reply};
+use crate::conversations::types::{GroupConversationState, WebSocketSend};
 use crate::state::AppState;
-use crate::conversations::types::GroupConversationState;
-use serde_json::Value;
-use tracing::info;
+
+/// A member's reply, or why it couldn't be generated
+type GeneratedReply = (String, anyhow::Result<String>);
+
+/// Aborts a spawned task when the conversation that started it ends
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Unregisters the group's conversation however it ends, including when
+/// the whole turn is cancelled
+struct Registration {
+    state: AppState,
+    group_id: String,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.state.group_conversations.remove(&self.group_id);
+    }
+}
+
+/// Replies being generated ahead of the members' turns to speak
+struct Lookahead {
+    replies: mpsc::UnboundedReceiver<GeneratedReply>,
+    /// One permit per reply the generator may start
+    permits: Arc<Semaphore>,
+    /// Members whose reply was started, in speaking order
+    started: Arc<Mutex<Vec<String>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Lookahead {
+    /// Stop generating and list the members whose replies were started
+    /// after the first `spoken`
+    async fn cancel(&mut self, spoken: usize) -> Vec<String> {
+        self.task.abort();
+        let _ = (&mut self.task).await;
+        let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+        let spoken = spoken.min(started.len());
+        started.split_off(spoken)
+    }
+}
+
+impl Drop for Lookahead {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
 
 /// Process group conversation
+///
+/// Each member's AI replies once, in queue order, and every reply is
+/// spoken to the whole group. With `group_lookahead` above 0, the next
+/// members' replies are generated (LLM only) while the current speaker
+/// talks, and synthesized when their turn comes. Interrupting a speaker
+/// discards replies that were generated but not spoken.
 pub async fn process_group_conversation(
     state: &AppState,
     initiator_uid: &str,
     group_members: &[String],
     user_input: &str,
-    _images: Option<&Vec<Value>>,
+    _images: Option<&Vec<ImageData>>,
     session_emoji: &str,
-    _sender: &tokio::sync::mpsc::UnboundedSender<String>,
+    _sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     info!("Processing group conversation with {} members", group_members.len());
+    let config = state.config();
+    let character_config = &config.character_config;
+    let lookahead = config.system_config.group_lookahead;
 
     // Initialize group conversation state
-    let group_id = format!("group_{}", initiator_uid);
-    let conversation_state = GroupConversationState::new(
+    let group_id = state
+        .chat_groups
+        .read()
+        .await
+        .get_client_group(initiator_uid)
+        .unwrap_or_else(|| format!("group_{}", initiator_uid));
+    record_group(state, &group_id, group_members);
+    let mut conversation_state = GroupConversationState::new(
         group_id.clone(),
         session_emoji.to_string(),
         group_members.to_vec(),
     );
+    if !user_input.is_empty() {
+        conversation_state
+            .conversation_history
+            .push(format!("{}: {}", character_config.human_name, user_input));
+        store_human_input(state, initiator_uid, &character_config.human_name, user_input)?;
+    }
+    conversation_state.advance_turn();
+    // Registered so `group-interrupt` can reach the current speaker
+    state.group_conversations.insert(group_id.clone(), conversation_state);
+    let registration = Registration {
+        state: state.clone(),
+        group_id: group_id.clone(),
+    };
+
+    let group_sender = group_sender(state, group_members);
+    let _ = group_sender.send(
+        serde_json::json!({
+            "type": "control",
+            "text": "conversation-chain-start"
+        })
+        .to_string(),
+    );
+
+    let result = take_turns(state, &group_id, group_members.len(), lookahead, &group_sender).await;
+
+    let _ = group_sender.send(
+        serde_json::json!({
+            "type": "control",
+            "text": "conversation-chain-end"
+        })
+        .to_string(),
+    );
+    drop(registration);
+    info!("Group conversation {} completed", group_id);
+
+    result
+}
+
+/// Let `turns` members speak, restarting the look-ahead whenever a speaker
+/// is interrupted
+async fn take_turns(
+    state: &AppState,
+    group_id: &str,
+    turns: usize,
+    lookahead: usize,
+    group_sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let mut spoken = 0;
+    while spoken < turns {
+        let Some(order) = upcoming_speakers(state, group_id, turns - spoken) else {
+            break;
+        };
+        let mut generated = start_lookahead(state, group_id, order, lookahead);
+        let mut spoken_this_run = 0;
 
-    // TODO: Process group conversation logic
-    // - Initialize contexts for each member
-    // - Process input
-    // - Generate responses for each AI participant
-    // - Broadcast messages
+        let interrupted = loop {
+            if spoken == turns {
+                break false;
+            }
+            let Some((speaker, reply)) = generated.replies.recv().await else {
+                break false;
+            };
+            spoken += 1;
+            // `group-interrupt` skipped the member while its reply was being
+            // generated, so the reply is discarded along with the rest
+            if !is_current_speaker(state, group_id, &speaker) {
+                break true;
+            }
+            spoken_this_run += 1;
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(e) => {
+                    warn!("No reply from {} in group {}: {}", speaker, group_id, e);
+                    advance(state, group_id);
+                    generated.permits.add_permits(1);
+                    continue;
+                }
+            };
 
-    info!("Group conversation {} completed", conversation_state.group_id);
+            let displayed = Arc::new(Mutex::new(String::new()));
+            let turn = tokio::spawn(speak_reply(
+                state.clone(),
+                speaker.clone(),
+                reply.clone(),
+                displayed.clone(),
+                group_sender.clone(),
+            ));
+            let _turn_guard = AbortOnDrop(turn.abort_handle());
+            if let Some(mut conversation) = state.group_conversations.get_mut(group_id) {
+                conversation.current_turn = Some(turn.abort_handle());
+            }
 
+            match turn.await {
+                Ok(result) => {
+                    result?;
+                    commit_reply(state, group_id, &speaker, &reply);
+                    advance(state, group_id);
+                    generated.permits.add_permits(1);
+                }
+                // `group-interrupt` already moved on to the next speaker
+                Err(e) if e.is_cancelled() => {
+                    let heard = displayed.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    commit_reply(state, group_id, &speaker, &format!("{}...", heard));
+                    break true;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        if !interrupted {
+            break;
+        }
+        // Later replies were written without knowing the speaker was cut off
+        for member in generated.cancel(spoken_this_run).await {
+            info!("Discarding unspoken reply from {} in group {}", member, group_id);
+            if let Some(agent) = state.agents.get(&member).map(|a| a.value().clone()) {
+                agent.lock().await.rewind_last_turn();
+            }
+        }
+    }
     Ok(())
 }
 
+/// The current speaker and those queued after it, up to `count`
+fn upcoming_speakers(state: &AppState, group_id: &str, count: usize) -> Option<Vec<String>> {
+    let conversation = state.group_conversations.get(group_id)?;
+    let speaker = conversation.current_speaker_uid.clone()?;
+    Some(
+        std::iter::once(speaker)
+            .chain(conversation.group_queue.iter().cloned())
+            .take(count)
+            .collect(),
+    )
+}
+
+fn is_current_speaker(state: &AppState, group_id: &str, uid: &str) -> bool {
+    state
+        .group_conversations
+        .get(group_id)
+        .is_some_and(|c| c.current_speaker_uid.as_deref() == Some(uid))
+}
+
+fn advance(state: &AppState, group_id: &str) {
+    if let Some(mut conversation) = state.group_conversations.get_mut(group_id) {
+        conversation.advance_turn();
+    }
+}
+
+/// Generate the replies of `order` one after another in the background
+///
+/// The first reply starts right away and up to `lookahead` more may be
+/// generated before the turns ahead of them end; each finished turn releases
+/// one more.
+fn start_lookahead(state: &AppState, group_id: &str, order: Vec<String>, lookahead: usize) -> Lookahead {
+    let (replies_tx, replies) = mpsc::unbounded_channel();
+    let permits = Arc::new(Semaphore::new(1 + lookahead));
+    let started = Arc::new(Mutex::new(Vec::new()));
+
+    // Written as each reply is generated; only spoken replies reach the
+    // group's shared history
+    let (mut history, mut memory_index) = state
+        .group_conversations
+        .get(group_id)
+        .map(|c| (c.conversation_history.clone(), c.memory_index.clone()))
+        .unwrap_or_default();
+
+    let name = state.config().character_config.character_name.clone();
+    let task_state = state.clone();
+    let task_permits = permits.clone();
+    let task_started = started.clone();
+    let task = tokio::spawn(async move {
+        for speaker in order {
+            let Ok(permit) = task_permits.acquire().await else {
+                break;
+            };
+            permit.forget();
+
+            let from = memory_index.get(&speaker).copied().unwrap_or(0).min(history.len());
+            let input = history[from..].join("\n");
+            task_started
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(speaker.clone());
+            let reply = generate_reply(&task_state, &speaker, input).await;
+            if let Ok(text) = &reply {
+                history.push(format!("{}: {}", name, text));
+                memory_index.insert(speaker.clone(), history.len());
+            }
+            if replies_tx.send((speaker, reply)).is_err() {
+                break;
+            }
+        }
+    });
+
+    Lookahead {
+        replies,
+        permits,
+        started,
+        task,
+    }
+}
+
+/// Ask the member's agent to reply to what was said since its last turn
+///
+/// # Returns
+/// The reply without think blocks, emotion keywords included
+pub async fn generate_reply(state: &AppState, speaker: &str, input: String) -> anyhow::Result<String> {
+    let context = state
+        .client_contexts
+        .get(speaker)
+        .map(|c| c.value().clone())
+        .ok_or_else(|| anyhow::anyhow!("No context for client {}", speaker))?;
+    let agent = state.get_or_create_agent(&context)?;
+    let mut agent = agent.lock().await;
+    agent.reset_interrupt();
+
+    let mut batch_input = BatchInput::new(vec![TextData {
+        source: TextSource::Input,
+        content: input,
+        from_name: None,
+    }]);
+    batch_input.metadata = Some(serde_json::json!({ "sampling": context.sampling.to_options() }));
+
+    let mut reply = String::new();
+    let mut think_parser = ThinkTagParser::new();
+    let mut outputs = agent.chat(batch_input).await;
+    while let Some(output) = outputs.next().await {
+        let output = match output {
+            // The whole sentences already given are the reply
+            Err(e) if e.is::<GenerationInterrupted>() => {
+                warn!("Reply from {} cut short: {}", speaker, e);
+                break;
+            }
+            output => output?,
+        };
+        match (output.as_sentence(), output.as_audio()) {
+            (Some(sentence), _) => reply.push_str(&think_parser.push(&sentence.display_text.text).visible),
+            (None, Some(audio)) => reply.push_str(&think_parser.push(&audio.display_text.text).visible),
+            (None, None) => {}
+        }
+    }
+    reply.push_str(&think_parser.finish().visible);
+    Ok(reply)
+}
+
+/// Synthesize the speaker's reply and send it to the whole group
+async fn speak_reply(
+    state: AppState,
+    speaker: String,
+    reply: String,
+    displayed: Arc<Mutex<String>>,
+    group_sender: WebSocketSend,
+) -> anyhow::Result<()> {
+    let config = state.config();
+    let character_config = &config.character_config;
+    let live2d_model = state.live2d_model();
+    let live2d_model = live2d_model.as_deref();
+    let tts_enabled = state.client_contexts.get(&speaker).is_some_and(|c| c.tts_enabled);
+    let tts_engine = if tts_enabled {
+        state.tts_engine_for(&speaker, None)
+    } else {
+        None
+    };
+    let tts_language = tts_engine.as_ref().and_then(|e| e.language());
+    // Every member gets the same audio; WAV plays anywhere, so it is used
+    // as soon as one member asks for it
+    let members = state.chat_groups.read().await.get_group_members(&speaker);
+    let audio_format = members
+        .iter()
+        .find_map(|m| state.client_contexts.get(m).and_then(|c| c.audio_format));
+    let tts_manager = reply_tts_manager(&state, character_config, tts_engine)
+        .with_preferred_format(audio_format)
+        .with_display_log(displayed);
+    let (jobs, queued_jobs) = tts_manager.channel();
+
+    let produce = async move {
+        for job in sentence_jobs(&reply, character_config, live2d_model, tts_language.as_deref()) {
+            if jobs.send(job).await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::join!(produce, tts_manager.run(queued_jobs, &group_sender));
+
+    for message_type in ["backend-synth-complete", "force-new-message"] {
+        let _ = group_sender.send(serde_json::json!({ "type": message_type }).to_string());
+    }
+    Ok(())
+}
+
+/// Add a spoken reply to the group's history and the speaker's chat history
+fn commit_reply(state: &AppState, group_id: &str, speaker: &str, reply: &str) {
+    let config = state.config();
+    let live2d_model = state.live2d_model();
+    let live2d_model = live2d_model.as_deref();
+    if let Some(mut conversation) = state.group_conversations.get_mut(group_id) {
+        conversation
+            .conversation_history
+            .push(format!("{}: {}", config.character_config.character_name, reply));
+        let spoken = conversation.conversation_history.len();
+        conversation.memory_index.insert(speaker.to_string(), spoken);
+    }
+    if let Some(context) = state.client_contexts.get(speaker).map(|c| c.value().clone()) {
+        let reply = rewrite(reply, config.character_config.display_rewrites());
+        let text = display_processor(live2d_model, &reply).text;
+        if let Err(e) = store_reply(&context, &config, &text) {
+            warn!("Failed to store group reply from {}: {}", speaker, e);
+        }
+    }
+}
+
+/// Note the group in each member's history, so reopening it restores the
+/// group
+fn record_group(state: &AppState, group_id: &str, members: &[String]) {
+    let fsync = state.config().system_config.fsync_history;
+    for member in members {
+        let Some(context) = state.client_contexts.get(member).map(|c| c.value().clone()) else {
+            continue;
+        };
+        let Some(history_uid) = &context.history_uid else {
+            continue;
+        };
+        if let Err(e) = chat_history::save_group(&context.conf_uid, history_uid, group_id, members, fsync) {
+            warn!("Failed to record group {} in history {}: {}", group_id, history_uid, e);
+        }
+    }
+}
+
+fn store_human_input(state: &AppState, client_uid: &str, human_name: &str, text: &str) -> anyhow::Result<()> {
+    let Some(context) = state.client_contexts.get(client_uid).map(|c| c.value().clone()) else {
+        return Ok(());
+    };
+    if let Some(history_uid) = &context.history_uid {
+        let fsync = state.config().system_config.fsync_history;
+        chat_history::store_message(&context.conf_uid, history_uid, "human", text, Some(human_name), None, fsync)?;
+    }
+    Ok(())
+}
+
+/// A sender that delivers every message to each member of the group
+fn group_sender(state: &AppState, members: &[String]) -> WebSocketSend {
+    let (sender, mut outbound) = mpsc::unbounded_channel::<String>();
+    let sender = WebSocketSend::new(sender);
+    let state = state.clone();
+    let members = members.to_vec();
+    tokio::spawn(async move {
+        while let Some(text) = outbound.recv().await {
+            for member in &members {
+                if let Some(member_sender) = state.client_senders.get(member) {
+                    let _ = member_sender.send(text.clone());
+                }
+            }
+        }
+    });
+    sender
+}
@@ -1,7 +1,13 @@
+use crate::agent::input_types::{BatchInput, TextData, TextSource, CONTINUE_PROMPT};
+use crate::chat_history;
 use crate::state::AppState;
+use crate::conversations::{TurnSignals, WebSocketSend};
 use crate::conversations::single_conversation::process_single_conversation;
 use crate::conversations::group_conversation::process_group_conversation;
+use crate::conversations::greeting::process_greeting;
+use crate::conversations::speculative;
 use serde_json::Value;
+use std::sync::Arc;
 use tracing::info;
 
 /// Handle conversation triggers
@@ -10,41 +16,127 @@ pub async fn handle_conversation_trigger(
     client_uid: &str,
     msg_type: &str,
     data: &Value,
-    sender: &tokio::sync::mpsc::UnboundedSender<String>,
+    signals: TurnSignals,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    let user_input = match msg_type {
-        "ai-speak-signal" => {
-            let _ = sender.send(serde_json::json!({
-                "type": "full-text",
-                "text": "AI wants to speak something..."
-            }).to_string());
-            String::new()
-        }
-        "text-input" => {
-            data.get("text")
-                .and_then(|v| v.as_str())
-                .unwrap_or("")
-                .to_string()
-        }
-        _ => {
-            // mic-audio-end - get from buffer
-            if let Some(buffer) = state.audio_buffers.get(client_uid) {
-                // TODO: Convert audio buffer to text via ASR
-                String::new()
-            } else {
+    if msg_type == "greeting" {
+        return process_greeting(state, client_uid, signals, sender).await;
+    }
+    // Only the end of the utterance it was prepared for can use a prepared reply
+    if msg_type != "mic-audio-end" {
+        speculative::discard(state, client_uid).await;
+    }
+
+    let (batch_input, input_timestamp) = if msg_type == "regenerate" {
+        // The user said the same thing at the same time; only the reply changes
+        rewind_last_turn(state, client_uid).await?
+    } else if msg_type == "edit-last-message" {
+        let text = data
+            .get("text")
+            .and_then(|v| v.as_str())
+            .filter(|t| !t.trim().is_empty())
+            .ok_or_else(|| anyhow::anyhow!("edit-last-message requires a non-empty 'text'"))?;
+        let (mut batch_input, original_timestamp) = rewind_last_turn(state, client_uid).await?;
+
+        // Replace the spoken/typed input, keeping clipboard text and images
+        batch_input.texts.retain(|t| t.source != TextSource::Input);
+        batch_input.texts.insert(0, TextData {
+            source: TextSource::Input,
+            content: text.to_string(),
+            from_name: Some(state.config().character_config.human_name.clone()),
+        });
+
+        let timestamp = original_timestamp.filter(|_| state.config().system_config.keep_edited_timestamp);
+        (batch_input, timestamp)
+    } else if msg_type == "continue" {
+        let mut batch_input = crate::conversations::utils::create_batch_input(
+            CONTINUE_PROMPT,
+            &serde_json::json!({}),
+            &state.config().character_config.human_name,
+        )?;
+        batch_input.continuation = true;
+        (batch_input, None)
+    } else {
+        let user_input = match msg_type {
+            "ai-speak-signal" => {
+                let _ = sender.send(serde_json::json!({
+                    "type": "full-text",
+                    "text": "AI wants to speak something..."
+                }).to_string());
                 String::new()
             }
+            "text-input" => {
+                data.get("text")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string()
+            }
+            _ => {
+                // mic-audio-end - transcribe the buffered audio
+                let prepared = speculative::take(state, client_uid);
+                let audio_data = state
+                    .audio_buffers
+                    .get_mut(client_uid)
+                    .map(|mut buffer| std::mem::take(buffer.value_mut()))
+                    .unwrap_or_default();
+
+                if audio_data.is_empty() {
+                    info!("No audio data in buffer for {}", client_uid);
+                    if let Some(prepared) = prepared {
+                        prepared.cancel().await;
+                    }
+                    return Ok(());
+                }
+                let language = crate::asr::client_language(state, client_uid);
+                let text = crate::asr::transcribe(state, audio_data, language).await?;
+
+                let _ = sender.send(serde_json::json!({
+                    "type": "user-input-transcription",
+                    "text": text
+                }).to_string());
+
+                if let Some(prepared) = prepared {
+                    if let Some(reply) = speculative::resolve(state, client_uid, prepared, &text).await {
+                        return speculative::commit(state, client_uid, &text, &reply, signals, sender).await;
+                    }
+                }
+                text
+            }
+        };
+
+        let mut batch_input = crate::conversations::utils::create_batch_input(
+            &user_input,
+            data,
+            &state.config().character_config.human_name,
+        )?;
+        if msg_type == "text-input" {
+            batch_input.prefill = data.get("prefill").and_then(|v| v.as_str()).map(|p| p.to_string());
         }
+        (batch_input, None)
     };
-
-    let images = data.get("images").and_then(|v| v.as_array());
+    let user_input = batch_input
+        .texts
+        .iter()
+        .filter(|t| t.source == TextSource::Input)
+        .map(|t| t.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    // Sent to an attached character, validated before the turn was spawned
+    let character_id = data.get("character_id").and_then(|v| v.as_str());
+    // A continuation belongs to the turn before it, which stays the one to
+    // redo; redoing only ever applies to the client's own character
+    if !batch_input.continuation && character_id.is_none() {
+        if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+            context.value_mut().last_input = Some(Arc::new(batch_input.clone()));
+        }
+    }
     let session_emoji = "🎭"; // TODO: Random emoji
 
     // Check if in group
     let groups = state.chat_groups.read().await;
     let group_members = groups.get_group_members(client_uid);
 
-    if group_members.len() > 1 {
+    if group_members.len() > 1 && character_id.is_none() {
         // Group conversation
         drop(groups);
         process_group_conversation(
@@ -52,7 +144,7 @@ pub async fn handle_conversation_trigger(
             client_uid,
             &group_members,
             &user_input,
-            images,
+            batch_input.images.as_ref(),
             session_emoji,
             sender,
         )
@@ -63,9 +155,11 @@ pub async fn handle_conversation_trigger(
         process_single_conversation(
             state,
             client_uid,
-            &user_input,
-            images,
-            session_emoji,
+            batch_input,
+            input_timestamp.as_deref(),
+            data.get("tts_engine").and_then(|v| v.as_str()).filter(|_| msg_type == "text-input"),
+            character_id,
+            signals,
             sender,
         )
         .await?;
@@ -74,3 +168,43 @@ pub async fn handle_conversation_trigger(
     Ok(())
 }
 
+/// Undo the client's last turn in agent memory and chat history so it can be
+/// answered again
+///
+/// # Returns
+/// The input of the undone turn, taken from the client's cache or else from
+/// the history, and the time it was originally stored
+async fn rewind_last_turn(
+    state: &AppState,
+    client_uid: &str,
+) -> anyhow::Result<(BatchInput, Option<String>)> {
+    let context = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| c.value().clone())
+        .ok_or_else(|| anyhow::anyhow!("No context for client {}", client_uid))?;
+    if context.last_input.is_none() && context.history_uid.is_none() {
+        anyhow::bail!("There is no previous turn to redo");
+    }
+
+    let removed = match &context.history_uid {
+        Some(history_uid) => chat_history::truncate_last_turn(&context.conf_uid, history_uid, state.config().system_config.fsync_history)?,
+        None => None,
+    };
+    if let Some(agent) = state.agents.get(client_uid).map(|a| a.value().clone()) {
+        agent.lock().await.rewind_last_turn();
+    }
+
+    let input = match (context.last_input, &removed) {
+        (Some(input), _) => (*input).clone(),
+        (None, Some(message)) => BatchInput::new(vec![TextData {
+            source: TextSource::Input,
+            content: message.content.clone(),
+            from_name: Some(state.config().character_config.human_name.clone()),
+        }]),
+        (None, None) => anyhow::bail!("There is no previous turn to redo"),
+    };
+
+    info!("Redoing last turn for {}", client_uid);
+    Ok((input, removed.map(|m| m.timestamp)))
+}
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::{ConversationOverflow, SystemConfig};
+
+/// Caps how many conversations run at once across all clients, so load
+/// doesn't pile up on the Python service
+pub struct ConversationLimiter {
+    /// None when unlimited
+    permits: Option<Arc<Semaphore>>,
+    limit: Option<usize>,
+    overflow: ConversationOverflow,
+    active: Arc<AtomicUsize>,
+    waiting: AtomicUsize,
+}
+
+/// A running conversation's slot, released when dropped, including when
+/// the task is aborted
+pub struct ConversationPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for ConversationPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a caller as queued until it gets a slot or gives up
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ConversationLimiter {
+    /// # Arguments
+    /// * `limit` - Conversations allowed at once; None for no limit
+    /// * `overflow` - What happens to conversations over the limit
+    pub fn new(limit: Option<usize>, overflow: ConversationOverflow) -> Self {
+        let limit = limit.map(|l| l.max(1));
+        Self {
+            permits: limit.map(|l| Arc::new(Semaphore::new(l))),
+            limit,
+            overflow,
+            active: Arc::new(AtomicUsize::new(0)),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn for_config(system_config: &SystemConfig) -> Self {
+        Self::new(
+            system_config.max_active_conversations,
+            system_config.conversation_overflow,
+        )
+    }
+
+    fn permit(&self, permit: Option<OwnedSemaphorePermit>) -> ConversationPermit {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ConversationPermit {
+            _permit: permit,
+            active: self.active.clone(),
+        }
+    }
+
+    /// Take a slot if one is free
+    pub fn try_acquire(&self) -> Option<ConversationPermit> {
+        match &self.permits {
+            None => Some(self.permit(None)),
+            Some(permits) => permits
+                .clone()
+                .try_acquire_owned()
+                .ok()
+                .map(|p| self.permit(Some(p))),
+        }
+    }
+
+    /// Wait for a slot
+    pub async fn acquire(&self) -> ConversationPermit {
+        let Some(permits) = &self.permits else {
+            return self.permit(None);
+        };
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.waiting);
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("conversation semaphore is never closed");
+        self.permit(Some(permit))
+    }
+
+    pub fn overflow(&self) -> ConversationOverflow {
+        self.overflow
+    }
+
+    /// Conversations allowed at once; None when unlimited
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Conversations running now
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Conversations waiting for a slot
+    pub fn queue_depth(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
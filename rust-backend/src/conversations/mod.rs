@@ -3,7 +3,14 @@ pub mod utils;
 pub mod handler;
 pub mod single_conversation;
 pub mod group_conversation;
+pub mod greeting;
+pub mod tts_manager;
+pub mod limiter;
+pub mod emotion_inference;
+pub mod speculative;
+pub mod filler;
 
 pub use types::*;
 pub use handler::*;
+pub use limiter::ConversationLimiter;
 
@@ -1,49 +1,543 @@
-use crate::state::AppState;
-use serde_json::Value;
-use tracing::info;
+use crate::agent::agents::{AgentInterface, GenerationInterrupted};
+use crate::agent::input_types::{BatchInput, TextSource};
+use crate::agent::transformers::{
+    actions_extractor, display_processor, rewrite, timed_actions_extractor, tts_filter, ThinkSplit,
+    ThinkTagParser,
+};
+use crate::chat_history;
+use crate::conversations::tts_manager::{TTSJob, TTSTaskManager};
+use crate::config::{CharacterConfig, Config, EmotionInferenceMethod};
+use crate::conversations::emotion_inference::EmotionInference;
+use crate::conversations::{TurnSignals, WebSocketSend};
+use crate::live2d_model::Live2DModel;
+use crate::state::{AppState, ClientContext, ClientType, ConversationState};
+use crate::utils::sentence_divider::{split_sentences_with_language, SegmentLanguage};
+use crate::tts::TTSInterface;
+use futures::StreamExt;
+use std::sync::Arc;
+use tracing::{info, warn};
 
 /// Process a single-user conversation turn
+///
+/// `input_timestamp` is recorded for the user input in the history instead of
+/// the current time, for turns that are being redone. A `continuation`
+/// input extends the last reply in the history instead of adding a turn,
+/// unless there is no reply to extend. Setting
+/// `signals.stop_audio` silences the rest of the reply without cutting it
+/// short. `tts_engine` overrides the client's TTS engine for this turn.
+/// With `character_id`, the character the client attached under that id
+/// answers instead of the client's own, with its own agent and voice; the
+/// turn is not saved to the history.
+#[allow(clippy::too_many_arguments)]
 pub async fn process_single_conversation(
     state: &AppState,
     client_uid: &str,
-    user_input: &str,
-    _images: Option<&Vec<Value>>,
-    _session_emoji: &str,
-    sender: &tokio::sync::mpsc::UnboundedSender<String>,
+    mut batch_input: BatchInput,
+    input_timestamp: Option<&str>,
+    tts_engine: Option<&str>,
+    character_id: Option<&str>,
+    signals: TurnSignals,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     info!("Processing single conversation for {}", client_uid);
 
+    let mut context = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| c.value().clone())
+        .ok_or_else(|| anyhow::anyhow!("No context for client {}", client_uid))?;
+    if character_id.is_some() {
+        // The history is the client's own character's memory, so attached
+        // characters' turns stay out of it
+        context.history_uid = None;
+    }
+    let character = character_id
+        .map(|id| state.attached_character(client_uid, id).map(|c| (id, c)))
+        .transpose()?;
+    let config = character.as_ref().map_or_else(|| state.config(), |(_, c)| c.config.clone());
+    let character_config = &config.character_config;
+    let text_only = context.client_type == ClientType::Text;
+
     // Send conversation start signals
+    if !text_only {
+        let _ = sender.send(serde_json::json!({
+            "type": "control",
+            "text": "conversation-chain-start"
+        }).to_string());
+    }
+
+    let user_input = batch_input
+        .texts
+        .iter()
+        .filter(|t| t.source == TextSource::Input)
+        .map(|t| t.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let continuation = batch_input.continuation;
+    if let Some(history_uid) = &context.history_uid {
+        // The request to continue is not something the user said
+        if !user_input.is_empty() && !continuation {
+            chat_history::store_message_at(
+                &context.conf_uid,
+                history_uid,
+                "human",
+                &user_input,
+                Some(&character_config.human_name),
+                None,
+                input_timestamp,
+                config.system_config.fsync_history,
+            )?;
+        }
+    }
+
+    let agent = match &character {
+        Some((id, character)) => state.get_or_create_character_agent(client_uid, id, character)?,
+        None => state.get_or_create_agent(&context)?,
+    };
+    let mut agent = agent.lock().await;
+    agent.reset_interrupt();
+
+    batch_input.metadata = Some(serde_json::json!({ "sampling": context.sampling.to_options() }));
+    let live2d_model = state.live2d_model();
+    let live2d_model = live2d_model.as_deref();
+
+    if text_only {
+        let full_response = stream_text_reply(
+            state,
+            client_uid,
+            &mut **agent,
+            batch_input,
+            live2d_model,
+            character_config,
+            sender,
+        )
+        .await?;
+        drop(agent);
+
+        let reply = display_processor(
+            live2d_model,
+            &rewrite(&full_response, character_config.display_rewrites()),
+        )
+        .text;
+        store_turn_reply(&context, &config, &reply, continuation)?;
+        let _ = sender.send(serde_json::json!({
+            "type": "text-done",
+            "text": reply
+        }).to_string());
+        return Ok(());
+    }
+
+    let tts_engine = match &character {
+        _ if !context.tts_enabled => None,
+        Some((_, character)) => character.tts_engine.clone(),
+        None => state.tts_engine_for(client_uid, tts_engine),
+    };
+    let tts_language = tts_engine.as_ref().and_then(|e| e.language());
+    let tts_manager = reply_tts_manager(state, character_config, tts_engine)
+        .with_preferred_format(context.audio_format)
+        .with_stop_signal(signals.stop_audio)
+        .with_display_log(signals.displayed);
+    let (jobs, queued_jobs) = tts_manager.channel();
+
+    // The producer waits on the bounded queue whenever TTS falls behind.
+    // Both halves run in this task, so aborting it on interrupt drops the
+    // queue along with every pending job.
+    let produce = async {
+        let jobs = jobs;
+        let mut full_response = String::new();
+        let mut think_parser = ThinkTagParser::new();
+        let mut outputs = agent.chat(batch_input).await;
+        let mut interruption = None;
+        let mut finished = false;
+        while !finished {
+            let split = match outputs.next().await {
+                // Said once the sentences before it have been spoken
+                Some(Err(e)) if e.is::<GenerationInterrupted>() => {
+                    interruption = Some(e);
+                    finished = true;
+                    think_parser.finish()
+                }
+                Some(output) => {
+                    let output = output?;
+                    if let Some(audio) = output.as_audio() {
+                        state.set_conversation_state(client_uid, ConversationState::Speaking, sender);
+                        full_response.push_str(&audio.display_text.text);
+                        let mut job = TTSJob::new(
+                            String::new(),
+                            audio.display_text.clone(),
+                            audio.actions.clone(),
+                        );
+                        job.audio_path = Some(audio.audio_path.clone());
+                        if jobs.send(job).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let Some(sentence_output) = output.as_sentence() else {
+                        continue;
+                    };
+                    think_parser.push(&sentence_output.display_text.text)
+                }
+                // Flush text held back in case it started a tag
+                None => {
+                    finished = true;
+                    think_parser.finish()
+                }
+            };
+            send_thinking(&split, character_config.show_thinking, sender);
+            let text = &split.visible;
+            if text.trim().is_empty() {
+                continue;
+            }
+            state.set_conversation_state(client_uid, ConversationState::Speaking, sender);
+            full_response.push_str(text);
+
+            for job in sentence_jobs(text, character_config, live2d_model, tts_language.as_deref()) {
+                if jobs.send(job).await.is_err() {
+                    break;
+                }
+            }
+        }
+        Ok::<_, anyhow::Error>((full_response, interruption))
+    };
+
+    let (produced, _) = tokio::join!(produce, tts_manager.run(queued_jobs, sender));
+    let (full_response, interruption) = produced?;
+    drop(agent);
+    if let Some(e) = interruption {
+        generation_interrupted(client_uid, &e, sender);
+    }
+
+    let _ = sender.send(serde_json::json!({
+        "type": "backend-synth-complete"
+    }).to_string());
+    let _ = sender.send(serde_json::json!({
+        "type": "force-new-message"
+    }).to_string());
+
+    store_turn_reply(
+        &context,
+        &config,
+        &display_processor(
+            live2d_model,
+            &rewrite(&full_response, character_config.display_rewrites()),
+        )
+        .text,
+        continuation,
+    )?;
+
+    // Send conversation end signal
     let _ = sender.send(serde_json::json!({
         "type": "control",
-        "text": "conversation-chain-start"
+        "text": "conversation-chain-end"
     }).to_string());
 
-    // Call Python agent service
-    let request = crate::python_service::AgentRequest {
-        messages: vec![crate::python_service::Message {
-            role: "user".to_string(),
-            content: user_input.to_string(),
-        }],
-        context: None,
+    Ok(())
+}
+
+/// TTS manager for a spoken reply, set up from the character's TTS config
+pub fn reply_tts_manager(
+    state: &AppState,
+    character_config: &CharacterConfig,
+    tts_engine: Option<Arc<dyn TTSInterface>>,
+) -> TTSTaskManager {
+    let tts_config = character_config.tts_config.as_ref();
+    let queue_depth = tts_config.map_or(4, |c| c.tts_queue_depth);
+    let tts_manager = TTSTaskManager::new(tts_engine, queue_depth)
+        .with_in_use(state.audio_in_use.clone())
+        .with_embedded_audio(tts_config.is_some_and(|c| c.embed_audio))
+        .with_limiter(state.tts_limiter.clone())
+        .with_partial_text(true);
+    let tts_manager = match emotion_inference(state, character_config) {
+        Some(inference) => tts_manager.with_emotion_inference(Arc::new(inference)),
+        None => tts_manager,
     };
+    match tts_config.and_then(|c| c.rvc_model.clone()).filter(|m| !m.is_empty()) {
+        Some(model) => tts_manager.with_voice_conversion(state.python_service.clone(), model),
+        None => tts_manager,
+    }
+}
+
+/// Emotion inference for untagged sentences, if the character has it on and
+/// the Live2D model has emotions to choose from
+fn emotion_inference(state: &AppState, character_config: &CharacterConfig) -> Option<EmotionInference> {
+    let config = character_config.emotion_inference.clone()?;
+    let emo_map = state.live2d_model()?.emo_map.clone();
+    if emo_map.is_empty() {
+        return None;
+    }
 
-    let response = state.python_service.chat(request).await?;
+    let method = config.method;
+    let provider = config.llm_provider.clone();
+    let inference = EmotionInference::new(config, emo_map, state.emotion_cache.clone())
+        .with_timed_expressions(character_config.timed_expressions);
+    if method != EmotionInferenceMethod::Llm {
+        return Some(inference);
+    }
+    match state.standalone_llm(&state.config(), provider.as_deref()) {
+        Ok(llm) => Some(inference.with_llm(llm)),
+        Err(e) => {
+            warn!("Emotion inference disabled, no LLM available: {}", e);
+            None
+        }
+    }
+}
+
+/// Speak a reply that is already complete as one conversation chain, for
+/// greetings and replies prepared ahead of time
+pub async fn speak_reply(
+    state: &AppState,
+    client_uid: &str,
+    text: &str,
+    signals: TurnSignals,
+    sender: &WebSocketSend,
+) {
+    speak_text(state, client_uid, text, None, signals, sender).await
+}
+
+/// Speak `text` as one conversation chain, as [`speak_reply`] does
+///
+/// `expressions` replace those found in the text and are shown with its
+/// first sentence. A text-only client gets the text as `text-done`, without
+/// expressions or TTS.
+pub async fn speak_text(
+    state: &AppState,
+    client_uid: &str,
+    text: &str,
+    expressions: Option<Vec<serde_json::Value>>,
+    signals: TurnSignals,
+    sender: &WebSocketSend,
+) {
+    let config = state.config();
+    let character_config = &config.character_config;
+    let live2d_model = state.live2d_model();
+    let live2d_model = live2d_model.as_deref();
+    let text_only = state
+        .client_contexts
+        .get(client_uid)
+        .is_some_and(|c| c.client_type == ClientType::Text);
+    if text_only {
+        state.set_conversation_state(client_uid, ConversationState::Speaking, sender);
+        let reply = display_processor(
+            live2d_model,
+            &rewrite(text, character_config.display_rewrites()),
+        )
+        .text;
+        let _ = sender.send(serde_json::json!({
+            "type": "text-done",
+            "text": reply
+        }).to_string());
+        return;
+    }
 
-    // Send response
     let _ = sender.send(serde_json::json!({
-        "type": "full-text",
-        "text": response.text
+        "type": "control",
+        "text": "conversation-chain-start"
     }).to_string());
+    state.set_conversation_state(client_uid, ConversationState::Speaking, sender);
 
-    // TODO: Process TTS, expressions, etc.
+    let (tts_enabled, audio_format) = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| (c.tts_enabled, c.audio_format))
+        .unwrap_or_default();
+    let tts_engine = if tts_enabled {
+        state.tts_engine_for(client_uid, None)
+    } else {
+        None
+    };
+    let tts_language = tts_engine.as_ref().and_then(|e| e.language());
+    let tts_manager = reply_tts_manager(state, character_config, tts_engine)
+        .with_preferred_format(audio_format)
+        .with_stop_signal(signals.stop_audio)
+        .with_display_log(signals.displayed);
+    let (jobs, queued_jobs) = tts_manager.channel();
 
-    // Send conversation end signal
+    let produce = async move {
+        let mut sentences = sentence_jobs(text, character_config, live2d_model, tts_language.as_deref());
+        if let Some(expressions) = expressions {
+            for (i, job) in sentences.iter_mut().enumerate() {
+                job.actions.expressions = (i == 0).then(|| expressions.clone());
+                job.actions.timed_expressions = None;
+            }
+        }
+        for job in sentences {
+            if jobs.send(job).await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::join!(produce, tts_manager.run(queued_jobs, sender));
+
+    for message_type in ["backend-synth-complete", "force-new-message"] {
+        let _ = sender.send(serde_json::json!({ "type": message_type }).to_string());
+    }
     let _ = sender.send(serde_json::json!({
         "type": "control",
         "text": "conversation-chain-end"
     }).to_string());
+}
+
+/// Split reply text into sentences to speak, as the Python pipeline does,
+/// each with its expressions and the character's display name
+///
+/// `tts_language` is the language of the engine that will speak them.
+pub fn sentence_jobs(
+    text: &str,
+    character_config: &CharacterConfig,
+    live2d_model: Option<&Live2DModel>,
+    tts_language: Option<&str>,
+) -> Vec<TTSJob> {
+    let language = SegmentLanguage::resolve(character_config.segment_language(), text);
+    split_sentences_with_language(text, language)
+        .into_iter()
+        .filter_map(|sentence| {
+            let sentence = rewrite(&sentence, character_config.display_rewrites());
+            // Expressions are extracted even when TTS is off so the avatar still emotes
+            let actions = if character_config.timed_expressions {
+                timed_actions_extractor(
+                    live2d_model,
+                    &sentence,
+                    character_config.speech_chars_per_second,
+                )
+            } else {
+                actions_extractor(live2d_model, &sentence)
+            };
+            let mut display_text = display_processor(live2d_model, &sentence);
+            if display_text.text.is_empty() && actions.expressions.is_none() {
+                return None;
+            }
+            display_text.name = Some(character_config.character_name.clone());
+            display_text.avatar = character_config.avatar.clone();
+            let tts_text = tts_filter(
+                &rewrite(&display_text.text, character_config.tts_rewrites()),
+                character_config.tts_preprocessor_config.as_ref(),
+                tts_language,
+            );
+            Some(TTSJob::new(tts_text, display_text, actions))
+        })
+        .collect()
+}
 
+/// Forward the agent's reply to a text-only client as `text-delta` messages,
+/// skipping sentence splitting, expressions and TTS
+///
+/// # Returns
+/// The full reply, emotion keywords included and think blocks removed
+async fn stream_text_reply(
+    state: &AppState,
+    client_uid: &str,
+    agent: &mut dyn AgentInterface,
+    batch_input: BatchInput,
+    live2d_model: Option<&Live2DModel>,
+    character_config: &CharacterConfig,
+    sender: &WebSocketSend,
+) -> anyhow::Result<String> {
+    let mut full_response = String::new();
+    let mut think_parser = ThinkTagParser::new();
+    let mut outputs = agent.chat(batch_input).await;
+    let mut finished = false;
+    while !finished {
+        let split = match outputs.next().await {
+            Some(Err(e)) if e.is::<GenerationInterrupted>() => {
+                generation_interrupted(client_uid, &e, sender);
+                finished = true;
+                think_parser.finish()
+            }
+            Some(output) => {
+                let output = output?;
+                match (output.as_sentence(), output.as_audio()) {
+                    (Some(sentence), _) => think_parser.push(&sentence.display_text.text),
+                    (None, Some(audio)) => think_parser.push(&audio.display_text.text),
+                    (None, None) => continue,
+                }
+            }
+            None => {
+                finished = true;
+                think_parser.finish()
+            }
+        };
+        send_thinking(&split, character_config.show_thinking, sender);
+        let text = split.visible;
+        if text.is_empty() {
+            continue;
+        }
+        state.set_conversation_state(client_uid, ConversationState::Speaking, sender);
+        full_response.push_str(&text);
+
+        // Not trimmed, so consecutive deltas join up
+        let text = rewrite(&text, character_config.display_rewrites());
+        let delta = match live2d_model {
+            Some(model) => model.remove_emotion_keywords(&text),
+            None => text,
+        };
+        if !delta.is_empty() {
+            let _ = sender.send(serde_json::json!({
+                "type": "text-delta",
+                "text": delta
+            }).to_string());
+        }
+    }
+    Ok(full_response)
+}
+
+/// Tell the client the reply broke off, so it stops waiting for the rest;
+/// what was said so far ends the turn as usual
+fn generation_interrupted(client_uid: &str, error: &anyhow::Error, sender: &WebSocketSend) {
+    warn!("Reply to {} cut short: {}", client_uid, error);
+    let _ = sender.send(serde_json::json!({
+        "type": "control",
+        "text": "generation-interrupted"
+    }).to_string());
+}
+
+/// Forward reasoning from a think block as it arrives
+///
+/// Reasoning is dropped unless `show_thinking` is set.
+fn send_thinking(split: &ThinkSplit, show_thinking: bool, sender: &WebSocketSend) {
+    if !show_thinking || (split.thinking.is_empty() && !split.thinking_done) {
+        return;
+    }
+    let _ = sender.send(serde_json::json!({
+        "type": "thinking",
+        "text": split.thinking,
+        "done": split.thinking_done
+    }).to_string());
+}
+
+/// Record the AI's reply in the client's current history, if any
+pub fn store_reply(context: &ClientContext, config: &Config, reply: &str) -> anyhow::Result<()> {
+    let character_config = &config.character_config;
+    if let Some(history_uid) = &context.history_uid {
+        chat_history::store_message(
+            &context.conf_uid,
+            history_uid,
+            "ai",
+            reply,
+            Some(&character_config.character_name),
+            character_config.avatar.as_deref(),
+            config.system_config.fsync_history,
+        )?;
+    }
     Ok(())
 }
 
+/// Record the reply of a turn, extending the previous reply in place when
+/// the turn continued it
+fn store_turn_reply(
+    context: &ClientContext,
+    config: &Config,
+    reply: &str,
+    continuation: bool,
+) -> anyhow::Result<()> {
+    if continuation {
+        if let Some(history_uid) = &context.history_uid {
+            let fsync = config.system_config.fsync_history;
+            if chat_history::extend_last_reply(&context.conf_uid, history_uid, reply, fsync)? {
+                return Ok(());
+            }
+        }
+    }
+    store_reply(context, config, reply)
+}
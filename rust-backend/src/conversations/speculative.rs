@@ -0,0 +1,386 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::agent::transformers::{display_processor, rewrite, ThinkTagParser};
+use crate::chat_history;
+use crate::config_manager::asr::SpeculativeASRConfig;
+use crate::conversations::single_conversation::{speak_reply, store_reply};
+use crate::conversations::utils::create_batch_input;
+use crate::conversations::{TurnSignals, WebSocketSend};
+use crate::state::AppState;
+
+/// Numbers utterances, so partial transcripts that arrive after their
+/// utterance ended are ignored
+static NEXT_UTTERANCE: AtomicU64 = AtomicU64::new(0);
+
+/// Partial transcription of the utterance a client is speaking, and the
+/// reply prepared for it
+pub struct Speculation {
+    utterance: u64,
+    transcribing: bool,
+    last_transcribed_at: Instant,
+    /// Latest partial transcript and since when it has read the same
+    partial: Option<(String, Instant)>,
+    prepared: Option<PreparedReply>,
+}
+
+impl Speculation {
+    fn new() -> Self {
+        Self {
+            utterance: NEXT_UTTERANCE.fetch_add(1, Ordering::Relaxed),
+            transcribing: false,
+            last_transcribed_at: Instant::now(),
+            partial: None,
+            prepared: None,
+        }
+    }
+}
+
+/// A reply being generated by the client's agent for a partial transcript,
+/// not yet sent or stored
+///
+/// Dropping it stops the generation and takes the turn back out of agent
+/// memory in the background; [`PreparedReply::cancel`] does so before
+/// returning.
+pub struct PreparedReply {
+    input: String,
+    handle: Option<JoinHandle<anyhow::Result<String>>>,
+    /// Set while the agent's memory holds the prepared turn
+    started: Arc<AtomicBool>,
+    state: AppState,
+    client_uid: String,
+}
+
+impl PreparedReply {
+    /// Stop generating and take the turn back out of agent memory
+    pub async fn cancel(mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            let _ = handle.await;
+        }
+        rewind(&self.state, &self.client_uid, &self.started).await;
+    }
+}
+
+impl Drop for PreparedReply {
+    fn drop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        handle.abort();
+        let state = self.state.clone();
+        let client_uid = self.client_uid.clone();
+        let started = self.started.clone();
+        tokio::spawn(async move {
+            let _ = handle.await;
+            rewind(&state, &client_uid, &started).await;
+        });
+    }
+}
+
+/// Undo the prepared turn in the client's agent memory if it is there
+async fn rewind(state: &AppState, client_uid: &str, started: &AtomicBool) {
+    if !started.load(Ordering::SeqCst) {
+        return;
+    }
+    let Some(agent) = state.agents.get(client_uid).map(|a| a.value().clone()) else {
+        return;
+    };
+    let mut agent = agent.lock().await;
+    // Checked again under the lock so the turn is only rewound once
+    if started.swap(false, Ordering::SeqCst) {
+        agent.rewind_last_turn();
+    }
+}
+
+fn settings(state: &AppState) -> Option<SpeculativeASRConfig> {
+    state
+        .config()
+        .character_config
+        .asr_config
+        .as_ref()
+        .and_then(|c| c.speculative.clone())
+}
+
+/// Transcribe the audio the client has sent so far, once every
+/// `partial_interval_ms`, and send the result as `partial-transcription`
+/// when it changed
+///
+/// Called as `mic-audio-data` arrives. Group members are left out, since
+/// their turns are taken in order.
+pub async fn on_audio(state: &AppState, client_uid: &str, sender: &WebSocketSend) {
+    let Some(settings) = settings(state) else {
+        return;
+    };
+    if state.chat_groups.read().await.get_group_members(client_uid).len() > 1 {
+        return;
+    }
+    let Some(audio) = state.audio_buffers.get(client_uid).map(|b| b.value().clone()) else {
+        return;
+    };
+
+    let utterance = {
+        let mut speculation = state
+            .speculations
+            .entry(client_uid.to_string())
+            .or_insert_with(Speculation::new);
+        let interval = Duration::from_millis(settings.partial_interval_ms);
+        if speculation.transcribing || speculation.last_transcribed_at.elapsed() < interval {
+            return;
+        }
+        speculation.transcribing = true;
+        speculation.last_transcribed_at = Instant::now();
+        speculation.utterance
+    };
+
+    let state = state.clone();
+    let client_uid = client_uid.to_string();
+    let sender = sender.clone();
+    tokio::spawn(async move {
+        let language = crate::asr::client_language(&state, &client_uid);
+        let result = crate::asr::transcribe(&state, audio, language).await;
+        on_partial(&state, &client_uid, utterance, result, &settings, &sender);
+    });
+}
+
+/// Record a partial transcript and prepare a reply once it has held still
+/// for `stable_ms`
+fn on_partial(
+    state: &AppState,
+    client_uid: &str,
+    utterance: u64,
+    result: anyhow::Result<String>,
+    settings: &SpeculativeASRConfig,
+    sender: &WebSocketSend,
+) {
+    let Some(mut speculation) = state.speculations.get_mut(client_uid) else {
+        return;
+    };
+    if speculation.utterance != utterance {
+        return;
+    }
+    speculation.transcribing = false;
+    let text = match result {
+        Ok(text) => text.trim().to_string(),
+        Err(e) => {
+            debug!("Partial transcription for {} failed: {}", client_uid, e);
+            return;
+        }
+    };
+    if text.is_empty() {
+        return;
+    }
+
+    let now = Instant::now();
+    let since = match &speculation.partial {
+        Some((previous, since)) if words(previous) == words(&text) => *since,
+        _ => {
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "partial-transcription",
+                    "text": text
+                })
+                .to_string(),
+            );
+            now
+        }
+    };
+    speculation.partial = Some((text.clone(), since));
+    if now.duration_since(since) < Duration::from_millis(settings.stable_ms) {
+        return;
+    }
+    if speculation
+        .prepared
+        .as_ref()
+        .is_some_and(|p| words(&p.input) == words(&text))
+    {
+        return;
+    }
+
+    debug!("Preparing a reply for {} to: {}", client_uid, text);
+    // A reply prepared for an earlier transcript is replaced
+    let previous = speculation.prepared.take();
+    let started = Arc::new(AtomicBool::new(false));
+    let handle = tokio::spawn(prepare_reply(
+        state.clone(),
+        client_uid.to_string(),
+        text.clone(),
+        previous,
+        started.clone(),
+    ));
+    speculation.prepared = Some(PreparedReply {
+        input: text,
+        handle: Some(handle),
+        started,
+        state: state.clone(),
+        client_uid: client_uid.to_string(),
+    });
+}
+
+/// Generate the agent's reply to `input` without sending it
+async fn prepare_reply(
+    state: AppState,
+    client_uid: String,
+    input: String,
+    previous: Option<PreparedReply>,
+    started: Arc<AtomicBool>,
+) -> anyhow::Result<String> {
+    if let Some(previous) = previous {
+        previous.cancel().await;
+    }
+
+    let context = state
+        .client_contexts
+        .get(&client_uid)
+        .map(|c| c.value().clone())
+        .ok_or_else(|| anyhow::anyhow!("No context for client {}", client_uid))?;
+    let human_name = state.config().character_config.human_name.clone();
+    let mut batch_input = create_batch_input(&input, &serde_json::json!({}), &human_name)?;
+    batch_input.metadata = Some(serde_json::json!({ "sampling": context.sampling.to_options() }));
+
+    let agent = state.get_or_create_agent(&context)?;
+    let mut agent = agent.lock().await;
+    agent.reset_interrupt();
+    started.store(true, Ordering::SeqCst);
+    let mut outputs = agent.chat(batch_input).await;
+    let mut think_parser = ThinkTagParser::new();
+    let mut reply = String::new();
+    while let Some(output) = outputs.next().await {
+        if let Some(sentence) = output?.as_sentence() {
+            reply.push_str(&think_parser.push(&sentence.display_text.text).visible);
+        }
+    }
+    reply.push_str(&think_parser.finish().visible);
+    Ok(reply)
+}
+
+/// Take the client's prepared reply when its utterance ends
+pub fn take(state: &AppState, client_uid: &str) -> Option<PreparedReply> {
+    state
+        .speculations
+        .remove(client_uid)
+        .and_then(|(_, speculation)| speculation.prepared)
+}
+
+/// Drop the client's speculation, undoing any reply it prepared
+pub async fn discard(state: &AppState, client_uid: &str) {
+    if let Some(prepared) = take(state, client_uid) {
+        prepared.cancel().await;
+    }
+}
+
+/// The prepared reply, if it was prepared for close enough to what the user
+/// ended up saying; otherwise it is discarded
+pub async fn resolve(
+    state: &AppState,
+    client_uid: &str,
+    mut prepared: PreparedReply,
+    final_text: &str,
+) -> Option<String> {
+    let min_similarity = settings(state).map_or(1.0, |s| s.min_similarity);
+    let similarity = word_similarity(&prepared.input, final_text);
+    if similarity < min_similarity {
+        info!(
+            "Discarding the reply prepared for {}: transcript changed from {:?} to {:?}",
+            client_uid, prepared.input, final_text
+        );
+        prepared.cancel().await;
+        return None;
+    }
+
+    let handle = prepared.handle.take()?;
+    match handle.await {
+        Ok(Ok(reply)) if !reply.trim().is_empty() => Some(reply),
+        result => {
+            if let Ok(Err(e)) = result {
+                warn!("Preparing a reply for {} failed: {}", client_uid, e);
+            }
+            rewind(state, client_uid, &prepared.started).await;
+            None
+        }
+    }
+}
+
+/// Answer `input` with a reply prepared ahead of time, storing the turn as
+/// a normal one would be
+///
+/// Agent memory holds the transcript the reply was prepared for, which may
+/// differ slightly from `input`.
+pub async fn commit(
+    state: &AppState,
+    client_uid: &str,
+    input: &str,
+    reply: &str,
+    signals: TurnSignals,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    info!("Using the reply prepared for {}", client_uid);
+    let context = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| c.value().clone())
+        .ok_or_else(|| anyhow::anyhow!("No context for client {}", client_uid))?;
+    let config = state.config();
+    let character_config = &config.character_config;
+
+    let batch_input = create_batch_input(input, &serde_json::json!({}), &character_config.human_name)?;
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().last_input = Some(Arc::new(batch_input));
+    }
+    if let Some(history_uid) = &context.history_uid {
+        chat_history::store_message(
+            &context.conf_uid,
+            history_uid,
+            "human",
+            input,
+            Some(&character_config.human_name),
+            None,
+            config.system_config.fsync_history,
+        )?;
+    }
+
+    speak_reply(state, client_uid, reply, signals, sender).await;
+    store_reply(
+        &context,
+        &config,
+        &display_processor(
+            state.live2d_model().as_deref(),
+            &rewrite(reply, character_config.display_rewrites()),
+        )
+        .text,
+    )
+}
+
+/// Lowercase words of `text`, ignoring punctuation
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 1 minus the word-level edit distance over the longer transcript's length
+fn word_similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (words(a), words(b));
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, word_a) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, word_b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(word_a != word_b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    1.0 - row[b.len()] as f32 / longest as f32
+}
@@ -0,0 +1,657 @@
+use std::sync::Arc;
+use futures::StreamExt;
+use regex::Regex;
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit};
+use tracing::{debug, error, warn};
+
+use crate::agent::output_types::{Actions, DisplayText};
+use crate::conversations::emotion_inference::EmotionInference;
+use crate::conversations::types::WebSocketSend;
+use crate::python_service::{PythonService, RVCRequest};
+use crate::tts::{AudioStream, TTSInterface, TTSLimiter};
+use crate::utils::audio_format::AudioFormat;
+use crate::utils::cache_janitor::{audio_key, AudioInUse};
+use crate::utils::stream_audio::{
+    encode_audio_file, pcm16_volumes, prepare_audio_chunk_payload, prepare_audio_file,
+    prepare_audio_payload, PreparedAudioFile,
+};
+
+/// A sentence waiting to be synthesized and sent to the client
+#[derive(Debug, Clone)]
+pub struct TTSJob {
+    pub tts_text: String,
+    pub display_text: DisplayText,
+    pub actions: Actions,
+    /// Audio already produced by the agent; synthesis is skipped
+    pub audio_path: Option<String>,
+}
+
+impl TTSJob {
+    pub fn new(tts_text: String, display_text: DisplayText, actions: Actions) -> Self {
+        Self {
+            tts_text,
+            display_text,
+            actions,
+            audio_path: None,
+        }
+    }
+}
+
+/// Whether text has anything to say; text made up only of whitespace and
+/// punctuation is not worth synthesizing
+pub fn has_speech(text: &str) -> bool {
+    let silent_pattern = Regex::new(r#"^[\s.,!?，。！？'"』」）】]*$"#).unwrap();
+    !silent_pattern.is_match(text)
+}
+
+/// Result of synthesizing one job
+enum Synthesized {
+    /// Whole-file audio, or `None` for a silent payload
+    File(Option<String>),
+    /// Audio streamed while it is being synthesized, holding its synthesis
+    /// slot until forwarded
+    Stream(AudioStream, Option<OwnedSemaphorePermit>),
+}
+
+/// Releases a turn's audio files from the in-use set, including when the
+/// turn is aborted
+struct InUseGuard {
+    set: Option<AudioInUse>,
+    keys: Vec<String>,
+}
+
+impl InUseGuard {
+    fn track(&mut self, path: &str) {
+        if let Some(set) = &self.set {
+            let key = audio_key(path);
+            set.insert(key.clone());
+            self.keys.push(key);
+        }
+    }
+}
+
+impl Drop for InUseGuard {
+    fn drop(&mut self) {
+        if let Some(set) = &self.set {
+            for key in &self.keys {
+                set.remove(key);
+            }
+        }
+    }
+}
+
+/// RVC model and the service that runs it
+struct VoiceConversion {
+    python_service: Arc<dyn PythonService>,
+    model: String,
+}
+
+/// Manages TTS for a conversation turn and sends the resulting payloads
+pub struct TTSTaskManager {
+    tts_engine: Option<Arc<dyn TTSInterface>>,
+    queue_depth: usize,
+    in_use: Option<AudioInUse>,
+    embed_audio: bool,
+    stop_audio: Option<watch::Receiver<bool>>,
+    display_log: Option<Arc<std::sync::Mutex<String>>>,
+    limiter: Option<Arc<TTSLimiter>>,
+    partial_text: bool,
+    voice_conversion: Option<VoiceConversion>,
+    emotion_inference: Option<Arc<EmotionInference>>,
+    preferred_format: Option<AudioFormat>,
+}
+
+/// Resolve once `stop` has been set; never, without a signal
+async fn audio_stopped(stop: Option<watch::Receiver<bool>>) {
+    match stop {
+        Some(mut stop) => {
+            if stop.wait_for(|stopped| *stopped).await.is_err() {
+                // Sender gone without stopping: the turn is over anyway
+                std::future::pending::<()>().await;
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
+
+impl TTSTaskManager {
+    /// Create a manager; `None` means TTS is disabled and only text is sent
+    ///
+    /// # Arguments
+    /// * `tts_engine` - Engine used for synthesis
+    /// * `queue_depth` - Sentences allowed to wait for synthesis, and to be
+    ///   synthesized concurrently, before the producer is made to wait
+    pub fn new(tts_engine: Option<Arc<dyn TTSInterface>>, queue_depth: usize) -> Self {
+        Self {
+            tts_engine,
+            queue_depth: queue_depth.max(1),
+            in_use: None,
+            embed_audio: false,
+            stop_audio: None,
+            display_log: None,
+            limiter: None,
+            partial_text: false,
+            voice_conversion: None,
+            emotion_inference: None,
+            preferred_format: None,
+        }
+    }
+
+    /// Mark audio sent during [`TTSTaskManager::run`] as in use so the cache
+    /// janitor leaves it alone until the turn is over
+    pub fn with_in_use(mut self, in_use: AudioInUse) -> Self {
+        self.in_use = Some(in_use);
+        self
+    }
+
+    /// Send whole-file audio base64-encoded in the payload rather than as a
+    /// path; streamed audio is always inline
+    pub fn with_embedded_audio(mut self, embed_audio: bool) -> Self {
+        self.embed_audio = embed_audio;
+        self
+    }
+
+    /// Stop producing audio once `stop` is set to true
+    ///
+    /// Jobs still flow through the queue afterwards, but are sent as text and
+    /// actions only: in-flight synthesis and streams are abandoned and queued
+    /// sentences are not synthesized, so the reply keeps appearing.
+    pub fn with_stop_signal(mut self, stop: watch::Receiver<bool>) -> Self {
+        self.stop_audio = Some(stop);
+        self
+    }
+
+    /// Append the text of every sentence sent to the client to `log`
+    pub fn with_display_log(mut self, log: Arc<std::sync::Mutex<String>>) -> Self {
+        self.display_log = Some(log);
+        self
+    }
+
+    /// Wait for a slot from `limiter` before each synthesis
+    pub fn with_limiter(mut self, limiter: Arc<TTSLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Send each sentence's text as `partial-text` as soon as it is picked up
+    /// for synthesis, so subtitles can be shown before the audio arrives
+    pub fn with_partial_text(mut self, partial_text: bool) -> Self {
+        self.partial_text = partial_text;
+        self
+    }
+
+    /// Run synthesized audio through RVC voice conversion with `model`
+    ///
+    /// Engines that stream are asked for whole files instead, as conversion
+    /// needs the complete audio. If conversion fails the original audio is
+    /// sent.
+    pub fn with_voice_conversion(
+        mut self,
+        python_service: Arc<dyn PythonService>,
+        model: String,
+    ) -> Self {
+        self.voice_conversion = Some(VoiceConversion {
+            python_service,
+            model,
+        });
+        self
+    }
+
+    /// Infer an expression for sentences that arrive without one, while
+    /// they are being synthesized
+    pub fn with_emotion_inference(mut self, inference: Arc<EmotionInference>) -> Self {
+        self.emotion_inference = Some(inference);
+        self
+    }
+
+    /// Transcode whole-file audio the engine wrote in another format to
+    /// `format`, as far as [`prepare_audio_file`] can
+    pub fn with_preferred_format(mut self, format: Option<AudioFormat>) -> Self {
+        self.preferred_format = format;
+        self
+    }
+
+    fn send_partial_text(&self, job: &TTSJob, index: usize, sender: &WebSocketSend) {
+        if !self.partial_text || job.display_text.text.is_empty() {
+            return;
+        }
+        let _ = sender.send(
+            serde_json::json!({
+                "type": "partial-text",
+                "index": index,
+                "text": job.display_text.text,
+                "name": job.display_text.name,
+                "avatar": job.display_text.avatar
+            })
+            .to_string(),
+        );
+    }
+
+    fn log_displayed(&self, display_text: &DisplayText) {
+        if let Some(log) = &self.display_log {
+            let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+            if !log.is_empty() && !display_text.text.is_empty() {
+                log.push(' ');
+            }
+            log.push_str(&display_text.text);
+        }
+    }
+
+    fn is_audio_stopped(&self) -> bool {
+        self.stop_audio.as_ref().is_some_and(|stop| *stop.borrow())
+    }
+
+    /// Create the bounded queue feeding [`TTSTaskManager::run`]
+    pub fn channel(&self) -> (mpsc::Sender<TTSJob>, mpsc::Receiver<TTSJob>) {
+        mpsc::channel(self.queue_depth)
+    }
+
+    /// Synthesize queued jobs and send them to the client in queue order
+    ///
+    /// Up to `queue_depth` jobs are synthesized at once. A sentence that
+    /// finishes before those ahead of it is held until they have been sent,
+    /// so the payloads go out in queue order whatever order synthesis
+    /// finishes in; each carries its place as `sequence`. Streamed sentences
+    /// are forwarded one after another, so audio never interleaves. Returns
+    /// once the sending side is dropped and the queue is empty. Dropping this
+    /// future (e.g. when the conversation is interrupted) discards any queued
+    /// jobs.
+    ///
+    /// With [`TTSTaskManager::with_partial_text`], a job's `partial-text` is
+    /// sent in queue order when its synthesis starts; its `index` is the
+    /// `sequence` of the audio that follows.
+    ///
+    /// # Returns
+    /// Whether any audio was generated
+    pub async fn run(&self, mut jobs: mpsc::Receiver<TTSJob>, sender: &WebSocketSend) -> bool {
+        let queued = futures::stream::poll_fn(|cx| jobs.poll_recv(cx));
+        let mut index = 0;
+        let mut synthesized = queued
+            .map(|job| {
+                self.send_partial_text(&job, index, sender);
+                index += 1;
+                (index - 1, job)
+            })
+            .map(|(sequence, mut job)| async move {
+                let mut actions = job.actions.clone();
+                let infer = async {
+                    if let Some(inference) = &self.emotion_inference {
+                        inference.apply(&job.display_text.text, &mut actions).await;
+                    }
+                };
+                let synthesize = async {
+                    match job.audio_path.clone() {
+                        Some(path) => Synthesized::File(Some(path)),
+                        None => tokio::select! {
+                            result = self.synthesize(&job) => result,
+                            _ = audio_stopped(self.stop_audio.clone()) => Synthesized::File(None),
+                        },
+                    }
+                };
+                let ((), result) = tokio::join!(infer, synthesize);
+                job.actions = actions;
+                (sequence, job, result)
+            })
+            // Yields in queue order, holding results that finish early
+            .buffered(self.queue_depth);
+
+        let mut in_use = InUseGuard {
+            set: self.in_use.clone(),
+            keys: Vec::new(),
+        };
+        let mut any_audio = false;
+        while let Some((sequence, job, result)) = synthesized.next().await {
+            let result = if self.is_audio_stopped() {
+                Synthesized::File(None)
+            } else {
+                result
+            };
+            let audio_path = match result {
+                Synthesized::Stream(stream, _permit) => {
+                    if self.forward_stream(&job, sequence, stream, sender).await {
+                        any_audio = true;
+                        continue;
+                    }
+                    // Nothing arrived; still show the sentence
+                    None
+                }
+                Synthesized::File(audio_path) => audio_path,
+            };
+
+            let prepared = match audio_path {
+                Some(path) => {
+                    in_use.track(&path);
+                    any_audio = true;
+                    let prepared = self.prepare_file(path).await;
+                    in_use.track(&prepared.path);
+                    Some(prepared)
+                }
+                None => None,
+            };
+            let audio = match &prepared {
+                Some(prepared) if self.embed_audio => match encode_audio_file(&prepared.path) {
+                    Ok(encoded) => Some(encoded),
+                    Err(e) => {
+                        error!("Failed to embed audio {}, sending path: {}", prepared.path, e);
+                        Some(prepared.path.clone())
+                    }
+                },
+                prepared => prepared.as_ref().map(|p| p.path.clone()),
+            };
+            let mut payload = prepare_audio_payload(
+                audio.as_deref(),
+                Some(&job.display_text),
+                Some(&job.actions),
+                false,
+            );
+            payload.sequence = Some(sequence);
+            if let Some(prepared) = prepared {
+                payload.volumes = prepared.volumes;
+                payload.format = prepared.format.map(|f| f.name().to_string());
+            }
+            let _ = sender.send(payload.to_string());
+            self.log_displayed(&job.display_text);
+        }
+        any_audio
+    }
+
+    /// Forward a streamed sentence as `audio-chunk` messages with running
+    /// volume data, each tagged with the sentence's `sequence`
+    ///
+    /// # Returns
+    /// Whether any audio was forwarded
+    async fn forward_stream(
+        &self,
+        job: &TTSJob,
+        sequence: usize,
+        mut stream: AudioStream,
+        sender: &WebSocketSend,
+    ) -> bool {
+        let mut seq = 0;
+        let mut peak = 0.0f32;
+        // A chunk may end in the middle of a sample
+        let mut carry: Vec<u8> = Vec::new();
+
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.chunks.next() => chunk,
+                _ = audio_stopped(self.stop_audio.clone()) => break,
+            };
+            let chunk = match chunk {
+                None => break,
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    error!("TTS stream failed: {}", e);
+                    break;
+                }
+            };
+
+            carry.extend_from_slice(&chunk);
+            let usable = carry.len() - carry.len() % 2;
+            if usable == 0 {
+                continue;
+            }
+            let pcm: Vec<u8> = carry.drain(..usable).collect();
+            let samples: Vec<i16> = pcm
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect();
+
+            // Normalize against the loudest slice heard so far in this sentence
+            let volumes = pcm16_volumes(&samples, stream.sample_rate, 20);
+            peak = volumes.iter().copied().fold(peak, f32::max);
+            let volumes: Vec<f32> = volumes
+                .iter()
+                .map(|v| if peak > 0.0 { v / peak } else { 0.0 })
+                .collect();
+
+            let first = seq == 0;
+            let mut payload = prepare_audio_chunk_payload(
+                &pcm,
+                stream.sample_rate,
+                &volumes,
+                seq,
+                first.then_some(&job.display_text),
+                first.then_some(&job.actions),
+                false,
+            );
+            payload["sequence"] = sequence.into();
+            let _ = sender.send(payload.to_string());
+            if first {
+                self.log_displayed(&job.display_text);
+            }
+            seq += 1;
+        }
+
+        if seq > 0 {
+            let mut payload =
+                prepare_audio_chunk_payload(&[], stream.sample_rate, &[], seq, None, None, true);
+            payload["sequence"] = sequence.into();
+            let _ = sender.send(payload.to_string());
+        }
+        seq > 0
+    }
+
+    /// Detect the format and volumes of a synthesized file, off the async
+    /// runtime since the file is decoded
+    async fn prepare_file(&self, path: String) -> PreparedAudioFile {
+        let preferred = self.preferred_format;
+        let fallback = PreparedAudioFile {
+            path: path.clone(),
+            format: None,
+            volumes: Vec::new(),
+        };
+        tokio::task::spawn_blocking(move || prepare_audio_file(&path, preferred))
+            .await
+            .unwrap_or(fallback)
+    }
+
+    /// Generate audio for a job, streaming when the engine supports it
+    ///
+    /// Returns a silent result (text and actions only) when TTS is disabled,
+    /// the text has nothing to speak, or synthesis fails.
+    async fn synthesize(&self, job: &TTSJob) -> Synthesized {
+        let engine = match &self.tts_engine {
+            Some(engine) if has_speech(&job.tts_text) => engine,
+            _ => {
+                debug!("Sending silent payload for: {}", job.display_text.text);
+                return Synthesized::File(None);
+            }
+        };
+
+        let permit = match &self.limiter {
+            Some(limiter) => Some(limiter.acquire().await),
+            None => None,
+        };
+        if engine.supports_streaming() && self.voice_conversion.is_none() {
+            match engine.synthesize_stream(&job.tts_text).await {
+                Ok(stream) => return Synthesized::Stream(stream, permit),
+                // Fall back to whole-file synthesis
+                Err(e) => debug!("TTS streaming unavailable, using whole file: {}", e),
+            }
+        }
+
+        match engine.generate_audio(&job.tts_text, None).await {
+            Ok(path) => Synthesized::File(Some(self.convert_voice(path).await)),
+            Err(e) => {
+                error!("Error preparing audio payload: {}", e);
+                Synthesized::File(None)
+            }
+        }
+    }
+
+    /// Convert synthesized audio with the configured RVC model, returning
+    /// the path to play; the input path when conversion is off or fails
+    async fn convert_voice(&self, audio_path: String) -> String {
+        let Some(conversion) = &self.voice_conversion else {
+            return audio_path;
+        };
+
+        let request = RVCRequest {
+            audio_path: audio_path.clone(),
+            model: conversion.model.clone(),
+        };
+        match conversion.python_service.convert_voice(request).await {
+            Ok(response) => response.audio_path,
+            Err(e) => {
+                warn!("RVC conversion with {} failed, playing unconverted audio: {}", conversion.model, e);
+                audio_path
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python_service::mock::MockPythonService;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Engine that takes `delay_ms(text)` to speak each text, noting the
+    /// order synthesis finishes in
+    struct FakeTTS {
+        delay_ms: fn(&str) -> u64,
+        finished: Mutex<Vec<String>>,
+    }
+
+    impl FakeTTS {
+        fn new(delay_ms: fn(&str) -> u64) -> Self {
+            Self {
+                delay_ms,
+                finished: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl TTSInterface for FakeTTS {
+        async fn generate_audio(&self, text: &str, _file_name_no_ext: Option<&str>) -> anyhow::Result<String> {
+            tokio::time::sleep(Duration::from_millis((self.delay_ms)(text))).await;
+            self.finished.lock().unwrap().push(text.to_string());
+            Ok(format!("cache/{}.wav", text))
+        }
+
+        fn remove_file(&self, _filepath: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn job(text: &str) -> TTSJob {
+        TTSJob::new(text.to_string(), DisplayText::new(text.to_string()), Actions::new())
+    }
+
+    /// Messages sent to the client, parsed
+    fn sent(mut messages: mpsc::UnboundedReceiver<String>) -> Vec<serde_json::Value> {
+        let mut sent = Vec::new();
+        while let Ok(message) = messages.try_recv() {
+            sent.push(serde_json::from_str(&message).unwrap());
+        }
+        sent
+    }
+
+    #[tokio::test]
+    async fn slow_synthesis_holds_back_the_producer() {
+        let depth = 2;
+        let texts: Vec<String> = (0..12).map(|i| format!("Sentence {}.", i)).collect();
+        let tts = Arc::new(FakeTTS::new(|_| 20));
+        let manager = TTSTaskManager::new(Some(tts.clone()), depth);
+        let (jobs, queued) = manager.channel();
+        let (sender, messages) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        let produce = async {
+            let mut most_ahead = 0;
+            for (i, text) in texts.iter().enumerate() {
+                jobs.send(job(text)).await.unwrap();
+                let finished = tts.finished.lock().unwrap().len();
+                most_ahead = most_ahead.max(i + 1 - finished);
+            }
+            drop(jobs);
+            most_ahead
+        };
+        let (most_ahead, _) = tokio::join!(produce, manager.run(queued, &sender));
+
+        // At most `depth` waiting in the queue and `depth` being synthesized
+        assert!(most_ahead <= 2 * depth, "producer got {} sentences ahead", most_ahead);
+        let audio = sent(messages).into_iter().filter(|m| m["type"] == "audio").count();
+        assert_eq!(audio, texts.len());
+    }
+
+    #[tokio::test]
+    async fn interrupting_discards_queued_jobs() {
+        let tts = Arc::new(FakeTTS::new(|_| 20));
+        let manager = TTSTaskManager::new(Some(tts.clone()), 1);
+        let (jobs, queued) = manager.channel();
+        let (sender, _messages) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        let producer = tokio::spawn(async move {
+            for text in ["One.", "Two.", "Three.", "Four."] {
+                jobs.send(job(text)).await?;
+            }
+            Ok::<_, mpsc::error::SendError<TTSJob>>(())
+        });
+        // Interrupted while the first sentence is being synthesized
+        let run = tokio::time::timeout(Duration::from_millis(5), manager.run(queued, &sender));
+        assert!(run.await.is_err());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(tts.finished.lock().unwrap().is_empty());
+        assert!(producer.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn audio_is_sent_in_queue_order_when_synthesis_finishes_reversed() {
+        let texts = ["One.", "Two.", "Three."];
+        let tts = Arc::new(FakeTTS::new(|text| match text {
+            "One." => 90,
+            "Two." => 45,
+            _ => 0,
+        }));
+        let manager = TTSTaskManager::new(Some(tts.clone()), 3);
+        let (jobs, queued) = manager.channel();
+        let (sender, messages) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        let produce = async move {
+            for text in texts {
+                jobs.send(job(text)).await.unwrap();
+            }
+        };
+        tokio::join!(produce, manager.run(queued, &sender));
+
+        assert_eq!(*tts.finished.lock().unwrap(), ["Three.", "Two.", "One."]);
+        let audio: Vec<_> = sent(messages)
+            .into_iter()
+            .filter(|m| m["type"] == "audio")
+            .map(|m| (m["sequence"].as_u64().unwrap(), m["display_text"]["text"].clone()))
+            .collect();
+        assert_eq!(
+            audio,
+            [(0, "One.".into()), (1, "Two.".into()), (2, "Three.".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn converted_audio_replaces_the_synthesized_file() {
+        let service = Arc::new(MockPythonService::new().with_voice_conversion("cache/One-rvc.wav"));
+        let manager = TTSTaskManager::new(Some(Arc::new(FakeTTS::new(|_| 0))), 1)
+            .with_voice_conversion(service.clone(), "kiyo".to_string());
+
+        assert_eq!(manager.convert_voice("cache/One.wav".to_string()).await, "cache/One-rvc.wav");
+        let requests = service.requests("/rvc/convert");
+        assert_eq!(requests[0]["audio_path"], "cache/One.wav");
+        assert_eq!(requests[0]["model"], "kiyo");
+    }
+
+    #[tokio::test]
+    async fn failed_conversion_plays_the_original_audio() {
+        let service = Arc::new(MockPythonService::new().with_error("/rvc/convert", "model not found"));
+        let manager = TTSTaskManager::new(Some(Arc::new(FakeTTS::new(|_| 0))), 1)
+            .with_voice_conversion(service, "kiyo".to_string());
+
+        assert_eq!(manager.convert_voice("cache/One.wav".to_string()).await, "cache/One.wav");
+    }
+}
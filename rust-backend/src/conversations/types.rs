@@ -1,46 +1,127 @@
 use serde::{Deserialize, Serialize};
+
+use crate::agent::output_types::{Actions, DisplayText};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
-/// WebSocket send function type
-pub type WebSocketSend = mpsc::UnboundedSender<String>;
+/// Sends JSON messages to one client
+///
+/// A sender may carry tags, such as the `request_id` of the turn it belongs
+/// to, which are set on every JSON object sent through it. Tags are added
+/// by the sending task before the message is queued, so tagged and untagged
+/// messages to the same client keep the order they were sent in.
+#[derive(Clone, Debug)]
+pub struct WebSocketSend {
+    sender: mpsc::UnboundedSender<String>,
+    tags: Arc<serde_json::Map<String, Value>>,
+}
+
+impl WebSocketSend {
+    pub fn new(sender: mpsc::UnboundedSender<String>) -> Self {
+        Self {
+            sender,
+            tags: Arc::default(),
+        }
+    }
+
+    /// A sender to the same client that also sets `field` to `value`
+    pub fn with_tag(&self, field: &str, value: &str) -> Self {
+        let mut tags = (*self.tags).clone();
+        tags.insert(field.to_string(), Value::String(value.to_string()));
+        Self {
+            sender: self.sender.clone(),
+            tags: Arc::new(tags),
+        }
+    }
+
+    /// Queue `text` for the client, tagging it when it is a JSON object
+    pub fn send(&self, text: String) -> Result<(), mpsc::error::SendError<String>> {
+        if self.tags.is_empty() {
+            return self.sender.send(text);
+        }
+        match serde_json::from_str::<Value>(&text) {
+            Ok(Value::Object(mut msg)) => {
+                msg.extend(self.tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+                self.sender.send(Value::Object(msg).to_string())
+            }
+            _ => self.sender.send(text),
+        }
+    }
+}
+
+impl From<mpsc::UnboundedSender<String>> for WebSocketSend {
+    fn from(sender: mpsc::UnboundedSender<String>) -> Self {
+        Self::new(sender)
+    }
+}
+
+/// Signals shared between a running turn and the client's receive loop
+#[derive(Clone)]
+pub struct TurnSignals {
+    /// Set to true to silence the rest of the turn's TTS
+    pub stop_audio: tokio::sync::watch::Receiver<bool>,
+    /// Reply text sent to the client so far, used when the turn is cut off
+    /// without the client reporting what it heard
+    pub displayed: Arc<std::sync::Mutex<String>>,
+}
 
 /// Broadcast function type
 pub type BroadcastFunc = Arc<dyn Fn(Vec<String>, Value, Option<String>) -> tokio::task::JoinHandle<()> + Send + Sync>;
 
-/// Audio payload structure
+/// The `audio` WebSocket message: one sentence of speech with its text and
+/// actions
+///
+/// `audio` is a `/cache` path or base64 audio, and is null when the
+/// sentence is shown without speech. Expressions in `actions` are model
+/// expression indices, or names for models that play them by name.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioPayload {
     #[serde(rename = "type")]
     pub payload_type: String,
     pub audio: Option<String>,
-    pub volumes: Option<Vec<f32>>,
-    pub slice_length: Option<i32>,
+    /// Volume of each `slice_length` ms of audio, for lip sync
+    pub volumes: Vec<f32>,
+    pub slice_length: u32,
+    /// Format of the audio, e.g. `mp3`, when it could be detected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
     pub display_text: Option<DisplayText>,
     pub actions: Option<Actions>,
-    pub forwarded: Option<bool>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DisplayText {
-    pub text: String,
-    pub name: Option<String>,
-    pub avatar: Option<String>,
+    /// Whether the sentence was relayed from another client in a group
+    pub forwarded: bool,
+    /// Whether this is the thinking filler, which a `stop-filler` control
+    /// cuts off
+    pub filler: bool,
+    /// Position of the sentence in its turn, from 0; a turn's payloads are
+    /// sent in this order, which is the order they should be played in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Actions {
-    pub expressions: Option<Vec<i32>>,
-    pub motions: Option<Vec<Motion>>,
+impl AudioPayload {
+    pub fn new(audio: Option<String>) -> Self {
+        Self {
+            payload_type: "audio".to_string(),
+            audio,
+            volumes: Vec::new(),
+            slice_length: 20,
+            format: None,
+            display_text: None,
+            actions: None,
+            forwarded: false,
+            filler: false,
+            sequence: None,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Motion {
-    pub group: String,
-    pub index: i32,
-    pub loop_motion: bool,
+impl std::fmt::Display for AudioPayload {
+    /// The payload as a JSON message
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| std::fmt::Error)?)
+    }
 }
 
 /// Group conversation state
@@ -52,6 +133,8 @@ pub struct GroupConversationState {
     pub group_queue: Vec<String>,
     pub session_emoji: String,
     pub current_speaker_uid: Option<String>,
+    /// The current speaker's in-flight turn, so it can be cut off
+    pub current_turn: Option<tokio::task::AbortHandle>,
 }
 
 impl GroupConversationState {
@@ -63,8 +146,26 @@ impl GroupConversationState {
             group_queue: group_members,
             session_emoji,
             current_speaker_uid: None,
+            current_turn: None,
         }
     }
+
+    /// Hand the turn to the next member in the queue; the current speaker
+    /// goes to the back of it
+    ///
+    /// # Returns
+    /// The new speaker, if anyone is queued
+    pub fn advance_turn(&mut self) -> Option<&str> {
+        self.current_turn = None;
+        if let Some(speaker) = self.current_speaker_uid.take() {
+            self.group_queue.push(speaker);
+        }
+        if self.group_queue.is_empty() {
+            return None;
+        }
+        self.current_speaker_uid = Some(self.group_queue.remove(0));
+        self.current_speaker_uid.as_deref()
+    }
 }
 
 /// Conversation configuration
@@ -76,3 +177,77 @@ pub struct ConversationConfig {
     pub character_name: String,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn silent_audio_payload_shape() {
+        let payload: Value = serde_json::from_str(&AudioPayload::new(None).to_string()).unwrap();
+
+        assert_eq!(
+            payload,
+            json!({
+                "type": "audio",
+                "audio": null,
+                "volumes": [],
+                "slice_length": 20,
+                "display_text": null,
+                "actions": null,
+                "forwarded": false,
+                "filler": false,
+            })
+        );
+    }
+
+    #[test]
+    fn full_audio_payload_shape() {
+        let mut actions = Actions::new();
+        actions.expressions = Some(vec![json!(3), json!("smile")]);
+        let payload = AudioPayload {
+            volumes: vec![0.5, 1.0],
+            format: Some("mp3".to_string()),
+            display_text: Some(DisplayText::new("Hello!".to_string())),
+            actions: Some(actions),
+            sequence: Some(2),
+            ..AudioPayload::new(Some("/cache/hello.mp3".to_string()))
+        };
+
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            json!({
+                "type": "audio",
+                "audio": "/cache/hello.mp3",
+                "volumes": [0.5, 1.0],
+                "slice_length": 20,
+                "format": "mp3",
+                "display_text": {"text": "Hello!", "name": "AI"},
+                "actions": {"expressions": [3, "smile"]},
+                "forwarded": false,
+                "filler": false,
+                "sequence": 2,
+            })
+        );
+    }
+
+    #[test]
+    fn tagged_messages_keep_their_place_among_untagged_ones() {
+        let (raw, mut outbound) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(raw);
+        let tagged = sender.with_tag("request_id", "r1").with_tag("character_id", "mio");
+
+        tagged.send(json!({ "type": "full-text", "text": "Hi" }).to_string()).unwrap();
+        sender.send(json!({ "type": "group-update" }).to_string()).unwrap();
+        tagged.send("not json".to_string()).unwrap();
+
+        let received: Vec<String> = std::iter::from_fn(|| outbound.try_recv().ok()).collect();
+        assert_eq!(
+            serde_json::from_str::<Value>(&received[0]).unwrap(),
+            json!({ "type": "full-text", "text": "Hi", "request_id": "r1", "character_id": "mio" })
+        );
+        assert_eq!(serde_json::from_str::<Value>(&received[1]).unwrap(), json!({ "type": "group-update" }));
+        assert_eq!(received[2], "not json");
+    }
+}
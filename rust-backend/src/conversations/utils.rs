@@ -1,16 +1,139 @@
-use serde_json::{json, Value};
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::agent::input_types::{BatchInput, FileData, ImageData, TextData, TextSource};
+
+use crate::conversations::types::WebSocketSend;
+
+/// Image types accepted from clients
+const ALLOWED_IMAGE_MIME_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// Largest decoded image accepted from a client
+const MAX_IMAGE_BYTES: usize = 10 * 1024 * 1024;
 
 /// Create batch input for agent
-pub fn create_batch_input(
-    input_text: &str,
-    images: Option<&Vec<Value>>,
-    from_name: &str,
-) -> Value {
-    json!({
-        "input_text": input_text,
-        "images": images,
-        "from_name": from_name
-    })
+///
+/// Besides the main input text, `data` may carry `texts` entries tagged with
+/// a `source` (e.g. clipboard contents), `images` with `source`, `data`
+/// and `mime_type`, and `files` with `name`, `data` and `mime_type`. Images
+/// are checked against the allowed MIME types and size limit, and raw
+/// base64 is turned into a data URI, as it is for files.
+pub fn create_batch_input(input_text: &str, data: &Value, from_name: &str) -> Result<BatchInput> {
+    let mut texts = Vec::new();
+    if !input_text.is_empty() {
+        texts.push(TextData {
+            source: TextSource::Input,
+            content: input_text.to_string(),
+            from_name: Some(from_name.to_string()),
+        });
+    }
+
+    for entry in data.get("texts").and_then(|v| v.as_array()).into_iter().flatten() {
+        let source: TextSource = serde_json::from_value(entry.get("source").cloned().unwrap_or(Value::Null))
+            .map_err(|_| anyhow::anyhow!("Invalid text source: {}", entry.get("source").unwrap_or(&Value::Null)))?;
+        let content = entry
+            .get("content")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Text entry is missing 'content'"))?;
+        texts.push(TextData {
+            source,
+            content: content.to_string(),
+            from_name: Some(from_name.to_string()),
+        });
+    }
+
+    let images = match data.get("images").and_then(|v| v.as_array()) {
+        Some(images) if !images.is_empty() => Some(
+            images
+                .iter()
+                .map(parse_image)
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        _ => None,
+    };
+
+    let files = match data.get("files").and_then(|v| v.as_array()) {
+        Some(files) if !files.is_empty() => Some(
+            files
+                .iter()
+                .map(parse_file)
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        _ => None,
+    };
+
+    let mut batch_input = BatchInput::new(texts);
+    batch_input.images = images;
+    batch_input.files = files;
+    Ok(batch_input)
+}
+
+/// Parse and validate one inbound file
+///
+/// Any type is accepted; the agent decides what it can read. Size limits
+/// are the agent's too, as they are configured per agent.
+fn parse_file(file: &Value) -> Result<FileData> {
+    let mut file: FileData = serde_json::from_value(file.clone())
+        .map_err(|e| anyhow::anyhow!("Invalid file: {}", e))?;
+
+    if file.name.trim().is_empty() {
+        anyhow::bail!("File is missing a name");
+    }
+    let well_formed = file
+        .mime_type
+        .split_once('/')
+        .is_some_and(|(kind, subtype)| !kind.is_empty() && !subtype.is_empty());
+    if !well_formed {
+        anyhow::bail!("Invalid mime_type for {}: {}", file.name, file.mime_type);
+    }
+    if let Some((prefix, _)) = file.data.split_once(";base64,") {
+        if prefix != format!("data:{}", file.mime_type) {
+            anyhow::bail!("File data URI does not match mime_type {}", file.mime_type);
+        }
+    }
+    if file.decode().is_none() {
+        anyhow::bail!("File {} is not valid base64", file.name);
+    }
+
+    if !file.data.starts_with("data:") {
+        file.data = format!("data:{};base64,{}", file.mime_type, file.data);
+    }
+    Ok(file)
+}
+
+/// Parse and validate one inbound image
+fn parse_image(image: &Value) -> Result<ImageData> {
+    let mut image: ImageData = serde_json::from_value(image.clone())
+        .map_err(|e| anyhow::anyhow!("Invalid image: {}", e))?;
+
+    if !ALLOWED_IMAGE_MIME_TYPES.contains(&image.mime_type.as_str()) {
+        anyhow::bail!("Unsupported image type: {}", image.mime_type);
+    }
+
+    let payload = match image.data.split_once(";base64,") {
+        Some((prefix, payload)) => {
+            if prefix != format!("data:{}", image.mime_type) {
+                anyhow::bail!("Image data URI does not match mime_type {}", image.mime_type);
+            }
+            payload
+        }
+        None => image.data.as_str(),
+    };
+
+    // Decoded size is three bytes for every four base64 characters
+    let decoded_len = payload.len() / 4 * 3;
+    if decoded_len > MAX_IMAGE_BYTES {
+        anyhow::bail!(
+            "Image is too large: {} bytes (limit {} bytes)",
+            decoded_len,
+            MAX_IMAGE_BYTES
+        );
+    }
+
+    if !image.data.starts_with("data:") {
+        image.data = format!("data:{};base64,{}", image.mime_type, image.data);
+    }
+    Ok(image)
 }
 
 /// EMOJI list for session identification
@@ -19,3 +142,15 @@ pub const EMOJI_LIST: &[&str] = &[
     "🎤", "🎧", "🎬", "🎮", "🎰", "🎱", "🎳", "🎴",
 ];
 
+
+/// A client sender that sets `request_id` on every JSON message sent
+/// through it
+pub fn with_request_id(sender: &WebSocketSend, request_id: &str) -> WebSocketSend {
+    sender.with_tag("request_id", request_id)
+}
+
+/// A client sender that sets `character_id`, naming the attached character
+/// a message comes from, on every JSON message sent through it
+pub fn with_character_id(sender: &WebSocketSend, character_id: &str) -> WebSocketSend {
+    sender.with_tag("character_id", character_id)
+}
@@ -1,46 +1,156 @@
 use serde_json::Value;
-use tracing::{info, warn};
-use axum::extract::ws::Message;
-use futures_util::SinkExt;
+use tracing::{debug, info, warn, error};
 
-use crate::state::AppState;
+use std::sync::Arc;
+use crate::config::{ConversationOverflow, InputOverflow};
+use crate::conversations::limiter::ConversationPermit;
+use crate::conversations::{TurnSignals, WebSocketSend};
+use crate::conversations::utils::{with_character_id, with_request_id};
+use crate::agent::output_types::DisplayText;
+use crate::utils::chunked_message::ChunkAssembler;
+use crate::utils::stream_audio::prepare_audio_payload;
+use crate::vad::{BargeInDetector, SpeechSegmenter};
+use crate::state::{
+    AppState, ClientContext, ClientType, ConversationState, ConversationTask, MicConfig, MicFormat,
+    SamplingOverrides,
+};
 
 pub async fn handle_message(
     state: &AppState,
     client_uid: &str,
     text: &str,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    let msg: Value = serde_json::from_str(text)?;
-    let msg_type = msg.get("type").and_then(|v| v.as_str());
+    let mut msg: Value = serde_json::from_str(text)?;
+    let msg_type = msg.get("type").and_then(|v| v.as_str()).map(str::to_owned);
 
-    match msg_type {
+    match msg_type.as_deref() {
         Some("add-client-to-group") => {
             handle_add_to_group(state, client_uid, &msg, sender).await?;
         }
         Some("remove-client-from-group") => {
             handle_remove_from_group(state, client_uid, &msg, sender).await?;
         }
+        Some("chunk") => {
+            if let Some(text) = assemble_chunk(state, client_uid, &msg, sender) {
+                Box::pin(handle_message(state, client_uid, &text, sender)).await?;
+            }
+        }
+        Some("request-capabilities") => {
+            handle_request_capabilities(state, client_uid, sender);
+        }
         Some("request-group-info") => {
             handle_group_info(state, client_uid, sender).await?;
         }
-        Some("text-input") => {
-            handle_text_input(state, client_uid, &msg, sender).await?;
+        Some("resume") => {
+            handle_resume(state, client_uid, &msg, sender).await?;
+        }
+        Some("group-interrupt") => {
+            handle_group_interrupt(state, client_uid, sender).await?;
         }
-        Some("mic-audio-end") => {
-            handle_audio_end(state, client_uid, &msg, sender).await?;
+        Some(
+            trigger @ ("text-input" | "mic-audio-end" | "ai-speak-signal" | "regenerate"
+            | "edit-last-message" | "continue"),
+        ) => {
+            // Voice input can't start a turn while the AI is busy; the
+            // client sends interrupt-signal to barge in
+            if trigger == "mic-audio-end" && state.conversation_state(client_uid).is_busy() {
+                info!("Ignoring mic-audio-end from {} during a conversation", client_uid);
+                if let Some(mut buffer) = state.audio_buffers.get_mut(client_uid) {
+                    buffer.value_mut().clear();
+                }
+                return Ok(());
+            }
+            let request_id = request_id(&msg);
+            let sender = with_request_id(sender, &request_id);
+            if matches!(trigger, "regenerate" | "edit-last-message" | "continue") {
+                // Checked up front so a failed redo doesn't cancel a running turn
+                let has_turn = if trigger == "continue" {
+                    state.client_contexts.get(client_uid).is_some_and(|c| {
+                        c.value().last_input.is_some() || c.value().history_uid.is_some()
+                    })
+                } else {
+                    has_previous_turn(state, client_uid)
+                };
+                if !has_turn {
+                    let error = if trigger == "continue" {
+                        "There is no previous reply to continue"
+                    } else {
+                        "There is no previous turn to redo"
+                    };
+                    send_error(&sender, error);
+                    return Ok(());
+                }
+            }
+            if trigger == "continue"
+                && state.chat_groups.read().await.get_group_members(client_uid).len() > 1
+            {
+                send_error(&sender, "continue is not available in group conversations");
+                return Ok(());
+            }
+            if trigger == "edit-last-message"
+                && msg.get("text").and_then(|v| v.as_str()).is_none_or(|t| t.trim().is_empty())
+            {
+                send_error(&sender, "edit-last-message requires a non-empty 'text'");
+                return Ok(());
+            }
+            if matches!(trigger, "text-input" | "edit-last-message")
+                && !limit_input_length(state, &mut msg, &sender)
+            {
+                return Ok(());
+            }
+            if trigger == "text-input" {
+                if let Err(e) = update_sampling(state, client_uid, &msg) {
+                    send_error(&sender, &e.to_string());
+                    return Ok(());
+                }
+            }
+            let sender = match msg.get("character_id").and_then(|v| v.as_str()) {
+                Some(character_id) => {
+                    if let Err(e) = check_character_turn(state, client_uid, trigger, character_id).await {
+                        send_error(&sender, &e.to_string());
+                        return Ok(());
+                    }
+                    with_character_id(&sender, character_id)
+                }
+                None => sender,
+            };
+            if matches!(trigger, "text-input" | "mic-audio-end") {
+                if let Err(e) = ensure_history(state, client_uid, &sender) {
+                    warn!("Failed to create a history for {}: {}", client_uid, e);
+                }
+            }
+            spawn_conversation(state, client_uid, trigger, &msg, request_id, sender);
         }
         Some("mic-audio-data") => {
-            handle_audio_data(state, client_uid, &msg).await?;
+            handle_audio_data(state, client_uid, &msg, sender).await?;
         }
         Some("raw-audio-data") => {
             handle_raw_audio_data(state, client_uid, &msg, sender).await?;
         }
-        Some("ai-speak-signal") => {
-            handle_ai_speak_signal(state, client_uid, sender).await?;
-        }
         Some("interrupt-signal") => {
-            handle_interrupt(state, client_uid, &msg).await?;
+            handle_interrupt(state, client_uid, &msg, sender).await?;
+        }
+        Some("stop-audio") => {
+            handle_stop_audio(state, client_uid, sender);
+        }
+        Some("mic-config") => {
+            handle_mic_config(state, client_uid, &msg, sender);
+        }
+        Some("set-asr-language") => {
+            handle_set_asr_language(state, client_uid, &msg, sender);
+        }
+        Some("set-tts-engine") => {
+            handle_set_tts_engine(state, client_uid, &msg, sender);
+        }
+        Some("set-tts-rate") => {
+            handle_set_tts_rate(state, client_uid, &msg, sender);
+        }
+        Some("set-barge-in") => {
+            handle_set_barge_in(state, client_uid, &msg, sender)?;
+        }
+        Some("client-type") => {
+            handle_client_type(state, client_uid, &msg, sender);
         }
         Some("fetch-configs") => {
             handle_fetch_configs(state, client_uid, sender).await?;
@@ -48,9 +158,18 @@ pub async fn handle_message(
         Some("switch-config") => {
             handle_switch_config(state, client_uid, &msg, sender).await?;
         }
+        Some("attach-character") => {
+            handle_attach_character(state, client_uid, &msg, sender);
+        }
+        Some("detach-character") => {
+            handle_detach_character(state, client_uid, &msg, sender);
+        }
         Some("fetch-backgrounds") => {
             handle_fetch_backgrounds(state, client_uid, sender).await?;
         }
+        Some("set-background") => {
+            handle_set_background(state, client_uid, &msg, sender).await?;
+        }
         Some("audio-play-start") => {
             handle_audio_play_start(state, client_uid, &msg, sender).await?;
         }
@@ -63,15 +182,30 @@ pub async fn handle_message(
         Some("create-new-history") => {
             handle_create_history(state, client_uid, sender).await?;
         }
+        Some("rename-history") => {
+            handle_rename_history(state, client_uid, &msg, sender).await?;
+        }
         Some("delete-history") => {
             handle_delete_history(state, client_uid, &msg, sender).await?;
         }
+        Some("clear-context") => {
+            handle_clear_context(state, client_uid, sender).await?;
+        }
         Some("expression-command") => {
             handle_expression_command(state, client_uid, &msg, sender).await?;
         }
         Some("motion-command") => {
             handle_motion_command(state, client_uid, &msg, sender).await?;
         }
+        Some("request-model-info") => {
+            handle_model_info(state, sender).await?;
+        }
+        Some("set-sampling") => {
+            handle_set_sampling(state, client_uid, &msg, sender).await?;
+        }
+        Some("set-tts-enabled") => {
+            handle_set_tts_enabled(state, client_uid, &msg, sender).await?;
+        }
         Some("frontend-playback-complete") => {
             // Ignore - just an acknowledgment
         }
@@ -84,14 +218,13 @@ pub async fn handle_message(
 }
 
 async fn handle_add_to_group(
-    state: &AppState,
+    _state: &AppState,
     client_uid: &str,
     msg: &Value,
-    _sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    _sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     let target_uid = msg.get("invitee_uid").and_then(|v| v.as_str());
     if let Some(target) = target_uid {
-        let groups = state.chat_groups.read().await;
         // Implementation for adding to group
         info!("Adding {} to group with {}", target, client_uid);
     }
@@ -102,7 +235,7 @@ async fn handle_remove_from_group(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    _sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    _sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     let target_uid = msg.get("target_uid").and_then(|v| v.as_str());
     if let Some(target) = target_uid {
@@ -111,68 +244,353 @@ async fn handle_remove_from_group(
     Ok(())
 }
 
-async fn handle_text_input(
+/// Add a part of a chunked message
+///
+/// # Returns
+/// The whole message once its last part has arrived
+fn assemble_chunk(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) -> Option<String> {
+    let max_bytes = state.config().system_config.max_chunked_message_bytes;
+    let mut assembler = state
+        .chunk_assemblers
+        .entry(client_uid.to_string())
+        .or_insert_with(|| ChunkAssembler::new(max_bytes));
+    match assembler.push(msg) {
+        Ok(text) => text,
+        Err(e) => {
+            warn!("Dropping chunked message from {}: {}", client_uid, e);
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "error",
+                    "message": e.to_string(),
+                    "chunk_id": msg.get("chunk_id")
+                })
+                .to_string(),
+            );
+            None
+        }
+    }
+}
+
+/// Whether the client has a turn to redo: a cached input, or a human
+/// message in its chat history
+fn has_previous_turn(state: &AppState, client_uid: &str) -> bool {
+    let Some(context) = state.client_contexts.get(client_uid).map(|c| c.value().clone()) else {
+        return false;
+    };
+    if context.last_input.is_some() {
+        return true;
+    }
+    context.history_uid.is_some_and(|history_uid| {
+        crate::chat_history::get_history(&context.conf_uid, &history_uid)
+            .is_ok_and(|messages| messages.iter().any(|m| m.role == "human"))
+    })
+}
+
+/// Correlation id of an inbound message, generated when the client omits it
+fn request_id(msg: &Value) -> String {
+    match msg.get("request_id") {
+        Some(Value::String(id)) if !id.is_empty() => id.clone(),
+        Some(Value::Number(id)) => id.to_string(),
+        _ => uuid::Uuid::new_v4().to_string(),
+    }
+}
+
+/// Run a conversation turn in the background so the receive loop stays free
+/// to handle interrupts. Any previous turn for this client is cancelled.
+///
+/// `sender` should already tag messages with `request_id`.
+pub fn spawn_conversation(
     state: &AppState,
     client_uid: &str,
+    msg_type: &str,
     msg: &Value,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
-) -> anyhow::Result<()> {
-    let text = msg.get("text").and_then(|v| v.as_str()).unwrap_or("");
-    
-    // Call Python agent service
-    let request = crate::python_service::AgentRequest {
-        messages: vec![crate::python_service::Message {
-            role: "user".to_string(),
-            content: text.to_string(),
-        }],
-        context: None,
+    request_id: String,
+    sender: WebSocketSend,
+) {
+    let mut inherited_permit = None;
+    if let Some((_, previous)) = state.conversation_tasks.remove(client_uid) {
+        info!(
+            "Cancelling previous conversation {} for {}",
+            previous.request_id, client_uid
+        );
+        previous.handle.abort();
+        inherited_permit = previous.permit.lock().unwrap().take();
+    }
+
+    state.set_conversation_state(client_uid, ConversationState::Thinking, &sender);
+
+    let task_state = state.clone();
+    let task_uid = client_uid.to_string();
+    let task_type = msg_type.to_string();
+    let task_msg = msg.clone();
+    let character_id = msg.get("character_id").and_then(|v| v.as_str()).map(str::to_owned);
+    let (stop_audio, audio_stopped) = tokio::sync::watch::channel(false);
+    let displayed = Arc::new(std::sync::Mutex::new(String::new()));
+    let signals = TurnSignals {
+        stop_audio: audio_stopped,
+        displayed: displayed.clone(),
     };
 
-    let response = state.python_service.chat(request).await?;
-    
-    // Send response back via WebSocket
-    let _ = sender.send(Message::Text(
+    let permit = Arc::new(std::sync::Mutex::new(None));
+    let task_permit = permit.clone();
+    // The task waits until its entry is in `conversation_tasks`, so the
+    // check at its end can't miss an entry that isn't there yet
+    let (registered, wait_registered) = tokio::sync::oneshot::channel::<()>();
+
+    let task = tokio::spawn(async move {
+        let _ = wait_registered.await;
+        let turn_permit = match inherited_permit {
+            Some(turn_permit) => Some(turn_permit),
+            None => conversation_permit(&task_state, &task_uid, &sender).await,
+        };
+        // Kept in the task entry so the slot is freed however the turn
+        // ends: here, or when the aborted task and its entry are dropped
+        if let Some(turn_permit) = turn_permit {
+            *task_permit.lock().unwrap() = Some(turn_permit);
+            if let Err(e) = crate::conversations::handle_conversation_trigger(
+                &task_state,
+                &task_uid,
+                &task_type,
+                &task_msg,
+                signals,
+                &sender,
+            )
+            .await
+            {
+                error!("Error in conversation for {}: {}", task_uid, e);
+                send_error(&sender, &e.to_string());
+            } else {
+                crate::summary_memory::after_turn(&task_state, &task_uid);
+            }
+            task_permit.lock().unwrap().take();
+        }
+        // A newer turn may have replaced this one while it was finishing;
+        // that turn owns the conversation state now
+        let task_id = tokio::task::id();
+        let owned = task_state
+            .conversation_tasks
+            .remove_if(&task_uid, |_, task| task.handle.id() == task_id)
+            .is_some();
+        if owned {
+            task_state.set_conversation_state(&task_uid, ConversationState::Listening, &sender);
+        }
+    });
+
+    state.conversation_tasks.insert(
+        client_uid.to_string(),
+        ConversationTask {
+            request_id,
+            character_id,
+            handle: task.abort_handle(),
+            stop_audio,
+            displayed,
+            permit,
+        },
+    );
+    let _ = registered.send(());
+}
+
+/// A slot for a turn under `max_active_conversations`
+///
+/// When none is free the client gets a `server-busy` control message and,
+/// depending on `conversation_overflow`, the turn either waits for a slot
+/// or is dropped.
+async fn conversation_permit(
+    state: &AppState,
+    client_uid: &str,
+    sender: &WebSocketSend,
+) -> Option<ConversationPermit> {
+    let limiter = &state.conversation_limiter;
+    if let Some(permit) = limiter.try_acquire() {
+        return Some(permit);
+    }
+
+    let _ = sender.send(serde_json::json!({
+        "type": "control",
+        "text": "server-busy"
+    }).to_string());
+    match limiter.overflow() {
+        ConversationOverflow::Queue => {
+            info!(
+                "Queuing conversation for {}: {} running, {} already queued",
+                client_uid,
+                limiter.active(),
+                limiter.queue_depth()
+            );
+            Some(limiter.acquire().await)
+        }
+        ConversationOverflow::Reject => {
+            warn!(
+                "Rejecting conversation for {}: {} running",
+                client_uid,
+                limiter.active()
+            );
+            None
+        }
+    }
+}
+
+/// Hold the message's `text` to `max_input_chars`, truncating it or
+/// rejecting the message as configured
+///
+/// # Returns
+/// Whether the message should still be processed
+fn limit_input_length(state: &AppState, msg: &mut Value, sender: &WebSocketSend) -> bool {
+    let config = state.config();
+    let Some(max_chars) = config.system_config.max_input_chars else {
+        return true;
+    };
+    let Some(text) = msg.get("text").and_then(|v| v.as_str()) else {
+        return true;
+    };
+    // Byte offset of the first character past the limit
+    let Some((cut, _)) = text.char_indices().nth(max_chars) else {
+        return true;
+    };
+    let length = text.chars().count();
+
+    match config.system_config.input_overflow {
+        InputOverflow::Reject => {
+            send_error(
+                sender,
+                &format!("Input is {} characters long; the limit is {}", length, max_chars),
+            );
+            false
+        }
+        InputOverflow::Truncate => {
+            info!("Truncating input of {} characters to {}", length, max_chars);
+            let truncated = text[..cut].to_string();
+            msg["text"] = Value::from(truncated);
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "control",
+                    "text": "input-truncated",
+                    "original_chars": length,
+                    "max_chars": max_chars
+                })
+                .to_string(),
+            );
+            true
+        }
+    }
+}
+
+/// Validate sampling overrides in a message and store them for the client
+fn update_sampling(state: &AppState, client_uid: &str, msg: &Value) -> anyhow::Result<()> {
+    let overrides = SamplingOverrides::from_message(msg)?;
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().sampling.merge(overrides);
+    }
+    if overrides.temperature.is_some() || overrides.top_p.is_some() {
+        persist_settings(state, client_uid);
+    }
+    Ok(())
+}
+
+/// Whether history writes are flushed to disk, from `fsync_history`
+fn fsync_history(state: &AppState) -> bool {
+    state.config().system_config.fsync_history
+}
+
+/// Save the client's settings with its current history, if it has one, so
+/// reopening the history restores them
+fn persist_settings(state: &AppState, client_uid: &str) {
+    let Some((conf_uid, Some(history_uid), settings)) = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| (c.conf_uid.clone(), c.history_uid.clone(), c.settings()))
+    else {
+        return;
+    };
+    if let Err(e) = crate::chat_history::save_settings(&conf_uid, &history_uid, &settings, fsync_history(state)) {
+        warn!("Failed to save settings for {} to {}: {}", client_uid, history_uid, e);
+    }
+}
+
+async fn handle_set_sampling(
+    state: &AppState,
+    client_uid: &str,
+    msg: &Value,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    if let Err(e) = update_sampling(state, client_uid, msg) {
+        send_error(sender, &e.to_string());
+        return Ok(());
+    }
+
+    let sampling = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| c.value().sampling)
+        .unwrap_or_default();
+    info!("Sampling overrides for {}: {:?}", client_uid, sampling);
+
+    let _ = sender.send(
         serde_json::json!({
-            "type": "full-text",
-            "text": response.text
+            "type": "sampling-updated",
+            "temperature": sampling.temperature,
+            "top_p": sampling.top_p
         })
         .to_string(),
-    ))
-    .await;
+    );
 
     Ok(())
 }
 
-async fn handle_audio_end(
+async fn handle_set_tts_enabled(
     state: &AppState,
     client_uid: &str,
-    _msg: &Value,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    msg: &Value,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    // Get accumulated audio data from buffer and clear it
-    let audio_data = if let Some(mut buffer) = state.audio_buffers.get_mut(client_uid) {
-        let data = buffer.value().clone();
-        buffer.value_mut().clear();
-        data
-    } else {
-        Vec::new()
-    };
+    let enabled = msg
+        .get("enabled")
+        .and_then(|v| v.as_bool())
+        .ok_or_else(|| anyhow::anyhow!("set-tts-enabled requires a boolean 'enabled' field"))?;
 
-    if audio_data.is_empty() {
-        warn!("No audio data in buffer for {}", client_uid);
-        return Ok(());
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().tts_enabled = enabled;
     }
+    persist_settings(state, client_uid);
+    info!("TTS {} for {}", if enabled { "enabled" } else { "disabled" }, client_uid);
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "tts-enabled",
+            "enabled": enabled
+        })
+        .to_string(),
+    );
 
-    // Call Python ASR service
-    let request = crate::python_service::ASRRequest { audio_data };
-    let response = state.python_service.transcribe(request).await?;
+    Ok(())
+}
 
-    // Process transcribed text as text input
-    let text_msg = serde_json::json!({
-        "type": "text-input",
-        "text": response.text
-    });
-    handle_text_input(state, client_uid, &text_msg, sender).await?;
+/// Toggle interrupting the AI by talking over it
+fn handle_set_barge_in(
+    state: &AppState,
+    client_uid: &str,
+    msg: &Value,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let enabled = msg
+        .get("enabled")
+        .and_then(|v| v.as_bool())
+        .ok_or_else(|| anyhow::anyhow!("set-barge-in requires a boolean 'enabled' field"))?;
+
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().barge_in = enabled;
+    }
+    state.barge_in_detectors.remove(client_uid);
+    persist_settings(state, client_uid);
+    info!("Barge-in {} for {}", if enabled { "enabled" } else { "disabled" }, client_uid);
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "barge-in",
+            "enabled": enabled
+        })
+        .to_string(),
+    );
 
     Ok(())
 }
@@ -180,45 +598,209 @@ async fn handle_audio_end(
 async fn handle_fetch_configs(
     state: &AppState,
     client_uid: &str,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     // Scan config directory and send list
-    let _ = sender.send(Message::Text(
+    let _ = sender.send(
         serde_json::json!({
             "type": "config-files",
             "configs": []
         })
         .to_string(),
-    ))
-    .await;
+    );
     Ok(())
 }
 
+/// Switch the server to another character config
+///
+/// The switch applies to every connected client, so while others are
+/// connected it is refused unless `shared_config_switch` is on.
 async fn handle_switch_config(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    _sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let Some(file) = msg.get("file").and_then(|v| v.as_str()) else {
+        return Ok(());
+    };
+    let others = state.client_contexts.iter().filter(|c| c.key() != client_uid).count();
+    if others > 0 && !state.config().system_config.shared_config_switch {
+        warn!("Refused switch-config to {} from {}: {} other clients connected", file, client_uid, others);
+        send_error(
+            sender,
+            &format!(
+                "Switching config would change the character for {} other connected client(s); \
+                 set system_config.shared_config_switch to allow it",
+                others
+            ),
+        );
+        return Ok(());
+    }
+    info!("Switching config to {} for {}", file, client_uid);
+
+    match load_alt_config(state, file) {
+        // Notifies every client and resets their agents
+        Ok(config) => {
+            if let Err(e) = state.update_config(config) {
+                send_error(sender, &format!("Invalid config {}: {}", file, e));
+            }
+        }
+        Err(e) => send_error(sender, &format!("Failed to load config {}: {}", file, e)),
+    }
+    Ok(())
+}
+
+/// Check that a turn addressed to `character_id` can be run
+///
+/// Only `text-input` can name a character, and not in a group conversation,
+/// where the group's members answer.
+async fn check_character_turn(
+    state: &AppState,
+    client_uid: &str,
+    trigger: &str,
+    character_id: &str,
 ) -> anyhow::Result<()> {
-    let config_file = msg.get("file").and_then(|v| v.as_str());
-    if let Some(file) = config_file {
-        info!("Switching config to {}", file);
-        // Reload config and notify client
+    if trigger != "text-input" {
+        anyhow::bail!("character_id is only accepted with text-input");
     }
+    if state.chat_groups.read().await.get_group_members(client_uid).len() > 1 {
+        anyhow::bail!("character_id is not available in group conversations");
+    }
+    state.attached_character(client_uid, character_id)?;
     Ok(())
 }
 
+/// Attach a character from a config file (as listed by `fetch-configs`) to
+/// the client, to be addressed with `character_id` on `text-input`
+///
+/// The character gets its own agent and voice; the client's own character
+/// and every other client are unaffected. `character_id` defaults to the
+/// file name.
+fn handle_attach_character(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) {
+    let Some(file) = msg.get("file").and_then(|v| v.as_str()) else {
+        send_error(sender, "attach-character requires a 'file'");
+        return;
+    };
+    let character_id = msg.get("character_id").and_then(|v| v.as_str()).unwrap_or(file);
+
+    let attached = load_alt_config(state, file)
+        .and_then(|config| state.attach_character(client_uid, character_id, config));
+    match attached {
+        Ok(character) => {
+            let character_config = &character.config.character_config;
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "character-attached",
+                    "character_id": character_id,
+                    "conf_name": character_config.conf_name,
+                    "character_name": character_config.character_name,
+                    "avatar": character_config.avatar
+                })
+                .to_string(),
+            );
+        }
+        Err(e) => send_error(sender, &format!("Failed to attach {}: {}", file, e)),
+    }
+}
+
+/// Detach the character attached as `character_id`, dropping its agent
+fn handle_detach_character(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) {
+    let Some(character_id) = msg.get("character_id").and_then(|v| v.as_str()) else {
+        send_error(sender, "detach-character requires a 'character_id'");
+        return;
+    };
+    if !state.detach_characters(client_uid, Some(character_id)) {
+        send_error(sender, &format!("No character attached as {}", character_id));
+        return;
+    }
+    info!("Detached character {} from {}", character_id, client_uid);
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "character-detached",
+            "character_id": character_id
+        })
+        .to_string(),
+    );
+}
+
+/// Build the config for a `switch-config` request: the character config from
+/// an alternative file merged over the current config
+fn load_alt_config(state: &AppState, file: &str) -> anyhow::Result<crate::config::Config> {
+    use crate::config_manager::utils::{deep_merge, read_config_file};
+
+    // Only bare file names, as listed by `fetch-configs`
+    let is_bare_name = std::path::Path::new(file)
+        .file_name()
+        .is_some_and(|name| name == file);
+    if file.is_empty() || !is_bare_name {
+        anyhow::bail!("Invalid config file name: {}", file);
+    }
+    // The default entry in the list is the base config itself, reloaded
+    // from wherever the server found it at startup
+    if file == "conf.jsonld" {
+        let path = state
+            .config_path
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("The base config was not loaded from a file"))?;
+        return crate::config::Config::load(&path.to_string_lossy());
+    }
+
+    let current = state.config();
+    let path = std::path::Path::new("config")
+        .join(&current.system_config.config_alts_dir)
+        .join(file);
+    let alt = read_config_file(&path.to_string_lossy())?;
+    let character_config = alt
+        .get("character_config")
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("{} has no character_config", file))?;
+
+    let mut merged = serde_json::to_value(current.as_ref())?;
+    deep_merge(&mut merged["character_config"], character_config);
+    Ok(serde_json::from_value(merged)?)
+}
+
 async fn handle_expression_command(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    _sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    let expression_id = msg.get("expression_id").and_then(|v| v.as_str());
-    if let Some(id) = expression_id {
-        info!("Expression command: {}", id);
-        // Send expression update to client
+    // Accept either an expression index or an emotion keyword from the emotion map
+    let expression_id = match msg.get("expression_id") {
+        Some(Value::Number(n)) => n.as_i64().map(|id| id as i32),
+        Some(Value::String(s)) => s.parse::<i32>().ok().or_else(|| {
+            state
+                .live2d_model()
+                .and_then(|model| model.emo_map.get(&s.to_lowercase()).copied())
+        }),
+        _ => None,
+    };
+
+    let Some(id) = expression_id else {
+        let raw = msg.get("expression_id").unwrap_or(&Value::Null);
+        send_error(sender, &format!("Invalid expression_id: {}", raw));
+        return Ok(());
+    };
+
+    if let Some(model) = state.live2d_model() {
+        if !model.has_expression(id) {
+            send_error(
+                sender,
+                &format!("Expression {} is not available for model {}", id, model.live2d_model_name),
+            );
+            return Ok(());
+        }
     }
+
+    info!("Expression command from {}: {}", client_uid, id);
+    let duration = msg.get("duration").and_then(|v| v.as_i64()).map(|d| d as i32);
+    let priority = msg.get("priority").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+    state
+        .backend_adapter(client_uid, sender.clone())?
+        .trigger_expression(id, duration, priority)
+        .await?;
     Ok(())
 }
 
@@ -226,132 +808,756 @@ async fn handle_motion_command(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    _sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     let motion_group = msg.get("motion_group").and_then(|v| v.as_str());
-    let motion_index = msg.get("motion_index").and_then(|v| v.as_u64());
-    if let (Some(group), Some(index)) = (motion_group, motion_index) {
-        info!("Motion command: {}/{}", group, index);
-        // Send motion update to client
+    let motion_index = msg.get("motion_index").and_then(|v| v.as_i64());
+    let (Some(group), Some(index)) = (motion_group, motion_index) else {
+        send_error(sender, "motion-command requires 'motion_group' and 'motion_index'");
+        return Ok(());
+    };
+    let index = index as i32;
+
+    if let Some(model) = state.live2d_model() {
+        if !model.has_motion(group, index) {
+            send_error(
+                sender,
+                &format!(
+                    "Motion {}/{} is not available for model {}",
+                    group, index, model.live2d_model_name
+                ),
+            );
+            return Ok(());
+        }
     }
+
+    info!("Motion command from {}: {}/{}", client_uid, group, index);
+    let loop_motion = msg.get("loop").and_then(|v| v.as_bool()).unwrap_or(false);
+    let priority = msg.get("priority").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+    state
+        .backend_adapter(client_uid, sender.clone())?
+        .trigger_motion(group, index, loop_motion, priority)
+        .await?;
     Ok(())
 }
 
+async fn handle_model_info(state: &AppState, sender: &WebSocketSend) -> anyhow::Result<()> {
+    let Some(model) = state.live2d_model() else {
+        send_error(sender, "No Live2D model is loaded");
+        return Ok(());
+    };
+
+    // Motion groups map to the list of playable indices
+    let motions: serde_json::Map<String, Value> = model
+        .motions
+        .iter()
+        .flatten()
+        .map(|(group, count)| (group.clone(), serde_json::json!((0..*count).collect::<Vec<_>>())))
+        .collect();
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "model-info",
+            "model_name": model.live2d_model_name,
+            "emotion_map": model.emo_map,
+            "expressions": model.expression_ids(),
+            "motions": motions
+        })
+        .to_string(),
+    );
+
+    Ok(())
+}
+
+/// Record the client's mic sample rate, format and channel count, and echo
+/// back what will be used
+fn handle_mic_config(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) {
+    let mic_config = match MicConfig::from_message(msg) {
+        Ok(mic_config) => mic_config,
+        Err(e) => {
+            send_error(sender, &format!("Invalid mic-config: {}", e));
+            return;
+        }
+    };
+
+    let asr_rate = crate::utils::audio::ASR_SAMPLE_RATE;
+    if mic_config.sample_rate != asr_rate {
+        info!(
+            "Client {} mic is {} Hz; audio will be resampled to {} Hz for ASR",
+            client_uid, mic_config.sample_rate, asr_rate
+        );
+    }
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().mic_config = mic_config;
+    }
+    // Drop audio from before the switch rather than mix the two formats
+    if let Some(mut buffer) = state.audio_buffers.get_mut(client_uid) {
+        buffer.value_mut().clear();
+    }
+    state.vad_segmenters.remove(client_uid);
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "mic-config",
+            "sample_rate": mic_config.sample_rate,
+            "format": match mic_config.format {
+                MicFormat::F32 => "f32",
+                MicFormat::Int16 => "int16",
+            },
+            "channels": mic_config.channels,
+            "asr_sample_rate": asr_rate
+        })
+        .to_string(),
+    );
+}
+
+/// Override the ASR language for this session; a null or empty `language`
+/// goes back to the configured one
+fn handle_set_asr_language(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) {
+    let requested = msg
+        .get("language")
+        .and_then(|v| v.as_str())
+        .filter(|l| !l.trim().is_empty());
+    let language = match requested.map(crate::config_manager::asr::validate_asr_language) {
+        Some(Ok(language)) => Some(language),
+        Some(Err(e)) => {
+            send_error(sender, &e.to_string());
+            return;
+        }
+        None => None,
+    };
+
+    info!("ASR language for {}: {}", client_uid, language.as_deref().unwrap_or("(config)"));
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().asr_language = language.clone();
+    }
+    persist_settings(state, client_uid);
+
+    let effective = language
+        .or_else(|| state.config().character_config.asr_config.as_ref()?.language());
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "asr-language",
+            "language": effective
+        })
+        .to_string(),
+    );
+}
+
+/// Choose the TTS engine for this session; a null or empty `engine` goes
+/// back to the configured one
+///
+/// An engine that is unknown or not configured is reported and the
+/// configured one is used instead.
+fn handle_set_tts_engine(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) {
+    let config = state.config();
+    let Some(tts_config) = config.character_config.tts_config.as_ref() else {
+        send_error(sender, "TTS is not configured");
+        return;
+    };
+    let requested = msg
+        .get("engine")
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|e| !e.is_empty() && *e != tts_config.tts_model);
+    let engine = match requested.map(|e| crate::tts::TTSFactory::validate_engine(tts_config, e).map(|_| e)) {
+        Some(Ok(engine)) => Some(engine.to_string()),
+        Some(Err(e)) => {
+            warn!("{}; using {} for {}", e, tts_config.tts_model, client_uid);
+            send_error(sender, &format!("{}; using {}", e, tts_config.tts_model));
+            None
+        }
+        None => None,
+    };
+
+    info!("TTS engine for {}: {}", client_uid, engine.as_deref().unwrap_or(&tts_config.tts_model));
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().tts_engine = engine.clone();
+    }
+    persist_settings(state, client_uid);
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "tts-engine",
+            "engine": engine.unwrap_or_else(|| tts_config.tts_model.clone())
+        })
+        .to_string(),
+    );
+}
+
+/// Set this session's speaking rate as a multiple of normal speed; a null
+/// `rate` goes back to the configured one
+///
+/// The reply says whether the current engine can change its rate; those
+/// that can't keep speaking at their own.
+fn handle_set_tts_rate(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) {
+    let range = crate::tts::client::TTS_RATE_RANGE;
+    let rate = match msg.get("rate") {
+        None | Some(Value::Null) => None,
+        Some(value) => match value.as_f64().filter(|r| range.contains(r)) {
+            Some(rate) => Some((rate * 100.0).round() / 100.0),
+            None => {
+                send_error(
+                    sender,
+                    &format!(
+                        "rate must be a number from {} to {}, or null for the configured rate",
+                        range.start(),
+                        range.end()
+                    ),
+                );
+                return;
+            }
+        },
+    };
+
+    let engine = state
+        .client_contexts
+        .get(client_uid)
+        .and_then(|c| c.value().tts_engine.clone())
+        .or_else(|| {
+            let config = state.config();
+            config.character_config.tts_config.as_ref().map(|c| c.tts_model.clone())
+        });
+    let supported = engine.as_deref().and_then(crate::tts::client::rate_field).is_some();
+    match rate {
+        Some(rate) if !supported => warn!(
+            "TTS rate {}x for {}: {} can't change its rate",
+            rate,
+            client_uid,
+            engine.as_deref().unwrap_or("no engine")
+        ),
+        _ => info!("TTS rate for {}: {:?}", client_uid, rate),
+    }
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().tts_rate = rate;
+    }
+    persist_settings(state, client_uid);
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "tts-rate",
+            "rate": rate,
+            "supported": supported
+        })
+        .to_string(),
+    );
+}
+
+/// Report what the server can do for this client, so the UI can hide
+/// features that aren't available rather than fail on them
+fn handle_request_capabilities(state: &AppState, client_uid: &str, sender: &WebSocketSend) {
+    let config = state.config();
+    let character = &config.character_config;
+
+    let agent_config = character.agent_config.as_ref();
+    let llm_provider = agent_config
+        .filter(|a| a.conversation_agent_choice == "basic_memory_agent")
+        .and_then(|a| a.agent_settings.basic_memory_agent.as_ref())
+        .map(|b| b.llm_provider.clone());
+    let vision = llm_provider
+        .as_deref()
+        .is_some_and(crate::agent::StatelessLLMFactory::supports_images);
+    let prefill = llm_provider
+        .as_deref()
+        .is_some_and(crate::agent::StatelessLLMFactory::supports_prefill);
+
+    let tts_config = character.tts_config.as_ref();
+    let (tts_enabled, tts_engine_name) = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| (c.value().tts_enabled, c.value().tts_engine.clone()))
+        .unwrap_or_default();
+    let tts_engine_name = tts_engine_name.or_else(|| tts_config.map(|c| c.tts_model.clone()));
+    let tts_engine = state.tts_engine_for(client_uid, None);
+    // RVC works on whole files, so it turns streaming off
+    let voice_conversion = tts_config
+        .and_then(|c| c.rvc_model.as_deref())
+        .is_some_and(|m| !m.is_empty());
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "capabilities",
+            "agent": {
+                "type": agent_config.map(|a| a.conversation_agent_choice.clone()),
+                "llm_provider": llm_provider,
+                "vision": vision,
+                "prefill": prefill
+            },
+            "tts": {
+                "engine": tts_engine_name,
+                "enabled": tts_engine.is_some() && tts_enabled,
+                "streaming": tts_engine.as_ref().is_some_and(|e| e.supports_streaming()) && !voice_conversion,
+                "rate_control": tts_engine_name.as_deref().and_then(crate::tts::client::rate_field).is_some(),
+                "engines": tts_config.map(|c| {
+                    crate::tts::factory::TTS_ENGINES
+                        .iter()
+                        .filter(|e| crate::tts::TTSFactory::validate_engine(c, e).is_ok())
+                        .collect::<Vec<_>>()
+                })
+            },
+            "asr": {
+                "engine": character.asr_config.as_ref().map(|c| c.asr_model.clone())
+            },
+            "vad": {
+                // Raw audio is segmented in-process when no VAD is configured
+                "available": true,
+                "engine": character.vad_config.as_ref().map_or("simple_vad", |c| c.vad_model.as_str()),
+                "barge_in": state.default_barge_in()
+            },
+            "live2d": state.live2d_model().is_some(),
+            // Placeholders a persona or tool prompt can use, as `{name}`
+            "prompt_variables": crate::prompts::VARIABLES
+                .iter()
+                .map(|(name, description)| serde_json::json!({ "name": name, "description": description }))
+                .collect::<Vec<_>>(),
+            // Invitations (add-client-to-group) are not implemented yet
+            "group_chat": false
+        })
+        .to_string(),
+    );
+}
+
+/// Switch the client between the avatar and text-only pipelines
+fn handle_client_type(state: &AppState, client_uid: &str, msg: &Value, sender: &WebSocketSend) {
+    let value = msg.get("client_type").and_then(|v| v.as_str()).unwrap_or_default();
+    let Some(client_type) = ClientType::parse(value) else {
+        send_error(sender, &format!("Unknown client_type: {} (expected live2d or text)", value));
+        return;
+    };
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().client_type = client_type;
+    }
+    info!("Client {} is now {:?}", client_uid, client_type);
+}
+
+/// Send an error message to the client
+fn send_error(sender: &WebSocketSend, message: &str) {
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "error",
+            "message": message
+        })
+        .to_string(),
+    );
+}
+
 async fn handle_group_info(
     state: &AppState,
     client_uid: &str,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     let groups = state.chat_groups.read().await;
     let members = groups.get_group_members(client_uid);
-    let group_id = groups.get_client_group(client_uid);
-    
-    let is_owner = if let Some(gid) = group_id {
-        groups.groups.get(&gid).map(|g| g.owner_uid == client_uid).unwrap_or(false)
-    } else {
-        false
-    };
+    let is_owner = groups.is_group_owner(client_uid);
     
-    let _ = sender.send(Message::Text(
+    let _ = sender.send(
         serde_json::json!({
             "type": "group-update",
             "members": members,
             "is_owner": is_owner
         })
         .to_string(),
-    ))
-    .await;
+    );
     
     Ok(())
 }
 
-async fn handle_audio_data(
+/// Pick up the session of an earlier connection: its history, settings,
+/// agent memory and group
+async fn handle_resume(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
+    let Some(token) = msg.get("token").and_then(|v| v.as_str()) else {
+        send_error(sender, "resume requires a 'token'");
+        return Ok(());
+    };
+    let Some(session) = state.take_resume_session(token) else {
+        send_error(sender, "Resume token is invalid or has expired");
+        return Ok(());
+    };
+    if session.conf_uid != state.config().character_config.conf_uid {
+        send_error(sender, "Cannot resume: the character has changed since you disconnected");
+        return Ok(());
+    }
+    info!("Client {} resumed session of {}", client_uid, session.client_uid);
+
+    let context = state.client_contexts.get_mut(client_uid).map(|mut context| {
+        let context = context.value_mut();
+        context.history_uid = session.history_uid.clone();
+        context.apply_settings(&session.settings);
+        context.clone()
+    });
+    // Loads the restored history into a fresh agent
+    state.reset_agent(client_uid);
+    if let Some(context) = context.filter(|c| c.history_uid.is_some()) {
+        if let Err(e) = state.get_or_create_agent(&context) {
+            warn!("Resumed {} without agent memory: {}", client_uid, e);
+        }
+    }
+
+    let mut group_id = None;
+    if let Some(gid) = &session.group_id {
+        let groups = state.chat_groups.read().await;
+        let rejoined = match groups.groups.get_mut(gid) {
+            Some(mut group) => {
+                group.members.retain(|m| m != &session.client_uid && m != client_uid);
+                group.members.push(client_uid.to_string());
+                if group.owner_uid == session.client_uid {
+                    group.owner_uid = client_uid.to_string();
+                }
+                true
+            }
+            None => false,
+        };
+        if rejoined {
+            groups.client_group_map.insert(client_uid.to_string(), gid.clone());
+            group_id = Some(gid.clone());
+        } else {
+            info!("Group {} of resumed client {} no longer exists", gid, client_uid);
+        }
+    }
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "resumed",
+            "history_uid": session.history_uid,
+            "group_id": group_id,
+            "settings": session.settings
+        })
+        .to_string(),
+    );
+    handle_group_info(state, client_uid, sender).await
+}
+
+/// Cut off whichever AI is speaking in the sender's group and pass the turn
+/// to the next member (group owner only)
+async fn handle_group_interrupt(
+    state: &AppState,
+    client_uid: &str,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let (group_id, is_owner, members) = {
+        let groups = state.chat_groups.read().await;
+        (
+            groups.get_client_group(client_uid).filter(|gid| !gid.is_empty()),
+            groups.is_group_owner(client_uid),
+            groups.get_group_members(client_uid),
+        )
+    };
+    let Some(group_id) = group_id else {
+        send_error(sender, "group-interrupt requires being in a group");
+        return Ok(());
+    };
+    if !is_owner {
+        send_error(sender, "Only the group owner can interrupt a speaker");
+        return Ok(());
+    }
+
+    let turn = state.group_conversations.get_mut(&group_id).and_then(|mut conversation| {
+        let speaker = conversation.current_speaker_uid.clone()?;
+        if let Some(turn) = conversation.current_turn.take() {
+            turn.abort();
+        }
+        let next = conversation.advance_turn().map(str::to_string);
+        Some((speaker, next))
+    });
+    let Some((speaker, next_speaker)) = turn else {
+        send_error(sender, "No one is speaking in this group");
+        return Ok(());
+    };
+    info!("Group owner {} interrupted {} in {}", client_uid, speaker, group_id);
+
+    if let Some(agent) = state.agents.get(&speaker).map(|a| a.value().clone()) {
+        agent.lock().await.handle_interrupt("");
+    }
+
+    let message = serde_json::json!({
+        "type": "control",
+        "text": "speaker-interrupted",
+        "speaker_uid": speaker,
+        "next_speaker_uid": next_speaker
+    })
+    .to_string();
+    for member in &members {
+        if let Some(member_sender) = state.client_senders.get(member) {
+            let _ = member_sender.send(message.clone());
+        }
+    }
+
+    Ok(())
+}
+
+/// Samples of a mic message, converted to mono at the ASR sample rate
+fn mic_samples(state: &AppState, client_uid: &str, msg: &Value) -> Vec<f32> {
+    let mic_config = state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| c.value().mic_config)
+        .unwrap_or_default();
+    let scale = match mic_config.format {
+        MicFormat::Int16 => 1.0 / 32768.0,
+        MicFormat::F32 => 1.0,
+    };
     let audio_data = msg
         .get("audio")
         .and_then(|v| v.as_array())
         .map(|arr| {
             arr.iter()
-                .filter_map(|v| v.as_f64().map(|f| f as f32))
+                .filter_map(|v| v.as_f64().map(|f| (f * scale) as f32))
                 .collect::<Vec<f32>>()
         })
         .unwrap_or_default();
-    
+    mic_config.prepare_for_asr(&audio_data)
+}
+
+async fn handle_audio_data(
+    state: &AppState,
+    client_uid: &str,
+    msg: &Value,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    // Mic input is dropped while the AI is thinking or speaking
+    if state.conversation_state(client_uid).is_busy() {
+        return Ok(());
+    }
+
+    let audio_data = mic_samples(state, client_uid, msg);
     if let Some(mut buffer) = state.audio_buffers.get_mut(client_uid) {
         buffer.value_mut().extend(audio_data);
     }
+    crate::conversations::speculative::on_audio(state, client_uid, sender).await;
     
     Ok(())
 }
 
+/// Segment streamed mic audio into utterances
+///
+/// Each finished utterance is buffered for transcription and the client is
+/// sent `mic-audio-end`, as if its own VAD had detected the end of speech.
+/// `vad_model` picks the built-in energy VAD (`simple_vad`, the default) or
+/// the Python service's model (`silero_vad`).
 async fn handle_raw_audio_data(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    // TODO: Process through VAD via Python service
-    // For now, just accumulate audio data
-    handle_audio_data(state, client_uid, msg).await?;
+    let audio_data = mic_samples(state, client_uid, msg);
+    match state.conversation_state(client_uid) {
+        ConversationState::Speaking if barge_in_enabled(state, client_uid) => {
+            if !detect_barge_in(state, client_uid, &audio_data) {
+                return Ok(());
+            }
+            barge_in(state, client_uid, sender).await?;
+            // The interrupt left the client listening; this chunk is the
+            // start of what they are saying
+        }
+        conversation_state if conversation_state.is_busy() => return Ok(()),
+        _ => {}
+    }
+
+    let config = state.config();
+    let vad_config = config.character_config.vad_config.as_ref();
+    let utterances = match vad_config.map(|c| c.vad_model.as_str()) {
+        Some("silero_vad") => {
+            let request = crate::python_service::VADRequest {
+                audio_data,
+                config: vad_config.and_then(|c| serde_json::to_value(c).ok()),
+            };
+            state.python_service.detect_speech(request).await?.audio_segments
+        }
+        _ => {
+            let simple_config = vad_config
+                .and_then(|c| c.simple_vad.clone())
+                .unwrap_or_default();
+            state
+                .vad_segmenters
+                .entry(client_uid.to_string())
+                .or_insert_with(|| SpeechSegmenter::new(&simple_config))
+                .push(&audio_data)
+        }
+    };
+
+    for utterance in utterances {
+        debug!("VAD detected {} samples of speech from {}", utterance.len(), client_uid);
+        if let Some(mut buffer) = state.audio_buffers.get_mut(client_uid) {
+            buffer.value_mut().extend(utterance);
+        }
+        let _ = sender.send(
+            serde_json::json!({
+                "type": "control",
+                "text": "mic-audio-end"
+            })
+            .to_string(),
+        );
+    }
     
-    // Send mic-audio-end signal (simplified - should use VAD)
-    let _ = sender.send(Message::Text(
+    Ok(())
+}
+
+fn barge_in_enabled(state: &AppState, client_uid: &str) -> bool {
+    state
+        .client_contexts
+        .get(client_uid)
+        .is_some_and(|c| c.value().barge_in)
+}
+
+/// Feed mic audio heard during playback to the client's barge-in detector
+fn detect_barge_in(state: &AppState, client_uid: &str, audio_data: &[f32]) -> bool {
+    let (threshold_db, min_speech_ms) = state
+        .config()
+        .character_config
+        .vad_config
+        .as_ref()
+        .map(|c| (c.barge_in_threshold_db, c.barge_in_min_speech_ms))
+        .unwrap_or((-30.0, 300));
+    state
+        .barge_in_detectors
+        .entry(client_uid.to_string())
+        .or_insert_with(|| BargeInDetector::new(threshold_db, min_speech_ms))
+        .push(audio_data)
+}
+
+/// Interrupt the running turn because the user started talking over it
+///
+/// The agent and history keep what had been shown when the user cut in.
+async fn barge_in(state: &AppState, client_uid: &str, sender: &WebSocketSend) -> anyhow::Result<()> {
+    state.barge_in_detectors.remove(client_uid);
+    let displayed = state
+        .conversation_tasks
+        .get(client_uid)
+        .map(|task| task.displayed.lock().map(|d| d.clone()).unwrap_or_default())
+        .unwrap_or_default();
+    info!("Barge-in from {} after: {}", client_uid, displayed);
+
+    // Lets the client stop playing queued audio
+    let _ = sender.send(
         serde_json::json!({
             "type": "control",
-            "text": "mic-audio-end"
+            "text": "interrupt"
         })
         .to_string(),
-    ))
-    .await;
-    
-    Ok(())
+    );
+    interrupt_conversation(state, client_uid, &displayed, None, sender).await
 }
 
-async fn handle_ai_speak_signal(
-    state: &AppState,
-    client_uid: &str,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
-) -> anyhow::Result<()> {
-    // Trigger AI to speak
-    let _ = sender.send(Message::Text(
+/// Silence the running turn without interrupting it
+///
+/// Unlike `interrupt-signal`, the agent keeps generating and the full reply is
+/// shown and saved to history; only speech that has not been sent yet is
+/// dropped.
+fn handle_stop_audio(state: &AppState, client_uid: &str, sender: &WebSocketSend) {
+    match state.conversation_tasks.get(client_uid) {
+        Some(task) => {
+            info!("Stopping audio for {} (request {})", client_uid, task.request_id);
+            task.stop_audio.send_replace(true);
+        }
+        None => info!("stop-audio from {} with no conversation running", client_uid),
+    }
+    let _ = sender.send(
         serde_json::json!({
-            "type": "full-text",
-            "text": "AI wants to speak something..."
+            "type": "control",
+            "text": "audio-stopped"
         })
         .to_string(),
-    ))
-    .await;
-    
-    // Process as empty text input to trigger conversation
-    let text_msg = serde_json::json!({
-        "type": "text-input",
-        "text": ""
-    });
-    handle_text_input(state, client_uid, &text_msg, sender).await?;
-    
-    Ok(())
+    );
 }
 
 async fn handle_interrupt(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     let heard_response = msg.get("text").and_then(|v| v.as_str()).unwrap_or("");
     info!("Interrupt signal from {}: {}", client_uid, heard_response);
-    
-    // Cancel conversation task
-    if let Some((_, handle)) = state.conversation_tasks.remove(client_uid) {
-        handle.abort();
+
+    let target = msg.get("request_id").map(|_| request_id(msg));
+    interrupt_conversation(state, client_uid, heard_response, target, sender).await
+}
+
+/// Cancel the client's turn, recording `heard_response` as what was said
+/// before the interruption
+///
+/// With `target`, only that request is cancelled; if a different one is
+/// running nothing happens.
+async fn interrupt_conversation(
+    state: &AppState,
+    client_uid: &str,
+    heard_response: &str,
+    target: Option<String>,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let removed = state.conversation_tasks.remove_if(client_uid, |_, task| {
+        target.as_ref().is_none_or(|id| *id == task.request_id)
+    });
+    let interrupted = match removed {
+        Some((_, task)) => {
+            task.handle.abort();
+            Some(task.character_id)
+        }
+        None => {
+            if let Some(id) = &target {
+                info!("Interrupt for {} ignored: request {} is not running", client_uid, id);
+                return Ok(());
+            }
+            None
+        }
+    };
+
+    // Record what the user actually heard, followed by the interruption marker
+    if let Some(character_id) = interrupted {
+        let agent = match &character_id {
+            Some(id) => state
+                .character_agents
+                .get(&(client_uid.to_string(), id.clone()))
+                .map(|a| a.value().clone()),
+            None => state.agents.get(client_uid).map(|a| a.value().clone()),
+        };
+        if let Some(agent) = agent {
+            agent.lock().await.handle_interrupt(heard_response);
+        }
+
+        // Attached characters' turns aren't saved to the history
+        let context = state
+            .client_contexts
+            .get(client_uid)
+            .map(|c| c.value().clone())
+            .filter(|_| character_id.is_none());
+        if let Some(ClientContext { conf_uid, history_uid: Some(history_uid), .. }) = context {
+            let config = state.config();
+            let fsync = config.system_config.fsync_history;
+            if !heard_response.is_empty() {
+                crate::chat_history::store_message(
+                    &conf_uid,
+                    &history_uid,
+                    "ai",
+                    heard_response,
+                    Some(&config.character_config.character_name),
+                    config.character_config.avatar.as_deref(),
+                    fsync,
+                )?;
+            }
+            crate::chat_history::store_message(
+                &conf_uid,
+                &history_uid,
+                "system",
+                "[Interrupted by user]",
+                None,
+                None,
+                fsync,
+            )?;
+        }
     }
     
     // Clear audio buffer
     if let Some(mut buffer) = state.audio_buffers.get_mut(client_uid) {
         buffer.value_mut().clear();
     }
+    state.set_conversation_state(client_uid, ConversationState::Listening, sender);
     
     Ok(())
 }
@@ -359,80 +1565,212 @@ async fn handle_interrupt(
 async fn handle_fetch_backgrounds(
     state: &AppState,
     _client_uid: &str,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     // TODO: Scan backgrounds directory
-    let _ = sender.send(Message::Text(
+    let _ = sender.send(
         serde_json::json!({
             "type": "background-files",
             "files": []
         })
         .to_string(),
-    ))
-    .await;
+    );
     
     Ok(())
 }
 
+async fn handle_set_background(
+    state: &AppState,
+    client_uid: &str,
+    msg: &Value,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let Some(file) = msg.get("file").and_then(|v| v.as_str()) else {
+        send_error(sender, "set-background requires a 'file' field");
+        return Ok(());
+    };
+
+    let url = match state.background_url(file) {
+        Ok(url) => url,
+        Err(e) => {
+            send_error(sender, &e.to_string());
+            return Ok(());
+        }
+    };
+
+    if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
+        context.value_mut().background = Some(file.to_string());
+    }
+    info!("Background for {} set to {}", client_uid, file);
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "set-background",
+            "file": file,
+            "url": url
+        })
+        .to_string(),
+    );
+
+    Ok(())
+}
+
+/// Show the text a client started playing to the rest of its group, as a
+/// silent forwarded payload
 async fn handle_audio_play_start(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    _sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    let groups = state.chat_groups.read().await;
-    let members = groups.get_group_members(client_uid);
-    
-    if members.len() > 1 {
-        let display_text = msg.get("display_text");
-        // Broadcast to other group members
-        // TODO: Implement broadcasting
-        info!("Audio play start for group with {} members", members.len());
+    let members = state.chat_groups.read().await.get_group_members(client_uid);
+    if members.len() <= 1 {
+        return Ok(());
+    }
+    let Some(display_text) = msg
+        .get("display_text")
+        .cloned()
+        .and_then(|v| serde_json::from_value::<DisplayText>(v).ok())
+    else {
+        return Ok(());
+    };
+
+    debug!("Forwarding audio play start from {} to {} members", client_uid, members.len() - 1);
+    let payload = prepare_audio_payload(None, Some(&display_text), None, true).to_string();
+    for member in members.iter().filter(|m| *m != client_uid) {
+        if let Some(member_sender) = state.client_senders.get(member) {
+            let _ = member_sender.send(payload.clone());
+        }
     }
-    
     Ok(())
 }
 
 async fn handle_history_list(
     state: &AppState,
     client_uid: &str,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    // TODO: Fetch history list from Python service or file system
-    let _ = sender.send(Message::Text(
+    let conf_uid = client_conf_uid(state, client_uid);
+    let histories = crate::chat_history::get_history_list(&conf_uid)?;
+    
+    let _ = sender.send(
         serde_json::json!({
             "type": "history-list",
-            "histories": []
+            "histories": histories
+        })
+        .to_string(),
+    );
+    
+    Ok(())
+}
+
+async fn handle_rename_history(
+    state: &AppState,
+    client_uid: &str,
+    msg: &Value,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    let history_uid = msg.get("history_uid").and_then(|v| v.as_str());
+    let title = msg.get("title").and_then(|v| v.as_str()).map(str::trim);
+    let (Some(history_uid), Some(title)) = (history_uid, title) else {
+        send_error(sender, "rename-history requires 'history_uid' and 'title'");
+        return Ok(());
+    };
+    if title.is_empty() {
+        send_error(sender, "History title cannot be empty");
+        return Ok(());
+    }
+    
+    let conf_uid = client_conf_uid(state, client_uid);
+    if let Err(e) = crate::chat_history::rename_history(&conf_uid, history_uid, title, fsync_history(state)) {
+        send_error(sender, &e.to_string());
+        return Ok(());
+    }
+    
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "history-renamed",
+            "history_uid": history_uid,
+            "title": title
         })
         .to_string(),
-    ))
-    .await;
+    );
     
     Ok(())
 }
 
+/// The character config the client is currently using
+fn client_conf_uid(state: &AppState, client_uid: &str) -> String {
+    state
+        .client_contexts
+        .get(client_uid)
+        .map(|c| c.value().conf_uid.clone())
+        .unwrap_or_else(|| state.config().character_config.conf_uid.clone())
+}
+
 async fn handle_fetch_history(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     let history_uid = msg.get("history_uid").and_then(|v| v.as_str());
     
     if let Some(uid) = history_uid {
+        let conf_uid = client_conf_uid(state, client_uid);
+        let metadata = crate::chat_history::get_metadata(&conf_uid, uid).unwrap_or_else(|e| {
+            warn!("Could not read metadata of history {}: {}", uid, e);
+            Default::default()
+        });
+        let settings = metadata.settings;
         if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
-            context.value_mut().history_uid = Some(uid.to_string());
+            let context = context.value_mut();
+            context.history_uid = Some(uid.to_string());
+            // Histories saved before settings were persisted keep the current ones
+            if let Some(settings) = &settings {
+                context.apply_settings(settings);
+            }
+        }
+        // Recreated on the next turn with memory loaded from this history
+        state.reset_agent(client_uid);
+
+        if let Some(settings) = settings {
+            info!("Restored settings for {} from history {}: {:?}", client_uid, uid, settings);
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "conversation-settings",
+                    "settings": settings
+                })
+                .to_string(),
+            );
+        }
+
+        // Histories of group conversations bring the client back to the group
+        if let Some(group_id) = metadata.group_id.filter(|g| !g.is_empty()) {
+            let participants = metadata.participants.unwrap_or_default();
+            let members = state
+                .chat_groups
+                .read()
+                .await
+                .restore_group(client_uid, &group_id, &participants);
+            info!("Restored group {} for {} from history {}: {:?}", group_id, client_uid, uid, members);
+            for member in &members {
+                let member_sender = state.client_senders.get(member).map(|s| s.value().clone());
+                if let Some(member_sender) = member_sender.filter(|_| member != client_uid) {
+                    handle_group_info(state, member, &member_sender).await?;
+                }
+            }
+            handle_group_info(state, client_uid, sender).await?;
         }
         
         // TODO: Fetch history from Python service
-        let _ = sender.send(Message::Text(
+        let _ = sender.send(
             serde_json::json!({
                 "type": "history-data",
                 "messages": []
             })
             .to_string(),
-        ))
-        .await;
+        );
     }
     
     Ok(())
@@ -441,24 +1779,109 @@ async fn handle_fetch_history(
 async fn handle_create_history(
     state: &AppState,
     client_uid: &str,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
-    // Generate new history UID
-    let history_uid = uuid::Uuid::new_v4().to_string();
+    start_new_history(state, client_uid, sender)?;
+    state.reset_agent(client_uid);
+    Ok(())
+}
+
+/// Create a history for the client's current character, make it the
+/// client's active history and announce it with `new-history-created`
+fn start_new_history(
+    state: &AppState,
+    client_uid: &str,
+    sender: &WebSocketSend,
+) -> anyhow::Result<String> {
+    let conf_uid = client_conf_uid(state, client_uid);
+    let history_uid = crate::chat_history::create_new_history(&conf_uid, fsync_history(state))?;
+    let metadata = crate::chat_history::get_metadata(&conf_uid, &history_uid)?;
     
     if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
         context.value_mut().history_uid = Some(history_uid.clone());
     }
+    // The new conversation starts with the settings in effect now
+    persist_settings(state, client_uid);
     
-    let _ = sender.send(Message::Text(
+    let _ = sender.send(
         serde_json::json!({
             "type": "new-history-created",
-            "history_uid": history_uid
+            "history_uid": history_uid,
+            "timestamp": metadata.timestamp
         })
         .to_string(),
-    ))
-    .await;
+    );
     
+    Ok(history_uid)
+}
+
+/// Give a client without an active history somewhere to store its first
+/// turn, if `auto_create_history` is on
+///
+/// The agent keeps its memory: anything said before the history existed
+/// simply isn't in the file.
+fn ensure_history(state: &AppState, client_uid: &str, sender: &WebSocketSend) -> anyhow::Result<()> {
+    let has_history = state
+        .client_contexts
+        .get(client_uid)
+        .is_none_or(|c| c.value().history_uid.is_some());
+    if has_history {
+        return Ok(());
+    }
+
+    if !state.config().system_config.auto_create_history {
+        debug!("{} has no history selected; this turn will not be stored", client_uid);
+        return Ok(());
+    }
+
+    let history_uid = start_new_history(state, client_uid, sender)?;
+    info!("Created history {} for {} on first input", history_uid, client_uid);
+    Ok(())
+}
+
+/// Wipe the agent's working memory back to the system prompt, keeping the
+/// history file
+///
+/// Unlike `create-new-history`, later turns go on being saved to the same
+/// history; unlike `delete-history`, nothing is removed from disk. The agent
+/// just no longer remembers what was said.
+async fn handle_clear_context(
+    state: &AppState,
+    client_uid: &str,
+    sender: &WebSocketSend,
+) -> anyhow::Result<()> {
+    if state.conversation_state(client_uid).is_busy() {
+        send_error(sender, "Can't clear the context while a reply is in progress");
+        return Ok(());
+    }
+    let Some(context) = state.client_contexts.get_mut(client_uid).map(|mut c| {
+        // There is no earlier turn left to redo
+        c.value_mut().last_input = None;
+        c.value().clone()
+    }) else {
+        return Ok(());
+    };
+
+    // A new agent would load the history, so one is created to be cleared
+    let agent = state.get_or_create_agent(&context)?;
+    let mut agent = agent.lock().await;
+    // Agents without a memory snapshot keep their memory elsewhere
+    if agent.memory_snapshot().is_none() {
+        send_error(sender, "This agent's memory can't be cleared");
+        return Ok(());
+    }
+    agent.clear_memory();
+    drop(agent);
+    info!("Cleared the context for {}", client_uid);
+
+    let _ = sender.send(
+        serde_json::json!({
+            "type": "context-cleared",
+            "history_uid": context.history_uid
+        })
+        .to_string(),
+    );
+
     Ok(())
 }
 
@@ -466,7 +1889,7 @@ async fn handle_delete_history(
     state: &AppState,
     client_uid: &str,
     msg: &Value,
-    sender: &mut futures_util::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    sender: &WebSocketSend,
 ) -> anyhow::Result<()> {
     let history_uid = msg.get("history_uid").and_then(|v| v.as_str());
     
@@ -474,23 +1897,255 @@ async fn handle_delete_history(
         // TODO: Delete history from Python service or file system
         
         // Clear if it's the current history
-        if let Some(mut context) = state.client_contexts.get_mut(client_uid) {
-            if context.value().history_uid.as_ref().map(|s| s.as_str()) == Some(uid) {
+        let was_current = match state.client_contexts.get_mut(client_uid) {
+            Some(mut context) if context.value().history_uid.as_deref() == Some(uid) => {
                 context.value_mut().history_uid = None;
+                true
             }
+            _ => false,
+        };
+        // Outside the context guard; reset_agent updates the context too
+        if was_current {
+            state.reset_agent(client_uid);
         }
         
-        let _ = sender.send(Message::Text(
+        let _ = sender.send(
             serde_json::json!({
                 "type": "history-deleted",
                 "success": true,
                 "history_uid": uid
             })
             .to_string(),
-        ))
-        .await;
+        );
     }
     
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python_service::mock::MockPythonService;
+    use crate::test_support;
+    use tokio::sync::mpsc;
+
+    async fn state(auto_create_history: bool) -> AppState {
+        let mut config = test_support::config();
+        config.system_config.auto_create_history = auto_create_history;
+        test_support::state(config).await
+    }
+
+    fn history_uid(state: &AppState, client_uid: &str) -> Option<String> {
+        state.client_contexts.get(client_uid).unwrap().history_uid.clone()
+    }
+
+    fn remove_histories(conf_uid: &str) {
+        let _ = std::fs::remove_dir_all(std::path::Path::new("chat_history").join(conf_uid));
+    }
+
+    #[tokio::test]
+    async fn first_input_creates_a_history_when_auto_create_is_on() {
+        let state = state(true).await;
+        let (client_uid, conf_uid) = test_support::connect(&state);
+        let (sender, mut rx) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        ensure_history(&state, &client_uid, &sender).unwrap();
+        let created = history_uid(&state, &client_uid);
+        // A later input keeps the history
+        ensure_history(&state, &client_uid, &sender).unwrap();
+        let histories = crate::chat_history::get_history_list(&conf_uid).unwrap();
+        remove_histories(&conf_uid);
+
+        let created = created.expect("history was not created");
+        assert_eq!(history_uid(&state, &client_uid).as_deref(), Some(created.as_str()));
+        assert_eq!(histories.len(), 1);
+        let message: Value = serde_json::from_str(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(message["type"], "new-history-created");
+        assert_eq!(message["history_uid"], created.as_str());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn redo_without_a_human_message_is_refused_before_spawning() {
+        let state = state(false).await;
+        let (client_uid, conf_uid) = test_support::connect(&state);
+        // A history holding only the character's greeting
+        let history_uid = crate::chat_history::create_new_history(&conf_uid, false).unwrap();
+        crate::chat_history::store_message(&conf_uid, &history_uid, "ai", "Hello!", None, None, false).unwrap();
+        state.client_contexts.get_mut(&client_uid).unwrap().history_uid = Some(history_uid);
+        let (sender, mut rx) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        handle_message(&state, &client_uid, r#"{"type": "regenerate"}"#, &sender).await.unwrap();
+        remove_histories(&conf_uid);
+
+        assert!(!state.conversation_tasks.contains_key(&client_uid));
+        let message: Value = serde_json::from_str(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(message["type"], "error");
+        assert_eq!(message["message"], "There is no previous turn to redo");
+    }
+
+    #[tokio::test]
+    async fn no_history_is_created_when_auto_create_is_off() {
+        let state = state(false).await;
+        let (client_uid, conf_uid) = test_support::connect(&state);
+        let (sender, mut rx) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        ensure_history(&state, &client_uid, &sender).unwrap();
+        let histories = crate::chat_history::get_history_list(&conf_uid).unwrap_or_default();
+        let before = history_uid(&state, &client_uid);
+        let silent = rx.try_recv().is_err();
+        // The client can still create one itself
+        handle_message(&state, &client_uid, r#"{"type": "create-new-history"}"#, &sender)
+            .await
+            .unwrap();
+        remove_histories(&conf_uid);
+
+        assert_eq!(before, None);
+        assert!(histories.is_empty());
+        assert!(silent);
+        assert!(history_uid(&state, &client_uid).is_some());
+        let message: Value = serde_json::from_str(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(message["type"], "new-history-created");
+    }
+
+    #[tokio::test]
+    async fn silero_vad_utterances_are_buffered_for_transcription() {
+        let mut config = test_support::config();
+        config.character_config.vad_config.as_mut().unwrap().vad_model = "silero_vad".to_string();
+        let service = Arc::new(MockPythonService::new().with_speech(vec![vec![0.25; 4], vec![0.5; 2]]));
+        let state = test_support::state_with(config, service.clone()).await;
+        let (client_uid, _) = test_support::connect(&state);
+        state.audio_buffers.insert(client_uid.clone(), Vec::new());
+        let (sender, mut rx) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        handle_message(&state, &client_uid, r#"{"type": "raw-audio-data", "audio": [0.1, 0.2]}"#, &sender)
+            .await
+            .unwrap();
+
+        assert_eq!(service.requests("/vad/detect")[0]["audio_data"].as_array().unwrap().len(), 2);
+        let buffered = state.audio_buffers.get(&client_uid).unwrap().clone();
+        assert_eq!(buffered, [0.25, 0.25, 0.25, 0.25, 0.5, 0.5]);
+        let mut ends = 0;
+        while let Ok(message) = rx.try_recv() {
+            let message: Value = serde_json::from_str(&message).unwrap();
+            assert_eq!(message["text"], "mic-audio-end");
+            ends += 1;
+        }
+        assert_eq!(ends, 2);
+    }
+
+    #[tokio::test]
+    async fn base_config_is_reloaded_from_its_startup_path() {
+        let dir = std::env::temp_dir().join(format!("base-config-{}", uuid::Uuid::new_v4().as_simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("custom.json");
+        let mut base = test_support::config();
+        base.character_config.conf_name = "Startup".to_string();
+        std::fs::write(&path, serde_json::to_string(&base).unwrap()).unwrap();
+
+        let state = test_support::state(test_support::config()).await.with_config_path(&path);
+        let reloaded = load_alt_config(&state, "conf.jsonld");
+        let without_path = load_alt_config(&test_support::state(test_support::config()).await, "conf.jsonld");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reloaded.unwrap().character_config.conf_name, "Startup");
+        assert!(without_path.is_err());
+    }
+
+    /// Switch to a base config named "Switched" as `client_uid`, returning
+    /// the conf_name in use afterwards and whether the client got an error
+    async fn switch_config(state: AppState, client_uid: &str) -> (String, bool) {
+        let dir = std::env::temp_dir().join(format!("switch-config-{}", uuid::Uuid::new_v4().as_simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("base.json");
+        let mut base = test_support::config();
+        base.character_config.conf_name = "Switched".to_string();
+        std::fs::write(&path, serde_json::to_string(&base).unwrap()).unwrap();
+        let state = state.with_config_path(&path);
+        let (sender, mut rx) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+
+        handle_message(&state, client_uid, r#"{"type": "switch-config", "file": "conf.jsonld"}"#, &sender)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut refused = false;
+        while let Ok(message) = rx.try_recv() {
+            let message: Value = serde_json::from_str(&message).unwrap();
+            refused |= message["type"] == "error";
+        }
+        (state.config().character_config.conf_name.clone(), refused)
+    }
+
+    #[tokio::test]
+    async fn a_client_connected_alone_can_switch_config() {
+        let state = state(false).await;
+        let (client_uid, _) = test_support::connect(&state);
+
+        assert_eq!(switch_config(state, &client_uid).await, ("Switched".to_string(), false));
+    }
+
+    #[tokio::test]
+    async fn switch_config_is_refused_while_others_are_connected() {
+        let state = state(false).await;
+        let (client_uid, _) = test_support::connect(&state);
+        test_support::connect(&state);
+
+        assert_eq!(switch_config(state, &client_uid).await, ("Test".to_string(), true));
+    }
+
+    #[tokio::test]
+    async fn shared_config_switch_moves_every_client() {
+        let mut config = test_support::config();
+        config.system_config.shared_config_switch = true;
+        let state = test_support::state(config).await;
+        let (client_uid, _) = test_support::connect(&state);
+        let (other_uid, _) = test_support::connect(&state);
+
+        assert_eq!(switch_config(state.clone(), &client_uid).await, ("Switched".to_string(), false));
+        let other = state.client_contexts.get(&other_uid).unwrap();
+        assert_eq!(other.conf_uid, "test");
+        assert_eq!(other.history_uid, None);
+    }
+
+    #[tokio::test]
+    async fn audio_play_start_is_shown_to_the_rest_of_the_group() {
+        let state = state(false).await;
+        let (client_uid, _) = test_support::connect(&state);
+        let (other_uid, _) = test_support::connect(&state);
+        {
+            let groups = state.chat_groups.read().await;
+            groups.groups.insert(
+                "group".to_string(),
+                crate::state::Group {
+                    owner_uid: client_uid.clone(),
+                    members: vec![client_uid.clone(), other_uid.clone()],
+                },
+            );
+            groups.client_group_map.insert(client_uid.clone(), "group".to_string());
+            groups.client_group_map.insert(other_uid.clone(), "group".to_string());
+        }
+        let (sender, mut own) = mpsc::unbounded_channel::<String>();
+        let sender = WebSocketSend::new(sender);
+        let (other_sender, mut other) = mpsc::unbounded_channel::<String>();
+        let other_sender = WebSocketSend::new(other_sender);
+        state.client_senders.insert(client_uid.clone(), sender.clone());
+        state.client_senders.insert(other_uid, other_sender);
+
+        let msg = r#"{"type": "audio-play-start", "display_text": {"text": "Hello!", "name": "Mio"}}"#;
+        handle_message(&state, &client_uid, msg, &sender).await.unwrap();
+
+        let forwarded: Value = serde_json::from_str(&other.try_recv().unwrap()).unwrap();
+        assert_eq!(forwarded["type"], "audio");
+        assert_eq!(forwarded["audio"], Value::Null);
+        assert_eq!(forwarded["forwarded"], true);
+        assert_eq!(forwarded["display_text"]["text"], "Hello!");
+        assert!(own.try_recv().is_err());
+    }
+}
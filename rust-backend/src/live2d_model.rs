@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use regex::Regex;
+use serde_json::Value;
+use tracing::{debug, info, warn, error};
+
+use crate::config_manager::system::find_model_json;
+use crate::config_manager::utils::load_text_file_with_guess_encoding;
+
+/// Information about a Live2D model loaded from the model dictionary.
+///
+/// This type only prepares and stores the model information (emotion map,
+/// motions, etc.). Sending payloads to the frontend is the caller's job.
+#[derive(Debug, Clone)]
+pub struct Live2DModel {
+    pub model_dict_path: String,
+    pub live2d_model_name: String,
+    pub model_info: Value,
+    /// Emotion keyword (lowercase) -> expression index
+    pub emo_map: BTreeMap<String, i32>,
+    /// Keywords formatted for prompts, e.g. `"[joy], [neutral],"`
+    pub emo_str: String,
+    /// Number of expressions declared by the model file, if it could be read
+    pub expression_count: Option<usize>,
+    /// Motion group -> number of motions, if the model file could be read
+    pub motions: Option<BTreeMap<String, usize>>,
+    emo_pattern: Option<Regex>,
+}
+
+impl Live2DModel {
+    /// Load a model by name from the model dictionary file
+    ///
+    /// # Arguments
+    /// * `live2d_model_name` - Name of the model entry in the dictionary
+    /// * `model_dict_path` - Path to `model_dict.json`
+    pub fn new(live2d_model_name: &str, model_dict_path: &str) -> Result<Self> {
+        let model_info = Self::lookup_model_info(live2d_model_name, model_dict_path)?;
+
+        let emo_map: BTreeMap<String, i32> = model_info
+            .get("emotionMap")
+            .and_then(|v| v.as_object())
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(k, v)| v.as_i64().map(|idx| (k.to_lowercase(), idx as i32)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let emo_str = emo_map
+            .keys()
+            .map(|key| format!("[{}],", key))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // Longest keys first so that e.g. `[joyful]` wins over `[joy]`
+        let mut keys: Vec<&String> = emo_map.keys().collect();
+        keys.sort_by_key(|k| std::cmp::Reverse(k.len()));
+        let emo_pattern = if keys.is_empty() {
+            None
+        } else {
+            let alternatives = keys
+                .iter()
+                .map(|k| regex::escape(k))
+                .collect::<Vec<_>>()
+                .join("|");
+            Some(Regex::new(&format!(r"(?i)\[({})\]", alternatives))?)
+        };
+
+        info!("Live2D model information loaded: {}", live2d_model_name);
+
+        Ok(Self {
+            model_dict_path: model_dict_path.to_string(),
+            live2d_model_name: live2d_model_name.to_string(),
+            model_info,
+            emo_map,
+            emo_str,
+            expression_count: None,
+            motions: None,
+            emo_pattern,
+        })
+    }
+
+    /// Read expressions and motion groups from the model's own definition
+    /// file (`.model.json` or `.model3.json`) under the Live2D models directory
+    ///
+    /// Models served from a remote URL are skipped; their expressions are
+    /// then validated against the emotion map only.
+    pub fn load_model_definition(&mut self, live2d_models_dir: &str) {
+        let Some(relative) = self.local_model_path() else {
+            debug!("Model {} is not served locally, skipping definition", self.live2d_model_name);
+            return;
+        };
+
+        let path = Path::new(live2d_models_dir).join(relative);
+        let definition: Value = match std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(serde_json::from_str(&content)?))
+        {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to read model definition {:?}: {}", path, e);
+                return;
+            }
+        };
+
+        // Cubism 3+ nests everything under FileReferences
+        let root = definition.get("FileReferences").unwrap_or(&definition);
+        let expressions = root.get("Expressions").or_else(|| root.get("expressions"));
+        let motions = root.get("Motions").or_else(|| root.get("motions"));
+
+        self.expression_count = expressions.and_then(|e| e.as_array()).map(|e| e.len());
+        self.motions = motions.and_then(|m| m.as_object()).map(|groups| {
+            groups
+                .iter()
+                .map(|(group, list)| (group.clone(), list.as_array().map_or(0, |l| l.len())))
+                .collect()
+        });
+    }
+
+    /// Check that the model file the frontend will load exists
+    ///
+    /// # Returns
+    /// The model file path, or `None` for models served from a remote URL.
+    /// Fails with the directories searched if no model file is there.
+    pub fn locate_model_file(&self, live2d_models_dir: &str) -> Result<Option<PathBuf>> {
+        let Some(relative) = self.local_model_path() else {
+            return Ok(None);
+        };
+        let path = Path::new(live2d_models_dir).join(relative);
+        if path.is_file() {
+            return Ok(Some(path));
+        }
+
+        let mut searched: Vec<PathBuf> = path.parent().map(Path::to_path_buf).into_iter().collect();
+        let named_dir = Path::new(live2d_models_dir).join(&self.live2d_model_name);
+        if !searched.contains(&named_dir) {
+            searched.push(named_dir);
+        }
+        if let Some(found) = searched.iter().find_map(|dir| find_model_json(dir)) {
+            anyhow::bail!(
+                "Live2D model file {:?} for {} not found, but {:?} exists; fix its url in {}",
+                path,
+                self.live2d_model_name,
+                found,
+                self.model_dict_path
+            );
+        }
+        anyhow::bail!(
+            "No .model.json or .model3.json found for Live2D model {} (searched: {})",
+            self.live2d_model_name,
+            searched.iter().map(|d| format!("{:?}", d)).collect::<Vec<_>>().join(", ")
+        )
+    }
+
+    /// Path of the model file relative to the Live2D models directory, if
+    /// the model is served by this backend
+    fn local_model_path(&self) -> Option<&str> {
+        self.model_info
+            .get("url")
+            .and_then(|u| u.as_str())
+            .and_then(|url| url.strip_prefix("/live2d-models/"))
+    }
+
+    /// Expression indices this model can play
+    pub fn expression_ids(&self) -> Vec<i32> {
+        match self.expression_count {
+            Some(count) => (0..count as i32).collect(),
+            None => {
+                let mut ids: Vec<i32> = self.emo_map.values().copied().collect();
+                ids.sort_unstable();
+                ids.dedup();
+                ids
+            }
+        }
+    }
+
+    /// Whether the expression index can be played by this model
+    pub fn has_expression(&self, expression_id: i32) -> bool {
+        match self.expression_count {
+            Some(count) => expression_id >= 0 && (expression_id as usize) < count,
+            None => self.emo_map.values().any(|&idx| idx == expression_id),
+        }
+    }
+
+    /// Whether the motion group and index exist in this model
+    ///
+    /// Returns true when the model definition could not be loaded.
+    pub fn has_motion(&self, motion_group: &str, motion_index: i32) -> bool {
+        match &self.motions {
+            Some(motions) => motions
+                .get(motion_group)
+                .is_some_and(|&count| motion_index >= 0 && (motion_index as usize) < count),
+            None => true,
+        }
+    }
+
+    fn lookup_model_info(model_name: &str, model_dict_path: &str) -> Result<Value> {
+        let content = load_text_file_with_guess_encoding(model_dict_path).inspect_err(|_| {
+            error!("Model dictionary file not found at {}.", model_dict_path);
+        })?;
+        let model_dict: Vec<Value> = serde_json::from_str(&content).inspect_err(|_| {
+            error!("Error decoding JSON from model dictionary file at {}.", model_dict_path);
+        })?;
+
+        model_dict
+            .into_iter()
+            .find(|model| model.get("name").and_then(|n| n.as_str()) == Some(model_name))
+            .ok_or_else(|| {
+                error!("Unable to find {} in {}.", model_name, model_dict_path);
+                anyhow::anyhow!("{} not found in model dictionary {}", model_name, model_dict_path)
+            })
+    }
+
+    /// Return the expression indices of all emotion keywords found in the
+    /// string, in the order they appear
+    pub fn extract_emotion(&self, str_to_check: &str) -> Vec<i32> {
+        let Some(pattern) = &self.emo_pattern else {
+            return Vec::new();
+        };
+
+        pattern
+            .captures_iter(str_to_check)
+            .filter_map(|caps| self.emo_map.get(&caps[1].to_lowercase()).copied())
+            .collect()
+    }
+
+    /// Like [`Live2DModel::extract_emotion`], paired with the character
+    /// offset each keyword had in the string once keywords are removed
+    pub fn extract_emotion_offsets(&self, str_to_check: &str) -> Vec<(usize, i32)> {
+        self.strip_emotions(str_to_check).1
+    }
+
+    /// Remove all emotion keywords from the string
+    pub fn remove_emotion_keywords(&self, target_str: &str) -> String {
+        self.strip_emotions(target_str).0
+    }
+
+    /// Remove emotion keywords along with the whitespace around them
+    ///
+    /// A run of keywords between words leaves a single space, or none before
+    /// punctuation or where the keywords weren't spaced off (as in CJK text).
+    ///
+    /// # Returns
+    /// The stripped text and, for each keyword in order, its expression and
+    /// character offset in the stripped text
+    fn strip_emotions(&self, text: &str) -> (String, Vec<(usize, i32)>) {
+        let Some(pattern) = &self.emo_pattern else {
+            return (text.to_string(), Vec::new());
+        };
+
+        let mut out = String::new();
+        let mut offsets = Vec::new();
+        // Whitespace was dropped since the last text kept
+        let mut gap = false;
+        let mut push_piece = |out: &mut String, piece: &str, after_tag: bool, before_tag: bool| {
+            let mut piece = piece;
+            if after_tag {
+                gap |= piece.starts_with(char::is_whitespace);
+                piece = piece.trim_start();
+            }
+            let trailing_gap = before_tag && piece.ends_with(char::is_whitespace);
+            if before_tag {
+                piece = piece.trim_end();
+            }
+            if !piece.is_empty() {
+                let punctuation = piece.starts_with(|c: char| ",.!?;:)]}、，。！？；：）」』".contains(c));
+                if gap && !out.is_empty() && !punctuation {
+                    out.push(' ');
+                }
+                out.push_str(piece);
+                gap = false;
+            }
+            gap |= trailing_gap;
+        };
+
+        let mut last = 0;
+        for caps in pattern.captures_iter(text) {
+            let whole = caps.get(0).unwrap();
+            push_piece(&mut out, &text[last..whole.start()], last > 0, true);
+            if let Some(&expression) = self.emo_map.get(&caps[1].to_lowercase()) {
+                offsets.push((out.chars().count(), expression));
+            }
+            last = whole.end();
+        }
+        push_piece(&mut out, &text[last..], last > 0, false);
+        (out, offsets)
+    }
+}
@@ -14,10 +14,18 @@ mod tts;
 mod translate;
 mod vad;
 mod chat_history;
+mod summary_memory;
+mod prompts;
+mod warmup;
+mod agent_reaper;
+mod live2d_model;
+#[cfg(test)]
+mod test_support;
 
 use anyhow::Result;
 use axum::Router;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tower_http::cors::CorsLayer;
 use tracing::info;
 
@@ -60,6 +68,11 @@ async fn main() -> Result<()> {
                 loaded_path = path;
                 break;
             }
+            Err(e) if std::path::Path::new(&path).exists() => {
+                // The file is there but invalid; don't hide why
+                tracing::warn!("Failed to load config from {}: {}", path, e);
+                continue;
+            }
             Err(e) => {
                 tracing::debug!("Failed to load config from {}: {}", path, e);
                 continue;
@@ -85,7 +98,27 @@ async fn main() -> Result<()> {
     info!("Initialized directories");
 
     // Initialize app state
-    let app_state = AppState::new(config.clone()).await?;
+    // Absolute, so the base config is found again whatever the working
+    // directory is when it is reloaded
+    let config_path = std::fs::canonicalize(&loaded_path).unwrap_or_else(|_| loaded_path.clone().into());
+    let app_state = AppState::new(config.clone()).await?.with_config_path(config_path);
+
+    // Clean up generated TTS audio in the background
+    utils::cache_janitor::CacheJanitor::new(
+        config.system_config.cache_dir.clone(),
+        Duration::from_secs(config.system_config.cache_ttl_secs),
+        Duration::from_secs(config.system_config.cache_scan_interval_secs.max(1)),
+        app_state.audio_in_use.clone(),
+        app_state.tts_engine(),
+    )
+    .spawn();
+
+    // Free the agents of clients that stay connected but idle
+    agent_reaper::spawn(app_state.clone());
+
+    if config.system_config.warmup_on_start {
+        warmup::spawn(app_state.clone());
+    }
 
     // Build application
     let app = Router::new()
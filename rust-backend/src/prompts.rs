@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use tracing::debug;
+
+use crate::config::Config;
+
+/// Variables a persona or tool prompt can use as `{name}`, with what each
+/// is replaced by
+pub const VARIABLES: [(&str, &str); 7] = [
+    ("character_name", "The character's name, or the config name when unset"),
+    ("human_name", "What the character calls the user"),
+    ("date", "Today's date, as 2024-01-31"),
+    ("time", "The time the conversation started, as 14:05"),
+    ("weekday", "Today's day of the week, as Wednesday"),
+    ("llm_provider", "The LLM provider answering as the character"),
+    ("model_capabilities", "What the LLM accepts: \"text only\" or \"text and images\""),
+];
+
+/// Values of [`VARIABLES`] for the active character, now
+pub fn variables(config: &Config) -> HashMap<&'static str, String> {
+    let character = &config.character_config;
+    let character_name = if character.character_name.is_empty() {
+        &character.conf_name
+    } else {
+        &character.character_name
+    };
+    let llm_provider = character
+        .agent_config
+        .as_ref()
+        .filter(|a| a.conversation_agent_choice == "basic_memory_agent")
+        .and_then(|a| a.agent_settings.basic_memory_agent.as_ref())
+        .map(|b| b.llm_provider.clone())
+        .unwrap_or_default();
+    let model_capabilities = if crate::agent::StatelessLLMFactory::supports_images(&llm_provider) {
+        "text and images"
+    } else {
+        "text only"
+    };
+    let now = chrono::Local::now();
+
+    HashMap::from([
+        ("character_name", character_name.clone()),
+        ("human_name", character.human_name.clone()),
+        ("date", now.format("%Y-%m-%d").to_string()),
+        ("time", now.format("%H:%M").to_string()),
+        ("weekday", now.format("%A").to_string()),
+        ("llm_provider", llm_provider),
+        ("model_capabilities", model_capabilities.to_string()),
+    ])
+}
+
+/// Replace each `{name}` in `template` with its value in `variables`
+///
+/// Placeholders with no value are left as written, so braces that aren't
+/// meant as variables (JSON examples, say) pass through unchanged.
+pub fn render(template: &str, variables: &HashMap<&'static str, String>) -> String {
+    let pattern = Regex::new(r"\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    let rendered = pattern.replace_all(template, |caps: &regex::Captures| {
+        let name = caps.get(1).unwrap().as_str();
+        match variables.get(name) {
+            Some(value) => value.clone(),
+            None => {
+                debug!("Leaving unknown prompt variable {{{}}} as written", name);
+                caps.get(0).unwrap().as_str().to_string()
+            }
+        }
+    });
+    rendered.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("character_name", "Mio".to_string()),
+            ("human_name", "Alice".to_string()),
+        ])
+    }
+
+    #[test]
+    fn known_variables_are_substituted() {
+        assert_eq!(
+            render("You are {character_name}. Greet {human_name}; {character_name} is cheerful.", &values()),
+            "You are Mio. Greet Alice; Mio is cheerful."
+        );
+        assert_eq!(render("{character_name}{human_name}", &values()), "MioAlice");
+    }
+
+    #[test]
+    fn unknown_placeholders_pass_through() {
+        assert_eq!(
+            render("Hi {human_name}, the {secret_word} is {  spaced }.", &values()),
+            "Hi Alice, the {secret_word} is {  spaced }."
+        );
+        let json = r#"Answer as {"name": "{character_name}", "mood": {mood}}"#;
+        assert_eq!(render(json, &values()), r#"Answer as {"name": "Mio", "mood": {mood}}"#);
+    }
+
+    #[test]
+    fn every_documented_variable_has_a_value() {
+        let mut config = crate::test_support::config();
+        config.character_config.character_name = String::new();
+        let values = variables(&config);
+
+        for (name, _) in VARIABLES {
+            assert!(values.contains_key(name), "{name} has no value");
+        }
+        // The config name stands in for an unset character name
+        assert_eq!(values["character_name"], config.character_config.conf_name);
+        assert!(["text only", "text and images"].contains(&values["model_capabilities"].as_str()));
+        assert_eq!(render("{date}", &values).len(), "2024-01-31".len());
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use serde::Serialize;
+
+use super::{
+    ASRRequest, ASRResponse, AgentRequest, AgentResponse, PythonService, RVCRequest, RVCResponse,
+    TTSRequest, TTSResponse, VADRequest, VADResponse,
+};
+
+/// A canned reply: the value, or the error message to fail with
+type Reply<T> = std::result::Result<T, String>;
+
+/// Replies queued for one endpoint
+///
+/// Each reply is given once, except the last, which is repeated once the
+/// others are used up.
+struct Replies<T>(Mutex<VecDeque<Reply<T>>>);
+
+impl<T: Clone> Replies<T> {
+    fn push(&self, reply: Reply<T>) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push_back(reply);
+    }
+
+    fn next(&self, endpoint: &str) -> Result<T> {
+        let mut replies = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let reply = if replies.len() > 1 {
+            replies.pop_front()
+        } else {
+            replies.front().cloned()
+        };
+        match reply {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(message)) => Err(anyhow::anyhow!(message)),
+            None => Err(anyhow::anyhow!("MockPythonService has no {} reply queued", endpoint)),
+        }
+    }
+}
+
+impl<T> Default for Replies<T> {
+    fn default() -> Self {
+        Self(Mutex::new(VecDeque::new()))
+    }
+}
+
+/// A streamed synthesis: sample rate and its chunks
+type CannedStream = (u32, Vec<Reply<Vec<u8>>>);
+
+/// [`PythonService`] answering from canned replies, for tests
+///
+/// Replies are queued per endpoint with the `with_*` builders; an endpoint
+/// with nothing queued fails. Every request is recorded and can be read
+/// back with [`MockPythonService::requests`].
+#[derive(Default)]
+pub struct MockPythonService {
+    tts: Replies<TTSResponse>,
+    tts_stream: Replies<CannedStream>,
+    rvc: Replies<RVCResponse>,
+    vad: Replies<VADResponse>,
+    asr: Replies<ASRResponse>,
+    chat: Replies<AgentResponse>,
+    healthy: Replies<bool>,
+    requests: Mutex<HashMap<&'static str, Vec<serde_json::Value>>>,
+}
+
+impl MockPythonService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `/tts/synthesize` with a file at `audio_path`
+    pub fn with_tts(self, audio_path: &str) -> Self {
+        self.tts.push(Ok(TTSResponse {
+            audio_path: audio_path.to_string(),
+            success: true,
+            error: None,
+        }));
+        self
+    }
+
+    /// Stream `chunks` of 16-bit PCM at `sample_rate` from `/tts/stream`;
+    /// an `Err` chunk fails the stream at that point
+    pub fn with_tts_stream(self, sample_rate: u32, chunks: Vec<Reply<Vec<u8>>>) -> Self {
+        self.tts_stream.push(Ok((sample_rate, chunks)));
+        self
+    }
+
+    /// Answer `/rvc/convert` with a converted file at `audio_path`
+    pub fn with_voice_conversion(self, audio_path: &str) -> Self {
+        self.rvc.push(Ok(RVCResponse {
+            audio_path: audio_path.to_string(),
+            success: true,
+            error: None,
+        }));
+        self
+    }
+
+    /// Answer `/vad/detect` with the utterances `segments`
+    pub fn with_speech(self, segments: Vec<Vec<f32>>) -> Self {
+        self.vad.push(Ok(VADResponse {
+            speech_detected: !segments.is_empty(),
+            audio_segments: segments,
+            success: true,
+            error: None,
+        }));
+        self
+    }
+
+    /// Answer `/asr/transcribe` with `text`
+    pub fn with_transcription(self, text: &str) -> Self {
+        self.asr.push(Ok(ASRResponse {
+            text: text.to_string(),
+            success: true,
+            error: None,
+        }));
+        self
+    }
+
+    /// Answer `/agent/chat` with `text`
+    pub fn with_chat(self, text: &str) -> Self {
+        self.chat.push(Ok(AgentResponse {
+            text: text.to_string(),
+            success: true,
+            error: None,
+        }));
+        self
+    }
+
+    /// Report the service as up or down
+    pub fn with_health(self, healthy: bool) -> Self {
+        self.healthy.push(Ok(healthy));
+        self
+    }
+
+    /// Fail the next request to `endpoint` (e.g. `/tts/synthesize`) with
+    /// `message`, as the service does when `success` is false
+    pub fn with_error(self, endpoint: &str, message: &str) -> Self {
+        let message = format!("Python service {} failed: {}", endpoint, message);
+        match endpoint {
+            "/tts/synthesize" => self.tts.push(Err(message)),
+            "/tts/stream" => self.tts_stream.push(Err(message)),
+            "/rvc/convert" => self.rvc.push(Err(message)),
+            "/vad/detect" => self.vad.push(Err(message)),
+            "/asr/transcribe" => self.asr.push(Err(message)),
+            "/agent/chat" => self.chat.push(Err(message)),
+            "/health" => self.healthy.push(Err(message)),
+            _ => panic!("MockPythonService has no endpoint {}", endpoint),
+        }
+        self
+    }
+
+    /// Requests made to `endpoint` so far, as JSON
+    pub fn requests(&self, endpoint: &str) -> Vec<serde_json::Value> {
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        requests.get(endpoint).cloned().unwrap_or_default()
+    }
+
+    fn record(&self, endpoint: &'static str, request: &impl Serialize) {
+        let request = serde_json::to_value(request).unwrap_or_default();
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        requests.entry(endpoint).or_default().push(request);
+    }
+}
+
+#[async_trait]
+impl PythonService for MockPythonService {
+    async fn synthesize_tts(&self, request: TTSRequest, config: Option<serde_json::Value>) -> Result<TTSResponse> {
+        self.record("/tts/synthesize", &serde_json::json!({ "request": request, "config": config }));
+        self.tts.next("/tts/synthesize")
+    }
+
+    async fn synthesize_tts_stream(
+        &self,
+        request: TTSRequest,
+        config: Option<serde_json::Value>,
+    ) -> Result<(u32, BoxStream<'static, Result<Vec<u8>>>)> {
+        self.record("/tts/stream", &serde_json::json!({ "request": request, "config": config }));
+        let (sample_rate, chunks) = self.tts_stream.next("/tts/stream")?;
+        let chunks = futures::stream::iter(chunks.into_iter().map(|c| c.map_err(anyhow::Error::msg))).boxed();
+        Ok((sample_rate, chunks))
+    }
+
+    async fn convert_voice(&self, request: RVCRequest) -> Result<RVCResponse> {
+        self.record("/rvc/convert", &request);
+        self.rvc.next("/rvc/convert")
+    }
+
+    async fn detect_speech(&self, request: VADRequest) -> Result<VADResponse> {
+        self.record("/vad/detect", &request);
+        self.vad.next("/vad/detect")
+    }
+
+    async fn transcribe(&self, request: ASRRequest) -> Result<ASRResponse> {
+        self.record("/asr/transcribe", &request);
+        self.asr.next("/asr/transcribe")
+    }
+
+    async fn chat(&self, request: AgentRequest) -> Result<AgentResponse> {
+        self.record("/agent/chat", &request);
+        self.chat.next("/agent/chat")
+    }
+
+    async fn health_check(&self) -> Result<bool> {
+        self.record("/health", &serde_json::Value::Null);
+        self.healthy.next("/health")
+    }
+}
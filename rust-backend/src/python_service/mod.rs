@@ -0,0 +1,365 @@
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use reqwest::Client;
+
+use crate::utils::http_status::HttpStatusError;
+
+#[cfg(test)]
+pub mod mock;
+
+/// The Python AI service's endpoints
+///
+/// Everything that talks to the service holds an `Arc<dyn PythonService>`,
+/// so tests can swap [`PythonServiceClient`] for `mock::MockPythonService`.
+#[async_trait]
+pub trait PythonService: Send + Sync {
+    /// Synthesize `request.text` to a file, with the engine's `config`
+    async fn synthesize_tts(&self, request: TTSRequest, config: Option<serde_json::Value>) -> Result<TTSResponse>;
+
+    /// Stream synthesized speech as 16-bit mono PCM
+    ///
+    /// # Returns
+    /// Sample rate and the audio's byte chunks
+    async fn synthesize_tts_stream(
+        &self,
+        request: TTSRequest,
+        config: Option<serde_json::Value>,
+    ) -> Result<(u32, BoxStream<'static, Result<Vec<u8>>>)>;
+
+    /// Run a synthesized file through RVC voice conversion
+    async fn convert_voice(&self, request: RVCRequest) -> Result<RVCResponse>;
+
+    /// Run the service's VAD over a chunk of 16 kHz mono audio
+    async fn detect_speech(&self, request: VADRequest) -> Result<VADResponse>;
+
+    async fn transcribe(&self, request: ASRRequest) -> Result<ASRResponse>;
+
+    async fn chat(&self, request: AgentRequest) -> Result<AgentResponse>;
+
+    /// Whether the service is up
+    async fn health_check(&self) -> Result<bool>;
+}
+
+/// [`PythonService`] over HTTP
+#[derive(Debug, Clone)]
+pub struct PythonServiceClient {
+    client: Client,
+    base_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSRequest {
+    pub text: String,
+    pub voice: Option<String>,
+    pub language: Option<String>,
+    /// Name (without extension) the service saves the audio under
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name_no_ext: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSResponse {
+    pub audio_path: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RVCRequest {
+    pub audio_path: String,
+    pub model: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RVCResponse {
+    pub audio_path: String,
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ASRRequest {
+    pub audio_data: Vec<f32>,
+    /// Sample rate of `audio_data`, so the service never has to guess it
+    pub sample_rate: u32,
+    /// Interleaved channels in `audio_data`
+    pub channels: u8,
+    /// Language hint; "auto" asks for detection, `None` uses the service default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The active character's `asr_config`, so the service transcribes with
+    /// its engine; `None` uses the service's own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ASRResponse {
+    pub text: String,
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentRequest {
+    pub messages: Vec<Message>,
+    pub context: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentResponse {
+    pub text: String,
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VADRequest {
+    pub audio_data: Vec<f32>,
+    /// The active character's `vad_config`, so the service detects speech
+    /// with its sensitivity; `None` uses the service's own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VADResponse {
+    pub speech_detected: bool,
+    /// Utterances that ended within the request, 16 kHz mono
+    pub audio_segments: Vec<Vec<f32>>,
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Responses carrying the service's `success` / `error` fields
+trait ServiceResponse {
+    fn success(&self) -> bool;
+    fn error(&self) -> Option<&str>;
+}
+
+macro_rules! impl_service_response {
+    ($($ty:ty),*) => {
+        $(impl ServiceResponse for $ty {
+            fn success(&self) -> bool {
+                self.success
+            }
+
+            fn error(&self) -> Option<&str> {
+                self.error.as_deref()
+            }
+        })*
+    };
+}
+
+impl_service_response!(TTSResponse, RVCResponse, ASRResponse, VADResponse, AgentResponse);
+
+impl PythonServiceClient {
+    pub fn new(base_url: String) -> Self {
+        Self {
+            client: Client::new(),
+            base_url,
+        }
+    }
+
+    /// POST to an endpoint and decode its response, turning HTTP errors and
+    /// `success: false` into `Err` with the service's error message
+    async fn post<B, R>(&self, path: &str, body: &B) -> Result<R>
+    where
+        B: Serialize + ?Sized,
+        R: serde::de::DeserializeOwned + ServiceResponse,
+    {
+        let url = format!("{}{}", self.base_url, path);
+        let response = self.client.post(&url).json(body).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            // FastAPI reports failures as {"detail": ...}
+            let text = response.text().await.unwrap_or_default();
+            let detail = serde_json::from_str::<serde_json::Value>(&text)
+                .ok()
+                .and_then(|v| v.get("detail").map(|d| d.as_str().map_or_else(|| d.to_string(), str::to_string)))
+                .unwrap_or(text);
+            return Err(HttpStatusError::new(status, format!("Python service {} failed ({}): {}", path, status, detail)).into());
+        }
+
+        let result: R = response.json().await?;
+        if !result.success() {
+            return Err(anyhow::anyhow!(
+                "Python service {} failed: {}",
+                path,
+                result.error().unwrap_or("unknown error")
+            ));
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl PythonService for PythonServiceClient {
+    async fn synthesize_tts(
+        &self, 
+        request: TTSRequest,
+        config: Option<serde_json::Value>,
+    ) -> Result<TTSResponse> {
+        // Create request body with config
+        let mut body = serde_json::json!({
+            "text": request.text,
+            "voice": request.voice,
+            "language": request.language,
+            "file_name_no_ext": request.file_name_no_ext,
+        });
+        
+        if let Some(config) = config {
+            body["config"] = config;
+        }
+        
+        self.post("/tts/synthesize", &body).await
+    }
+
+    /// The sample rate comes from the `X-Sample-Rate` header
+    async fn synthesize_tts_stream(
+        &self,
+        request: TTSRequest,
+        config: Option<serde_json::Value>,
+    ) -> Result<(u32, BoxStream<'static, Result<Vec<u8>>>)> {
+        let url = format!("{}/tts/stream", self.base_url);
+
+        let mut body = serde_json::json!({
+            "text": request.text,
+            "voice": request.voice,
+            "language": request.language,
+        });
+
+        if let Some(config) = config {
+            body["config"] = config;
+        }
+
+        let response = self.client.post(&url).json(&body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(HttpStatusError::new(status, format!("TTS stream request failed ({}): {}", status, text)).into());
+        }
+
+        let sample_rate = response
+            .headers()
+            .get("x-sample-rate")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(24000);
+
+        let chunks = response
+            .bytes_stream()
+            .map(|chunk| chunk.map(|b| b.to_vec()).map_err(anyhow::Error::from))
+            .boxed();
+
+        Ok((sample_rate, chunks))
+    }
+
+    async fn convert_voice(&self, request: RVCRequest) -> Result<RVCResponse> {
+        self.post("/rvc/convert", &request).await
+    }
+
+    async fn detect_speech(&self, request: VADRequest) -> Result<VADResponse> {
+        self.post("/vad/detect", &request).await
+    }
+
+    async fn transcribe(&self, request: ASRRequest) -> Result<ASRResponse> {
+        self.post("/asr/transcribe", &request).await
+    }
+
+    async fn chat(&self, request: AgentRequest) -> Result<AgentResponse> {
+        self.post("/agent/chat", &request).await
+    }
+
+    async fn health_check(&self) -> Result<bool> {
+        let url = format!("{}/health", self.base_url);
+        let response = self.client.get(&url).send().await?;
+        Ok(response.status().is_success())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::Router;
+
+    /// A client for a local service answering every POST with `status` and `body`
+    async fn client(status: StatusCode, body: serde_json::Value) -> PythonServiceClient {
+        let app = Router::new().fallback(move || async move { (status, axum::Json(body)) });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        PythonServiceClient::new(format!("http://{}", addr))
+    }
+
+    fn chat_request() -> AgentRequest {
+        AgentRequest {
+            messages: vec![Message { role: "user".to_string(), content: "hi".to_string() }],
+            context: None,
+        }
+    }
+
+    fn asr_request() -> ASRRequest {
+        ASRRequest {
+            audio_data: vec![0.0; 160],
+            sample_rate: 16000,
+            channels: 1,
+            language: None,
+            config: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn success_false_is_an_error_with_the_service_message() {
+        let service = client(
+            StatusCode::OK,
+            serde_json::json!({"text": "", "success": false, "error": "model not loaded"}),
+        )
+        .await;
+
+        let err = service.chat(chat_request()).await.unwrap_err().to_string();
+        assert!(err.contains("/agent/chat"), "{err}");
+        assert!(err.contains("model not loaded"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn success_false_without_a_message_is_still_an_error() {
+        let service = client(StatusCode::OK, serde_json::json!({"text": "", "success": false})).await;
+
+        let err = service.transcribe(asr_request()).await.unwrap_err().to_string();
+        assert!(err.contains("/asr/transcribe"), "{err}");
+        assert!(err.contains("unknown error"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn http_errors_carry_the_fastapi_detail() {
+        let service = client(StatusCode::INTERNAL_SERVER_ERROR, serde_json::json!({"detail": "boom"})).await;
+
+        let err = service.transcribe(asr_request()).await.unwrap_err().to_string();
+        assert!(err.contains("500"), "{err}");
+        assert!(err.contains("boom"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn successful_responses_are_returned() {
+        let service = client(StatusCode::OK, serde_json::json!({"text": "hello", "success": true})).await;
+
+        assert_eq!(service.chat(chat_request()).await.unwrap().text, "hello");
+        assert_eq!(service.transcribe(asr_request()).await.unwrap().text, "hello");
+    }
+}
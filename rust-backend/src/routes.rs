@@ -1,18 +1,27 @@
 use axum::{
-    extract::{State, Path, Multipart},
+    extract::{State, Path, Multipart, Query},
     routing::{get, post},
     Router,
     Json,
-    http::StatusCode,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
 };
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::path::PathBuf;
-use tower_http::services::ServeDir;
 
+use crate::conversations::WebSocketSend;
 use crate::state::AppState;
+use crate::agent::MemorySnapshot;
+use crate::utils::auth::require_auth;
+use crate::utils::redact::{config_secrets, redact_value};
+use crate::utils::static_guard::{StaticDir, AUDIO_EXTENSIONS, IMAGE_EXTENSIONS};
 
 pub fn create_routes(state: AppState) -> Router<AppState> {
-    let system_config = &state.config.system_config;
+    let config = state.config();
+    let system_config = &config.system_config;
+    let static_dir =
+        |dir: &str| StaticDir::new(dir).with_follow_symlinks(system_config.follow_static_symlinks);
     
     Router::new()
         // WebSocket
@@ -27,33 +36,96 @@ pub fn create_routes(state: AppState) -> Router<AppState> {
         .route("/api/switch-character/:character_id", post(switch_character))
         .route("/api/expression", post(expression_command))
         .route("/api/motion", post(motion_command))
+        .route("/api/speak", post(speak_command))
+        .route("/api/broadcast", post(broadcast_announcement))
+        .route("/api/history/:conf_uid/:history_uid/export", get(export_history))
+        .route("/api/debug/memory/:client_uid", get(debug_memory))
         .route("/asr", post(transcribe_audio))
         
-        // Static file serving
-        .nest_service("/cache", ServeDir::new(&system_config.cache_dir))
-        .nest_service("/live2d-models", ServeDir::new(&system_config.live2d_models_dir))
-        .nest_service("/bg", ServeDir::new(&system_config.backgrounds_dir))
-        .nest_service("/characters", ServeDir::new(&system_config.characters_dir))
-        .nest_service("/avatars", ServeDir::new(&system_config.avatars_dir))
+        // Static file serving, confined to each directory
+        .nest_service(
+            "/cache",
+            static_dir(&system_config.cache_dir)
+                .with_allowed_extensions(AUDIO_EXTENSIONS)
+                .with_sniffed_audio_type(true)
+                .into_router(),
+        )
+        .nest_service("/live2d-models", static_dir(&system_config.live2d_models_dir).into_router())
+        .nest_service(
+            "/bg",
+            static_dir(&system_config.backgrounds_dir).with_allowed_extensions(IMAGE_EXTENSIONS).into_router(),
+        )
+        .nest_service("/characters", static_dir(&system_config.characters_dir).into_router())
+        .nest_service("/avatars", static_dir(&system_config.avatars_dir).into_router())
+        .layer(axum::middleware::from_fn_with_state(state.clone(), require_auth))
 }
 
 async fn websocket_handler(
     ws: axum::extract::ws::WebSocketUpgrade,
+    query: Query<std::collections::HashMap<String, String>>,
     State(state): State<AppState>,
 ) -> axum::response::Response {
-    crate::websocket::websocket_handler(ws, State(state)).await
+    crate::websocket::websocket_handler(ws, query, State(state)).await
 }
 
-async fn health_check(State(state): State<AppState>) -> Json<Value> {
+/// Subsystem status for monitoring and load-balancer probes
+///
+/// Responds 503 with `status: degraded` when a subsystem needed to hold a
+/// conversation (the Python service or the LLM config) is unhealthy.
+async fn health_check(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
     let python_healthy = state.python_service.health_check().await.unwrap_or(false);
-    Json(json!({
-        "status": "ok",
-        "python_service": python_healthy
-    }))
+    let config = state.config();
+    let character = &config.character_config;
+
+    let llm_configured =
+        character.agent_config.is_some() && config.validate_agent_wiring().is_ok();
+    let tts_configured = character.tts_config.is_some();
+    let asr_configured = character.asr_config.is_some();
+
+    let cache_dir = &config.system_config.cache_dir;
+    let cache_free_bytes = fs2::available_space(cache_dir).ok();
+
+    let healthy = python_healthy && llm_configured;
+    let status_code = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status_code,
+        Json(json!({
+            "status": if healthy { "ok" } else { "degraded" },
+            "uptime_secs": state.started_at.elapsed().as_secs(),
+            "python_service": python_healthy,
+            "active_clients": state.client_contexts.len(),
+            "active_conversations": state.conversation_tasks.len(),
+            "config": {
+                "conf_name": character.conf_name,
+                "llm": llm_configured,
+                "tts": tts_configured,
+                "asr": asr_configured
+            },
+            "cache": {
+                "dir": cache_dir,
+                "free_bytes": cache_free_bytes
+            },
+            "tts_synthesis": {
+                "max_concurrent": state.tts_limiter.limit(),
+                "in_flight": state.tts_limiter.in_flight(),
+                "queued": state.tts_limiter.queue_depth()
+            },
+            "conversations": {
+                "max_active": state.conversation_limiter.limit(),
+                "active": state.conversation_limiter.active(),
+                "queued": state.conversation_limiter.queue_depth()
+            }
+        })),
+    )
 }
 
 async fn get_backgrounds(State(state): State<AppState>) -> Json<Value> {
-    let backgrounds_dir = PathBuf::from(&state.config.system_config.backgrounds_dir);
+    let backgrounds_dir = PathBuf::from(&state.config().system_config.backgrounds_dir);
     let mut backgrounds = Vec::new();
     
     if let Ok(entries) = std::fs::read_dir(&backgrounds_dir) {
@@ -82,7 +154,8 @@ async fn get_backgrounds(State(state): State<AppState>) -> Json<Value> {
 
 async fn get_base_config(State(state): State<AppState>) -> Json<Value> {
     // Return base configuration for Live2D viewer
-    let character = &state.config.character_config;
+    let config = state.config();
+    let character = &config.character_config;
     Json(json!({
         "character": {
             "id": character.conf_uid,
@@ -94,9 +167,138 @@ async fn get_base_config(State(state): State<AppState>) -> Json<Value> {
     }))
 }
 
+/// What a client's agent would send to its LLM: the assembled system
+/// prompt and the messages in memory, with secrets redacted
+///
+/// Only served with `debug_endpoints` on. For a client whose agent hasn't
+/// been created yet, the system prompt it would start with is shown.
+async fn debug_memory(
+    State(state): State<AppState>,
+    Path(client_uid): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let config = state.config();
+    if !config.system_config.debug_endpoints {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))));
+    }
+    let Some(context) = state.client_contexts.get(&client_uid).map(|c| c.value().clone()) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Client not connected: {}", client_uid)})),
+        ));
+    };
+
+    let agent = state.agents.get(&client_uid).map(|a| a.value().clone());
+    let snapshot = match &agent {
+        Some(agent) => {
+            // Don't wait out a turn that is streaming
+            let Ok(agent) = agent.try_lock() else {
+                return Err((
+                    StatusCode::CONFLICT,
+                    Json(json!({"error": "The agent is busy with a turn; try again"})),
+                ));
+            };
+            agent.memory_snapshot()
+        }
+        None => Some(MemorySnapshot {
+            system: state.build_system_prompt(&config),
+            messages: Vec::new(),
+        }),
+    };
+
+    let config_value = serde_json::to_value(&*config).unwrap_or_default();
+    let secrets = config_secrets(&config_value);
+    let mut memory = match snapshot {
+        Some(snapshot) => json!({
+            "system": snapshot.system,
+            "messages": snapshot.messages
+        }),
+        // The agent keeps its memory elsewhere
+        None => Value::Null,
+    };
+    redact_value(&mut memory, &secrets);
+
+    Ok(Json(json!({
+        "client_uid": client_uid,
+        "conf_uid": context.conf_uid,
+        "history_uid": context.history_uid,
+        "agent_created": agent.is_some(),
+        "memory": memory
+    })))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default = "default_export_format")]
+    format: String,
+}
+
+fn default_export_format() -> String {
+    "md".to_string()
+}
+
+/// Download a chat history as a Markdown transcript or as JSON messages
+async fn export_history(
+    State(state): State<AppState>,
+    Path((conf_uid, history_uid)): Path<(String, String)>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let bad_request = |e: anyhow::Error| (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()})));
+    let internal = |e: anyhow::Error| {
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()})))
+    };
+
+    if !crate::chat_history::history_exists(&conf_uid, &history_uid).map_err(bad_request)? {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("History not found: {}", history_uid)})),
+        ));
+    }
+
+    let (body, content_type, extension) = match query.format.as_str() {
+        "md" | "markdown" => {
+            let config = state.config();
+            let character = &config.character_config;
+            let markdown = crate::chat_history::export_markdown(
+                &conf_uid,
+                &history_uid,
+                &character.character_name,
+                &character.human_name,
+            )
+            .map_err(internal)?;
+            (markdown, "text/markdown; charset=utf-8", "md")
+        }
+        "json" => {
+            let messages = crate::chat_history::get_history(&conf_uid, &history_uid).map_err(internal)?;
+            let json = serde_json::to_string_pretty(&messages).map_err(|e| internal(e.into()))?;
+            (json, "application/json", "json")
+        }
+        other => {
+            return Err(bad_request(anyhow::anyhow!(
+                "Unsupported export format: {} (expected md or json)",
+                other
+            )))
+        }
+    };
+
+    // History uids may contain quotes or non-ASCII, which don't belong in a header
+    let file_stem: String = history_uid
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let disposition = format!("attachment; filename=\"{}.{}\"", file_stem, extension);
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
 async fn switch_character(
     State(_state): State<AppState>,
-    Path(character_id): Path<String>,
+    Path(_character_id): Path<String>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     // TODO: Implement character switching
     Err((
@@ -105,30 +307,77 @@ async fn switch_character(
     ))
 }
 
+type ApiError = (StatusCode, Json<Value>);
+
+/// Clients a REST command is sent to: the one named by `clientUid` (or
+/// `client_uid`), or every connected client
+fn command_targets(
+    state: &AppState,
+    payload: &Value,
+) -> Result<Vec<(String, WebSocketSend)>, ApiError> {
+    let client_uid = payload
+        .get("clientUid")
+        .or_else(|| payload.get("client_uid"))
+        .and_then(|v| v.as_str());
+    match client_uid {
+        Some(client_uid) => state
+            .client_senders
+            .get(client_uid)
+            .map(|sender| vec![(client_uid.to_string(), sender.value().clone())])
+            .ok_or_else(|| (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": format!("Client {} is not connected", client_uid)}))
+            )),
+        None => Ok(state
+            .client_senders
+            .iter()
+            .map(|sender| (sender.key().clone(), sender.value().clone()))
+            .collect()),
+    }
+}
+
+fn adapter_error(e: anyhow::Error) -> ApiError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({"error": e.to_string()}))
+    )
+}
+
 async fn expression_command(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Json(payload): Json<Value>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    // TODO: Implement expression command
     let expression_id = payload.get("expressionId")
         .and_then(|v| v.as_u64())
         .ok_or_else(|| (
             StatusCode::BAD_REQUEST,
             Json(json!({"error": "expressionId is required"}))
         ))?;
-    
-    // TODO: Trigger expression through adapter
+    let expression_id = expression_id as i32;
+    let duration = payload.get("duration").and_then(|v| v.as_i64()).map(|d| d as i32);
+    let priority = payload.get("priority").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+
+    let targets = command_targets(&state, &payload)?;
+    for (client_uid, sender) in &targets {
+        state
+            .backend_adapter(client_uid, sender.clone())
+            .map_err(adapter_error)?
+            .trigger_expression(expression_id, duration, priority)
+            .await
+            .map_err(adapter_error)?;
+    }
+
     Ok(Json(json!({
         "status": "success",
-        "expression_id": expression_id
+        "expression_id": expression_id,
+        "clients": targets.len()
     })))
 }
 
 async fn motion_command(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Json(payload): Json<Value>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    // TODO: Implement motion command
     let motion_group = payload.get("motionGroup")
         .and_then(|v| v.as_str())
         .ok_or_else(|| (
@@ -143,11 +392,212 @@ async fn motion_command(
             Json(json!({"error": "motionIndex is required"}))
         ))?;
     
-    // TODO: Trigger motion through adapter
+    let motion_index = motion_index as i32;
+    let loop_motion = payload.get("loop").and_then(|v| v.as_bool()).unwrap_or(false);
+    let priority = payload.get("priority").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+
+    let targets = command_targets(&state, &payload)?;
+    for (client_uid, sender) in &targets {
+        state
+            .backend_adapter(client_uid, sender.clone())
+            .map_err(adapter_error)?
+            .trigger_motion(motion_group, motion_index, loop_motion, priority)
+            .await
+            .map_err(adapter_error)?;
+    }
+
     Ok(Json(json!({
         "status": "success",
         "motion_group": motion_group,
-        "motion_index": motion_index
+        "motion_index": motion_index,
+        "clients": targets.len()
+    })))
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    (StatusCode::BAD_REQUEST, Json(json!({"error": message.into()})))
+}
+
+/// How prominently clients show an announcement
+const ANNOUNCEMENT_SEVERITIES: [&str; 3] = ["info", "warning", "critical"];
+
+/// Send an operator announcement (maintenance, a restart) to every
+/// connected client
+///
+/// Takes `message`, a `severity` of info (the default), warning or
+/// critical, and optionally `dismiss_after_ms` for clients to hide it
+/// after. Observers only get it with `include_observers`. Only available
+/// with `auth_enabled`, as anyone reaching the server could send one
+/// otherwise.
+async fn broadcast_announcement(
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Json<Value>, ApiError> {
+    let config = state.config();
+    if !config.system_config.auth_enabled {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "Broadcasting requires auth_enabled"})),
+        ));
+    }
+
+    let message = payload
+        .get("message")
+        .and_then(|v| v.as_str())
+        .filter(|m| !m.trim().is_empty())
+        .ok_or_else(|| bad_request("message is required"))?;
+    let severity = match payload.get("severity") {
+        None | Some(Value::Null) => "info",
+        Some(severity) => severity
+            .as_str()
+            .filter(|s| ANNOUNCEMENT_SEVERITIES.contains(s))
+            .ok_or_else(|| {
+                bad_request(format!("severity must be one of {}", ANNOUNCEMENT_SEVERITIES.join(", ")))
+            })?,
+    };
+    let dismiss_after_ms = match payload.get("dismiss_after_ms") {
+        None | Some(Value::Null) => None,
+        Some(ms) => Some(
+            ms.as_u64()
+                .filter(|&ms| ms > 0)
+                .ok_or_else(|| bad_request("dismiss_after_ms must be a positive integer"))?,
+        ),
+    };
+    let include_observers = payload
+        .get("include_observers")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let announcement = json!({
+        "type": "announcement",
+        "id": uuid::Uuid::new_v4().to_string(),
+        "message": message,
+        "severity": severity,
+        "dismiss_after_ms": dismiss_after_ms,
+        "sent_at": chrono::Utc::now().to_rfc3339(),
+    })
+    .to_string();
+
+    let mut clients = 0;
+    for sender in state.client_senders.iter() {
+        if sender.value().send(announcement.clone()).is_ok() {
+            clients += 1;
+        }
+    }
+    let mut observers = 0;
+    if include_observers {
+        for observer in state.observers.iter() {
+            if observer.sender.send(announcement.clone()).is_ok() {
+                observers += 1;
+            }
+        }
+    }
+    tracing::info!(
+        "Broadcast {} announcement to {} clients and {} observers",
+        severity,
+        clients,
+        observers
+    );
+
+    Ok(Json(json!({
+        "status": "success",
+        "clients": clients,
+        "observers": observers
+    })))
+}
+
+/// Make the character say `text` without going through the agent
+///
+/// The text gets the same expression and TTS handling as a reply sentence.
+/// `expressions` (indices or emotion keywords) replaces any emotion tags in
+/// the text. Clients with TTS turned off get the text and expressions only.
+async fn speak_command(
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Json<Value>, ApiError> {
+    use crate::conversations::single_conversation::speak_text;
+    use crate::conversations::TurnSignals;
+    use crate::state::ConversationState;
+
+    let text = payload
+        .get("text")
+        .and_then(|v| v.as_str())
+        .filter(|t| !t.trim().is_empty())
+        .ok_or_else(|| bad_request("text is required"))?;
+    let targets = command_targets(&state, &payload)?;
+    let live2d_model = state.live2d_model();
+    let live2d_model = live2d_model.as_deref();
+
+    let expressions = match payload.get("expressions") {
+        Some(requested) => {
+            let requested = requested
+                .as_array()
+                .ok_or_else(|| bad_request("expressions must be an array"))?;
+            let expressions = requested
+                .iter()
+                .map(|e| match e {
+                    Value::Number(n) => n.as_i64().map(Value::from),
+                    Value::String(keyword) => live2d_model
+                        .and_then(|m| m.emo_map.get(&keyword.to_lowercase()))
+                        .map(|&id| Value::from(id)),
+                    _ => None,
+                }
+                .ok_or_else(|| bad_request(format!("Unknown expression: {}", e))))
+                .collect::<Result<Vec<_>, _>>()?;
+            Some(expressions)
+        }
+        None => None,
+    };
+
+    // Each client hears the text in its own voice, as a reply of its own
+    let spoken = targets.into_iter().map(|(client_uid, sender)| {
+        let state = &state;
+        let expressions = expressions.clone();
+        async move {
+            let (tap, mut tapped) = tokio::sync::mpsc::unbounded_channel();
+            let tap = WebSocketSend::new(tap);
+            let (_stop_audio, audio_stopped) = tokio::sync::watch::channel(false);
+            let signals = TurnSignals {
+                stop_audio: audio_stopped,
+                displayed: Default::default(),
+            };
+            state.set_conversation_state(&client_uid, ConversationState::Thinking, &sender);
+            let speak = {
+                let client_uid = client_uid.as_str();
+                async move {
+                    speak_text(state, client_uid, text, expressions, signals, &tap).await
+                }
+            };
+            // Passes the reply on as it is produced, noting the audio sent
+            let forward = async {
+                let mut audio = Vec::new();
+                while let Some(message) = tapped.recv().await {
+                    if let Ok(payload) = serde_json::from_str::<Value>(&message) {
+                        if payload["type"] == "audio" {
+                            audio.extend(payload["audio"].as_str().map(str::to_owned));
+                        }
+                    }
+                    let _ = sender.send(message);
+                }
+                audio
+            };
+            let ((), audio) = tokio::join!(speak, forward);
+            // A turn the client started meanwhile sets its own state
+            if !state.conversation_tasks.contains_key(&client_uid) {
+                state.set_conversation_state(&client_uid, ConversationState::Listening, &sender);
+            }
+            (client_uid, Value::from(audio))
+        }
+    });
+    let audio = futures::future::join_all(spoken)
+        .await
+        .into_iter()
+        .collect::<serde_json::Map<_, _>>();
+
+    Ok(Json(json!({
+        "status": "success",
+        "clients": audio.len(),
+        "audio": audio
     })))
 }
 
@@ -155,14 +605,29 @@ async fn transcribe_audio(
     State(state): State<AppState>,
     mut multipart: Multipart,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    // TODO: Implement audio transcription via Python service
     while let Some(field) = multipart.next_field().await.unwrap_or(None) {
         if field.name() == Some("file") {
             if let Ok(data) = field.bytes().await {
-                // TODO: Send to Python ASR service
-                return Ok(Json(json!({
-                    "text": "Transcription not yet implemented"
-                })));
+                use crate::utils::audio::{decode_wav, resample_linear, ASR_SAMPLE_RATE};
+
+                let (samples, sample_rate) = decode_wav(&data).map_err(|e| (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"error": format!("Invalid audio file: {}", e)}))
+                ))?;
+                let samples = resample_linear(&samples, sample_rate, ASR_SAMPLE_RATE);
+                let language = state
+                    .config()
+                    .character_config
+                    .asr_config
+                    .as_ref()
+                    .and_then(|c| c.language());
+                let text = crate::asr::transcribe(&state, samples, language)
+                    .await
+                    .map_err(|e| (
+                        StatusCode::BAD_GATEWAY,
+                        Json(json!({"error": format!("Transcription failed: {}", e)}))
+                    ))?;
+                return Ok(Json(json!({ "text": text })));
             }
         }
     }
@@ -173,3 +638,37 @@ async fn transcribe_audio(
     ))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python_service::mock::MockPythonService;
+    use crate::test_support;
+    use std::sync::Arc;
+
+    async fn health(service: MockPythonService) -> (StatusCode, Value) {
+        let state = test_support::state_with(test_support::config(), Arc::new(service)).await;
+        let (status, Json(body)) = health_check(State(state)).await;
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn health_follows_the_python_service() {
+        let (status, body) = health(MockPythonService::new().with_health(true)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["python_service"], true);
+
+        let (status, body) = health(MockPythonService::new().with_health(false)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["python_service"], false);
+    }
+
+    #[tokio::test]
+    async fn unreachable_python_service_is_degraded() {
+        let (status, body) = health(MockPythonService::new().with_error("/health", "connection refused")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["python_service"], false);
+    }
+}
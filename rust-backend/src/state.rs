@@ -1,19 +1,180 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use dashmap::DashMap;
-use tokio::sync::RwLock;
+use dashmap::{DashMap, DashSet};
+use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
+use tracing::{debug, error, info, warn};
 
+use crate::adapters::{AdapterFactory, BackendAdapter};
+use crate::agent::agents::AgentInterface;
+use crate::chat_history::ConversationSettings;
+use crate::agent::input_types::BatchInput;
+use crate::agent::agent_factory::AgentFactory;
+use crate::agent::{StatelessLLMFactory, StatelessLLMInterface};
 use crate::config::Config;
-use crate::python_service::PythonServiceClient;
+use crate::conversations::emotion_inference::EmotionCache;
+use crate::conversations::filler::{self, PendingFiller};
+use crate::conversations::limiter::ConversationPermit;
+use crate::conversations::speculative::Speculation;
+use crate::conversations::{ConversationLimiter, GroupConversationState, WebSocketSend};
+use crate::live2d_model::Live2DModel;
+use crate::python_service::{PythonService, PythonServiceClient};
+use crate::tts::{TTSFactory, TTSInterface, TTSLimiter};
+use crate::utils::audio_format::AudioFormat;
+use crate::utils::cache_janitor::AudioInUse;
+use crate::utils::chunked_message::ChunkAssembler;
+use crate::vad::{BargeInDetector, SpeechSegmenter};
+
+/// What is built from the configured character, replaced along with the
+/// configuration
+#[derive(Clone, Default)]
+struct CharacterResources {
+    live2d_model: Option<Arc<Live2DModel>>,
+    /// Why the Live2D model couldn't be loaded; clients are then told the
+    /// server runs audio-only
+    live2d_unavailable: Option<String>,
+    tts_engine: Option<Arc<dyn TTSInterface>>,
+}
+
+impl CharacterResources {
+    /// Load the character's Live2D model and build its TTS engine
+    ///
+    /// A model that can't be loaded leaves the server audio-only; a TTS
+    /// engine that can't be built is an error.
+    fn build(config: &Config, python_service: &Arc<dyn PythonService>) -> anyhow::Result<Self> {
+        let live2d_models_dir = &config.system_config.live2d_models_dir;
+        let (live2d_model, live2d_unavailable) = match Live2DModel::new(
+            &config.character_config.live2d_model_name,
+            &config.system_config.model_dict_path,
+        )
+        .and_then(|model| model.locate_model_file(live2d_models_dir).map(|_| model))
+        {
+            Ok(mut model) => {
+                model.load_model_definition(live2d_models_dir);
+                (Some(Arc::new(model)), None)
+            }
+            Err(e) => {
+                error!("{}", e);
+                warn!("Running in audio-only mode without a Live2D avatar or expressions");
+                (None, Some(e.to_string()))
+            }
+        };
+
+        let tts_engine = match &config.character_config.tts_config {
+            Some(tts_config) => Some(TTSFactory::create_tts(tts_config, python_service.clone())?),
+            None => None,
+        };
+        Ok(Self {
+            live2d_model,
+            live2d_unavailable,
+            tts_engine,
+        })
+    }
+}
 
 #[derive(Clone)]
 pub struct AppState {
-    pub config: Config,
+    /// Current configuration; read through [`AppState::config`] and replaced
+    /// with [`AppState::update_config`]
+    config: Arc<std::sync::RwLock<Arc<Config>>>,
+    /// File the configuration was loaded from at startup; `switch-config`
+    /// back to the base config reloads it
+    pub config_path: Option<Arc<std::path::PathBuf>>,
     pub client_contexts: Arc<DashMap<String, ClientContext>>,
+    /// Outbound channel of every connected client
+    pub client_senders: Arc<DashMap<String, WebSocketSend>>,
     pub chat_groups: Arc<RwLock<ChatGroupManager>>,
-    pub python_service: Arc<PythonServiceClient>,
+    /// Group conversations in progress, keyed by group id
+    pub group_conversations: Arc<DashMap<String, GroupConversationState>>,
+    pub python_service: Arc<dyn PythonService>,
+    /// Mic audio awaiting transcription, already mono at the ASR sample rate
     pub audio_buffers: Arc<DashMap<String, Vec<f32>>>,
-    pub conversation_tasks: Arc<DashMap<String, tokio::task::AbortHandle>>,
+    /// Messages clients are sending in `chunk` parts
+    pub chunk_assemblers: Arc<DashMap<String, ChunkAssembler>>,
+    /// Partial transcripts and replies prepared from them, for clients
+    /// speaking with `asr_config.speculative` on
+    pub speculations: Arc<DashMap<String, Speculation>>,
+    /// Thinking fillers of turns waiting for their first sentence
+    pub thinking_fillers: Arc<DashMap<String, PendingFiller>>,
+    /// Built-in VAD state for clients streaming `raw-audio-data`
+    pub vad_segmenters: Arc<DashMap<String, SpeechSegmenter>>,
+    /// Barge-in detection for clients streaming audio while the AI speaks
+    pub barge_in_detectors: Arc<DashMap<String, BargeInDetector>>,
+    pub conversation_tasks: Arc<DashMap<String, ConversationTask>>,
+    /// One agent per client so memory persists across turns
+    pub agents: Arc<DashMap<String, SharedAgent>>,
+    /// Characters clients attached with `attach-character`, keyed by client
+    /// uid and character id
+    pub attached_characters: Arc<DashMap<(String, String), AttachedCharacter>>,
+    /// Agents of attached characters, keyed like `attached_characters`
+    pub character_agents: Arc<DashMap<(String, String), SharedAgent>>,
+    /// Live2D model and TTS engine of the current configuration; read
+    /// through [`AppState::live2d_model`] and [`AppState::tts_engine`]
+    resources: Arc<std::sync::RwLock<CharacterResources>>,
+    /// Engines built for clients that chose another engine or rate than
+    /// the configured ones, keyed by client uid then engine and rate
+    pub client_tts_engines: Arc<DashMap<String, ClientTTSEngines>>,
+    /// Shared by every conversation so the TTS backend isn't overloaded
+    pub tts_limiter: Arc<TTSLimiter>,
+    /// Shared by every client so conversations don't overload the server
+    pub conversation_limiter: Arc<ConversationLimiter>,
+    /// Emotions inferred for untagged sentences, shared by every client
+    pub emotion_cache: Arc<EmotionCache>,
+    /// Cached audio still referenced by a running conversation
+    pub audio_in_use: AudioInUse,
+    pub started_at: std::time::Instant,
+    /// WebSocket connections open now, capped by `max_connections`
+    pub connections: Arc<AtomicUsize>,
+    /// Sessions of disconnected clients, keyed by resume token
+    pub resume_sessions: Arc<DashMap<String, ResumeSession>>,
+    /// Read-only connections mirroring participants' messages, keyed by
+    /// observer uid
+    pub observers: Arc<DashMap<String, Observer>>,
+    /// Held while a memory summary is read, rewritten and saved, so
+    /// concurrent updates don't overwrite each other
+    pub summary_lock: Arc<Mutex<()>>,
+}
+
+pub type SharedAgent = Arc<Mutex<Box<dyn AgentInterface>>>;
+
+/// An open WebSocket's place under `max_connections`, given back when
+/// dropped, however the connection ends
+pub struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A client's extra TTS engines, keyed by engine name and rate
+pub type ClientTTSEngines = HashMap<String, Arc<dyn TTSInterface>>;
+
+/// A character answering alongside the client's own, with its own agent
+/// and voice
+#[derive(Clone)]
+pub struct AttachedCharacter {
+    /// The current config with the character's config merged over it
+    pub config: Arc<Config>,
+    /// None when the character has no tts_config
+    pub tts_engine: Option<Arc<dyn TTSInterface>>,
+}
+
+/// A conversation turn running in the background for a client
+pub struct ConversationTask {
+    /// Correlation id echoed on every message the turn sends
+    pub request_id: String,
+    /// Attached character answering in this turn; None for the client's own
+    pub character_id: Option<String>,
+    pub handle: tokio::task::AbortHandle,
+    /// Set to true to silence the turn's TTS while its text carries on
+    pub stop_audio: tokio::sync::watch::Sender<bool>,
+    /// Reply text the turn has sent so far
+    pub displayed: Arc<std::sync::Mutex<String>>,
+    /// The turn's slot under `max_active_conversations`, once it has one;
+    /// a turn replacing this one takes it over
+    pub permit: Arc<std::sync::Mutex<Option<ConversationPermit>>>,
 }
 
 #[derive(Clone)]
@@ -21,6 +182,297 @@ pub struct ClientContext {
     pub client_uid: String,
     pub conf_uid: String,
     pub history_uid: Option<String>,
+    /// When false, responses are sent as text only and TTS is skipped
+    pub tts_enabled: bool,
+    pub sampling: SamplingOverrides,
+    /// Background file currently shown to the client
+    pub background: Option<String>,
+    pub conversation_state: ConversationState,
+    /// Input of the most recent turn, kept so it can be regenerated
+    pub last_input: Option<Arc<BatchInput>>,
+    pub client_type: ClientType,
+    /// Format the client asked for whole-file TTS audio in, with
+    /// `?audio_format=` when connecting
+    pub audio_format: Option<AudioFormat>,
+    pub mic_config: MicConfig,
+    /// ASR language chosen with `set-asr-language`, overriding the config
+    pub asr_language: Option<String>,
+    /// Whether talking over the AI interrupts it; the mic then stays open
+    /// during turns
+    pub barge_in: bool,
+    /// TTS engine chosen with `set-tts-engine`, overriding the config
+    pub tts_engine: Option<String>,
+    /// Speaking rate chosen with `set-tts-rate`, as a multiple of normal
+    /// speed; None for the rate in the config
+    pub tts_rate: Option<f64>,
+    /// Token issued at connect that lets a later connection `resume` this one
+    pub resume_token: String,
+    /// When the client last sent anything, pings included
+    pub last_activity: std::time::Instant,
+    /// When the conversation last changed state; unlike `last_activity`,
+    /// pings don't move it, so it tells an idle client from a gone one
+    pub last_turn: std::time::Instant,
+}
+
+/// Messages not mirrored to observers: the resume token carries the
+/// client's credentials, and announcements reach observers only when they
+/// are sent to them directly
+const PRIVATE_MESSAGE_TYPES: [&str; 2] = ["resume-token", "announcement"];
+
+/// Whose messages an observer receives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObserverScope {
+    /// Every client talking to this character config
+    Conf(String),
+    /// The chat group; its owner's messages are mirrored, which include
+    /// everything broadcast to the group
+    Group(String),
+}
+
+/// A read-only connection that sees participants' messages but can't send
+/// input
+pub struct Observer {
+    pub scope: ObserverScope,
+    pub sender: WebSocketSend,
+}
+
+/// What a disconnected client had open, kept so a new connection can pick
+/// it up with the client's resume token
+#[derive(Debug, Clone)]
+pub struct ResumeSession {
+    pub client_uid: String,
+    pub conf_uid: String,
+    pub history_uid: Option<String>,
+    pub group_id: Option<String>,
+    pub settings: ConversationSettings,
+    pub expires_at: std::time::Instant,
+}
+
+impl ClientContext {
+    /// The settings worth restoring when the client reopens this conversation
+    pub fn settings(&self) -> ConversationSettings {
+        ConversationSettings {
+            tts_enabled: Some(self.tts_enabled),
+            temperature: self.sampling.temperature,
+            top_p: self.sampling.top_p,
+            asr_language: self.asr_language.clone(),
+            barge_in: Some(self.barge_in),
+            tts_engine: self.tts_engine.clone(),
+            tts_rate: self.tts_rate,
+        }
+    }
+
+    /// Restore settings saved with a history
+    pub fn apply_settings(&mut self, settings: &ConversationSettings) {
+        if let Some(tts_enabled) = settings.tts_enabled {
+            self.tts_enabled = tts_enabled;
+        }
+        self.sampling = SamplingOverrides {
+            temperature: settings.temperature,
+            top_p: settings.top_p,
+        };
+        self.asr_language = settings.asr_language.clone();
+        if let Some(barge_in) = settings.barge_in {
+            self.barge_in = barge_in;
+        }
+        self.tts_engine = settings.tts_engine.clone();
+        self.tts_rate = settings.tts_rate;
+    }
+}
+
+/// Sample encoding of `mic-audio-data` arrays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicFormat {
+    /// Floats in -1.0..=1.0
+    F32,
+    /// Integers in the 16-bit PCM range
+    Int16,
+}
+
+/// Mic audio format declared by the client with `mic-config`
+#[derive(Debug, Clone, Copy)]
+pub struct MicConfig {
+    pub sample_rate: u32,
+    pub format: MicFormat,
+    pub channels: u16,
+}
+
+impl Default for MicConfig {
+    /// What the web frontend's VAD produces
+    fn default() -> Self {
+        Self {
+            sample_rate: crate::utils::audio::ASR_SAMPLE_RATE,
+            format: MicFormat::F32,
+            channels: 1,
+        }
+    }
+}
+
+impl MicConfig {
+    /// Read a `mic-config` message; missing fields keep their defaults
+    pub fn from_message(msg: &serde_json::Value) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Some(rate) = msg.get("sample_rate") {
+            let rate = rate
+                .as_u64()
+                .filter(|r| (8000..=192000).contains(r))
+                .ok_or_else(|| anyhow::anyhow!("sample_rate must be between 8000 and 192000"))?;
+            config.sample_rate = rate as u32;
+        }
+        if let Some(format) = msg.get("format") {
+            config.format = match format.as_str() {
+                Some("f32") => MicFormat::F32,
+                Some("int16") => MicFormat::Int16,
+                _ => anyhow::bail!("format must be f32 or int16"),
+            };
+        }
+        if let Some(channels) = msg.get("channels") {
+            let channels = channels
+                .as_u64()
+                .filter(|c| (1..=8).contains(c))
+                .ok_or_else(|| anyhow::anyhow!("channels must be between 1 and 8"))?;
+            config.channels = channels as u16;
+        }
+        Ok(config)
+    }
+
+    /// Convert buffered samples to mono at the ASR sample rate
+    pub fn prepare_for_asr(&self, samples: &[f32]) -> Vec<f32> {
+        use crate::utils::audio::{downmix, resample_linear, ASR_SAMPLE_RATE};
+
+        if !samples.len().is_multiple_of(self.channels as usize) {
+            warn!(
+                "Mic buffer of {} samples is not a whole number of {}-channel frames",
+                samples.len(),
+                self.channels
+            );
+        }
+        let mono = downmix(samples, self.channels);
+        if self.sample_rate != ASR_SAMPLE_RATE {
+            debug!("Resampling mic audio {} Hz -> {} Hz", self.sample_rate, ASR_SAMPLE_RATE);
+        }
+        resample_linear(&mono, self.sample_rate, ASR_SAMPLE_RATE)
+    }
+}
+
+/// What kind of frontend a client is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClientType {
+    /// Avatar frontend; receives audio, expressions and mic control
+    #[default]
+    Live2D,
+    /// Headless integration; receives only text deltas and a final done
+    Text,
+}
+
+impl ClientType {
+    /// Parse the `client_type` query parameter or handshake field
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "live2d" => Some(ClientType::Live2D),
+            "text" => Some(ClientType::Text),
+            _ => None,
+        }
+    }
+}
+
+/// Where a client is in the listen / think / speak cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConversationState {
+    /// Connected, microphone not started yet
+    #[default]
+    Idle,
+    /// Waiting for user input with the microphone on
+    Listening,
+    /// Input received, waiting for the first reply from the agent
+    Thinking,
+    /// Reply is being streamed to the client
+    Speaking,
+}
+
+impl ConversationState {
+    /// Whether moving from `self` to `next` is allowed
+    ///
+    /// A new turn may start while thinking or speaking, since it cancels the
+    /// current one; staying in the same state is always allowed.
+    pub fn can_transition_to(self, next: ConversationState) -> bool {
+        use ConversationState::*;
+        self == next
+            || matches!(
+                (self, next),
+                (Idle, Listening | Thinking)
+                    | (Listening, Thinking)
+                    | (Thinking, Speaking | Listening)
+                    | (Speaking, Listening | Thinking)
+            )
+    }
+
+    /// Whether microphone input should be dropped in this state
+    pub fn is_busy(self) -> bool {
+        matches!(self, ConversationState::Thinking | ConversationState::Speaking)
+    }
+}
+
+/// Per-client sampling overrides for the LLM.
+///
+/// Only OpenAI-compatible providers honour these; other providers ignore them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SamplingOverrides {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+}
+
+impl SamplingOverrides {
+    /// Read `temperature` / `top_p` from a client message, rejecting values
+    /// outside the range accepted by OpenAI-compatible APIs
+    pub fn from_message(msg: &serde_json::Value) -> anyhow::Result<Self> {
+        let temperature = Self::read_field(msg, "temperature", 0.0, 2.0)?;
+        let top_p = Self::read_field(msg, "top_p", 0.0, 1.0)?;
+        Ok(Self { temperature, top_p })
+    }
+
+    fn read_field(
+        msg: &serde_json::Value,
+        name: &str,
+        min: f32,
+        max: f32,
+    ) -> anyhow::Result<Option<f32>> {
+        match msg.get(name) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(value) => {
+                let v = value
+                    .as_f64()
+                    .ok_or_else(|| anyhow::anyhow!("{} must be a number", name))? as f32;
+                if !(min..=max).contains(&v) {
+                    anyhow::bail!("{} must be between {} and {}, got {}", name, min, max, v);
+                }
+                Ok(Some(v))
+            }
+        }
+    }
+
+    /// Overwrite the fields that are set in `other`
+    pub fn merge(&mut self, other: SamplingOverrides) {
+        if other.temperature.is_some() {
+            self.temperature = other.temperature;
+        }
+        if other.top_p.is_some() {
+            self.top_p = other.top_p;
+        }
+    }
+
+    /// Build the LLM request options; unset fields fall back to the
+    /// provider's configured values
+    pub fn to_options(self) -> serde_json::Value {
+        let mut context = serde_json::Map::new();
+        if let Some(temperature) = self.temperature {
+            context.insert("temperature".to_string(), serde_json::json!(temperature));
+        }
+        if let Some(top_p) = self.top_p {
+            context.insert("top_p".to_string(), serde_json::json!(top_p));
+        }
+        serde_json::Value::Object(context)
+    }
 }
 
 pub struct ChatGroupManager {
@@ -28,32 +480,696 @@ pub struct ChatGroupManager {
     pub groups: DashMap<String, Group>, // group_id -> Group
 }
 
+/// A chat group; its id is its key in [`ChatGroupManager::groups`]
 pub struct Group {
-    pub group_id: String,
     pub owner_uid: String,
     pub members: Vec<String>,
 }
 
 impl AppState {
+    /// State talking to the Python service at `PYTHON_SERVICE_URL`
     pub async fn new(config: Config) -> anyhow::Result<Self> {
-        let python_service = Arc::new(PythonServiceClient::new(
+        let python_service: Arc<dyn PythonService> = Arc::new(PythonServiceClient::new(
             std::env::var("PYTHON_SERVICE_URL")
                 .unwrap_or_else(|_| "http://localhost:8000".to_string()),
         ));
+        Self::with_python_service(config, python_service).await
+    }
+
+    /// State using `python_service` for everything run in Python, including
+    /// the TTS engine built from `config`
+    pub async fn with_python_service(
+        config: Config,
+        python_service: Arc<dyn PythonService>,
+    ) -> anyhow::Result<Self> {
+        let resources = CharacterResources::build(&config, &python_service)?;
+        let tts_limiter = Arc::new(TTSLimiter::for_config(config.character_config.tts_config.as_ref()));
+        let conversation_limiter = Arc::new(ConversationLimiter::for_config(&config.system_config));
 
         Ok(Self {
-            config,
+            config: Arc::new(std::sync::RwLock::new(Arc::new(config))),
+            config_path: None,
             client_contexts: Arc::new(DashMap::new()),
+            client_senders: Arc::new(DashMap::new()),
             chat_groups: Arc::new(RwLock::new(ChatGroupManager::new())),
+            group_conversations: Arc::new(DashMap::new()),
             python_service,
             audio_buffers: Arc::new(DashMap::new()),
+            chunk_assemblers: Arc::new(DashMap::new()),
+            speculations: Arc::new(DashMap::new()),
+            thinking_fillers: Arc::new(DashMap::new()),
+            vad_segmenters: Arc::new(DashMap::new()),
+            barge_in_detectors: Arc::new(DashMap::new()),
             conversation_tasks: Arc::new(DashMap::new()),
+            agents: Arc::new(DashMap::new()),
+            attached_characters: Arc::new(DashMap::new()),
+            character_agents: Arc::new(DashMap::new()),
+            resources: Arc::new(std::sync::RwLock::new(resources)),
+            tts_limiter,
+            conversation_limiter,
+            emotion_cache: Arc::new(EmotionCache::default()),
+            client_tts_engines: Arc::new(DashMap::new()),
+            audio_in_use: Arc::new(DashSet::new()),
+            started_at: std::time::Instant::now(),
+            connections: Arc::new(AtomicUsize::new(0)),
+            resume_sessions: Arc::new(DashMap::new()),
+            observers: Arc::new(DashMap::new()),
+            summary_lock: Arc::new(Mutex::new(())),
         })
     }
 
+    /// Reload the base config from `path` on `switch-config`
+    pub fn with_config_path(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config_path = Some(Arc::new(path.into()));
+        self
+    }
+
+    /// Snapshot of the current configuration
+    ///
+    /// The snapshot stays consistent for as long as it is held, even if the
+    /// configuration is replaced in the meantime.
+    pub fn config(&self) -> Arc<Config> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// The character's Live2D model; `None` when running audio-only
+    pub fn live2d_model(&self) -> Option<Arc<Live2DModel>> {
+        self.resources.read().unwrap_or_else(|e| e.into_inner()).live2d_model.clone()
+    }
+
+    /// Why the character's Live2D model couldn't be loaded
+    pub fn live2d_unavailable(&self) -> Option<String> {
+        self.resources.read().unwrap_or_else(|e| e.into_inner()).live2d_unavailable.clone()
+    }
+
+    /// The configured TTS engine; see [`AppState::tts_engine_for`] for the
+    /// one a client speaks with
+    pub fn tts_engine(&self) -> Option<Arc<dyn TTSInterface>> {
+        self.resources.read().unwrap_or_else(|e| e.into_inner()).tts_engine.clone()
+    }
+
+    /// Replace the configuration for every client and tell each of them
+    ///
+    /// The configuration is server-wide, so a switch moves every client to
+    /// the new character; callers acting for one client must check
+    /// `shared_config_switch` first. Its Live2D model and TTS
+    /// engine are built first, and nothing changes if that fails. Agents are
+    /// dropped so the next turn picks up the new persona and LLM settings.
+    /// Clients whose character changed are moved off their current history,
+    /// since histories are stored per character.
+    pub fn update_config(&self, config: Config) -> anyhow::Result<()> {
+        config.validate_agent_wiring()?;
+        let resources = CharacterResources::build(&config, &self.python_service)?;
+        let config = Arc::new(config);
+        let previous = {
+            let mut current = self.config.write().unwrap_or_else(|e| e.into_inner());
+            *self.resources.write().unwrap_or_else(|e| e.into_inner()) = resources;
+            std::mem::replace(&mut *current, config.clone())
+        };
+        let character = &config.character_config;
+        info!(
+            "Configuration updated: {} -> {}",
+            previous.character_config.conf_name, character.conf_name
+        );
+
+        // Built from the previous tts_config
+        self.client_tts_engines.clear();
+        // Inferred with the previous emotion_inference settings
+        self.emotion_cache.clear();
+        // Tuned to the previous vad_config; rebuilt on the next mic chunk
+        self.vad_segmenters.clear();
+        self.barge_in_detectors.clear();
+
+        let client_uids: Vec<String> = self.client_contexts.iter().map(|c| c.key().clone()).collect();
+        for client_uid in client_uids {
+            if let Some(mut context) = self.client_contexts.get_mut(&client_uid) {
+                let context = context.value_mut();
+                if context.conf_uid != character.conf_uid {
+                    context.conf_uid = character.conf_uid.clone();
+                    context.history_uid = None;
+                }
+            }
+            self.reset_agent(&client_uid);
+
+            let Some(sender) = self.client_senders.get(&client_uid).map(|s| s.value().clone()) else {
+                continue;
+            };
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "set-model-and-conf",
+                    "model_info": self
+                        .live2d_model()
+                        .map(|m| m.model_info.clone())
+                        .unwrap_or_else(|| serde_json::json!({})),
+                    "conf_name": character.conf_name,
+                    "conf_uid": character.conf_uid,
+                    "client_uid": client_uid
+                })
+                .to_string(),
+            );
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "config-switched",
+                    "message": format!("Switched to config: {}", character.conf_name)
+                })
+                .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// The configured backend adapter, acting for `client_uid` and sending
+    /// through `sender`
+    pub fn backend_adapter(
+        &self,
+        client_uid: &str,
+        sender: WebSocketSend,
+    ) -> anyhow::Result<Box<dyn BackendAdapter>> {
+        let context = self
+            .client_contexts
+            .get(client_uid)
+            .map(|c| Arc::new(c.value().clone()))
+            .ok_or_else(|| anyhow::anyhow!("No context for client {}", client_uid))?;
+        AdapterFactory::create_adapter(
+            &self.config().system_config.backend_adapter,
+            context,
+            self.python_service.clone(),
+            sender,
+        )
+    }
+
+    /// Count a new WebSocket connection, unless `max_connections` are
+    /// already open
+    pub fn try_open_connection(&self) -> Option<ConnectionSlot> {
+        let limit = self.config().system_config.max_connections;
+        self.connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                limit.is_none_or(|limit| open < limit).then_some(open + 1)
+            })
+            .ok()?;
+        Some(ConnectionSlot(self.connections.clone()))
+    }
+
     pub fn generate_client_uid(&self) -> String {
         Uuid::new_v4().to_string()
     }
+
+    /// Get the client's agent, creating it (and loading memory from the
+    /// current history) if it doesn't exist yet
+    ///
+    /// The entry is held while the agent is built, so concurrent callers
+    /// (a turn and a greeting, say) share one agent.
+    pub fn get_or_create_agent(&self, context: &ClientContext) -> anyhow::Result<SharedAgent> {
+        let agent = self.agents.entry(context.client_uid.clone()).or_try_insert_with(|| {
+            let mut agent = self.create_agent(&self.config())?;
+            if let Some(history_uid) = &context.history_uid {
+                agent.set_memory_from_history(&context.conf_uid, history_uid);
+            }
+            anyhow::Ok(Arc::new(Mutex::new(agent)))
+        })?;
+        Ok(agent.value().clone())
+    }
+
+    /// Get the agent of a character the client attached, creating it if it
+    /// doesn't exist yet
+    ///
+    /// It starts with an empty memory: the history holds the other
+    /// characters' replies too.
+    pub fn get_or_create_character_agent(
+        &self,
+        client_uid: &str,
+        character_id: &str,
+        character: &AttachedCharacter,
+    ) -> anyhow::Result<SharedAgent> {
+        let key = (client_uid.to_string(), character_id.to_string());
+        let agent = self.character_agents.entry(key).or_try_insert_with(|| {
+            anyhow::Ok(Arc::new(Mutex::new(self.create_agent(&character.config)?)))
+        })?;
+        Ok(agent.value().clone())
+    }
+
+    /// Build an agent for the character in `config`
+    fn create_agent(&self, config: &Config) -> anyhow::Result<Box<dyn AgentInterface>> {
+        let agent_config = config
+            .character_config
+            .agent_config
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No agent_config in character config"))?;
+
+        AgentFactory::create_agent(
+            agent_config,
+            &self.build_system_prompt(config),
+            self.python_service.clone(),
+        )
+    }
+
+    /// Attach the character in `config` to the client as `character_id`,
+    /// replacing any character attached under that id
+    pub fn attach_character(
+        &self,
+        client_uid: &str,
+        character_id: &str,
+        config: Config,
+    ) -> anyhow::Result<AttachedCharacter> {
+        config.validate_agent_wiring()?;
+        let tts_engine = match &config.character_config.tts_config {
+            Some(tts_config) => Some(TTSFactory::create_tts(tts_config, self.python_service.clone())?),
+            None => None,
+        };
+        let character = AttachedCharacter {
+            config: Arc::new(config),
+            tts_engine,
+        };
+
+        let key = (client_uid.to_string(), character_id.to_string());
+        self.character_agents.remove(&key);
+        self.attached_characters.insert(key, character.clone());
+        info!("Attached character {} to {}", character_id, client_uid);
+        Ok(character)
+    }
+
+    /// The character the client attached as `character_id`
+    pub fn attached_character(&self, client_uid: &str, character_id: &str) -> anyhow::Result<AttachedCharacter> {
+        self.attached_characters
+            .get(&(client_uid.to_string(), character_id.to_string()))
+            .map(|c| c.value().clone())
+            .ok_or_else(|| anyhow::anyhow!("No character attached as {}", character_id))
+    }
+
+    /// Detach one of the client's characters, or all of them with `None`
+    ///
+    /// # Returns
+    /// Whether anything was attached
+    pub fn detach_characters(&self, client_uid: &str, character_id: Option<&str>) -> bool {
+        let matches = |key: &(String, String)| {
+            key.0 == client_uid && character_id.is_none_or(|id| key.1 == id)
+        };
+        let attached = self.attached_characters.iter().filter(|c| matches(c.key())).count();
+        self.attached_characters.retain(|key, _| !matches(key));
+        self.character_agents.retain(|key, _| !matches(key));
+        attached > 0
+    }
+
+    /// A fresh LLM outside any client's agent, for side requests such as
+    /// summaries; `provider` defaults to the basic memory agent's
+    pub fn standalone_llm(
+        &self,
+        config: &Config,
+        provider: Option<&str>,
+    ) -> anyhow::Result<Arc<dyn StatelessLLMInterface>> {
+        let agent_config = config
+            .character_config
+            .agent_config
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No agent_config in character config"))?;
+        let provider = provider
+            .or_else(|| {
+                agent_config
+                    .agent_settings
+                    .basic_memory_agent
+                    .as_ref()
+                    .map(|b| b.llm_provider.as_str())
+            })
+            .ok_or_else(|| anyhow::anyhow!("No LLM provider configured for the basic_memory_agent"))?;
+        let llm_configs = serde_json::to_value(&agent_config.llm_configs)?;
+        let llm_config = llm_configs
+            .get(provider)
+            .filter(|c| !c.is_null())
+            .ok_or_else(|| anyhow::anyhow!("Configuration not found for LLM provider: {}", provider))?;
+        StatelessLLMFactory::create_llm(provider, self.python_service.clone(), None, llm_config)
+    }
+
+    /// Assemble the system prompt: the operator prefix, the character persona
+    /// with tool prompts appended in name order, then the operator suffix
+    ///
+    /// Template variables (see [`crate::prompts::VARIABLES`]) are filled in
+    /// the persona and tool prompts.
+    pub fn build_system_prompt(&self, config: &Config) -> String {
+        let system_config = &config.system_config;
+        let variables = crate::prompts::variables(config);
+        let mut persona_prompt = crate::prompts::render(&config.character_config.persona_prompt, &variables);
+
+        for (prompt_name, prompt_file) in &system_config.tool_prompts {
+            // Only added to group members' memory, not the system prompt
+            if prompt_name == "group_conversation_prompt" {
+                continue;
+            }
+
+            let path = std::path::Path::new(&system_config.prompts_dir)
+                .join(format!("{}.txt", prompt_file));
+            let mut prompt_content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Skipping tool prompt {} ({:?}): {}", prompt_name, path, e);
+                    continue;
+                }
+            };
+
+            if prompt_name == "live2d_expression_prompt" {
+                let Some(model) = self.live2d_model() else {
+                    continue;
+                };
+                prompt_content = prompt_content.replace("[<insert_emomap_keys>]", &model.emo_str);
+            }
+
+            persona_prompt.push_str(&crate::prompts::render(&prompt_content, &variables));
+        }
+
+        let memory = crate::summary_memory::memory_prompt(&config.character_config);
+        let system_prompt = [
+            system_config.system_prompt_prefix.trim(),
+            persona_prompt.trim(),
+            memory.trim(),
+            system_config.system_prompt_suffix.trim(),
+        ]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+        debug!("\n === System Prompt ===\n{}", system_prompt);
+        system_prompt
+    }
+
+    /// Create an unguessable resume token, clearing out expired sessions
+    pub fn issue_resume_token(&self) -> String {
+        let now = std::time::Instant::now();
+        self.resume_sessions.retain(|_, session| session.expires_at > now);
+        format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+    }
+
+    /// Keep a disconnecting client's session for `resume_token_ttl_secs`
+    pub fn save_resume_session(&self, context: &ClientContext, group_id: Option<String>) {
+        let ttl = std::time::Duration::from_secs(self.config().system_config.resume_token_ttl_secs);
+        if ttl.is_zero() {
+            return;
+        }
+        self.resume_sessions.insert(
+            context.resume_token.clone(),
+            ResumeSession {
+                client_uid: context.client_uid.clone(),
+                conf_uid: context.conf_uid.clone(),
+                history_uid: context.history_uid.clone(),
+                group_id,
+                settings: context.settings(),
+                expires_at: std::time::Instant::now() + ttl,
+            },
+        );
+    }
+
+    /// Claim the session saved under `token`; each token resumes once
+    pub fn take_resume_session(&self, token: &str) -> Option<ResumeSession> {
+        self.resume_sessions
+            .remove(token)
+            .map(|(_, session)| session)
+            .filter(|session| session.expires_at > std::time::Instant::now())
+    }
+
+    /// Drop the agent of a client idle for `ttl`, freeing its memory
+    ///
+    /// The chat history stays, so the next turn rebuilds the agent from it.
+    /// Attached characters' agents are kept, as their memory is not stored
+    /// anywhere else. Nothing is dropped while a turn is running or anything
+    /// else holds the agent.
+    ///
+    /// # Returns
+    /// Whether any agent was dropped
+    pub fn reap_idle_agents(&self, client_uid: &str, ttl: std::time::Duration) -> bool {
+        let idle = self.client_contexts.get(client_uid).is_none_or(|context| {
+            !context.conversation_state.is_busy() && context.last_turn.elapsed() >= ttl
+        });
+        if !idle || self.conversation_tasks.contains_key(client_uid) {
+            return false;
+        }
+
+        // Checked under the map's lock, so a turn can't pick the agent up
+        // while it is being removed
+        self.agents
+            .remove_if(client_uid, |_, agent| Arc::strong_count(agent) == 1)
+            .is_some()
+    }
+
+    /// Drop the client's agent so the next turn starts from a fresh instance
+    ///
+    /// The cached last input goes with it; it belonged to the old history.
+    pub fn reset_agent(&self, client_uid: &str) {
+        if let Some(mut context) = self.client_contexts.get_mut(client_uid) {
+            context.value_mut().last_input = None;
+        }
+        if self.agents.remove(client_uid).is_some() {
+            info!("Dropped agent for {}", client_uid);
+        }
+    }
+
+    /// Resolve a background file name to its `/bg/` URL, checking that it
+    /// exists in the backgrounds directory
+    pub fn background_url(&self, file: &str) -> anyhow::Result<String> {
+        // Only bare file names; anything else could escape the directory
+        let is_bare_name = std::path::Path::new(file)
+            .file_name()
+            .is_some_and(|name| name == file);
+        if file.is_empty() || !is_bare_name {
+            anyhow::bail!("Invalid background file name: {}", file);
+        }
+
+        let path = std::path::Path::new(&self.config().system_config.backgrounds_dir).join(file);
+        if !path.is_file() {
+            anyhow::bail!("Background not found: {}", file);
+        }
+        Ok(format!("/bg/{}", file))
+    }
+
+    /// The client's current conversation state
+    pub fn conversation_state(&self, client_uid: &str) -> ConversationState {
+        self.client_contexts
+            .get(client_uid)
+            .map(|c| c.value().conversation_state)
+            .unwrap_or_default()
+    }
+
+    /// Move the client to a new conversation state, telling the frontend to
+    /// stop the microphone when a turn starts and to restart it once the
+    /// client is listening again
+    ///
+    /// Every client is also sent `thinking-start`/`thinking-end` and
+    /// `speaking-start`/`speaking-end` controls as the turn moves between
+    /// waiting for the first output and delivering the reply, so it can show
+    /// a typing indicator. The character's thinking filler runs for as long
+    /// as the client is thinking.
+    ///
+    /// # Returns
+    /// Whether the transition was valid and applied
+    pub fn set_conversation_state(
+        &self,
+        client_uid: &str,
+        next: ConversationState,
+        sender: &WebSocketSend,
+    ) -> bool {
+        let (previous, client_type, barge_in) = {
+            let Some(mut context) = self.client_contexts.get_mut(client_uid) else {
+                return false;
+            };
+            let previous = context.value().conversation_state;
+            if !previous.can_transition_to(next) {
+                warn!(
+                    "Ignoring conversation state change {:?} -> {:?} for {}",
+                    previous, next, client_uid
+                );
+                return false;
+            }
+            context.value_mut().conversation_state = next;
+            if previous != next {
+                context.value_mut().last_turn = std::time::Instant::now();
+            }
+            (previous, context.value().client_type, context.value().barge_in)
+        };
+
+        if previous == next {
+            return true;
+        }
+        debug!("Conversation state for {}: {:?} -> {:?}", client_uid, previous, next);
+
+        let mut controls = Vec::new();
+        match previous {
+            ConversationState::Thinking => controls.push("thinking-end"),
+            ConversationState::Speaking => controls.push("speaking-end"),
+            _ => {}
+        }
+        match next {
+            ConversationState::Thinking => controls.push("thinking-start"),
+            ConversationState::Speaking => controls.push("speaking-start"),
+            _ => {}
+        }
+        // Text clients have no microphone to control
+        if client_type != ClientType::Text {
+            match (previous, next) {
+                (_, ConversationState::Listening) => controls.push("start-mic"),
+                // With barge-in the mic stays open so the user can talk over the reply
+                (ConversationState::Idle | ConversationState::Listening, ConversationState::Thinking)
+                    if !barge_in =>
+                {
+                    controls.insert(0, "stop-mic")
+                }
+                _ => {}
+            }
+        }
+
+        if previous == ConversationState::Thinking {
+            filler::stop(self, client_uid, sender);
+        }
+        for text in controls {
+            let _ = sender.send(
+                serde_json::json!({
+                    "type": "control",
+                    "text": text
+                })
+                .to_string(),
+            );
+        }
+        if next == ConversationState::Thinking {
+            filler::start(self, client_uid, sender);
+        }
+        true
+    }
+
+    /// Note that the client is still there
+    pub fn record_activity(&self, client_uid: &str) {
+        if let Some(mut context) = self.client_contexts.get_mut(client_uid) {
+            context.value_mut().last_activity = std::time::Instant::now();
+        }
+    }
+
+    /// Whether barge-in is enabled by default for new clients
+    pub fn default_barge_in(&self) -> bool {
+        self.config()
+            .character_config
+            .vad_config
+            .as_ref()
+            .is_some_and(|c| c.barge_in)
+    }
+
+    /// TTS engine for a client's turn: `requested` for this message, else
+    /// the client's `set-tts-engine` choice, else the configured engine,
+    /// speaking at the client's `set-tts-rate` rate
+    ///
+    /// Engines other than the configured one, or at another rate, are built
+    /// once per client and cached. An engine that can't be built falls back
+    /// to the configured one with a warning; one whose rate can't be changed
+    /// speaks at its own.
+    pub fn tts_engine_for(&self, client_uid: &str, requested: Option<&str>) -> Option<Arc<dyn TTSInterface>> {
+        let (chosen, rate) = self
+            .client_contexts
+            .get(client_uid)
+            .map(|c| (c.tts_engine.clone(), c.tts_rate))
+            .unwrap_or_default();
+        let chosen = requested.map(str::to_string).or(chosen);
+        if chosen.is_none() && rate.is_none() {
+            return self.tts_engine();
+        }
+        let config = self.config();
+        let Some(tts_config) = config.character_config.tts_config.as_ref() else {
+            if let Some(engine) = chosen {
+                warn!("Ignoring TTS engine {}: no tts_config", engine);
+            }
+            return self.tts_engine();
+        };
+        let engine = chosen.unwrap_or_else(|| tts_config.tts_model.clone());
+        if engine == tts_config.tts_model && rate.is_none() {
+            return self.tts_engine();
+        }
+        let key = match rate {
+            Some(rate) => format!("{} at {}x", engine, rate),
+            None => engine.clone(),
+        };
+        if let Some(cached) = self
+            .client_tts_engines
+            .get(client_uid)
+            .and_then(|engines| engines.get(&key).cloned())
+        {
+            return Some(cached);
+        }
+
+        let tts = if engine == tts_config.tts_model {
+            self.tts_engine()?
+        } else {
+            match TTSFactory::create_tts_for_engine(tts_config, &engine, self.python_service.clone()) {
+                Ok(tts) => tts,
+                Err(e) => {
+                    warn!("{}; using {}", e, tts_config.tts_model);
+                    return self.tts_engine();
+                }
+            }
+        };
+        let tts = rate.and_then(|rate| tts.with_rate(rate)).unwrap_or(tts);
+        self.client_tts_engines
+            .entry(client_uid.to_string())
+            .or_default()
+            .insert(key, tts.clone());
+        Some(tts)
+    }
+
+    /// Copy a message sent to a participant to the observers watching it
+    ///
+    /// The copy carries `observed_client_uid` so observers of several
+    /// clients can tell them apart. Messages that would let an observer act
+    /// as the client, like its resume token, are not mirrored, and neither
+    /// are announcements.
+    pub async fn mirror_to_observers(&self, client_uid: &str, text: &str) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let conf_uid = self.client_contexts.get(client_uid).map(|c| c.conf_uid.clone());
+        let owned_group = {
+            let groups = self.chat_groups.read().await;
+            groups
+                .get_client_group(client_uid)
+                .filter(|_| groups.is_group_owner(client_uid))
+        };
+        let watching: Vec<WebSocketSend> = self
+            .observers
+            .iter()
+            .filter(|observer| match &observer.scope {
+                ObserverScope::Conf(uid) => conf_uid.as_ref() == Some(uid),
+                ObserverScope::Group(gid) => owned_group.as_ref() == Some(gid),
+            })
+            .map(|observer| observer.sender.clone())
+            .collect();
+        if watching.is_empty() {
+            return;
+        }
+
+        let message = match serde_json::from_str::<serde_json::Value>(text) {
+            Ok(serde_json::Value::Object(object))
+                if object
+                    .get("type")
+                    .and_then(|t| t.as_str())
+                    .is_some_and(|t| PRIVATE_MESSAGE_TYPES.contains(&t)) =>
+            {
+                return;
+            }
+            Ok(serde_json::Value::Object(mut object)) => {
+                object.insert("observed_client_uid".to_string(), client_uid.into());
+                serde_json::Value::Object(object).to_string()
+            }
+            _ => text.to_string(),
+        };
+        for sender in watching {
+            let _ = sender.send(message.clone());
+        }
+    }
+
+    /// Whether TTS is enabled by default for new clients
+    pub fn default_tts_enabled(&self) -> bool {
+        self.config()
+            .character_config
+            .tts_config
+            .as_ref()
+            .map(|c| c.tts_enabled)
+            .unwrap_or(false)
+    }
 }
 
 impl ChatGroupManager {
@@ -76,5 +1192,115 @@ impl ChatGroupManager {
         }
         vec![]
     }
+
+    /// Whether the client owns the group it is in
+    pub fn is_group_owner(&self, client_uid: &str) -> bool {
+        self.get_client_group(client_uid)
+            .and_then(|gid| self.groups.get(&gid).map(|g| g.owner_uid == client_uid))
+            .unwrap_or(false)
+    }
+
+    /// Put the client in the group a history was spoken in, recreating the
+    /// group with the client as owner if it no longer exists
+    ///
+    /// Of the group's former `participants`, those still connected and in
+    /// no other group join it too. The client leaves any other group it is
+    /// in.
+    ///
+    /// # Returns
+    /// The group's members
+    pub fn restore_group(&self, client_uid: &str, group_id: &str, participants: &[String]) -> Vec<String> {
+        if let Some(current) = self.get_client_group(client_uid).filter(|g| !g.is_empty() && g != group_id) {
+            let now_empty = self.groups.get_mut(&current).is_some_and(|mut group| {
+                group.members.retain(|m| m != client_uid);
+                group.members.is_empty()
+            });
+            if now_empty {
+                self.groups.remove(&current);
+            }
+        }
+
+        let mut group = self.groups.entry(group_id.to_string()).or_insert_with(|| Group {
+            owner_uid: client_uid.to_string(),
+            members: Vec::new(),
+        });
+        self.client_group_map.insert(client_uid.to_string(), group_id.to_string());
+        for uid in std::iter::once(client_uid).chain(participants.iter().map(String::as_str)) {
+            let available = self
+                .client_group_map
+                .get(uid)
+                .is_some_and(|g| g.is_empty() || g.as_str() == group_id);
+            if available && !group.members.iter().any(|m| m == uid) {
+                self.client_group_map.insert(uid.to_string(), group_id.to_string());
+                group.members.push(uid.to_string());
+            }
+        }
+        group.members.clone()
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_callers_share_one_agent() {
+        let state = test_support::state(test_support::config()).await;
+        let (client_uid, _) = test_support::connect(&state);
+        let context = state.client_contexts.get(&client_uid).unwrap().clone();
+
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let state = state.clone();
+                let context = context.clone();
+                tokio::spawn(async move { state.get_or_create_agent(&context).unwrap() })
+            })
+            .collect();
+        let mut agents = Vec::new();
+        for caller in callers {
+            agents.push(caller.await.unwrap());
+        }
+
+        let kept = state.agents.get(&client_uid).unwrap().clone();
+        assert!(agents.iter().all(|agent| Arc::ptr_eq(agent, &kept)));
+    }
+
+    #[tokio::test]
+    async fn tool_prompts_are_appended_in_name_order() {
+        let dir = std::env::temp_dir().join(format!("tool-prompts-{}", uuid::Uuid::new_v4().as_simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.join(format!("{}.txt", name)), format!("[{}]", name)).unwrap();
+        }
+        let mut config = test_support::config();
+        config.character_config.persona_prompt = String::new();
+        config.system_config.prompts_dir = dir.to_string_lossy().into_owned();
+        for name in ["c", "a", "b"] {
+            config.system_config.tool_prompts.insert(format!("{}_prompt", name), name.to_string());
+        }
+        let state = test_support::state(config.clone()).await;
+
+        let prompt = state.build_system_prompt(&config);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(prompt.contains("[a][b][c]"), "{prompt}");
+    }
+
+    #[tokio::test]
+    async fn idle_reaping_keeps_attached_characters() {
+        let state = test_support::state(test_support::config()).await;
+        let (client_uid, _) = test_support::connect(&state);
+        let context = state.client_contexts.get(&client_uid).unwrap().clone();
+        let agent = state.get_or_create_agent(&context).unwrap();
+        let key = (client_uid.clone(), "guest".to_string());
+        state.character_agents.insert(key.clone(), Arc::new(Mutex::new(state.create_agent(&state.config()).unwrap())));
+        drop(agent);
+
+        assert!(state.reap_idle_agents(&client_uid, std::time::Duration::ZERO));
+
+        assert!(!state.agents.contains_key(&client_uid));
+        assert!(state.character_agents.contains_key(&key));
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use futures::StreamExt;
+use tracing::{debug, info, warn};
+
+use crate::chat_history;
+use crate::config::CharacterConfig;
+use crate::state::{AppState, ClientContext};
+
+/// Section of the system prompt carrying the character's memory summary;
+/// empty when summary memory is off or nothing has been summarized yet
+pub fn memory_prompt(character_config: &CharacterConfig) -> String {
+    if character_config.summary_memory.is_none() {
+        return String::new();
+    }
+    match chat_history::load_summary(&character_config.conf_uid) {
+        Ok(memory) if !memory.summary.trim().is_empty() => format!(
+            "What you remember from earlier conversations with {}:\n{}",
+            character_config.human_name,
+            memory.summary.trim()
+        ),
+        Ok(_) => String::new(),
+        Err(e) => {
+            warn!("Failed to load memory summary for {}: {}", character_config.conf_uid, e);
+            String::new()
+        }
+    }
+}
+
+/// Update the summary once the client has had `every_n_turns` turns that
+/// aren't in it yet
+pub fn after_turn(state: &AppState, client_uid: &str) {
+    let config = state.config();
+    let Some(every_n_turns) = config
+        .character_config
+        .summary_memory
+        .as_ref()
+        .map(|c| c.every_n_turns)
+        .filter(|n| *n > 0)
+    else {
+        return;
+    };
+    if let Some(context) = state.client_contexts.get(client_uid) {
+        spawn_update(state, context.value(), every_n_turns);
+    }
+}
+
+/// Fold whatever the client said since the last update into the summary
+/// when its session ends
+pub fn after_session(state: &AppState, context: &ClientContext) {
+    if state.config().character_config.summary_memory.is_some() {
+        spawn_update(state, context, 1);
+    }
+}
+
+fn spawn_update(state: &AppState, context: &ClientContext, min_turns: usize) {
+    let Some(history_uid) = context.history_uid.clone() else {
+        return;
+    };
+    let state = state.clone();
+    let conf_uid = context.conf_uid.clone();
+    tokio::spawn(async move {
+        if let Err(e) = update_summary(&state, &conf_uid, &history_uid, min_turns).await {
+            warn!("Failed to update memory summary for {}: {}", conf_uid, e);
+        }
+    });
+}
+
+/// Rewrite the summary with the history's messages that aren't in it yet,
+/// if they include at least `min_turns` user turns
+async fn update_summary(
+    state: &AppState,
+    conf_uid: &str,
+    history_uid: &str,
+    min_turns: usize,
+) -> anyhow::Result<()> {
+    let config = state.config();
+    let character_config = &config.character_config;
+    let Some(summary_config) = &character_config.summary_memory else {
+        return Ok(());
+    };
+    // Summaries belong to the character the history was written with
+    if character_config.conf_uid != conf_uid {
+        return Ok(());
+    }
+
+    let _guard = state.summary_lock.lock().await;
+    let mut memory = chat_history::load_summary(conf_uid)?;
+    let messages = chat_history::get_history(conf_uid, history_uid)?;
+    let done = memory
+        .summarized
+        .get(history_uid)
+        .copied()
+        .unwrap_or(0)
+        .min(messages.len());
+    let new_messages = &messages[done..];
+    let turns = new_messages.iter().filter(|m| m.role == "human").count();
+    if turns == 0 || turns < min_turns {
+        return Ok(());
+    }
+
+    let transcript = new_messages
+        .iter()
+        .map(|m| {
+            let name = m.name.clone().unwrap_or_else(|| match m.role.as_str() {
+                "human" => character_config.human_name.clone(),
+                _ => character_config.character_name.clone(),
+            });
+            format!("{}: {}", name, m.content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let system = format!(
+        "You keep the long-term memory of {character}, who talks with {human}. \
+         Update the notes below with what matters from the new conversation: \
+         facts about {human}, their preferences, and anything {character} promised \
+         or should bring up again. Drop details that no longer matter. Write plain \
+         notes, at most {max} characters, and reply with the notes only.",
+        character = character_config.character_name,
+        human = character_config.human_name,
+        max = summary_config.max_chars,
+    );
+    let previous = if memory.summary.trim().is_empty() {
+        "(none yet)"
+    } else {
+        memory.summary.trim()
+    };
+    let mut message = HashMap::new();
+    message.insert("role".to_string(), serde_json::json!("user"));
+    message.insert(
+        "content".to_string(),
+        serde_json::json!(format!("Current notes:\n{}\n\nNew conversation:\n{}", previous, transcript)),
+    );
+
+    let llm = state.standalone_llm(&config, None)?;
+    let mut tokens = llm.chat_completion(vec![message], Some(&system)).await?;
+    let mut summary = String::new();
+    while let Some(token) = tokens.next().await {
+        summary.push_str(&token?);
+    }
+    let summary = summary.trim();
+    if summary.is_empty() {
+        debug!("LLM returned an empty memory summary for {}; keeping the old one", conf_uid);
+        return Ok(());
+    }
+
+    memory.summary = summary.chars().take(summary_config.max_chars).collect();
+    memory.updated_at = Some(chrono::Utc::now().to_rfc3339());
+    memory.summarized.insert(history_uid.to_string(), messages.len());
+    chat_history::save_summary(conf_uid, &memory, config.system_config.fsync_history)?;
+    info!("Updated memory summary for {} with {} turns", conf_uid, turns);
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Shared fixtures for tests
+
+use std::sync::Arc;
+
+use crate::config::{CharacterConfig, Config, SystemConfig};
+use crate::python_service::mock::MockPythonService;
+use crate::python_service::PythonService;
+use crate::state::{AppState, ClientContext, ClientType};
+
+/// A character talking through a local Ollama model, with the built-in VAD
+/// and no TTS, Live2D model or API keys
+pub fn config() -> Config {
+    let character_config: CharacterConfig = serde_json::from_value(serde_json::json!({
+        "conf_name": "Test",
+        "conf_uid": "test",
+        "live2d_model_name": "none",
+        "character_name": "Mio",
+        "avatar": null,
+        "human_name": "Human",
+        "persona_prompt": "You are Mio.",
+        "agent_config": {
+            "conversation_agent_choice": "basic_memory_agent",
+            "agent_settings": {
+                "basic_memory_agent": { "llm_provider": "ollama_llm" }
+            },
+            "llm_configs": {
+                "ollama_llm": { "base_url": "http://127.0.0.1:9/v1", "model": "test" }
+            }
+        },
+        "vad_config": { "vad_model": "simple_vad", "silero_vad": null }
+    }))
+    .expect("test character config");
+
+    Config {
+        system_config: SystemConfig::default(),
+        character_config,
+    }
+}
+
+/// State for `config` whose Python service is `service`
+pub async fn state_with(config: Config, service: Arc<dyn PythonService>) -> AppState {
+    AppState::with_python_service(config, service).await.unwrap()
+}
+
+/// State for `config` whose Python service has no replies queued
+pub async fn state(config: Config) -> AppState {
+    state_with(config, Arc::new(MockPythonService::new())).await
+}
+
+/// Connect a client talking to its own test character, returning its
+/// client and conf uids
+pub fn connect(state: &AppState) -> (String, String) {
+    let client_uid = state.generate_client_uid();
+    let conf_uid = format!("test-{}", uuid::Uuid::new_v4().as_simple());
+    state.client_contexts.insert(
+        client_uid.clone(),
+        ClientContext {
+            client_uid: client_uid.clone(),
+            conf_uid: conf_uid.clone(),
+            history_uid: None,
+            tts_enabled: false,
+            sampling: Default::default(),
+            background: None,
+            conversation_state: Default::default(),
+            last_input: None,
+            client_type: ClientType::Text,
+            audio_format: None,
+            mic_config: Default::default(),
+            asr_language: None,
+            barge_in: false,
+            tts_engine: None,
+            tts_rate: None,
+            resume_token: state.issue_resume_token(),
+            last_activity: std::time::Instant::now(),
+            last_turn: std::time::Instant::now(),
+        },
+    );
+    (client_uid, conf_uid)
+}
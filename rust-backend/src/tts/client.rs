@@ -1,48 +1,99 @@
 use async_trait::async_trait;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
-use tracing::{debug, error};
-use crate::python_service::PythonServiceClient;
-use super::interface::{TTSInterface, TTSRequest};
+use tracing::{debug, error, warn};
+use crate::python_service::PythonService;
+use super::interface::{AudioStream, TTSInterface, TTSRequest};
+use super::voice;
+
+/// Speaking rates a client may choose, as multiples of normal speed
+pub const TTS_RATE_RANGE: RangeInclusive<f64> = 0.5..=2.0;
+
+/// Field of each engine's section that sets its speaking rate
+const RATE_FIELDS: [(&str, &str); 5] = [
+    ("azure_tts", "rate"),
+    ("melo_tts", "speed"),
+    ("cosyvoice2_tts", "speed"),
+    ("sherpa_onnx_tts", "speed"),
+    ("siliconflow_tts", "speed"),
+];
+
+/// Field of `engine`'s section that sets its speaking rate; None for
+/// engines whose rate can't be changed
+pub fn rate_field(engine: &str) -> Option<&'static str> {
+    RATE_FIELDS.iter().find(|(e, _)| *e == engine).map(|(_, field)| *field)
+}
 
 /// TTS client that communicates with Python TTS service
+#[derive(Clone)]
 pub struct TTSClient {
-    python_service: Arc<PythonServiceClient>,
+    /// Engine the config selects, which decides how voices and languages
+    /// are spelled
+    engine: String,
+    python_service: Arc<dyn PythonService>,
     default_voice: Option<String>,
     default_language: Option<String>,
     tts_config: Option<serde_json::Value>,
+    streaming: bool,
 }
 
 impl TTSClient {
     /// Create a new TTS client
     pub fn new(
-        python_service: Arc<PythonServiceClient>,
+        engine: &str,
+        python_service: Arc<dyn PythonService>,
         default_voice: Option<String>,
         default_language: Option<String>,
         tts_config: Option<serde_json::Value>,
+        streaming: bool,
     ) -> Self {
         Self {
+            engine: engine.to_string(),
             python_service,
             default_voice,
             default_language,
             tts_config,
+            streaming,
         }
     }
 
     /// Synthesize text to speech using Python service
+    ///
+    /// The audio is saved as `file_name_no_ext`, or under a fresh name from
+    /// [`audio_file_name`] so concurrent requests never share a file.
+    ///
+    /// `voice` and `language` override the configured ones for this request;
+    /// a language the engine doesn't speak is ignored with a warning.
     pub async fn synthesize(
         &self,
         text: &str,
         voice: Option<&str>,
         language: Option<&str>,
+        file_name_no_ext: Option<&str>,
     ) -> Result<String, anyhow::Error> {
+        let overridden = voice.is_some() || language.is_some();
+        let voice = voice
+            .map(|v| voice::normalize_voice(&self.engine, v, None))
+            .or_else(|| self.default_voice.clone());
+        let language = language
+            .and_then(|l| {
+                let normalized = voice::normalize_language(&self.engine, l);
+                if normalized.is_none() {
+                    warn!("{} can't speak language {:?}, using the configured one", self.engine, l);
+                }
+                normalized
+            })
+            .or_else(|| self.default_language.clone());
+        if let (true, Some(voice), Some(language)) = (overridden, &voice, &language) {
+            if let Some(problem) = voice::combination_problem(&self.engine, voice, language) {
+                warn!("{}", problem);
+            }
+        }
+
         let request = TTSRequest {
             text: text.to_string(),
-            voice: voice
-                .map(|v| v.to_string())
-                .or_else(|| self.default_voice.clone()),
-            language: language
-                .map(|l| l.to_string())
-                .or_else(|| self.default_language.clone()),
+            voice,
+            language,
             config: self.tts_config.clone(),
         };
 
@@ -50,10 +101,14 @@ impl TTSClient {
                text, request.config.is_some());
         
         // Convert to Python service request format
+        let file_name = file_name_no_ext.map(str::to_string).unwrap_or_else(|| {
+            audio_file_name(&request.text, request.voice.as_deref(), request.language.as_deref())
+        });
         let python_request = crate::python_service::TTSRequest {
             text: request.text,
             voice: request.voice,
             language: request.language,
+            file_name_no_ext: Some(file_name.clone()),
         };
         
         // Add config to the request if available
@@ -61,27 +116,100 @@ impl TTSClient {
         let response = self
             .python_service
             .synthesize_tts(python_request, request.config.clone())
-            .await?;
+            .await
+            .inspect_err(|e| error!("TTS synthesis failed: {}", e))?;
 
-        if response.success {
-            debug!("TTS synthesis successful: {}", response.audio_path);
-            Ok(response.audio_path)
-        } else {
-            let error_msg = response.error.unwrap_or_else(|| "Unknown error".to_string());
-            error!("TTS synthesis failed: {}", error_msg);
-            Err(anyhow::anyhow!("TTS synthesis failed: {}", error_msg))
+        debug!("TTS synthesis successful: {}", response.audio_path);
+        let saved_as = std::path::Path::new(&response.audio_path).file_stem();
+        if saved_as.is_some_and(|stem| stem != file_name.as_str()) {
+            warn!("TTS engine ignored the requested file name {}: {}", file_name, response.audio_path);
         }
+        Ok(response.audio_path)
     }
 }
 
+/// Cache file name for one synthesis: `<request hash>_<uuid>`
+///
+/// The hash is the same for identical text, voice and language, so a cache can
+/// find earlier results for a request by prefix; the uuid keeps two syntheses
+/// of the same text from writing one file.
+pub fn audio_file_name(text: &str, voice: Option<&str>, language: Option<&str>) -> String {
+    format!(
+        "{:016x}_{}",
+        request_hash(text, voice, language),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// FNV-1a over the request, stable across builds unlike `DefaultHasher`
+fn request_hash(text: &str, voice: Option<&str>, language: Option<&str>) -> u64 {
+    let parts = [text, voice.unwrap_or(""), language.unwrap_or("")];
+    parts.iter().fold(0xcbf29ce484222325, |hash, part| {
+        // The separator keeps ("ab", "c") and ("a", "bc") apart
+        part.bytes()
+            .chain(std::iter::once(0xff))
+            .fold(hash, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+    })
+}
+
 #[async_trait]
 impl TTSInterface for TTSClient {
     async fn generate_audio(
         &self,
         text: &str,
-        _file_name_no_ext: Option<&str>,
+        file_name_no_ext: Option<&str>,
     ) -> Result<String, anyhow::Error> {
-        self.synthesize(text, None, None).await
+        self.synthesize(text, None, None, file_name_no_ext).await
+    }
+
+    fn language(&self) -> Option<String> {
+        self.default_language.clone().or_else(|| {
+            // Edge and Azure voices are named after their locale, e.g. en-US-AvaNeural
+            if !matches!(self.engine.as_str(), "edge_tts" | "azure_tts") {
+                return None;
+            }
+            let mut parts = self.default_voice.as_deref()?.split('-');
+            let (language, region) = (parts.next()?, parts.next()?);
+            voice::canonical_language_tag(&format!("{}-{}", language, region))
+        })
+    }
+
+    fn with_rate(&self, rate: f64) -> Option<Arc<dyn TTSInterface>> {
+        let field = rate_field(&self.engine)?;
+        let mut tts_config = self.tts_config.clone()?;
+        let section = tts_config.get_mut(&self.engine)?.as_object_mut()?;
+        // Azure's rate goes into SSML as text
+        let rate = if self.engine == "azure_tts" {
+            serde_json::json!(rate.to_string())
+        } else {
+            serde_json::json!(rate)
+        };
+        section.insert(field.to_string(), rate);
+        Some(Arc::new(Self {
+            tts_config: Some(tts_config),
+            ..self.clone()
+        }))
+    }
+
+    fn supports_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    async fn synthesize_stream(&self, text: &str) -> Result<AudioStream, anyhow::Error> {
+        let request = crate::python_service::TTSRequest {
+            text: text.to_string(),
+            voice: self.default_voice.clone(),
+            language: self.default_language.clone(),
+            file_name_no_ext: None,
+        };
+
+        let (sample_rate, chunks) = self
+            .python_service
+            .synthesize_tts_stream(request, self.tts_config.clone())
+            .await?;
+        debug!("TTS stream opened at {} Hz: {}", sample_rate, text);
+
+        Ok(AudioStream { sample_rate, chunks })
     }
 
     fn remove_file(&self, filepath: &str) -> Result<(), anyhow::Error> {
@@ -96,3 +224,88 @@ impl TTSInterface for TTSClient {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python_service::mock::MockPythonService;
+    use futures::StreamExt;
+
+    fn client(service: &Arc<MockPythonService>, streaming: bool) -> TTSClient {
+        TTSClient::new(
+            "melo_tts",
+            service.clone(),
+            Some("EN-US".to_string()),
+            Some("EN".to_string()),
+            Some(serde_json::json!({"melo_tts": {"speaker": "EN-US", "language": "EN", "speed": 1.0}})),
+            streaming,
+        )
+    }
+
+    #[tokio::test]
+    async fn synthesis_sends_the_configured_voice_and_config() {
+        let service = Arc::new(MockPythonService::new().with_tts("cache/hello.wav"));
+
+        let path = client(&service, false).generate_audio("Hello!", Some("hello")).await.unwrap();
+
+        assert_eq!(path, "cache/hello.wav");
+        let requests = service.requests("/tts/synthesize");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["request"]["text"], "Hello!");
+        assert_eq!(requests[0]["request"]["voice"], "EN-US");
+        assert_eq!(requests[0]["request"]["language"], "EN");
+        assert_eq!(requests[0]["request"]["file_name_no_ext"], "hello");
+        assert_eq!(requests[0]["config"]["melo_tts"]["speaker"], "EN-US");
+    }
+
+    #[tokio::test]
+    async fn per_request_language_is_spelled_for_the_engine() {
+        let service = Arc::new(MockPythonService::new().with_tts("cache/a.wav"));
+        let client = client(&service, false);
+
+        client.synthesize("こんにちは", Some("JP"), Some("ja-JP"), None).await.unwrap();
+        // Not a language MeloTTS speaks; the configured one is used
+        client.synthesize("Hi", None, Some("tlh"), None).await.unwrap();
+
+        let requests = service.requests("/tts/synthesize");
+        assert_eq!(requests[0]["request"]["voice"], "JP");
+        assert_eq!(requests[0]["request"]["language"], "JP");
+        assert_eq!(requests[1]["request"]["language"], "EN");
+    }
+
+    #[tokio::test]
+    async fn service_failures_are_errors() {
+        let service = Arc::new(MockPythonService::new().with_error("/tts/synthesize", "out of memory"));
+
+        let err = client(&service, false).generate_audio("Hello!", None).await.unwrap_err();
+
+        assert!(err.to_string().contains("out of memory"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn streams_come_back_with_their_sample_rate() {
+        let chunks = vec![Ok(vec![1, 0]), Ok(vec![2, 0]), Err("connection reset".to_string())];
+        let service = Arc::new(MockPythonService::new().with_tts_stream(22050, chunks));
+        let client = client(&service, true);
+        assert!(client.supports_streaming());
+
+        let stream = client.synthesize_stream("Hello!").await.unwrap();
+        let chunks: Vec<_> = stream.chunks.collect().await;
+
+        assert_eq!(stream.sample_rate, 22050);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].as_ref().unwrap(), &[1, 0]);
+        assert!(chunks[2].is_err());
+        assert_eq!(service.requests("/tts/stream")[0]["request"]["voice"], "EN-US");
+    }
+
+    #[test]
+    fn rate_goes_into_the_engine_section() {
+        let service = Arc::new(MockPythonService::new());
+        let client = client(&service, false);
+
+        assert!(client.with_rate(1.5).is_some());
+        let edge = TTSClient::new("edge_tts", service, None, None, Some(serde_json::json!({})), false);
+        assert!(edge.with_rate(1.5).is_none());
+    }
+}
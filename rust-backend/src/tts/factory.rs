@@ -1,10 +1,39 @@
 use std::sync::Arc;
 use anyhow::Result;
-use tracing::info;
-use crate::python_service::PythonServiceClient;
-use crate::config_manager::tts::TTSConfig;
+use tracing::{info, warn};
+use crate::python_service::PythonService;
+use crate::config_manager::tts::{
+    AzureTTSConfig, BarkTTSConfig, CoquiTTSConfig, CosyVoiceConfig, EdgeTTSConfig,
+    ElevenLabsTTSConfig, FishAPITTSConfig, GPTSoVITSConfig, MeloTTSConfig, MinimaxTTSConfig,
+    OpenAITTSConfig, SiliconFlowTTSConfig, TTSConfig, TTSEngineConfig, XTTSConfig,
+};
 use super::client::TTSClient;
 use super::interface::TTSInterface;
+use super::voice;
+
+/// Engines that can deliver audio while it is being synthesized
+const STREAMING_ENGINES: [&str; 2] = ["edge_tts", "azure_tts"];
+
+/// Engines the Python TTS service can run
+pub const TTS_ENGINES: [&str; 17] = [
+    "azure_tts",
+    "bark_tts",
+    "edge_tts",
+    "cosyvoice_tts",
+    "cosyvoice2_tts",
+    "melo_tts",
+    "coqui_tts",
+    "x_tts",
+    "gpt_sovits_tts",
+    "fish_api_tts",
+    "sherpa_onnx_tts",
+    "siliconflow_tts",
+    "openai_tts",
+    "spark_tts",
+    "minimax_tts",
+    "elevenlabs_tts",
+    "pyttsx3_tts",
+];
 
 /// Factory for creating TTS engines/clients
 pub struct TTSFactory;
@@ -20,7 +49,7 @@ impl TTSFactory {
     /// Boxed TTSInterface implementation
     pub fn create_tts(
         tts_config: &TTSConfig,
-        python_service: Arc<PythonServiceClient>,
+        python_service: Arc<dyn PythonService>,
     ) -> Result<Arc<dyn TTSInterface>> {
         info!("Initializing TTS engine: {}", tts_config.tts_model);
 
@@ -28,78 +57,202 @@ impl TTSFactory {
         let (default_voice, default_language, config_json) = 
             Self::extract_config_from_tts_config(tts_config)?;
 
+        // Only engines whose Python backend can stream are asked to
+        let streaming = tts_config.stream_audio
+            && STREAMING_ENGINES.contains(&tts_config.tts_model.as_str());
+
         let client = TTSClient::new(
+            &tts_config.tts_model,
             python_service,
             default_voice,
             default_language,
             config_json,
+            streaming,
         );
 
         Ok(Arc::new(client))
     }
 
+    /// Create a client for another engine configured in the same TTSConfig
+    ///
+    /// # Arguments
+    /// * `tts_config` - TTS configuration holding the engine's section
+    /// * `engine` - Engine to use instead of `tts_model`
+    /// * `python_service` - Python service client for making HTTP requests
+    pub fn create_tts_for_engine(
+        tts_config: &TTSConfig,
+        engine: &str,
+        python_service: Arc<dyn PythonService>,
+    ) -> Result<Arc<dyn TTSInterface>> {
+        Self::validate_engine(tts_config, engine)?;
+        let mut engine_config = tts_config.clone();
+        engine_config.tts_model = engine.to_string();
+        Self::create_tts(&engine_config, python_service)
+    }
+
+    /// Check that `engine` is a known engine with a section in the config
+    pub fn validate_engine(tts_config: &TTSConfig, engine: &str) -> Result<()> {
+        if !TTS_ENGINES.contains(&engine) {
+            anyhow::bail!("Unknown TTS engine: {} (expected one of {})", engine, TTS_ENGINES.join(", "));
+        }
+        let config_json = serde_json::to_value(tts_config)?;
+        if config_json.get(engine).is_none_or(|section| section.is_null()) {
+            anyhow::bail!("TTS engine {} has no {} section in tts_config", engine, engine);
+        }
+        Ok(())
+    }
+
     /// Extract configuration values from TTSConfig
+    ///
+    /// The engine's voice and language are canonicalized first, in the
+    /// JSON passed on too, so e.g. `en_US` reaches the engine as `en-US`.
+    ///
+    /// # Returns
+    /// The engine's default voice and language, and the whole config as
+    /// JSON for the Python service
     fn extract_config_from_tts_config(
         tts_config: &TTSConfig,
     ) -> Result<(Option<String>, Option<String>, Option<serde_json::Value>)> {
         // Convert TTSConfig to JSON for passing to Python service
-        let config_json = serde_json::to_value(tts_config)?;
+        let mut config_json = serde_json::to_value(tts_config)?;
+        let engine = tts_config.tts_model.as_str();
+        voice::normalize_engine_section(engine, &mut config_json)?;
 
-        // Extract voice and language based on TTS model type
-        // Since TTSConfig uses serde_json::Value for flexibility, we extract from the JSON
-        let (voice, language) = match tts_config.tts_model.as_str() {
-            "azure_tts" => {
-                if let Some(azure_config) = &tts_config.azure_tts {
-                    if let Some(voice_val) = azure_config.get("voice") {
-                        let voice_str = voice_val.as_str().map(|v| v.to_string());
-                        (voice_str, None)
-                    } else {
-                        (None, None)
-                    }
-                } else {
-                    (None, None)
-                }
-            }
-            "edge_tts" => {
-                if let Some(edge_config) = &tts_config.edge_tts {
-                    if let Some(voice_val) = edge_config.get("voice") {
-                        let voice_str = voice_val.as_str().map(|v| v.to_string());
-                        (voice_str, None)
-                    } else {
-                        (None, None)
-                    }
-                } else {
-                    (None, None)
-                }
-            }
-            "melo_tts" => {
-                if let Some(melo_config) = &tts_config.melo_tts {
-                    let voice = melo_config.get("speaker").and_then(|v| v.as_str());
-                    let lang = melo_config.get("language").and_then(|v| v.as_str());
-                    (
-                        voice.map(|v| v.to_string()),
-                        lang.map(|l| l.to_string()),
-                    )
-                } else {
-                    (None, None)
-                }
+        let (voice, language) = match engine {
+            "azure_tts" => Self::voice_and_language::<AzureTTSConfig>(engine, &config_json),
+            "bark_tts" => Self::voice_and_language::<BarkTTSConfig>(engine, &config_json),
+            "edge_tts" => Self::voice_and_language::<EdgeTTSConfig>(engine, &config_json),
+            "melo_tts" => Self::voice_and_language::<MeloTTSConfig>(engine, &config_json),
+            "gpt_sovits_tts" => Self::voice_and_language::<GPTSoVITSConfig>(engine, &config_json),
+            "cosyvoice_tts" | "cosyvoice2_tts" => {
+                Self::voice_and_language::<CosyVoiceConfig>(engine, &config_json)
             }
+            "fish_api_tts" => Self::voice_and_language::<FishAPITTSConfig>(engine, &config_json),
+            "openai_tts" => Self::voice_and_language::<OpenAITTSConfig>(engine, &config_json),
+            "coqui_tts" => Self::voice_and_language::<CoquiTTSConfig>(engine, &config_json),
+            "x_tts" => Self::voice_and_language::<XTTSConfig>(engine, &config_json),
+            "elevenlabs_tts" => Self::voice_and_language::<ElevenLabsTTSConfig>(engine, &config_json),
+            "minimax_tts" => Self::voice_and_language::<MinimaxTTSConfig>(engine, &config_json),
+            "siliconflow_tts" => Self::voice_and_language::<SiliconFlowTTSConfig>(engine, &config_json),
             _ => {
-                // For other TTS models, try to extract from config JSON
-                let voice = config_json
-                    .get("voice")
-                    .or_else(|| config_json.get("speaker"))
-                    .and_then(|v| v.as_str())
-                    .map(|v| v.to_string());
-                let language = config_json
-                    .get("language")
-                    .and_then(|v| v.as_str())
-                    .map(|l| l.to_string());
-                (voice, language)
+                // No struct for this engine; guess from common field names
+                let section = config_json.get(engine).unwrap_or(&config_json);
+                let field = |names: &[&str]| {
+                    names
+                        .iter()
+                        .find_map(|name| section.get(*name).and_then(|v| v.as_str()))
+                        .filter(|v| !v.trim().is_empty())
+                        .map(|v| v.to_string())
+                };
+                (field(&["voice", "speaker"]), field(&["language"]))
             }
         };
 
         Ok((voice, language, Some(config_json)))
     }
+
+    /// Default voice and language from the engine's section of the config;
+    /// none when the section is missing or doesn't match its struct
+    fn voice_and_language<T: TTSEngineConfig>(
+        engine: &str,
+        config_json: &serde_json::Value,
+    ) -> (Option<String>, Option<String>) {
+        let Some(section) = config_json.get(engine).filter(|s| !s.is_null()) else {
+            return (None, None);
+        };
+        match serde_json::from_value::<T>(section.clone()) {
+            Ok(engine_config) => (engine_config.voice(), engine_config.language()),
+            Err(e) => {
+                warn!("Invalid {} section in tts_config, using no default voice: {}", engine, e);
+                (None, None)
+            }
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Default voice and language extracted for `engine` with this section
+    fn extract(engine: &str, section: serde_json::Value) -> (Option<String>, Option<String>) {
+        let tts_config: TTSConfig =
+            serde_json::from_value(json!({ "tts_model": engine, engine: section })).unwrap();
+        let (voice, language, _) = TTSFactory::extract_config_from_tts_config(&tts_config).unwrap();
+        (voice, language)
+    }
+
+    fn some(voice: &str, language: Option<&str>) -> (Option<String>, Option<String>) {
+        (Some(voice.to_string()), language.map(str::to_string))
+    }
+
+    #[test]
+    fn azure_bark_and_edge_voices() {
+        let azure = json!({
+            "api_key": "key", "region": "eastus", "voice": "en-US-AshleyNeural",
+            "pitch": "0", "rate": "1.0"
+        });
+        assert_eq!(extract("azure_tts", azure), some("en-US-AshleyNeural", None));
+        assert_eq!(extract("bark_tts", json!({"voice": "v2/en_speaker_1"})), some("v2/en_speaker_1", None));
+        assert_eq!(extract("edge_tts", json!({"voice": "zh-CN-XiaoxiaoNeural"})), some("zh-CN-XiaoxiaoNeural", None));
+    }
+
+    #[test]
+    fn melo_speaker_and_language() {
+        assert_eq!(extract("melo_tts", json!({"speaker": "EN-Default", "language": "EN"})), some("EN-Default", Some("EN")));
+        // Spelled the way MeloTTS names its languages
+        assert_eq!(extract("melo_tts", json!({"speaker": "JP", "language": "ja-JP"})), some("JP", Some("JP")));
+    }
+
+    #[test]
+    fn gpt_sovits_reference_audio_and_text_language() {
+        let section = json!({"ref_audio_path": "refs/mio.wav", "text_lang": "zh", "prompt_lang": "ja"});
+        assert_eq!(extract("gpt_sovits_tts", section), some("refs/mio.wav", Some("zh")));
+    }
+
+    #[test]
+    fn cosyvoice_pretrained_voice() {
+        let section = json!({"sft_dropdown": "中文女", "mode_checkbox_group": "预训练音色"});
+        assert_eq!(extract("cosyvoice_tts", section.clone()), some("中文女", None));
+        assert_eq!(extract("cosyvoice2_tts", section), some("中文女", None));
+    }
+
+    #[test]
+    fn hosted_engine_voices() {
+        assert_eq!(extract("fish_api_tts", json!({"reference_id": "abc123"})), some("abc123", None));
+        assert_eq!(extract("openai_tts", json!({"voice": "alloy", "model": "tts-1"})), some("alloy", None));
+        assert_eq!(extract("elevenlabs_tts", json!({"voice_id": "JBFqnC"})), some("JBFqnC", None));
+        assert_eq!(extract("minimax_tts", json!({"voice_id": "female-shaonv"})), some("female-shaonv", None));
+        assert_eq!(
+            extract("siliconflow_tts", json!({"default_voice": "FunAudioLLM/CosyVoice2-0.5B:alex"})),
+            some("FunAudioLLM/CosyVoice2-0.5B:alex", None)
+        );
+    }
+
+    #[test]
+    fn coqui_and_xtts_speaker_and_language() {
+        let coqui = json!({
+            "model_name": "tts_models/multilingual/multi-dataset/xtts_v2",
+            "speaker_wav": "me.wav",
+            "language": "en"
+        });
+        assert_eq!(extract("coqui_tts", coqui), some("me.wav", Some("en")));
+        assert_eq!(extract("x_tts", json!({"speaker_wav": "female", "language": "ja"})), some("female", Some("ja")));
+    }
+
+    #[test]
+    fn engines_without_a_struct_use_common_field_names() {
+        assert_eq!(extract("sherpa_onnx_tts", json!({"speaker": "3", "language": ""})), some("3", None));
+    }
+
+    #[test]
+    fn missing_blank_or_invalid_sections_give_no_defaults() {
+        let tts_config: TTSConfig = serde_json::from_value(json!({"tts_model": "openai_tts"})).unwrap();
+        let (voice, language, _) = TTSFactory::extract_config_from_tts_config(&tts_config).unwrap();
+        assert_eq!((voice, language), (None, None));
+        assert_eq!(extract("openai_tts", json!({"voice": "  "})), (None, None));
+        // Azure's fields are required
+        assert_eq!(extract("azure_tts", json!({"voice": "en-US-AshleyNeural"})), (None, None));
+    }
+}
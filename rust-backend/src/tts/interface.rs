@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 use serde::{Deserialize, Serialize};
 
 /// TTS request for synthesizing text to speech
@@ -18,6 +19,14 @@ pub struct TTSResponse {
     pub error: Option<String>,
 }
 
+/// Speech streamed while it is being synthesized
+pub struct AudioStream {
+    /// Sample rate of the PCM data
+    pub sample_rate: u32,
+    /// Chunks of 16-bit little-endian mono PCM
+    pub chunks: BoxStream<'static, Result<Vec<u8>, anyhow::Error>>,
+}
+
 /// TTS interface trait - actual implementation in Python service
 #[async_trait]
 pub trait TTSInterface: Send + Sync {
@@ -25,7 +34,8 @@ pub trait TTSInterface: Send + Sync {
     /// 
     /// # Arguments
     /// * `text` - The text to synthesize
-    /// * `file_name_no_ext` - Optional filename without extension (deprecated, handled by Python)
+    /// * `file_name_no_ext` - Cache file name without extension; a unique one
+    ///   is generated when `None`
     /// 
     /// # Returns
     /// Path to the generated audio file
@@ -37,4 +47,28 @@ pub trait TTSInterface: Send + Sync {
 
     /// Remove an audio file from the filesystem
     fn remove_file(&self, filepath: &str) -> Result<(), anyhow::Error>;
+
+    /// Language the engine speaks, as its config spells it; None when it
+    /// isn't configured
+    fn language(&self) -> Option<String> {
+        None
+    }
+
+    /// A copy of the engine speaking at `rate` times normal speed; None when
+    /// the engine's rate can't be changed
+    fn with_rate(&self, _rate: f64) -> Option<std::sync::Arc<dyn TTSInterface>> {
+        None
+    }
+
+    /// Whether the engine can stream audio while it is being synthesized
+    fn supports_streaming(&self) -> bool {
+        false
+    }
+
+    /// Stream speech for the text as PCM chunks
+    ///
+    /// Only called when `supports_streaming` returns true.
+    async fn synthesize_stream(&self, _text: &str) -> Result<AudioStream, anyhow::Error> {
+        Err(anyhow::anyhow!("Streaming synthesis is not supported by this engine"))
+    }
 }
@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config_manager::tts::TTSConfig;
+
+/// Hosted engines, which handle parallel requests well; local engines share
+/// one model and default to a single synthesis at a time
+const CLOUD_ENGINES: [&str; 6] = [
+    "edge_tts",
+    "azure_tts",
+    "openai_tts",
+    "elevenlabs_tts",
+    "fish_api_tts",
+    "siliconflow_tts",
+];
+
+const CLOUD_CONCURRENCY: usize = 4;
+const LOCAL_CONCURRENCY: usize = 1;
+
+/// Caps how many syntheses run at once across all conversations
+pub struct TTSLimiter {
+    permits: Arc<Semaphore>,
+    limit: usize,
+    waiting: AtomicUsize,
+}
+
+/// Counts a caller as queued until it gets a permit or gives up
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl TTSLimiter {
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(limit)),
+            limit,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Limiter for the configured engine: `max_concurrent_syntheses` if set,
+    /// otherwise a default for local or hosted engines
+    pub fn for_config(tts_config: Option<&TTSConfig>) -> Self {
+        let limit = tts_config
+            .and_then(|c| c.max_concurrent_syntheses)
+            .unwrap_or_else(|| match tts_config {
+                Some(c) if CLOUD_ENGINES.contains(&c.tts_model.as_str()) => CLOUD_CONCURRENCY,
+                _ => LOCAL_CONCURRENCY,
+            });
+        Self::new(limit)
+    }
+
+    /// Wait for a synthesis slot; it is released when the permit is dropped
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.waiting);
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("TTS semaphore is never closed")
+    }
+
+    /// Syntheses allowed at once
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Syntheses running now
+    pub fn in_flight(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+
+    /// Syntheses waiting for a slot
+    pub fn queue_depth(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
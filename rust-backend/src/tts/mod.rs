@@ -1,7 +1,9 @@
 pub mod interface;
 pub mod client;
 pub mod factory;
+pub mod limiter;
+pub mod voice;
 
-pub use interface::{TTSInterface, TTSRequest, TTSResponse};
-pub use client::TTSClient;
+pub use interface::{AudioStream, TTSInterface};
 pub use factory::TTSFactory;
+pub use limiter::TTSLimiter;
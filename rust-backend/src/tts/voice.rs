@@ -0,0 +1,286 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+use tracing::warn;
+
+/// MeloTTS languages and the speakers each has
+const MELO_SPEAKERS: &[(&str, &[&str])] = &[
+    ("EN", &["EN-US", "EN-BR", "EN_INDIA", "EN-AU", "EN-Default"]),
+    ("EN_NEWEST", &["EN-Newest"]),
+    ("ES", &["ES"]),
+    ("FR", &["FR"]),
+    ("ZH", &["ZH"]),
+    ("JP", &["JP"]),
+    ("KR", &["KR"]),
+];
+
+/// Languages XTTS speaks
+const XTTS_LANGUAGES: &[&str] = &[
+    "en", "es", "fr", "de", "it", "pt", "pl", "tr", "ru", "nl", "cs", "ar", "zh-cn", "hu", "ko",
+    "ja", "hi",
+];
+
+/// Values GPT-SoVITS takes for `text_lang` and `prompt_lang`
+const GPT_SOVITS_LANGUAGES: &[&str] = &[
+    "auto", "auto_yue", "en", "zh", "ja", "ko", "yue", "all_zh", "all_ja", "all_ko", "all_yue",
+];
+
+/// Languages of Bark's `v2/<language>_speaker_<n>` voice presets
+const BARK_LANGUAGES: &[&str] = &[
+    "en", "de", "es", "fr", "hi", "it", "ja", "ko", "pl", "pt", "ru", "tr", "zh",
+];
+
+/// Voices of OpenAI's own TTS API; compatible servers have their own
+const OPENAI_VOICES: &[&str] = &[
+    "alloy", "ash", "ballad", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer", "verse",
+];
+
+/// `tag` as a canonical BCP-47 language tag, e.g. `en_us` as `en-US`;
+/// None if it isn't shaped like one
+pub fn canonical_language_tag(tag: &str) -> Option<String> {
+    let mut subtags = tag.trim().split(['-', '_']);
+    let language = subtags.next()?;
+    if !(2..=8).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut canonical = vec![language.to_ascii_lowercase()];
+    let mut private_use = false;
+    for subtag in subtags {
+        if subtag.is_empty() || subtag.len() > 8 || !subtag.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let is_alpha = subtag.chars().all(|c| c.is_ascii_alphabetic());
+        let is_digit = subtag.chars().all(|c| c.is_ascii_digit());
+        // Script (`Hant`) and region (`US`, `419`) casing only applies
+        // before any extension or private use subtags
+        let canonical_subtag = if private_use {
+            subtag.to_ascii_lowercase()
+        } else if subtag.len() == 4 && is_alpha {
+            let mut script = subtag.to_ascii_lowercase();
+            script[..1].make_ascii_uppercase();
+            script
+        } else if (subtag.len() == 2 && is_alpha) || (subtag.len() == 3 && is_digit) {
+            subtag.to_ascii_uppercase()
+        } else {
+            private_use = subtag.len() == 1;
+            subtag.to_ascii_lowercase()
+        };
+        canonical.push(canonical_subtag);
+    }
+    Some(canonical.join("-"))
+}
+
+/// The entry of `known` that `value` names, ignoring case and whether
+/// `-` or `_` separates its parts
+fn find_known(value: &str, known: &[&'static str]) -> Option<&'static str> {
+    let fold = |s: &str| s.trim().to_ascii_lowercase().replace('_', "-");
+    let value = fold(value);
+    known.iter().copied().find(|k| fold(k) == value)
+}
+
+/// Primary language subtag of `tag`, e.g. `ja` for `ja-JP`
+fn primary_language(tag: &str) -> Option<String> {
+    canonical_language_tag(tag).map(|t| t.split('-').next().unwrap_or_default().to_string())
+}
+
+/// `language` as `engine` spells it, e.g. `JP` for MeloTTS given `ja-JP`
+///
+/// # Returns
+/// None when the engine doesn't speak it or it isn't a language tag
+pub fn normalize_language(engine: &str, language: &str) -> Option<String> {
+    match engine {
+        "melo_tts" => {
+            let languages: Vec<&str> = MELO_SPEAKERS.iter().map(|(l, _)| *l).collect();
+            find_known(language, &languages).map(str::to_string).or_else(|| {
+                let code = match primary_language(language)?.as_str() {
+                    "ja" => "JP",
+                    "ko" => "KR",
+                    other => return find_known(other, &languages).map(str::to_string),
+                };
+                Some(code.to_string())
+            })
+        }
+        "x_tts" | "coqui_tts" => find_known(language, XTTS_LANGUAGES)
+            .or_else(|| {
+                let primary = primary_language(language)?;
+                let primary = if primary == "zh" { "zh-cn" } else { &primary };
+                find_known(primary, XTTS_LANGUAGES)
+            })
+            .map(str::to_string),
+        "gpt_sovits_tts" => find_known(language, GPT_SOVITS_LANGUAGES)
+            .or_else(|| find_known(&primary_language(language)?, GPT_SOVITS_LANGUAGES))
+            .map(str::to_string),
+        _ => canonical_language_tag(language),
+    }
+}
+
+/// `voice` with its language part canonicalized where the engine names
+/// voices after one; names the engine is known not to have are logged
+pub fn normalize_voice(engine: &str, voice: &str, section: Option<&Map<String, Value>>) -> String {
+    let voice = voice.trim();
+    match engine {
+        "edge_tts" | "azure_tts" => {
+            // e.g. en-US-AvaMultilingualNeural
+            let parts: Vec<&str> = voice.splitn(3, ['-', '_']).collect();
+            if let [language, region, name] = parts[..] {
+                if let Some(locale) = canonical_language_tag(&format!("{}-{}", language, region)) {
+                    return format!("{}-{}", locale, name);
+                }
+            }
+            warn!(
+                "{} voice {:?} doesn't look like a voice name such as en-US-AvaMultilingualNeural",
+                engine, voice
+            );
+            voice.to_string()
+        }
+        "bark_tts" => {
+            // Presets are all lowercase
+            let lowercase = voice.to_ascii_lowercase();
+            let Some(preset) = lowercase.strip_prefix("v2/") else {
+                // A path to a custom voice prompt
+                return voice.to_string();
+            };
+            let known = preset.split_once("_speaker_").and_then(|(language, number)| {
+                let language = find_known(language, BARK_LANGUAGES)?;
+                let number: u8 = number.parse().ok().filter(|n| *n <= 9)?;
+                Some(format!("v2/{}_speaker_{}", language, number))
+            });
+            known.unwrap_or_else(|| {
+                warn!(
+                    "bark_tts voice {:?} is not a preset; presets are v2/<language>_speaker_<0-9> with language one of {}",
+                    voice,
+                    BARK_LANGUAGES.join(", ")
+                );
+                voice.to_string()
+            })
+        }
+        "melo_tts" => {
+            let speakers: Vec<&str> = MELO_SPEAKERS.iter().flat_map(|(_, s)| s.iter().copied()).collect();
+            find_known(voice, &speakers).map(str::to_string).unwrap_or_else(|| {
+                warn!(
+                    "melo_tts speaker {:?} is not one of {}",
+                    voice,
+                    speakers.join(", ")
+                );
+                voice.to_string()
+            })
+        }
+        "openai_tts" => {
+            if let Some(known) = find_known(voice, OPENAI_VOICES) {
+                return known.to_string();
+            }
+            // Only OpenAI's own API is held to its voice list
+            let base_url = section
+                .and_then(|s| s.get("base_url"))
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            if base_url.is_empty() || base_url.contains("api.openai.com") {
+                warn!(
+                    "openai_tts voice {:?} is not one of {}",
+                    voice,
+                    OPENAI_VOICES.join(", ")
+                );
+            }
+            voice.to_string()
+        }
+        _ => voice.to_string(),
+    }
+}
+
+/// Why `voice` can't speak `language` on `engine`, if it can't
+pub fn combination_problem(engine: &str, voice: &str, language: &str) -> Option<String> {
+    match engine {
+        "melo_tts" => {
+            let (language, speakers) = MELO_SPEAKERS.iter().find(|(l, _)| *l == language)?;
+            if find_known(voice, speakers).is_some() {
+                return None;
+            }
+            Some(format!(
+                "melo_tts speaker {} can't speak {}; use one of {}",
+                voice,
+                language,
+                speakers.join(", ")
+            ))
+        }
+        "edge_tts" | "azure_tts" => {
+            if voice.contains("Multilingual") {
+                return None;
+            }
+            let voice_language = primary_language(voice.split('-').next()?)?;
+            let language = primary_language(language)?;
+            (voice_language != language).then(|| {
+                format!(
+                    "{} voice {} speaks {}, not {}; pick a {} voice or a Multilingual one",
+                    engine, voice, voice_language, language, language
+                )
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Fields of an engine's section holding its voice and its languages
+fn fields(engine: &str) -> (Option<&'static str>, &'static [&'static str]) {
+    match engine {
+        "edge_tts" | "azure_tts" | "bark_tts" | "openai_tts" => (Some("voice"), &[]),
+        "melo_tts" => (Some("speaker"), &["language"]),
+        "x_tts" | "coqui_tts" => (None, &["language"]),
+        "gpt_sovits_tts" => (None, &["text_lang", "prompt_lang"]),
+        _ => (None, &[]),
+    }
+}
+
+/// Canonicalize the voice and language in `engine`'s section of
+/// `tts_config`, in place
+///
+/// Values the engine doesn't know are logged and left as written, as the
+/// engine may still accept them; a voice that can't speak the configured
+/// language is an error.
+pub fn normalize_engine_section(engine: &str, tts_config: &mut Value) -> Result<()> {
+    let Some(section) = tts_config.get_mut(engine).and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    let (voice_field, language_fields) = fields(engine);
+    let value = |section: &Map<String, Value>, field: &str| {
+        section
+            .get(field)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+
+    // Coqui's single-language models ignore the language
+    let single_language = engine == "coqui_tts"
+        && !value(section, "model_name").is_some_and(|m| m.to_ascii_lowercase().contains("xtts"));
+    for field in language_fields {
+        let Some(language) = value(section, field).filter(|_| !single_language) else {
+            continue;
+        };
+        match normalize_language(engine, &language) {
+            Some(normalized) => {
+                section.insert(field.to_string(), Value::String(normalized));
+            }
+            None => warn!(
+                "{}.{} {:?} is not a language {} supports",
+                engine, field, language, engine
+            ),
+        }
+    }
+
+    let Some(voice_field) = voice_field else {
+        return Ok(());
+    };
+    let Some(voice) = value(section, voice_field) else {
+        return Ok(());
+    };
+    let voice = normalize_voice(engine, &voice, Some(section));
+    section.insert(voice_field.to_string(), Value::String(voice.clone()));
+
+    if let Some(language) = language_fields.first().and_then(|f| value(section, f)) {
+        if let Some(problem) = combination_problem(engine, &voice, &language) {
+            anyhow::bail!("Invalid tts_config.{}: {}", engine, problem);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+/// Sample rate the ASR engines expect
+pub const ASR_SAMPLE_RATE: u32 = 16000;
+
+/// Average interleaved channels down to mono
+///
+/// A trailing partial frame is dropped.
+pub fn downmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Resample mono audio with linear interpolation
+///
+/// Good enough for speech going to ASR; not meant for playback.
+pub fn resample_linear(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() || from_rate == 0 || to_rate == 0 {
+        return samples.to_vec();
+    }
+
+    let ratio = from_rate as f64 / to_rate as f64;
+    let out_len = ((samples.len() as f64) / ratio).floor() as usize;
+    let last = samples.len() - 1;
+    (0..out_len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let index = (pos.floor() as usize).min(last);
+            let next = (index + 1).min(last);
+            let frac = (pos - index as f64) as f32;
+            samples[index] + (samples[next] - samples[index]) * frac
+        })
+        .collect()
+}
+
+/// Encode mono samples in -1.0..=1.0 as a 16-bit PCM WAV file
+///
+/// Samples outside the range are clipped.
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    const CHANNELS: u16 = 1;
+    const BITS_PER_SAMPLE: u16 = 16;
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let byte_rate = sample_rate * block_align as u32;
+    let data_len = (samples.len() * block_align as usize) as u32;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+/// Decode a 16-bit PCM WAV file to samples in -1.0..=1.0
+///
+/// Multi-channel audio is downmixed to mono.
+///
+/// # Returns
+/// The samples and their sample rate
+pub fn decode_wav(bytes: &[u8]) -> anyhow::Result<(Vec<f32>, u32)> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        anyhow::bail!("Not a WAV file");
+    }
+
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into()?) as usize;
+        let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                format = Some((
+                    u16::from_le_bytes([body[0], body[1]]),
+                    u16::from_le_bytes([body[2], body[3]]),
+                    u32::from_le_bytes(body[4..8].try_into()?),
+                    u16::from_le_bytes([body[14], body[15]]),
+                ));
+            }
+            b"data" => {
+                let (audio_format, channels, sample_rate, bits) =
+                    format.ok_or_else(|| anyhow::anyhow!("WAV data chunk before fmt chunk"))?;
+                if audio_format != 1 || bits != 16 {
+                    anyhow::bail!(
+                        "Unsupported WAV encoding (format {}, {} bits); expected 16-bit PCM",
+                        audio_format,
+                        bits
+                    );
+                }
+                let samples: Vec<f32> = body
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / i16::MAX as f32)
+                    .collect();
+                return Ok((downmix(&samples, channels), sample_rate));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        pos += 8 + len + len % 2;
+    }
+    anyhow::bail!("WAV file has no data chunk")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_round_trip_keeps_samples() {
+        let samples: Vec<f32> = (0..480).map(|i| (i as f32 * 0.05).sin() * 0.8).collect();
+
+        let (decoded, sample_rate) = decode_wav(&encode_wav(&samples, 16000)).unwrap();
+
+        assert_eq!(sample_rate, 16000);
+        assert_eq!(decoded.len(), samples.len());
+        for (a, b) in samples.iter().zip(&decoded) {
+            assert!((a - b).abs() <= 1.0 / i16::MAX as f32, "{a} vs {b}");
+        }
+    }
+
+    #[test]
+    fn wav_header_has_the_chunk_sizes() {
+        let wav = encode_wav(&[0.0; 100], 24000);
+
+        assert_eq!(wav.len(), 44 + 200);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 200);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 24000);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 48000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 200);
+    }
+
+    #[test]
+    fn out_of_range_samples_are_clamped() {
+        let (decoded, _) = decode_wav(&encode_wav(&[2.0, -3.0, 1.0, -1.0], 16000)).unwrap();
+        assert_eq!(decoded, [1.0, -1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn non_wav_bytes_are_rejected() {
+        assert!(decode_wav(b"not a wav file at all").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::Context;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tracing::debug;
+
+use super::audio::downmix;
+
+/// Container or codec of an audio file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Mp3,
+    /// Ogg holding Vorbis
+    Ogg,
+    /// Ogg holding Opus, which can be detected but not decoded
+    Opus,
+    Flac,
+    /// Raw ADTS AAC
+    Aac,
+    /// MP4 audio, usually AAC
+    M4a,
+    Webm,
+}
+
+const FORMATS: [AudioFormat; 8] = [
+    AudioFormat::Wav,
+    AudioFormat::Mp3,
+    AudioFormat::Ogg,
+    AudioFormat::Opus,
+    AudioFormat::Flac,
+    AudioFormat::Aac,
+    AudioFormat::M4a,
+    AudioFormat::Webm,
+];
+
+impl AudioFormat {
+    /// Name of the format, also its usual file extension
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Mp3 => "mp3",
+            Self::Ogg => "ogg",
+            Self::Opus => "opus",
+            Self::Flac => "flac",
+            Self::Aac => "aac",
+            Self::M4a => "m4a",
+            Self::Webm => "webm",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Wav => "audio/wav",
+            Self::Mp3 => "audio/mpeg",
+            Self::Ogg => "audio/ogg",
+            Self::Opus => "audio/ogg; codecs=opus",
+            Self::Flac => "audio/flac",
+            Self::Aac => "audio/aac",
+            Self::M4a => "audio/mp4",
+            Self::Webm => "audio/webm",
+        }
+    }
+
+    /// The format named `name` or having it as extension, ignoring case
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            "wave" => Some(Self::Wav),
+            "mpeg" => Some(Self::Mp3),
+            "oga" => Some(Self::Ogg),
+            "mp4" => Some(Self::M4a),
+            _ => FORMATS.into_iter().find(|f| f.name() == name),
+        }
+    }
+
+    /// Format from the leading bytes of a file
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [b'O', b'g', b'g', b'S', ..] => {
+                // The first page carries the codec's identification header
+                let opus = bytes.windows(8).any(|w| w == b"OpusHead");
+                Some(if opus { Self::Opus } else { Self::Ogg })
+            }
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(Self::M4a),
+            [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(Self::Webm),
+            // ADTS frames have layer bits 00, MPEG audio frames don't
+            [0xFF, second, ..] if second & 0xF6 == 0xF0 => Some(Self::Aac),
+            [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    /// Format of a file from its contents, or its extension when the
+    /// contents aren't recognized
+    pub fn detect(path: &Path) -> Option<Self> {
+        let mut head = Vec::with_capacity(64);
+        let sniffed = std::fs::File::open(path)
+            .and_then(|file| file.take(64).read_to_end(&mut head))
+            .ok()
+            .and_then(|_| Self::sniff(&head));
+        sniffed.or_else(|| Self::parse(path.extension()?.to_str()?))
+    }
+}
+
+/// Decode an audio file of any format symphonia reads to mono samples in
+/// -1.0..=1.0
+///
+/// Packets that fail to decode are skipped, so a damaged file still gives
+/// what could be read.
+///
+/// # Returns
+/// The samples and their sample rate
+pub fn decode_file(path: &Path) -> anyhow::Result<(Vec<f32>, u32)> {
+    let mut hint = Hint::new();
+    if let Some(format) = AudioFormat::detect(path) {
+        hint.with_extension(format.name());
+    }
+    let file = std::fs::File::open(path)?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .with_context(|| format!("Unrecognized audio format in {}", path.display()))?;
+    let mut reader = probed.format;
+
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow::anyhow!("No audio track in {}", path.display()))?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or_default();
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .with_context(|| format!("Unsupported codec in {}", path.display()))?;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(e)) => {
+                debug!("Skipping undecodable packet in {}: {}", path.display(), e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        sample_rate = spec.rate;
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend(downmix(buffer.samples(), spec.channels.count() as u16));
+    }
+    Ok((samples, sample_rate))
+}
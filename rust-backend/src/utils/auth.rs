@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use tracing::warn;
+
+use crate::state::AppState;
+
+/// Route prefixes serving static directories, which `auth_exempt_static`
+/// opens up
+pub const STATIC_ROUTES: &[&str] = &["/cache", "/live2d-models", "/bg", "/characters", "/avatars"];
+
+/// Routes that never need the token, so load balancers can probe them
+const PUBLIC_ROUTES: &[&str] = &["/api/health"];
+
+/// Refuse requests without the configured `auth_token` with 401, when
+/// `auth_enabled` is on
+///
+/// Applies to the WebSocket upgrade as well as REST routes. Browsers can't
+/// set headers on a WebSocket upgrade, so the token may also come as a
+/// `?token=` query parameter.
+pub async fn require_auth(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = state.config();
+    let system_config = &config.system_config;
+    if !system_config.auth_enabled {
+        return next.run(request).await;
+    }
+
+    let path = request.uri().path();
+    let exempt = PUBLIC_ROUTES.contains(&path)
+        || (system_config.auth_exempt_static && STATIC_ROUTES.iter().any(|prefix| under(path, prefix)));
+    if exempt {
+        return next.run(request).await;
+    }
+
+    let expected = system_config.auth_token.as_deref().unwrap_or_default();
+    match request_token(&request) {
+        Some(token) if !expected.is_empty() && constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            next.run(request).await
+        }
+        token => {
+            warn!(
+                "Refused {} {}: {} token",
+                request.method(),
+                path,
+                if token.is_some() { "wrong" } else { "no" }
+            );
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                Json(json!({"error": "Unauthorized"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Whether `path` is `prefix` or below it
+fn under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Token from the `Authorization` or `X-API-Key` header, or the `token`
+/// query parameter
+fn request_token(request: &Request) -> Option<String> {
+    let headers = request.headers();
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            let (scheme, token) = v.trim().split_once(' ')?;
+            scheme.eq_ignore_ascii_case("bearer").then(|| token.trim().to_string())
+        });
+    let api_key = || {
+        headers
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+    };
+    let query = || {
+        request.uri().query()?.split('&').find_map(|pair| {
+            let value = pair.strip_prefix("token=")?;
+            percent_decode_str(&value.replace('+', " "))
+                .decode_utf8()
+                .ok()
+                .map(|v| v.into_owned())
+        })
+    };
+    bearer.or_else(api_key).or_else(query)
+}
+
+/// Compare without stopping at the first differing byte, so response times
+/// don't reveal how much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use axum::{routing::get, Router};
+
+    const TOKEN: &str = "s3cret token";
+
+    /// Base URL of a server with a few routes behind `require_auth`
+    async fn serve(auth_enabled: bool, auth_exempt_static: bool) -> String {
+        let mut config = test_support::config();
+        config.system_config.auth_enabled = auth_enabled;
+        config.system_config.auth_token = Some(TOKEN.to_string());
+        config.system_config.auth_exempt_static = auth_exempt_static;
+        let state = test_support::state(config).await;
+
+        let app = Router::new()
+            .route("/api/health", get(|| async { "ok" }))
+            .route("/api/config", get(|| async { "ok" }))
+            .route("/cache/a.wav", get(|| async { "ok" }))
+            .route("/cachex/a.wav", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(state, require_auth));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    async fn status(request: reqwest::RequestBuilder) -> u16 {
+        request.send().await.unwrap().status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn everything_is_open_when_auth_is_off() {
+        let base = serve(false, false).await;
+        assert_eq!(status(reqwest::Client::new().get(format!("{base}/api/config"))).await, 200);
+    }
+
+    #[tokio::test]
+    async fn missing_or_wrong_tokens_are_refused() {
+        let base = serve(true, false).await;
+        let client = reqwest::Client::new();
+        let url = format!("{base}/api/config");
+
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+        assert_eq!(status(client.get(&url).bearer_auth("s3cret")).await, 401);
+        assert_eq!(status(client.get(&url).header("x-api-key", "s3cret token!")).await, 401);
+        assert_eq!(status(client.get(&url).header("authorization", format!("Basic {TOKEN}"))).await, 401);
+        assert_eq!(status(client.get(format!("{url}?token=wrong"))).await, 401);
+    }
+
+    #[tokio::test]
+    async fn the_token_is_accepted_from_each_place() {
+        let base = serve(true, false).await;
+        let client = reqwest::Client::new();
+        let url = format!("{base}/api/config");
+
+        assert_eq!(status(client.get(&url).bearer_auth(TOKEN)).await, 200);
+        assert_eq!(status(client.get(&url).header("authorization", format!("bearer  {TOKEN} "))).await, 200);
+        assert_eq!(status(client.get(&url).header("x-api-key", TOKEN)).await, 200);
+        assert_eq!(status(client.get(format!("{url}?a=1&token=s3cret%20token"))).await, 200);
+        assert_eq!(status(client.get(format!("{url}?token=s3cret+token"))).await, 200);
+    }
+
+    #[tokio::test]
+    async fn health_and_exempt_static_routes_need_no_token() {
+        let client = reqwest::Client::new();
+
+        let base = serve(true, false).await;
+        assert_eq!(status(client.get(format!("{base}/api/health"))).await, 200);
+        assert_eq!(status(client.get(format!("{base}/cache/a.wav"))).await, 401);
+
+        let base = serve(true, true).await;
+        assert_eq!(status(client.get(format!("{base}/cache/a.wav"))).await, 200);
+        // Only whole path segments match a static prefix
+        assert_eq!(status(client.get(format!("{base}/cachex/a.wav"))).await, 401);
+        assert_eq!(status(client.get(format!("{base}/api/config"))).await, 401);
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_values() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+        assert!(!constant_time_eq(b"", b"token"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use dashmap::DashSet;
+use tracing::{debug, info, warn};
+
+use crate::tts::TTSInterface;
+
+/// Extensions of audio files the TTS engines write into the cache
+const AUDIO_EXTENSIONS: [&str; 7] = ["wav", "mp3", "ogg", "opus", "flac", "m4a", "pcm"];
+
+/// Audio files that a running conversation still refers to, keyed by file name
+pub type AudioInUse = Arc<DashSet<String>>;
+
+/// Key used in [`AudioInUse`] for an audio path
+pub fn audio_key(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Periodically deletes generated audio older than the TTL from the cache
+pub struct CacheJanitor {
+    cache_dir: String,
+    ttl: Duration,
+    interval: Duration,
+    in_use: AudioInUse,
+    tts_engine: Option<Arc<dyn TTSInterface>>,
+}
+
+impl CacheJanitor {
+    pub fn new(
+        cache_dir: String,
+        ttl: Duration,
+        interval: Duration,
+        in_use: AudioInUse,
+        tts_engine: Option<Arc<dyn TTSInterface>>,
+    ) -> Self {
+        Self {
+            cache_dir,
+            ttl,
+            interval,
+            in_use,
+            tts_engine,
+        }
+    }
+
+    /// Start the janitor on the runtime
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        info!(
+            "Cache janitor started for {} (ttl={:?}, interval={:?})",
+            self.cache_dir, self.ttl, self.interval
+        );
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.interval);
+            // The first tick fires immediately; skip it so startup isn't slowed
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let (files, bytes) = self.sweep();
+                if files > 0 {
+                    info!("Cache janitor reclaimed {} files ({} bytes)", files, bytes);
+                }
+            }
+        })
+    }
+
+    /// Remove expired audio files once
+    ///
+    /// # Returns
+    /// Number of files and bytes reclaimed
+    pub fn sweep(&self) -> (usize, u64) {
+        let entries = match std::fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Cache janitor could not read {}: {}", self.cache_dir, e);
+                return (0, 0);
+            }
+        };
+
+        let now = SystemTime::now();
+        let mut files = 0;
+        let mut bytes = 0;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_audio = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+            if !is_audio {
+                continue;
+            }
+
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+            if !metadata.is_file() || age < self.ttl {
+                continue;
+            }
+
+            let path_str = path.to_string_lossy().to_string();
+            if self.in_use.contains(&audio_key(&path_str)) {
+                debug!("Cache janitor skipping in-use file {}", path_str);
+                continue;
+            }
+
+            let removed = match &self.tts_engine {
+                Some(engine) => engine.remove_file(&path_str),
+                None => std::fs::remove_file(&path).map_err(anyhow::Error::from),
+            };
+            match removed {
+                Ok(()) => {
+                    files += 1;
+                    bytes += metadata.len();
+                }
+                Err(e) => warn!("Cache janitor failed to remove {}: {}", path_str, e),
+            }
+        }
+
+        (files, bytes)
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+/// Most parts a chunked message may be split into
+pub const MAX_CHUNKS: usize = 10_000;
+
+/// Partial messages that stop receiving parts are dropped after this long
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A message arriving in parts
+struct Partial {
+    parts: Vec<Option<String>>,
+    received: usize,
+    bytes: usize,
+    last_part_at: Instant,
+}
+
+/// Reassembles messages a client sent in parts, for payloads (images,
+/// audio) larger than one WebSocket message may be
+///
+/// Each part is `{"type": "chunk", "chunk_id", "index", "total", "data"}`.
+/// Joining the `data` of parts `0..total` in order gives the original
+/// message text, which is then handled like any other message. Parts may
+/// arrive in any order.
+pub struct ChunkAssembler {
+    partial: HashMap<String, Partial>,
+    max_bytes: usize,
+}
+
+impl ChunkAssembler {
+    /// # Arguments
+    /// * `max_bytes` - Largest reassembled message accepted
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            partial: HashMap::new(),
+            max_bytes,
+        }
+    }
+
+    /// Add a part
+    ///
+    /// # Returns
+    /// The complete message text once every part has arrived. On error the
+    /// partial message is dropped.
+    pub fn push(&mut self, msg: &Value) -> anyhow::Result<Option<String>> {
+        self.partial.retain(|_, p| p.last_part_at.elapsed() < CHUNK_TIMEOUT);
+
+        let chunk_id = msg
+            .get("chunk_id")
+            .and_then(|v| v.as_str())
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("chunk requires a 'chunk_id'"))?
+            .to_string();
+        let result = self.add_part(&chunk_id, msg);
+        if result.is_err() {
+            self.partial.remove(&chunk_id);
+        }
+        result
+    }
+
+    fn add_part(&mut self, chunk_id: &str, msg: &Value) -> anyhow::Result<Option<String>> {
+        let field = |name: &str| {
+            msg.get(name)
+                .and_then(|v| v.as_u64())
+                .map(|v| v as usize)
+                .ok_or_else(|| anyhow::anyhow!("chunk {} requires a numeric '{}'", chunk_id, name))
+        };
+        let index = field("index")?;
+        let total = field("total")?;
+        let data = msg
+            .get("data")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("chunk {} requires a string 'data'", chunk_id))?;
+
+        if total == 0 || total > MAX_CHUNKS {
+            anyhow::bail!("chunk {}: total must be between 1 and {}", chunk_id, MAX_CHUNKS);
+        }
+        if index >= total {
+            anyhow::bail!("chunk {}: index {} is out of range for {} parts", chunk_id, index, total);
+        }
+
+        let partial = self.partial.entry(chunk_id.to_string()).or_insert_with(|| Partial {
+            parts: vec![None; total],
+            received: 0,
+            bytes: 0,
+            last_part_at: Instant::now(),
+        });
+        if partial.parts.len() != total {
+            anyhow::bail!(
+                "chunk {}: total changed from {} to {}",
+                chunk_id,
+                partial.parts.len(),
+                total
+            );
+        }
+        if partial.parts[index].is_some() {
+            anyhow::bail!("chunk {}: part {} was sent twice", chunk_id, index);
+        }
+
+        partial.bytes += data.len();
+        if partial.bytes > self.max_bytes {
+            anyhow::bail!("chunk {}: message exceeds the {} byte limit", chunk_id, self.max_bytes);
+        }
+        partial.parts[index] = Some(data.to_string());
+        partial.received += 1;
+        partial.last_part_at = Instant::now();
+
+        if partial.received < total {
+            return Ok(None);
+        }
+        let partial = self.partial.remove(chunk_id).expect("partial message was just updated");
+        Ok(Some(partial.parts.into_iter().flatten().collect()))
+    }
+}
@@ -0,0 +1,26 @@
+use reqwest::StatusCode;
+
+/// An HTTP request answered with a failure status
+///
+/// Kept as the error's source so callers can act on the status, e.g. retry
+/// a rate-limited request, without parsing the message.
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: StatusCode,
+    /// What failed, including the response body
+    pub message: String,
+}
+
+impl HttpStatusError {
+    pub fn new(status: StatusCode, message: String) -> Self {
+        Self { status, message }
+    }
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
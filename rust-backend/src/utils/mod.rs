@@ -1,4 +1,14 @@
+pub mod audio;
+pub mod audio_format;
+pub mod auth;
+pub mod cache_janitor;
+pub mod chunked_message;
+pub mod http_status;
+pub mod number_reading;
+pub mod redact;
 pub mod sentence_divider;
+pub mod static_guard;
 pub mod stream_audio;
 pub mod tts_preprocessor;
+pub mod utf8_decoder;
 
@@ -0,0 +1,487 @@
+use regex::{Captures, Regex};
+
+use crate::utils::sentence_divider::is_cjk;
+use crate::utils::tts_preprocessor::{normalize_numbers, number_to_words, plural};
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September",
+    "October", "November", "December",
+];
+
+/// English-speaking regions that write dates day first
+const DAY_FIRST_REGIONS: &[&str] = &["gb", "au", "nz", "ie", "in", "za"];
+
+const CHINESE_DIGITS: [char; 10] = ['零', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+/// How numbers are read aloud in a TTS language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberLocale {
+    /// English; `day_first` for regions that write 3/4 for the 3rd of April
+    English { day_first: bool },
+    Chinese,
+}
+
+impl NumberLocale {
+    /// Locale of a TTS language as engines spell it, e.g. `en-GB`, `ZH`,
+    /// `zh-cn` or `all_zh`
+    ///
+    /// # Returns
+    /// None for languages without reading rules
+    pub fn from_language(language: &str) -> Option<Self> {
+        let language = language.trim().to_ascii_lowercase();
+        let language = language.strip_prefix("all_").unwrap_or(&language);
+        let mut subtags = language.split(['-', '_']);
+        match subtags.next()? {
+            "en" => {
+                let region = subtags.next().unwrap_or_default();
+                Some(Self::English {
+                    day_first: DAY_FIRST_REGIONS.contains(&region),
+                })
+            }
+            "zh" | "cmn" | "yue" => Some(Self::Chinese),
+            _ => None,
+        }
+    }
+
+    /// Locale guessed from the text, for engines that don't say what they
+    /// speak: Chinese for Han text, none for Japanese
+    pub fn detect(text: &str) -> Option<Self> {
+        let kana = text
+            .chars()
+            .any(|c| matches!(c as u32, 0x3040..=0x30FF | 0xFF66..=0xFF9F));
+        if kana {
+            None
+        } else if text.chars().any(is_cjk) {
+            Some(Self::Chinese)
+        } else {
+            Some(Self::English { day_first: false })
+        }
+    }
+}
+
+/// Rewrite numbers, dates, times and ordinals as `locale` reads them aloud
+///
+/// "3/4/2024" becomes "March fourth, twenty twenty-four" in American
+/// English, "the third of April, twenty twenty-four" in British English and
+/// "二零二四年三月四日" in Chinese; "1,000" becomes "one thousand" or "一千".
+/// Digits that are part of a longer token ("v2", "5G") are left as
+/// written.
+pub fn read_numbers(text: &str, locale: NumberLocale) -> String {
+    match locale {
+        NumberLocale::English { day_first } => read_english(text, day_first),
+        NumberLocale::Chinese => read_chinese(text),
+    }
+}
+
+/// Replace the matches of `pattern` that stand on their own, or leave one
+/// as written where `read` gives None
+///
+/// `\b` can't tell where a number ends, as it sees no boundary between a
+/// digit and a Han character.
+fn replace_standalone(
+    text: &str,
+    pattern: &Regex,
+    mut read: impl FnMut(&Captures) -> Option<String>,
+) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in pattern.captures_iter(text) {
+        let matched = caps.get(0).expect("group 0 is the whole match");
+        let glued_before = runs_on(
+            matched.as_str().chars().next(),
+            text[..matched.start()].chars().rev(),
+        );
+        let glued_after = runs_on(
+            matched.as_str().chars().next_back(),
+            text[matched.end()..].chars(),
+        );
+        if glued_before || glued_after {
+            continue;
+        }
+        if let Some(spoken) = read(&caps) {
+            result.push_str(&text[last..matched.start()]);
+            result.push_str(&spoken);
+            last = matched.end();
+        }
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// Whether a match whose text ends in `edge` continues into `neighbours`,
+/// the text next to it read away from the match, as in "v2" or "1.2.3";
+/// only a match ending in a digit or letter can
+fn runs_on(edge: Option<char>, mut neighbours: impl Iterator<Item = char>) -> bool {
+    if !edge.is_some_and(|c| c.is_ascii_alphanumeric()) {
+        return false;
+    }
+    match neighbours.next() {
+        Some(c) if c.is_ascii_alphanumeric() => true,
+        Some('.' | ',' | ':' | '/' | '-') => neighbours.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// Numeric value of an optional capture group
+fn number(caps: &Captures, group: usize) -> Option<u64> {
+    caps.get(group)?.as_str().parse().ok()
+}
+
+/// Month and day if they make a date
+fn valid_date(month: u64, day: u64) -> Option<(u64, u64)> {
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then_some((month, day))
+}
+
+/// Month and day of a date written without the year first; `day_first`
+/// decides which comes first unless only one order makes a date
+fn month_and_day(first: u64, second: u64, day_first: bool) -> Option<(u64, u64)> {
+    let (month, day) = if day_first { (second, first) } else { (first, second) };
+    valid_date(month, day).or_else(|| valid_date(day, month))
+}
+
+fn read_english(text: &str, day_first: bool) -> String {
+    let spoken_date = |year: Option<u64>, month: u64, day: u64| {
+        let month = MONTHS[month as usize - 1];
+        let date = if day_first {
+            format!("the {} of {}", ordinal_words(day), month)
+        } else {
+            format!("{} {}", month, ordinal_words(day))
+        };
+        match year {
+            Some(year) => format!("{}, {}", date, year_words(year)),
+            None => date,
+        }
+    };
+
+    let year_first = Regex::new(r"(\d{4})[-/](\d{1,2})[-/](\d{1,2})").unwrap();
+    let text = replace_standalone(text, &year_first, |caps| {
+        let (month, day) = valid_date(number(caps, 2)?, number(caps, 3)?)?;
+        Some(spoken_date(number(caps, 1), month, day))
+    });
+    // Without a year, 1/2 is more likely a fraction than a date
+    let year_last = Regex::new(r"(\d{1,2})/(\d{1,2})/(\d{4}|\d{2})").unwrap();
+    let text = replace_standalone(&text, &year_last, |caps| {
+        let (month, day) = month_and_day(number(caps, 1)?, number(caps, 2)?, day_first)?;
+        Some(spoken_date(number(caps, 3), month, day))
+    });
+
+    let time = Regex::new(r"(\d{1,2}):(\d{2})(?::(\d{2}))?(?:\s?([AaPp])(?:\.[Mm]\.|[Mm]))?").unwrap();
+    let text = replace_standalone(&text, &time, |caps| {
+        let (hour, minute) = (number(caps, 1)?, number(caps, 2)?);
+        let meridiem = caps.get(4).map(|m| m.as_str().to_ascii_lowercase());
+        let max_hour = if meridiem.is_some() { 12 } else { 23 };
+        if hour > max_hour || minute > 59 {
+            return None;
+        }
+        let mut spoken = number_to_words(hour);
+        match minute {
+            0 if meridiem.is_some() => {}
+            // Midnight and afternoon hours on a 24-hour clock
+            0 if hour == 0 || hour > 12 => spoken.push_str(" hundred"),
+            0 => spoken.push_str(" o'clock"),
+            1..=9 => spoken = format!("{} oh {}", spoken, number_to_words(minute)),
+            _ => spoken = format!("{} {}", spoken, number_to_words(minute)),
+        }
+        if let Some(second) = number(caps, 3).filter(|s| *s > 0) {
+            if second > 59 {
+                return None;
+            }
+            spoken = format!(
+                "{} and {} {}",
+                spoken,
+                number_to_words(second),
+                plural(second, "second", "seconds")
+            );
+        }
+        if let Some(meridiem) = meridiem {
+            spoken = format!("{} {} m", spoken, meridiem);
+        }
+        Some(spoken)
+    });
+
+    let ordinal = Regex::new(r"(?i)(\d{1,3}(?:,\d{3})+|\d+)(st|nd|rd|th)").unwrap();
+    let text = replace_standalone(&text, &ordinal, |caps| {
+        let n: u64 = caps[1].replace(',', "").parse().ok()?;
+        Some(ordinal_words(n))
+    });
+
+    normalize_numbers(&text)
+}
+
+/// A number as an English ordinal, e.g. 21 → "twenty-first"
+pub fn ordinal_words(n: u64) -> String {
+    let words = number_to_words(n);
+    let split = words.rfind([' ', '-']).map_or(0, |i| i + 1);
+    let (head, last) = words.split_at(split);
+    let last = match last {
+        "one" => "first".to_string(),
+        "two" => "second".to_string(),
+        "three" => "third".to_string(),
+        "five" => "fifth".to_string(),
+        "eight" => "eighth".to_string(),
+        "nine" => "ninth".to_string(),
+        "twelve" => "twelfth".to_string(),
+        tens if tens.ends_with('y') => format!("{}ieth", &tens[..tens.len() - 1]),
+        other => format!("{}th", other),
+    };
+    format!("{}{}", head, last)
+}
+
+/// A year as it is said in English, e.g. 1999 → "nineteen ninety-nine"
+fn year_words(year: u64) -> String {
+    match year {
+        // Two-digit years, as in 3/4/24
+        0..=99 => number_to_words(year),
+        2000..=2009 => number_to_words(year),
+        1000..=9999 if year.is_multiple_of(100) => format!("{} hundred", number_to_words(year / 100)),
+        1000..=9999 => {
+            let rest = year % 100;
+            let rest = if rest < 10 {
+                format!("oh {}", number_to_words(rest))
+            } else {
+                number_to_words(rest)
+            };
+            format!("{} {}", number_to_words(year / 100), rest)
+        }
+        _ => number_to_words(year),
+    }
+}
+
+fn read_chinese(text: &str) -> String {
+    let spoken_date = |year: Option<&str>, month: u64, day: u64| {
+        let year = year.map(|y| format!("{}年", chinese_digits(y))).unwrap_or_default();
+        format!("{}{}月{}日", year, number_to_chinese(month), number_to_chinese(day))
+    };
+
+    let year_first = Regex::new(r"(\d{4})[-/.](\d{1,2})[-/.](\d{1,2})").unwrap();
+    let text = replace_standalone(text, &year_first, |caps| {
+        let (month, day) = valid_date(number(caps, 2)?, number(caps, 3)?)?;
+        Some(spoken_date(Some(&caps[1]), month, day))
+    });
+    let year_last = Regex::new(r"(\d{1,2})/(\d{1,2})/(\d{4})").unwrap();
+    let text = replace_standalone(&text, &year_last, |caps| {
+        let (month, day) = month_and_day(number(caps, 1)?, number(caps, 2)?, false)?;
+        Some(spoken_date(Some(&caps[3]), month, day))
+    });
+    // Years are read digit by digit: 2024年 is 二零二四年
+    let year = Regex::new(r"(\d{4})年").unwrap();
+    let text = replace_standalone(&text, &year, |caps| Some(format!("{}年", chinese_digits(&caps[1]))));
+
+    let time = Regex::new(r"(\d{1,2}):(\d{2})(?::(\d{2}))?").unwrap();
+    let text = replace_standalone(&text, &time, |caps| {
+        let (hour, minute) = (number(caps, 1)?, number(caps, 2)?);
+        let second = number(caps, 3);
+        if hour > 23 || minute > 59 || second.is_some_and(|s| s > 59) {
+            return None;
+        }
+        // Two o'clock is 两点, not 二点
+        let mut spoken = if hour == 2 {
+            "两点".to_string()
+        } else {
+            format!("{}点", number_to_chinese(hour))
+        };
+        if minute > 0 || second.is_some_and(|s| s > 0) {
+            let zero = if minute < 10 { "零" } else { "" };
+            spoken = format!("{}{}{}分", spoken, zero, number_to_chinese(minute));
+        }
+        if let Some(second) = second.filter(|s| *s > 0) {
+            spoken = format!("{}{}秒", spoken, number_to_chinese(second));
+        }
+        Some(spoken)
+    });
+
+    let ordinal = Regex::new(r"(?i)(\d+)(st|nd|rd|th)").unwrap();
+    let text = replace_standalone(&text, &ordinal, |caps| {
+        Some(format!("第{}", read_chinese_number(&caps[1])?))
+    });
+
+    let numbers =
+        Regex::new(r"([$€£¥￥])?(\d{1,3}(?:,\d{3})+|\d+)(?:\.(\d+))?([%％])?").unwrap();
+    replace_standalone(&text, &numbers, |caps| {
+        let whole = read_chinese_number(&caps[2])?;
+        let fraction = caps.get(3).map(|f| f.as_str());
+        if caps.get(4).is_some() {
+            let fraction = fraction.map(|f| format!("点{}", chinese_digits(f))).unwrap_or_default();
+            let symbol = caps.get(1).map_or("", |s| s.as_str());
+            return Some(format!("{}百分之{}{}", symbol, whole, fraction));
+        }
+
+        let Some(symbol) = caps.get(1).map(|s| s.as_str()) else {
+            let fraction = fraction.map(|f| format!("点{}", chinese_digits(f))).unwrap_or_default();
+            return Some(format!("{}{}", whole, fraction));
+        };
+        if matches!(symbol, "¥" | "￥") {
+            // 元, 角 and 分
+            let cents = fraction.filter(|f| f.len() == 2).and_then(|f| f.parse::<u64>().ok());
+            if let Some(cents) = cents {
+                let mut spoken = format!("{}元", whole);
+                match (cents / 10, cents % 10) {
+                    (0, 0) => {}
+                    (0, fen) => spoken.push_str(&format!("零{}分", CHINESE_DIGITS[fen as usize])),
+                    (jiao, 0) => spoken.push_str(&format!("{}角", CHINESE_DIGITS[jiao as usize])),
+                    (jiao, fen) => spoken.push_str(&format!(
+                        "{}角{}分",
+                        CHINESE_DIGITS[jiao as usize], CHINESE_DIGITS[fen as usize]
+                    )),
+                }
+                return Some(spoken);
+            }
+        }
+        let unit = match symbol {
+            "$" => "美元",
+            "€" => "欧元",
+            "£" => "英镑",
+            _ => "元",
+        };
+        let fraction = fraction
+            .map(|f| f.trim_end_matches('0'))
+            .filter(|f| !f.is_empty())
+            .map(|f| format!("点{}", chinese_digits(f)))
+            .unwrap_or_default();
+        Some(format!("{}{}{}", whole, fraction, unit))
+    })
+}
+
+/// Written digits, with or without thousands separators, in Chinese;
+/// numbers with a leading zero (codes, phone numbers) digit by digit
+fn read_chinese_number(digits: &str) -> Option<String> {
+    let digits = digits.replace(',', "");
+    if digits.len() > 1 && digits.starts_with('0') {
+        return Some(chinese_digits(&digits));
+    }
+    digits.parse().ok().map(number_to_chinese)
+}
+
+/// Each digit as its Chinese numeral, e.g. 2024 → 二零二四
+fn chinese_digits(digits: &str) -> String {
+    digits
+        .chars()
+        .filter_map(|d| d.to_digit(10))
+        .map(|d| CHINESE_DIGITS[d as usize])
+        .collect()
+}
+
+/// A whole number in Chinese numerals, e.g. 10005 → 一万零五
+pub fn number_to_chinese(n: u64) -> String {
+    if n == 0 {
+        return "零".to_string();
+    }
+    // Past 亿亿, read digit by digit
+    if n >= 10_000_000_000_000_000 {
+        return chinese_digits(&n.to_string());
+    }
+
+    let mut spoken = String::new();
+    let mut gap = false;
+    for (group, unit) in [(n / 100_000_000, "亿"), (n / 10_000 % 10_000, "万"), (n % 10_000, "")] {
+        if group == 0 {
+            gap = !spoken.is_empty();
+            continue;
+        }
+        // A skipped place in the middle is read as 零 once
+        if !spoken.is_empty() && (gap || group < 1000) {
+            spoken.push('零');
+        }
+        gap = false;
+        let words = if unit == "亿" {
+            number_to_chinese(group)
+        } else {
+            below_ten_thousand(group)
+        };
+        spoken.push_str(&words);
+        spoken.push_str(unit);
+    }
+    // 10 to 19 are 十 to 十九, not 一十九
+    match spoken.strip_prefix("一十") {
+        Some(rest) => format!("十{}", rest),
+        None => spoken,
+    }
+}
+
+/// 1 to 9999 in Chinese numerals
+fn below_ten_thousand(n: u64) -> String {
+    let mut spoken = String::new();
+    let mut gap = false;
+    for (place, unit) in [(1000, "千"), (100, "百"), (10, "十"), (1, "")] {
+        let digit = n / place % 10;
+        if digit == 0 {
+            gap = !spoken.is_empty();
+            continue;
+        }
+        if gap {
+            spoken.push('零');
+            gap = false;
+        }
+        spoken.push(CHINESE_DIGITS[digit as usize]);
+        spoken.push_str(unit);
+    }
+    spoken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const US: NumberLocale = NumberLocale::English { day_first: false };
+    const GB: NumberLocale = NumberLocale::English { day_first: true };
+
+    #[test]
+    fn locale_from_tts_language() {
+        assert_eq!(NumberLocale::from_language("en_US"), Some(US));
+        assert_eq!(NumberLocale::from_language(" en-GB "), Some(GB));
+        assert_eq!(NumberLocale::from_language("ZH"), Some(NumberLocale::Chinese));
+        assert_eq!(NumberLocale::from_language("all_zh"), Some(NumberLocale::Chinese));
+        assert_eq!(NumberLocale::from_language("ja"), None);
+        assert_eq!(NumberLocale::detect("今天很好"), Some(NumberLocale::Chinese));
+        assert_eq!(NumberLocale::detect("今日はいい天気"), None);
+        assert_eq!(NumberLocale::detect("Hello"), Some(US));
+    }
+
+    #[test]
+    fn english_dates() {
+        assert_eq!(read_numbers("On 3/4/2024.", US), "On March fourth, twenty twenty-four.");
+        assert_eq!(read_numbers("On 3/4/2024.", GB), "On the third of April, twenty twenty-four.");
+        assert_eq!(read_numbers("2024-12-25", US), "December twenty-fifth, twenty twenty-four");
+        assert_eq!(read_numbers("1/2/1999", US), "January second, nineteen ninety-nine");
+        assert_eq!(read_numbers("1/2/2005", US), "January second, two thousand five");
+    }
+
+    #[test]
+    fn english_numbers_times_and_ordinals() {
+        assert_eq!(read_numbers("1,000 people", US), "one thousand people");
+        assert_eq!(read_numbers("at 7:05 pm", US), "at seven oh five p m");
+        assert_eq!(read_numbers("at 9:00", US), "at nine o'clock");
+        assert_eq!(read_numbers("at 14:00", US), "at fourteen hundred");
+        assert_eq!(read_numbers("the 21st time", US), "the twenty-first time");
+        assert_eq!(read_numbers("v2 and 5G", US), "v2 and 5G");
+    }
+
+    #[test]
+    fn chinese_dates() {
+        assert_eq!(read_numbers("3/4/2024", NumberLocale::Chinese), "二零二四年三月四日");
+        assert_eq!(read_numbers("2024-03-04", NumberLocale::Chinese), "二零二四年三月四日");
+        assert_eq!(read_numbers("2024年到了", NumberLocale::Chinese), "二零二四年到了");
+    }
+
+    #[test]
+    fn chinese_numbers_and_times() {
+        assert_eq!(read_numbers("1,000人", NumberLocale::Chinese), "一千人");
+        assert_eq!(read_numbers("共10005个", NumberLocale::Chinese), "共一万零五个");
+        assert_eq!(read_numbers("编号010", NumberLocale::Chinese), "编号零一零");
+        assert_eq!(read_numbers("14:30", NumberLocale::Chinese), "十四点三十分");
+        assert_eq!(read_numbers("2:05", NumberLocale::Chinese), "两点零五分");
+        assert_eq!(read_numbers("排名3rd", NumberLocale::Chinese), "排名第三");
+        assert_eq!(read_numbers("涨了12.5%", NumberLocale::Chinese), "涨了百分之十二点五");
+        assert_eq!(read_numbers("¥3.50", NumberLocale::Chinese), "三元五角");
+        assert_eq!(read_numbers("¥3.05", NumberLocale::Chinese), "三元零五分");
+        assert_eq!(read_numbers("$5", NumberLocale::Chinese), "五美元");
+    }
+
+    #[test]
+    fn chinese_numerals() {
+        assert_eq!(number_to_chinese(0), "零");
+        assert_eq!(number_to_chinese(15), "十五");
+        assert_eq!(number_to_chinese(105), "一百零五");
+        assert_eq!(number_to_chinese(100_000_001), "一亿零一");
+    }
+}
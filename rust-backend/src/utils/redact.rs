@@ -0,0 +1,59 @@
+use regex::Regex;
+use serde_json::Value;
+
+/// Placeholder that replaces secrets
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Config keys whose string values are treated as secrets
+const SECRET_KEY_PARTS: [&str; 4] = ["key", "token", "secret", "password"];
+
+/// String values under secret-looking keys anywhere in `config`, e.g.
+/// `llm_api_key` or `auth_token`
+pub fn config_secrets(config: &Value) -> Vec<String> {
+    fn collect(value: &Value, secret: bool, out: &mut Vec<String>) {
+        match value {
+            Value::String(s) if secret && s.len() >= 4 => out.push(s.clone()),
+            Value::Object(map) => {
+                for (key, value) in map {
+                    let key = key.to_lowercase();
+                    let secret = SECRET_KEY_PARTS.iter().any(|part| key.contains(part));
+                    collect(value, secret, out);
+                }
+            }
+            Value::Array(items) => items.iter().for_each(|v| collect(v, secret, out)),
+            _ => {}
+        }
+    }
+
+    let mut secrets = Vec::new();
+    collect(config, false, &mut secrets);
+    // Longest first so a secret containing another is replaced whole
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    secrets.dedup();
+    secrets
+}
+
+/// Replace `secrets`, and anything shaped like a well-known API key, with
+/// [`REDACTED`]
+pub fn redact(text: &str, secrets: &[String]) -> String {
+    let mut text = text.to_string();
+    for secret in secrets {
+        text = text.replace(secret.as_str(), REDACTED);
+    }
+
+    // OpenAI/Anthropic style keys, Google API keys and bearer tokens
+    let key_pattern =
+        Regex::new(r"\b(sk-[A-Za-z0-9_\-]{16,}|AIza[0-9A-Za-z_\-]{30,})|(?i:bearer\s+)[A-Za-z0-9._\-]{16,}")
+            .unwrap();
+    key_pattern.replace_all(&text, REDACTED).into_owned()
+}
+
+/// [`redact`] every string inside `value`
+pub fn redact_value(value: &mut Value, secrets: &[String]) {
+    match value {
+        Value::String(s) => *s = redact(s, secrets),
+        Value::Array(items) => items.iter_mut().for_each(|v| redact_value(v, secrets)),
+        Value::Object(map) => map.values_mut().for_each(|v| redact_value(v, secrets)),
+        _ => {}
+    }
+}
@@ -1,25 +1,235 @@
+/// Sentence-ending punctuation shared by all languages
+const END_PUNCTUATIONS: [char; 7] = ['.', '!', '?', '。', '！', '？', '…'];
+
+/// Closing quotes and brackets that belong to the sentence they follow
+const CLOSING_MARKS: [char; 10] = ['"', '\'', '」', '』', '）', '】', ')', ']', '”', '’'];
+
+const ABBREVIATIONS: [&str; 13] = [
+    "Mr.", "Mrs.", "Dr.", "Prof.", "Inc.", "Ltd.", "Jr.", "Sr.", "e.g.", "i.e.", "vs.", "St.", "Rd.",
+];
+
+/// Language family used to decide where sentences end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentLanguage {
+    /// Space-separated scripts where `.` ends a sentence only before whitespace
+    Latin,
+    /// Chinese and Japanese, which end sentences without trailing spaces
+    Cjk,
+}
+
+impl SegmentLanguage {
+    /// Resolve a configured language code, detecting from the text for `auto`
+    ///
+    /// # Arguments
+    /// * `language` - `"auto"` or a language code such as `"en"`, `"zh"`, `"ja"`
+    /// * `text` - Text used for detection when the language is `"auto"`
+    pub fn resolve(language: &str, text: &str) -> Self {
+        let code = language.to_lowercase();
+        match code.split(['-', '_']).next().unwrap_or("") {
+            "zh" | "ja" | "cn" | "jp" => SegmentLanguage::Cjk,
+            "auto" | "" => detect_language(text),
+            _ => SegmentLanguage::Latin,
+        }
+    }
+}
+
+/// Whether a character is Han or Japanese kana
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF     // Hiragana, Katakana
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0xFF66..=0xFF9F   // Half-width Katakana
+    )
+}
+
+/// Detect whether text is mostly CJK or space-separated
+pub fn detect_language(text: &str) -> SegmentLanguage {
+    let cjk = text.chars().filter(|c| is_cjk(*c)).count();
+    let latin = text.chars().filter(|c| c.is_ascii_alphabetic()).count();
+
+    // A single CJK character carries roughly a word, so weight them up
+    if cjk > 0 && cjk * 4 >= latin {
+        SegmentLanguage::Cjk
+    } else {
+        SegmentLanguage::Latin
+    }
+}
+
 /// Detect if text is a complete sentence
 pub fn is_complete_sentence(text: &str) -> bool {
-    let trimmed = text.trim();
+    let trimmed = text.trim().trim_end_matches(CLOSING_MARKS);
     if trimmed.is_empty() {
         return false;
     }
-    
-    // Check for sentence-ending punctuation
-    trimmed.ends_with('.') || 
-    trimmed.ends_with('!') || 
-    trimmed.ends_with('?') ||
-    trimmed.ends_with('。') ||
-    trimmed.ends_with('！') ||
-    trimmed.ends_with('？')
+
+    if ABBREVIATIONS.iter().any(|abbrev| trimmed.ends_with(abbrev)) {
+        return false;
+    }
+
+    trimmed.ends_with(END_PUNCTUATIONS)
+}
+
+/// Split text into complete sentences and the remaining incomplete text
+///
+/// Sentences keep their terminating punctuation so TTS can use it for prosody.
+pub fn segment_text(text: &str, language: SegmentLanguage) -> (Vec<String>, String) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if !END_PUNCTUATIONS.contains(&c) {
+            i += 1;
+            continue;
+        }
+
+        // Swallow runs like "?!", "..." or "。」" into the same sentence
+        let mut end = i + 1;
+        while end < chars.len() && END_PUNCTUATIONS.contains(&chars[end]) {
+            end += 1;
+        }
+        let punct_end = end;
+        while end < chars.len() && CLOSING_MARKS.contains(&chars[end]) {
+            end += 1;
+        }
+
+        // A quoted exclamation that the sentence carries on from, e.g. 「すごい！」と言った。
+        if end > punct_end && chars.get(end).is_some_and(|n| is_cjk(*n)) {
+            i = end;
+            continue;
+        }
+
+        let is_boundary = if c == '.' && end - i == 1 {
+            is_period_boundary(&chars, start, i, end, language)
+        } else {
+            // Full-width terminators never need trailing whitespace; ASCII
+            // ones do in Latin text so "v2.0!beta" style tokens stay intact
+            language == SegmentLanguage::Cjk
+                || !c.is_ascii()
+                || end == chars.len()
+                || chars[end].is_whitespace()
+        };
+
+        if is_boundary {
+            let sentence: String = chars[start..end].iter().collect();
+            let sentence = sentence.trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence.to_string());
+            }
+            start = end;
+        }
+        i = end;
+    }
+
+    let remaining: String = chars[start..].iter().collect();
+    (sentences, remaining.trim().to_string())
+}
+
+/// Decide whether a single ASCII period at `pos` ends a sentence
+fn is_period_boundary(
+    chars: &[char],
+    start: usize,
+    pos: usize,
+    end: usize,
+    language: SegmentLanguage,
+) -> bool {
+    let prev = pos.checked_sub(1).map(|p| chars[p]);
+    let next = chars.get(end).copied();
+
+    match next {
+        None => return true,
+        Some(n) if n.is_whitespace() => {}
+        Some(n) => {
+            // Half-width period used as a full stop between CJK characters
+            return language == SegmentLanguage::Cjk
+                && prev.is_some_and(is_cjk)
+                && is_cjk(n);
+        }
+    }
+
+    // Followed by whitespace: only abbreviations prevent a split
+    let so_far: String = chars[start..end].iter().collect();
+    !ABBREVIATIONS.iter().any(|abbrev| so_far.ends_with(abbrev))
 }
 
-/// Split text into sentences (simplified)
+/// Split text into sentences, detecting the language from the text
 pub fn split_sentences(text: &str) -> Vec<String> {
-    // Simple sentence splitting by punctuation
-    text.split(&['.', '!', '?', '。', '！', '？'][..])
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect()
+    split_sentences_with_language(text, detect_language(text))
 }
 
+/// Split text into sentences using the given language rules
+///
+/// Trailing text without terminating punctuation is returned as a last sentence.
+pub fn split_sentences_with_language(text: &str, language: SegmentLanguage) -> Vec<String> {
+    let (mut sentences, remaining) = segment_text(text, language);
+    if !remaining.is_empty() {
+        sentences.push(remaining);
+    }
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_japanese_on_full_width_terminators() {
+        let text = "こんにちは！今日はいい天気ですね。散歩に行きませんか？";
+        assert_eq!(
+            split_sentences(text),
+            ["こんにちは！", "今日はいい天気ですね。", "散歩に行きませんか？"]
+        );
+    }
+
+    #[test]
+    fn keeps_quoted_exclamations_in_their_sentence() {
+        let text = "彼は「すごい！」と言った。それから帰った。";
+        assert_eq!(split_sentences(text), ["彼は「すごい！」と言った。", "それから帰った。"]);
+    }
+
+    #[test]
+    fn splits_chinese_without_breaking_latin_periods() {
+        let text = "我在用Python 3.12写代码。版本v2.0很好用！你呢？";
+        assert_eq!(
+            split_sentences(text),
+            ["我在用Python 3.12写代码。", "版本v2.0很好用！", "你呢？"]
+        );
+    }
+
+    #[test]
+    fn half_width_period_between_chinese_ends_a_sentence() {
+        assert_eq!(
+            split_sentences_with_language("今天很冷.明天会更冷.", SegmentLanguage::Cjk),
+            ["今天很冷.", "明天会更冷."]
+        );
+    }
+
+    #[test]
+    fn english_keeps_abbreviations_and_decimals() {
+        let text = "Dr. Smith paid $3.50 today. Was it worth it?! Maybe";
+        assert_eq!(
+            split_sentences(text),
+            ["Dr. Smith paid $3.50 today.", "Was it worth it?!", "Maybe"]
+        );
+    }
+
+    #[test]
+    fn configured_language_overrides_detection() {
+        let text = "OK。";
+        assert_eq!(SegmentLanguage::resolve("zh-CN", text), SegmentLanguage::Cjk);
+        assert_eq!(SegmentLanguage::resolve("en", "你好"), SegmentLanguage::Latin);
+        assert_eq!(SegmentLanguage::resolve("auto", "你好，世界"), SegmentLanguage::Cjk);
+        assert_eq!(SegmentLanguage::resolve("auto", "Hello world"), SegmentLanguage::Latin);
+    }
+
+    #[test]
+    fn segment_text_holds_back_the_unfinished_sentence() {
+        let (sentences, remaining) = segment_text("第一句。第二句还没", SegmentLanguage::Cjk);
+        assert_eq!(sentences, ["第一句。"]);
+        assert_eq!(remaining, "第二句还没");
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use percent_encoding::percent_decode_str;
+use tower_http::services::ServeDir;
+use tracing::warn;
+
+use super::audio_format::AudioFormat;
+
+/// Audio formats TTS engines write to the cache
+pub const AUDIO_EXTENSIONS: &[&str] = &["wav", "mp3", "ogg", "opus", "flac", "aac", "m4a", "webm"];
+
+/// Raster image formats served as backgrounds; SVG is left out because it
+/// can carry script
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+
+/// A directory served over HTTP, with the rules a request path must pass
+/// before it reaches [`ServeDir`]
+#[derive(Debug, Clone)]
+pub struct StaticDir {
+    root: PathBuf,
+    allowed_extensions: Option<&'static [&'static str]>,
+    follow_symlinks: bool,
+    sniff_audio_type: bool,
+}
+
+impl StaticDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            allowed_extensions: None,
+            follow_symlinks: false,
+            sniff_audio_type: false,
+        }
+    }
+
+    /// Only serve files with one of `extensions` (lowercase, without the dot)
+    pub fn with_allowed_extensions(mut self, extensions: &'static [&'static str]) -> Self {
+        self.allowed_extensions = Some(extensions);
+        self
+    }
+
+    /// Serve symlinks whose target is still inside the directory; without
+    /// this, any symlink on the path is refused
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Answer with the `Content-Type` of the audio format a file actually
+    /// holds, which engines don't always match to the extension
+    pub fn with_sniffed_audio_type(mut self, sniff_audio_type: bool) -> Self {
+        self.sniff_audio_type = sniff_audio_type;
+        self
+    }
+
+    /// Check a request path, relative to the directory, against the rules
+    ///
+    /// Paths that don't exist pass, so [`ServeDir`] can answer 404 itself.
+    pub fn check(&self, request_path: &str) -> Result<(), StatusCode> {
+        let decoded = percent_decode_str(request_path)
+            .decode_utf8()
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        let mut relative = PathBuf::new();
+        for component in Path::new(decoded.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                // `..`, a drive prefix or a second root
+                _ => return Err(StatusCode::BAD_REQUEST),
+            }
+        }
+
+        if let Some(allowed) = self.allowed_extensions {
+            let extension = relative
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_ascii_lowercase());
+            // Directories have no extension and get no index either
+            if !extension.is_some_and(|e| allowed.contains(&e.as_str())) {
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+
+        let path = self.root.join(&relative);
+        if !self.follow_symlinks {
+            let mut current = self.root.clone();
+            for part in relative.iter() {
+                current.push(part);
+                match std::fs::symlink_metadata(&current) {
+                    Ok(meta) if meta.file_type().is_symlink() => return Err(StatusCode::FORBIDDEN),
+                    Ok(_) => {}
+                    Err(_) => return Ok(()),
+                }
+            }
+        }
+
+        // Resolve whatever symlinks are left and make sure we end up inside
+        let Ok(resolved) = path.canonicalize() else {
+            return Ok(());
+        };
+        let root = self.root.canonicalize().map_err(|_| StatusCode::NOT_FOUND)?;
+        if !resolved.starts_with(&root) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(())
+    }
+
+    /// Serve the directory behind the checks
+    pub fn into_router(self) -> Router {
+        let serve = ServeDir::new(&self.root);
+        Router::new()
+            .fallback_service(serve)
+            .layer(middleware::from_fn_with_state(Arc::new(self), guard))
+    }
+}
+
+async fn guard(State(dir): State<Arc<StaticDir>>, request: Request, next: Next) -> Response {
+    match dir.check(request.uri().path()) {
+        Ok(()) if dir.sniff_audio_type => {
+            let path = percent_decode_str(request.uri().path())
+                .decode_utf8()
+                .map(|p| dir.root.join(p.trim_start_matches('/')))
+                .ok();
+            let mut response = next.run(request).await;
+            let format = path
+                .filter(|_| response.status().is_success())
+                .and_then(|p| AudioFormat::detect(&p));
+            if let Some(format) = format {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_TYPE, HeaderValue::from_static(format.mime_type()));
+            }
+            response
+        }
+        Ok(()) => next.run(request).await,
+        Err(status) => {
+            warn!(
+                "Refused static request {} under {}: {}",
+                request.uri().path(),
+                dir.root.display(),
+                status
+            );
+            status.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A served directory next to a secret file outside it, removed on Drop
+    struct Fixture {
+        base: PathBuf,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("static-{}", uuid::Uuid::new_v4().as_simple()));
+            let root = base.join("served");
+            std::fs::create_dir_all(root.join("sub")).unwrap();
+            std::fs::write(base.join("secret.txt"), "secret").unwrap();
+            std::fs::write(root.join("hello.txt"), "hello").unwrap();
+            std::fs::write(root.join("bg.PNG"), "png").unwrap();
+            std::fs::write(root.join("run.sh"), "#!/bin/sh").unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::symlink;
+                symlink(base.join("secret.txt"), root.join("escape.txt")).unwrap();
+                symlink(&base, root.join("sub").join("up")).unwrap();
+                symlink(root.join("hello.txt"), root.join("inside.txt")).unwrap();
+            }
+            Self { base }
+        }
+
+        fn dir(&self) -> StaticDir {
+            StaticDir::new(self.base.join("served"))
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn parent_components_are_refused() {
+        let fixture = Fixture::new();
+        let dir = fixture.dir();
+        for path in ["/../secret.txt", "/sub/../../secret.txt", "/%2e%2e/secret.txt", "/sub/%2E%2E/%2e%2e/secret.txt"] {
+            assert_eq!(dir.check(path), Err(StatusCode::BAD_REQUEST), "{path}");
+        }
+        assert_eq!(dir.check("/./hello.txt"), Ok(()));
+        assert_eq!(dir.check("/missing.txt"), Ok(()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_escapes_are_refused() {
+        let fixture = Fixture::new();
+        for dir in [fixture.dir(), fixture.dir().with_follow_symlinks(true)] {
+            assert_eq!(dir.check("/escape.txt"), Err(StatusCode::FORBIDDEN));
+            assert_eq!(dir.check("/sub/up/secret.txt"), Err(StatusCode::FORBIDDEN));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_inside_are_served_only_when_followed() {
+        let fixture = Fixture::new();
+        assert_eq!(fixture.dir().check("/inside.txt"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(fixture.dir().with_follow_symlinks(true).check("/inside.txt"), Ok(()));
+    }
+
+    #[test]
+    fn extensions_outside_the_allowlist_are_refused() {
+        let fixture = Fixture::new();
+        let dir = fixture.dir().with_allowed_extensions(IMAGE_EXTENSIONS);
+        assert_eq!(dir.check("/bg.PNG"), Ok(()));
+        assert_eq!(dir.check("/run.sh"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(dir.check("/sub"), Err(StatusCode::FORBIDDEN));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn router_refuses_escapes_and_serves_files() {
+        let fixture = Fixture::new();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = fixture.dir().into_router();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let get = |path: &str| reqwest::get(format!("http://{}{}", addr, path));
+
+        assert_eq!(get("/escape.txt").await.unwrap().status(), 403);
+        // The client resolves the `..` itself, leaving a path inside the root
+        assert_eq!(get("/%2e%2e/secret.txt").await.unwrap().status(), 404);
+        let response = get("/hello.txt").await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "hello");
+    }
+}
@@ -1,22 +1,163 @@
+use std::path::Path;
+
+use base64::Engine;
 use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::agent::output_types::{Actions, DisplayText};
+use crate::conversations::AudioPayload;
+use crate::utils::audio::encode_wav;
+use crate::utils::audio_format::{decode_file, AudioFormat};
+
+/// Length of each volume slice sent for lip sync, in milliseconds
+const SLICE_MS: u32 = 20;
+
+/// A synthesized audio file ready to send to a client
+#[derive(Debug, Clone)]
+pub struct PreparedAudioFile {
+    pub path: String,
+    /// None when neither the contents nor the extension are recognized
+    pub format: Option<AudioFormat>,
+    /// Volume of each slice, relative to the loudest one
+    pub volumes: Vec<f32>,
+}
 
 /// Prepare audio payload for WebSocket
+///
+/// A payload without an audio path is still sent when TTS is skipped so the
+/// frontend can show the text and play expressions silently.
 pub fn prepare_audio_payload(
     audio_path: Option<&str>,
-    display_text: Option<&str>,
-    actions: Option<serde_json::Value>,
+    display_text: Option<&DisplayText>,
+    actions: Option<&Actions>,
     forwarded: bool,
+) -> AudioPayload {
+    AudioPayload {
+        display_text: display_text.cloned(),
+        actions: actions.cloned(),
+        forwarded,
+        ..AudioPayload::new(audio_path.map(str::to_string))
+    }
+}
+
+/// Detect a synthesized file's format and compute its volume envelope,
+/// decoding it whatever the codec; transcode it to `preferred` when it is
+/// in another format
+///
+/// Only WAV can be produced, so other preferences are met only by engines
+/// that already write them. Blocks while the file is decoded.
+pub fn prepare_audio_file(audio_path: &str, preferred: Option<AudioFormat>) -> PreparedAudioFile {
+    let path = Path::new(audio_path);
+    let format = AudioFormat::detect(path);
+    let decoded = decode_file(path)
+        .inspect_err(|e| warn!("Can't decode {} for lip sync: {}", audio_path, e))
+        .ok();
+    let volumes = decoded
+        .as_ref()
+        .map(|(samples, sample_rate)| {
+            let volumes = volumes(samples, *sample_rate, SLICE_MS);
+            let peak = volumes.iter().copied().fold(0.0f32, f32::max);
+            volumes
+                .iter()
+                .map(|v| if peak > 0.0 { v / peak } else { 0.0 })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let prepared = PreparedAudioFile {
+        path: audio_path.to_string(),
+        format,
+        volumes,
+    };
+    let Some(preferred) = preferred.filter(|p| Some(*p) != format) else {
+        return prepared;
+    };
+    match (preferred, &decoded) {
+        (AudioFormat::Wav, Some((samples, sample_rate))) => {
+            let target = path.with_extension("wav");
+            match std::fs::write(&target, encode_wav(samples, *sample_rate)) {
+                Ok(()) => PreparedAudioFile {
+                    path: target.to_string_lossy().to_string(),
+                    format: Some(AudioFormat::Wav),
+                    ..prepared
+                },
+                Err(e) => {
+                    warn!("Failed to transcode {} to wav: {}", audio_path, e);
+                    prepared
+                }
+            }
+        }
+        _ => {
+            debug!(
+                "Sending {} as {}; it can't be transcoded to {}",
+                audio_path,
+                format.map_or("an unknown format", |f| f.name()),
+                preferred.name()
+            );
+            prepared
+        }
+    }
+}
+
+/// Read an audio file and base64-encode it for embedding in a payload
+pub fn encode_audio_file(audio_path: &str) -> anyhow::Result<String> {
+    let bytes = std::fs::read(audio_path)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Prepare an incremental `audio-chunk` payload for streamed speech
+///
+/// The first chunk of a sentence carries its display text and actions; the
+/// closing message has no audio and `final` set.
+pub fn prepare_audio_chunk_payload(
+    pcm: &[u8],
+    sample_rate: u32,
+    volumes: &[f32],
+    seq: usize,
+    display_text: Option<&DisplayText>,
+    actions: Option<&Actions>,
+    is_final: bool,
 ) -> serde_json::Value {
+    let audio = if pcm.is_empty() {
+        None
+    } else {
+        Some(base64::engine::general_purpose::STANDARD.encode(pcm))
+    };
+
     json!({
-        "type": "audio",
-        "audio": audio_path,
-        "volumes": [],
+        "type": "audio-chunk",
+        "audio": audio,
+        "format": "pcm_s16le",
+        "sample_rate": sample_rate,
+        "volumes": volumes,
         "slice_length": 20,
-        "display_text": display_text.map(|t| json!({
-            "text": t
-        })),
-        "actions": actions,
-        "forwarded": forwarded
+        "seq": seq,
+        "display_text": display_text.map(|t| t.to_dict()),
+        "actions": actions.map(|a| a.to_dict()),
+        "final": is_final
     })
 }
 
+/// RMS volume of each `slice_ms` slice of samples in -1.0..=1.0
+pub fn volumes(samples: &[f32], sample_rate: u32, slice_ms: u32) -> Vec<f32> {
+    let slice_len = ((sample_rate * slice_ms) / 1000).max(1) as usize;
+    samples
+        .chunks(slice_len)
+        .map(|slice| {
+            let sum: f64 = slice.iter().map(|&s| (s as f64).powi(2)).sum();
+            (sum / slice.len() as f64).sqrt() as f32
+        })
+        .collect()
+}
+
+/// RMS volume of each `slice_ms` slice of 16-bit PCM samples
+pub fn pcm16_volumes(samples: &[i16], sample_rate: u32, slice_ms: u32) -> Vec<f32> {
+    let slice_len = ((sample_rate * slice_ms) / 1000).max(1) as usize;
+    samples
+        .chunks(slice_len)
+        .map(|slice| {
+            let sum: f64 = slice.iter().map(|&s| (s as f64 / i16::MAX as f64).powi(2)).sum();
+            (sum / slice.len() as f64).sqrt() as f32
+        })
+        .collect()
+}
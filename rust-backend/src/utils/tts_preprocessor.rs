@@ -13,22 +13,29 @@ pub fn tts_filter(
         result = filter_pattern(&result, '*', '*');
     }
 
+    // Full-width variants are common in Chinese and Japanese replies
     if ignore_brackets {
         result = filter_pattern(&result, '[', ']');
+        result = filter_pattern(&result, '【', '】');
+        result = filter_pattern(&result, '［', '］');
     }
 
     if ignore_parentheses {
         result = filter_pattern(&result, '(', ')');
+        result = filter_pattern(&result, '（', '）');
     }
 
     if ignore_angle_brackets {
         result = filter_pattern(&result, '<', '>');
+        result = filter_pattern(&result, '＜', '＞');
     }
 
     if remove_special_char {
         result = result
             .chars()
-            .filter(|c| c.is_alphanumeric() || c.is_whitespace() || ".,!?;:".contains(*c))
+            .filter(|c| {
+                c.is_alphanumeric() || c.is_whitespace() || ".,!?;:。，、！？；：".contains(*c)
+            })
             .collect();
     }
 
@@ -57,3 +64,273 @@ fn filter_pattern(text: &str, start: char, end: char) -> String {
     result
 }
 
+
+const ONES: [&str; 20] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const SCALES: [(u64, &str); 3] = [
+    (1_000_000_000, "billion"),
+    (1_000_000, "million"),
+    (1_000, "thousand"),
+];
+
+/// Spell out a whole number in English, e.g. 1234 → "one thousand two hundred thirty-four"
+pub fn number_to_words(n: u64) -> String {
+    if n < 20 {
+        return ONES[n as usize].to_string();
+    }
+    if n < 100 {
+        return match n % 10 {
+            0 => TENS[(n / 10) as usize].to_string(),
+            unit => format!("{}-{}", TENS[(n / 10) as usize], ONES[unit as usize]),
+        };
+    }
+    if n < 1000 {
+        return match n % 100 {
+            0 => format!("{} hundred", ONES[(n / 100) as usize]),
+            rest => format!("{} hundred {}", ONES[(n / 100) as usize], number_to_words(rest)),
+        };
+    }
+    // Anything past the billions is read digit by digit
+    if n >= 1_000_000_000_000 {
+        return spell_digits(&n.to_string());
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = n;
+    for (scale, name) in SCALES {
+        if rest >= scale {
+            parts.push(format!("{} {}", number_to_words(rest / scale), name));
+            rest %= scale;
+        }
+    }
+    if rest > 0 {
+        parts.push(number_to_words(rest));
+    }
+    parts.join(" ")
+}
+
+fn spell_digits(digits: &str) -> String {
+    digits
+        .chars()
+        .filter_map(|d| d.to_digit(10))
+        .map(|d| ONES[d as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Spoken names for a currency symbol: (unit, units, subunit, subunits)
+fn currency_names(symbol: &str) -> Option<(&'static str, &'static str, &'static str, &'static str)> {
+    match symbol {
+        "$" => Some(("dollar", "dollars", "cent", "cents")),
+        "€" => Some(("euro", "euros", "cent", "cents")),
+        "£" => Some(("pound", "pounds", "penny", "pence")),
+        "¥" => Some(("yen", "yen", "", "")),
+        _ => None,
+    }
+}
+
+pub fn plural<'a>(n: u64, one: &'a str, many: &'a str) -> &'a str {
+    if n == 1 {
+        one
+    } else {
+        many
+    }
+}
+
+/// Rewrite numbers, currency amounts and percentages in spoken English
+///
+/// "$5" becomes "five dollars", "$5.50" "five dollars and fifty cents",
+/// "12.5%" "twelve point five percent" and "1,024" "one thousand twenty-four".
+/// Digits glued to letters ("v2", "5th") are left alone. The output is
+/// English regardless of the sentence's language, so this suits English
+/// characters only.
+pub fn normalize_numbers(text: &str) -> String {
+    let pattern = regex::Regex::new(
+        r"([$€£¥])?\b(\d{1,3}(?:,\d{3})+|\d+)(?:\.(\d+))?(?:(%)|\b)",
+    )
+    .unwrap();
+
+    pattern
+        .replace_all(text, |caps: &regex::Captures| {
+            let digits = caps[2].replace(',', "");
+            let Ok(whole) = digits.parse::<u64>() else {
+                return caps[0].to_string();
+            };
+            let fraction = caps.get(3).map(|f| f.as_str());
+            let percent = caps.get(4).is_some();
+            let mut spoken = number_to_words(whole);
+
+            let currency = caps.get(1).and_then(|c| currency_names(c.as_str()));
+            if let Some((unit, units, subunit, subunits)) = currency.filter(|_| !percent) {
+                return match fraction {
+                    // Two decimals on a currency are its minor unit
+                    Some(minor) if minor.len() == 2 && !subunit.is_empty() => {
+                        let minor: u64 = minor.parse().unwrap_or(0);
+                        let major = format!("{} {}", spoken, plural(whole, unit, units));
+                        if minor == 0 {
+                            major
+                        } else {
+                            format!(
+                                "{} and {} {}",
+                                major,
+                                number_to_words(minor),
+                                plural(minor, subunit, subunits)
+                            )
+                        }
+                    }
+                    Some(minor) => format!("{} point {} {}", spoken, spell_digits(minor), units),
+                    None => format!("{} {}", spoken, plural(whole, unit, units)),
+                };
+            }
+
+            if let Some(fraction) = fraction {
+                spoken = format!("{} point {}", spoken, spell_digits(fraction));
+            }
+            if percent {
+                spoken.push_str(" percent");
+            }
+            match caps.get(1).filter(|_| percent) {
+                // A currency sign before a percentage is kept as written
+                Some(symbol) => format!("{}{}", symbol.as_str(), spoken),
+                None => spoken,
+            }
+        })
+        .to_string()
+}
+
+/// Whether a character belongs to an emoji sequence, including the joiners,
+/// variation selectors and skin tone modifiers that glue sequences together
+fn is_emoji_part(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF   // pictographs, emoticons, transport, flags, skin tones
+            | 0x2600..=0x27BF   // miscellaneous symbols and dingbats
+            | 0x2B00..=0x2BFF   // arrows and stars such as ⭐
+            | 0x200D            // zero width joiner
+            | 0xFE0F            // emoji presentation selector
+            | 0xE0020..=0xE007F // tag sequences
+    )
+}
+
+/// Spoken description of common emoji
+fn describe_emoji(c: char) -> Option<&'static str> {
+    Some(match c {
+        '😀' | '😃' | '😄' | '😁' | '🙂' | '😊' | '☺' => "smiling face",
+        '😆' | '😂' | '🤣' => "laughing face",
+        '😉' => "winking face",
+        '😍' | '🥰' | '😘' => "face with hearts",
+        '😢' | '😭' | '🥲' => "crying face",
+        '😠' | '😡' | '🤬' => "angry face",
+        '😮' | '😲' | '😯' | '😱' => "surprised face",
+        '🤔' => "thinking face",
+        '😅' => "nervous smile",
+        '😴' => "sleeping face",
+        '🙄' => "eye roll",
+        '😳' => "flushed face",
+        '❤' | '💕' | '💖' | '💗' | '💓' | '🧡' | '💛' | '💚' | '💙' | '💜' => "heart",
+        '💔' => "broken heart",
+        '👍' => "thumbs up",
+        '👎' => "thumbs down",
+        '👏' => "clapping",
+        '🙏' => "folded hands",
+        '👋' => "waving hand",
+        '🎉' | '🥳' => "party",
+        '🔥' => "fire",
+        '⭐' | '🌟' | '✨' => "sparkles",
+        '💯' => "one hundred",
+        '🎵' | '🎶' => "music",
+        '🐱' | '😺' => "cat",
+        '🐶' => "dog",
+        _ => return None,
+    })
+}
+
+/// Drop emoji, or replace the common ones with a short description when
+/// `describe` is set
+///
+/// Emoji without a description are dropped either way, so TTS engines never
+/// receive raw codepoints.
+pub fn filter_emoji(text: &str, describe: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut replaced = false;
+    for c in text.chars() {
+        if !is_emoji_part(c) {
+            result.push(c);
+            continue;
+        }
+        replaced = true;
+        if let Some(description) = describe.then(|| describe_emoji(c)).flatten() {
+            result.push(' ');
+            result.push_str(description);
+            result.push(' ');
+        }
+    }
+
+    if !replaced {
+        return result;
+    }
+    // Tidy the spacing left behind by removed or described emoji
+    let collapsed = result.split_whitespace().collect::<Vec<_>>().join(" ");
+    regex::Regex::new(r" ([.,!?;:])")
+        .unwrap()
+        .replace_all(&collapsed, "$1")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_full_width_brackets_and_parentheses() {
+        let text = "【笑】你好（小声）世界［注］";
+        assert_eq!(tts_filter(text, false, true, true, true, true), "你好世界");
+    }
+
+    #[test]
+    fn keeps_brackets_when_not_ignored() {
+        let text = "【笑】你好（小声）";
+        assert_eq!(tts_filter(text, false, false, false, true, true), text);
+    }
+
+    #[test]
+    fn reads_currency_amounts() {
+        assert_eq!(normalize_numbers("It costs $5."), "It costs five dollars.");
+        assert_eq!(normalize_numbers("$1"), "one dollar");
+        assert_eq!(normalize_numbers("$5.50"), "five dollars and fifty cents");
+        assert_eq!(normalize_numbers("£1.01"), "one pound and one penny");
+        assert_eq!(normalize_numbers("€3.00"), "three euros");
+        assert_eq!(normalize_numbers("¥500"), "five hundred yen");
+        assert_eq!(normalize_numbers("$1,250"), "one thousand two hundred fifty dollars");
+    }
+
+    #[test]
+    fn reads_percentages() {
+        assert_eq!(normalize_numbers("Up 12.5% today"), "Up twelve point five percent today");
+        assert_eq!(normalize_numbers("100%"), "one hundred percent");
+    }
+
+    #[test]
+    fn leaves_digits_glued_to_letters() {
+        assert_eq!(normalize_numbers("v2 came 5th"), "v2 came 5th");
+    }
+
+    #[test]
+    fn drops_or_describes_emoji_in_a_sentence() {
+        let text = "Great job 👍🔥! See you 🫠 soon 😊.";
+        assert_eq!(filter_emoji(text, true), "Great job thumbs up fire! See you soon smiling face.");
+        assert_eq!(filter_emoji(text, false), "Great job! See you soon.");
+    }
+
+    #[test]
+    fn drops_whole_emoji_sequences() {
+        assert_eq!(filter_emoji("👨‍👩‍👧 hi 👋🏽", false), "hi");
+        assert_eq!(filter_emoji("No emoji here.", true), "No emoji here.");
+    }
+}
@@ -0,0 +1,109 @@
+/// Decodes text arriving as a stream of byte chunks
+///
+/// A multi-byte character split across two chunks is held back until the
+/// rest of it arrives, so CJK text isn't corrupted at chunk boundaries.
+/// Bytes that can never be valid UTF-8 become U+FFFD rather than failing
+/// the stream.
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    /// Start of a character whose remaining bytes haven't arrived yet
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode `chunk` after whatever the previous one left incomplete
+    ///
+    /// # Returns
+    /// Every complete character so far; an incomplete one at the end is
+    /// kept for the next chunk
+    pub fn push(&mut self, chunk: &[u8]) -> String {
+        self.pending.extend_from_slice(chunk);
+        let mut text = String::with_capacity(self.pending.len());
+        let mut rest = self.pending.as_slice();
+        while !rest.is_empty() {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).expect("checked by from_utf8"));
+                    match e.error_len() {
+                        Some(invalid) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[invalid..];
+                        }
+                        // Cut off by the end of the chunk
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        let kept = rest.len();
+        self.pending.drain(..self.pending.len() - kept);
+        text
+    }
+
+    /// End the stream
+    ///
+    /// # Returns
+    /// U+FFFD if it ended partway through a character, otherwise nothing
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "你好，世界！こんにちは 😀 ok";
+
+    #[test]
+    fn cjk_split_mid_character_is_reassembled() {
+        let bytes = TEXT.as_bytes();
+        for split in 0..=bytes.len() {
+            let mut decoder = Utf8Decoder::new();
+            let mut text = decoder.push(&bytes[..split]);
+            text.push_str(&decoder.push(&bytes[split..]));
+            text.push_str(&decoder.finish());
+            assert_eq!(text, TEXT, "split at byte {split}");
+        }
+    }
+
+    #[test]
+    fn byte_by_byte_chunks_only_emit_whole_characters() {
+        let mut decoder = Utf8Decoder::new();
+        let pieces: Vec<String> = TEXT.as_bytes().iter().map(|b| decoder.push(&[*b])).collect();
+
+        assert_eq!(pieces.concat(), TEXT);
+        // "你" is three bytes, emitted once the last one arrives
+        assert_eq!(pieces[..3], ["", "", "你"]);
+        assert_eq!(decoder.finish(), "");
+    }
+
+    #[test]
+    fn invalid_bytes_are_replaced_without_failing() {
+        let mut decoder = Utf8Decoder::new();
+        assert_eq!(decoder.push(b"a\xffb\xe4"), "a\u{FFFD}b");
+        assert_eq!(decoder.push(b"\xbd\xa0c"), "你c");
+    }
+
+    #[test]
+    fn stream_ending_mid_character_gives_a_replacement() {
+        let mut decoder = Utf8Decoder::new();
+        assert_eq!(decoder.push(&"好".as_bytes()[..2]), "");
+        assert_eq!(decoder.finish(), "\u{FFFD}");
+        assert_eq!(decoder.finish(), "");
+    }
+}
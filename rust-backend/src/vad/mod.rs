@@ -1,5 +1,6 @@
-// VAD module - interfaces for Python service integration
+// VAD module - built-in energy VAD, plus interfaces for Python service integration
 pub mod interface;
+pub mod simple;
 
 pub use interface::*;
-
+pub use simple::{BargeInDetector, SpeechSegmenter};
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+use crate::config_manager::vad::SimpleVADConfig;
+use crate::utils::audio::ASR_SAMPLE_RATE;
+
+/// What a frame means for the utterance in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeechState {
+    Silence,
+    /// Enough loud frames in a row: the user started talking
+    SpeechStart,
+    Speech,
+    /// The hangover ran out: the user stopped talking
+    SpeechEnd,
+}
+
+/// Energy-based voice activity detector
+///
+/// Speech starts after `required_hits` frames above the threshold and ends
+/// after `hangover_frames` frames below it, so short pauses between words
+/// don't split an utterance.
+pub struct SimpleVad {
+    threshold_db: f32,
+    required_hits: u32,
+    hangover_frames: u32,
+    hits: u32,
+    misses: u32,
+    active: bool,
+}
+
+impl SimpleVad {
+    pub fn new(config: &SimpleVADConfig) -> Self {
+        Self {
+            threshold_db: config.energy_threshold_db,
+            required_hits: config.required_hits.max(1),
+            hangover_frames: config.hangover_frames.max(1),
+            hits: 0,
+            misses: 0,
+            active: false,
+        }
+    }
+
+    /// Classify the next frame
+    pub fn process(&mut self, frame: &[f32]) -> SpeechState {
+        let loud = frame_db(frame) >= self.threshold_db;
+
+        if self.active {
+            if loud {
+                self.misses = 0;
+                return SpeechState::Speech;
+            }
+            self.misses += 1;
+            if self.misses >= self.hangover_frames {
+                self.active = false;
+                self.misses = 0;
+                self.hits = 0;
+                return SpeechState::SpeechEnd;
+            }
+            return SpeechState::Speech;
+        }
+
+        if !loud {
+            self.hits = 0;
+            return SpeechState::Silence;
+        }
+        self.hits += 1;
+        if self.hits >= self.required_hits {
+            self.active = true;
+            self.misses = 0;
+            return SpeechState::SpeechStart;
+        }
+        SpeechState::Silence
+    }
+}
+
+/// Level of a frame in dBFS
+fn frame_db(frame: &[f32]) -> f32 {
+    if frame.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+    20.0 * rms.max(1e-9).log10()
+}
+
+/// Cuts a client's mic stream into utterances with [`SimpleVad`]
+///
+/// Audio must be mono at [`ASR_SAMPLE_RATE`]. The frames that triggered speech
+/// are kept so the first syllable isn't lost.
+pub struct SpeechSegmenter {
+    vad: SimpleVad,
+    frame_len: usize,
+    /// Samples short of a whole frame
+    carry: Vec<f32>,
+    /// Recent quiet frames, prepended when speech starts
+    preroll: VecDeque<Vec<f32>>,
+    preroll_frames: usize,
+    speech: Vec<f32>,
+}
+
+impl SpeechSegmenter {
+    pub fn new(config: &SimpleVADConfig) -> Self {
+        let frame_ms = config.frame_ms.clamp(10, 100) as usize;
+        Self {
+            vad: SimpleVad::new(config),
+            frame_len: ASR_SAMPLE_RATE as usize * frame_ms / 1000,
+            carry: Vec::new(),
+            preroll: VecDeque::new(),
+            preroll_frames: config.required_hits.max(1) as usize,
+            speech: Vec::new(),
+        }
+    }
+
+    /// Feed samples in
+    ///
+    /// # Returns
+    /// Utterances that finished within these samples
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        self.carry.extend_from_slice(samples);
+        let whole = self.carry.len() - self.carry.len() % self.frame_len;
+        let frames: Vec<f32> = self.carry.drain(..whole).collect();
+
+        let mut utterances = Vec::new();
+        for frame in frames.chunks_exact(self.frame_len) {
+            match self.vad.process(frame) {
+                SpeechState::Silence => {
+                    self.preroll.push_back(frame.to_vec());
+                    if self.preroll.len() > self.preroll_frames {
+                        self.preroll.pop_front();
+                    }
+                }
+                SpeechState::SpeechStart => {
+                    self.speech = self.preroll.drain(..).flatten().collect();
+                    self.speech.extend_from_slice(frame);
+                }
+                SpeechState::Speech => self.speech.extend_from_slice(frame),
+                SpeechState::SpeechEnd => {
+                    self.speech.extend_from_slice(frame);
+                    utterances.push(std::mem::take(&mut self.speech));
+                }
+            }
+        }
+        utterances
+    }
+}
+
+/// Watches mic audio while the AI is speaking for the user talking over it
+///
+/// Uses a stricter threshold than utterance detection and needs speech to
+/// last `min_speech_ms`, so speaker bleed and short noises are ignored.
+pub struct BargeInDetector {
+    vad: SimpleVad,
+    frame_len: usize,
+    carry: Vec<f32>,
+}
+
+impl BargeInDetector {
+    pub fn new(threshold_db: f32, min_speech_ms: u32) -> Self {
+        const FRAME_MS: u32 = 30;
+        let config = SimpleVADConfig {
+            energy_threshold_db: threshold_db,
+            frame_ms: FRAME_MS,
+            required_hits: min_speech_ms.div_ceil(FRAME_MS).max(1),
+            hangover_frames: 1,
+        };
+        Self {
+            vad: SimpleVad::new(&config),
+            frame_len: (ASR_SAMPLE_RATE * FRAME_MS / 1000) as usize,
+            carry: Vec::new(),
+        }
+    }
+
+    /// Feed mono samples at [`ASR_SAMPLE_RATE`]
+    ///
+    /// # Returns
+    /// Whether sustained speech started within these samples
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        self.carry.extend_from_slice(samples);
+        let whole = self.carry.len() - self.carry.len() % self.frame_len;
+        let frames: Vec<f32> = self.carry.drain(..whole).collect();
+        frames
+            .chunks_exact(self.frame_len)
+            .any(|frame| self.vad.process(frame) == SpeechState::SpeechStart)
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use tracing::{info, warn};
+
+use crate::state::AppState;
+
+/// What the LLM is asked to warm it up
+const WARMUP_PROMPT: &str = "Reply with one word.";
+
+/// What the TTS engine says to warm it up
+const WARMUP_SPEECH: &str = "Hello.";
+
+/// Warm up the default character's LLM and TTS engine in the background,
+/// once the Python service answers its health check
+///
+/// Replies are thrown away; failures are only logged, as the server works
+/// without the warmup.
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let started = Instant::now();
+        match state.python_service.health_check().await {
+            Ok(true) => {}
+            Ok(false) => {
+                info!("Skipping warmup: the Python service is unhealthy");
+                return;
+            }
+            Err(e) => {
+                info!("Skipping warmup: the Python service is unavailable: {}", e);
+                return;
+            }
+        }
+
+        let (llm, tts) = tokio::join!(warm_llm(&state), warm_tts(&state));
+        for (name, result) in [("LLM", llm), ("TTS", tts)] {
+            match result {
+                Ok(Some(elapsed)) => info!("Warmed up {} in {} ms", name, elapsed.as_millis()),
+                Ok(None) => {}
+                Err(e) => warn!("{} warmup failed: {}", name, e),
+            }
+        }
+        info!("Warmup finished in {} ms", started.elapsed().as_millis());
+    });
+}
+
+/// Send a short chat to the character's LLM
+///
+/// # Returns
+/// How long the reply took
+async fn warm_llm(state: &AppState) -> anyhow::Result<Option<Duration>> {
+    let config = state.config();
+    let llm = state.standalone_llm(&config, None)?;
+    let mut message = HashMap::new();
+    message.insert("role".to_string(), serde_json::json!("user"));
+    message.insert("content".to_string(), serde_json::json!(WARMUP_PROMPT));
+
+    let started = Instant::now();
+    let mut tokens = llm.chat_completion(vec![message], None).await?;
+    while let Some(token) = tokens.next().await {
+        token?;
+    }
+    Ok(Some(started.elapsed()))
+}
+
+/// Synthesize a short phrase with the character's TTS engine and delete it
+///
+/// # Returns
+/// How long synthesis took; None without a TTS engine
+async fn warm_tts(state: &AppState) -> anyhow::Result<Option<Duration>> {
+    let Some(tts_engine) = state.tts_engine() else {
+        return Ok(None);
+    };
+    let started = Instant::now();
+    let path = tts_engine.generate_audio(WARMUP_SPEECH, Some("warmup")).await?;
+    let elapsed = started.elapsed();
+    if let Err(e) = tts_engine.remove_file(&path) {
+        warn!("Failed to remove warmup audio {}: {}", path, e);
+    }
+    Ok(Some(elapsed))
+}
@@ -1,33 +1,147 @@
 use axum::{
-    extract::{ws::Message, State, WebSocketUpgrade},
-    response::Response,
+    extract::{ws::Message, Query, State, WebSocketUpgrade},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
 };
+use dashmap::mapref::entry::Entry;
+use std::collections::HashMap;
 use axum::extract::ws::WebSocket;
-use serde_json::json;
-use tracing::{info, error};
+use serde_json::{json, Value};
+use tracing::{debug, info, error, warn};
 use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
 
-use crate::state::AppState;
+use crate::state::{AppState, ClientType, ConnectionSlot, ConversationState, Observer, ObserverScope};
+use crate::conversations::utils::with_request_id;
+use crate::conversations::WebSocketSend;
 use crate::handlers;
+use crate::utils::audio_format::AudioFormat;
 
+/// Upgrade to a WebSocket
+///
+/// `?client_type=text` connects a text-only client. `?client_uid=<id>`
+/// proposes a stable id (letters, digits, `-` and `_`, up to 64 characters);
+/// a malformed one is replaced by a generated id, and one that is already
+/// connected is refused.
+///
+/// `?audio_format=wav` asks for whole-file TTS audio in WAV whatever the
+/// engine writes.
+///
+/// `?role=observer` connects a read-only observer of the clients using
+/// `conf_uid` (the current config by default) or of the group `group_id`.
+///
+/// Once `max_connections` are open, the upgrade is refused with a 503.
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
+    Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
 ) -> Response {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+    let Some(slot) = state.try_open_connection() else {
+        let limit = state.config().system_config.max_connections;
+        warn!("Refusing WebSocket connection: {:?} connections already open", limit);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "error": "Too many connections; try again later",
+                "max_connections": limit
+            })),
+        )
+            .into_response();
+    };
+
+    // Leave room to read an oversized message and refuse it with an error
+    // rather than dropping the connection
+    let max_message_bytes = state.config().system_config.max_ws_message_bytes;
+    let transport_limit = max_message_bytes.saturating_mul(4);
+    let ws = ws.max_message_size(transport_limit).max_frame_size(transport_limit);
+
+    if params.get("role").is_some_and(|role| role == "observer") {
+        let scope = match (params.get("group_id"), params.get("conf_uid")) {
+            (Some(group_id), _) => ObserverScope::Group(group_id.clone()),
+            (None, Some(conf_uid)) => ObserverScope::Conf(conf_uid.clone()),
+            (None, None) => ObserverScope::Conf(state.config().character_config.conf_uid.clone()),
+        };
+        return ws.on_upgrade(move |socket| handle_observer_socket(socket, state, scope, slot));
+    }
+
+    let client_type = params
+        .get("client_type")
+        .and_then(|t| ClientType::parse(t))
+        .unwrap_or_default();
+    let audio_format = params.get("audio_format").and_then(|name| {
+        let format = AudioFormat::parse(name).filter(|f| *f == AudioFormat::Wav);
+        if format.is_none() {
+            warn!("Ignoring audio_format {:?}: only wav can be produced", name);
+        }
+        format
+    });
+    let proposed_uid = params.get("client_uid").cloned().filter(|uid| {
+        let valid = is_valid_client_uid(uid);
+        if !valid {
+            warn!("Ignoring malformed client_uid {:?}", uid);
+        }
+        valid
+    });
+    ws.on_upgrade(move |socket| handle_socket(socket, state, client_type, audio_format, proposed_uid, slot))
+}
+
+fn is_valid_client_uid(uid: &str) -> bool {
+    (1..=64).contains(&uid.len())
+        && uid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
-    let client_uid = state.generate_client_uid();
-    info!("New WebSocket connection: {}", client_uid);
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    client_type: ClientType,
+    audio_format: Option<AudioFormat>,
+    proposed_uid: Option<String>,
+    // Held until the connection is cleaned up
+    _slot: ConnectionSlot,
+) {
+    let client_uid = proposed_uid.unwrap_or_else(|| state.generate_client_uid());
+    info!("New WebSocket connection: {} ({:?})", client_uid, client_type);
+    let config = state.config();
 
     // Initialize client context
     let context = crate::state::ClientContext {
         client_uid: client_uid.clone(),
-        conf_uid: state.config.character_config.conf_uid.clone(),
+        conf_uid: config.character_config.conf_uid.clone(),
         history_uid: None,
+        tts_enabled: state.default_tts_enabled(),
+        sampling: Default::default(),
+        background: config.character_config.default_background.clone(),
+        conversation_state: Default::default(),
+        last_input: None,
+        client_type,
+        audio_format,
+        mic_config: Default::default(),
+        asr_language: None,
+        barge_in: state.default_barge_in(),
+        tts_engine: None,
+        tts_rate: None,
+        resume_token: state.issue_resume_token(),
+        last_activity: std::time::Instant::now(),
+        last_turn: std::time::Instant::now(),
     };
-    state.client_contexts.insert(client_uid.clone(), context);
+    let resume_token = context.resume_token.clone();
+    // Claim the uid atomically so two connections can't both adopt it
+    match state.client_contexts.entry(client_uid.clone()) {
+        Entry::Occupied(_) => {
+            warn!("Refusing connection: client_uid {} is already connected", client_uid);
+            let error = json!({
+                "type": "error",
+                "message": format!("client_uid {} is already connected", client_uid)
+            });
+            let _ = socket.send(Message::Text(error.to_string())).await;
+            let _ = socket.close().await;
+            return;
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(context);
+        }
+    }
     
     // Initialize audio buffer
     state.audio_buffers.insert(client_uid.clone(), Vec::new());
@@ -39,19 +153,41 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     }
 
     use futures_util::StreamExt as _;
-    let (mut sender, mut receiver) = socket.split();
+    let (mut ws_sender, mut receiver) = socket.split();
+
+    // Outbound messages go through a channel so conversation tasks can
+    // keep sending while the receive loop handles new input
+    let (sender, mut outbound) = mpsc::unbounded_channel::<String>();
+    let sender = WebSocketSend::new(sender);
+    state.client_senders.insert(client_uid.clone(), sender.clone());
+    let writer_state = state.clone();
+    let writer_uid = client_uid.clone();
+    let writer = tokio::spawn(async move {
+        while let Some(text) = outbound.recv().await {
+            writer_state.mirror_to_observers(&writer_uid, &text).await;
+            if let Err(e) = ws_sender.send(Message::Text(text)).await {
+                error!("Failed to send message: {}", e);
+                break;
+            }
+        }
+    });
 
     // Send initial messages matching Python backend
-    let initial_messages = vec![
+    let mut initial_messages = vec![
         json!({
             "type": "full-text",
             "text": "Connection established"
         }),
         json!({
             "type": "set-model-and-conf",
-            "model_info": {}, // TODO: Load from config
-            "conf_name": state.config.character_config.conf_name,
-            "conf_uid": state.config.character_config.conf_uid,
+            // Text clients have no avatar to load
+            "model_info": match (state.live2d_model(), client_type) {
+                (Some(model), ClientType::Live2D) => model.model_info.clone(),
+                (None, ClientType::Live2D) => json!({}),
+                (_, ClientType::Text) => Value::Null,
+            },
+            "conf_name": config.character_config.conf_name,
+            "conf_uid": config.character_config.conf_uid,
             "client_uid": client_uid
         }),
         json!({
@@ -60,53 +196,269 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
             "is_owner": false
         }),
         json!({
-            "type": "control",
-            "text": "start-mic"
+            "type": "resume-token",
+            "token": resume_token,
+            "ttl_secs": config.system_config.resume_token_ttl_secs
         }),
     ];
 
+    // Tell avatar clients not to wait for a model that can't be loaded
+    if let (None, ClientType::Live2D) = (state.live2d_model(), client_type) {
+        initial_messages.push(json!({
+            "type": "control",
+            "text": "audio-only",
+            "reason": state.live2d_unavailable()
+        }));
+    }
+
+    let background = config
+        .character_config
+        .default_background
+        .as_ref()
+        .filter(|_| client_type == ClientType::Live2D);
+    if let Some(file) = background {
+        match state.background_url(file) {
+            Ok(url) => initial_messages.push(json!({
+                "type": "set-background",
+                "file": file,
+                "url": url
+            })),
+            Err(e) => warn!("Ignoring default background: {}", e),
+        }
+    }
+
     for msg in initial_messages {
-        if let Err(e) = sender.send(Message::Text(msg.to_string())).await {
-            error!("Failed to send initial message: {}", e);
-            return;
+        if sender.send(msg.to_string()).is_err() {
+            error!("Failed to send initial message");
+            break;
         }
     }
+    // Sends start-mic
+    state.set_conversation_state(&client_uid, ConversationState::Listening, &sender);
+
+    // Text clients have no voice or avatar to greet with
+    if client_type == ClientType::Live2D && config.character_config.greeting.is_some() {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let greeting_sender = with_request_id(&sender, &request_id);
+        handlers::spawn_conversation(&state, &client_uid, "greeting", &Value::Null, request_id, greeting_sender);
+    }
 
     // Handle incoming messages
+    let max_message_bytes = config.system_config.max_ws_message_bytes;
     while let Some(msg) = receiver.next().await {
+        if msg.is_ok() {
+            state.record_activity(&client_uid);
+        }
         match msg {
+            Ok(Message::Text(text)) if text.len() > max_message_bytes => {
+                warn!("Refusing {} byte message from {}", text.len(), client_uid);
+                let error = json!({
+                    "type": "error",
+                    "message": format!(
+                        "Message of {} bytes exceeds the {} byte limit; send it as chunk messages",
+                        text.len(),
+                        max_message_bytes
+                    ),
+                    "max_message_bytes": max_message_bytes
+                });
+                let _ = sender.send(error.to_string());
+            }
             Ok(Message::Text(text)) => {
-                if let Err(e) = handlers::handle_message(&state, &client_uid, &text, &mut sender).await {
+                if let Err(e) = handlers::handle_message(&state, &client_uid, &text, &sender).await {
                     error!("Error handling message: {}", e);
                 }
             }
+            // Pings are answered by the WebSocket layer; both only count as activity
+            Ok(Message::Ping(_) | Message::Pong(_)) => {}
+            Ok(Message::Binary(data)) => {
+                debug!("Ignoring {} byte binary message from {}", data.len(), client_uid);
+            }
             Ok(Message::Close(_)) => {
                 info!("Client {} disconnected", client_uid);
                 break;
             }
             Err(e) => {
                 error!("WebSocket error: {}", e);
+                let error = json!({
+                    "type": "error",
+                    "message": format!("WebSocket error: {}", e)
+                });
+                let _ = sender.send(error.to_string());
                 break;
             }
-            _ => {}
         }
     }
 
     // Cleanup
-    state.client_contexts.remove(&client_uid);
+    state.client_senders.remove(&client_uid);
+    if let Some((_, context)) = state.client_contexts.remove(&client_uid) {
+        let group_id = state
+            .chat_groups
+            .read()
+            .await
+            .get_client_group(&client_uid)
+            .filter(|gid| !gid.is_empty());
+        state.save_resume_session(&context, group_id);
+        crate::summary_memory::after_session(&state, &context);
+    }
     state.audio_buffers.remove(&client_uid);
+    state.chunk_assemblers.remove(&client_uid);
+    crate::conversations::speculative::discard(&state, &client_uid).await;
+    crate::conversations::filler::cancel(&state, &client_uid);
+    state.vad_segmenters.remove(&client_uid);
+    state.barge_in_detectors.remove(&client_uid);
+    state.client_tts_engines.remove(&client_uid);
     
     // Cancel any running conversation tasks
-    if let Some((_, handle)) = state.conversation_tasks.remove(&client_uid) {
-        handle.abort();
+    if let Some((_, task)) = state.conversation_tasks.remove(&client_uid) {
+        task.handle.abort();
     }
+    state.reset_agent(&client_uid);
+    state.detach_characters(&client_uid, None);
     
     // Remove from groups
     {
         let groups = state.chat_groups.write().await;
         groups.client_group_map.remove(&client_uid);
     }
+
+    writer.abort();
     
     info!("Cleaned up client {}", client_uid);
 }
 
+
+/// Serve a read-only observer until it disconnects
+///
+/// Observers only receive mirrored messages; anything they send other than
+/// a `ping` is refused.
+async fn handle_observer_socket(socket: WebSocket, state: AppState, scope: ObserverScope, _slot: ConnectionSlot) {
+    let observer_uid = state.generate_client_uid();
+    info!("New observer {} of {:?}", observer_uid, scope);
+
+    let (mut ws_sender, mut receiver) = socket.split();
+    let (sender, mut outbound) = mpsc::unbounded_channel::<String>();
+    let sender = WebSocketSend::new(sender);
+    let writer = tokio::spawn(async move {
+        while let Some(text) = outbound.recv().await {
+            if ws_sender.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let (conf_uid, group_id) = match &scope {
+        ObserverScope::Conf(conf_uid) => (Some(conf_uid.clone()), None),
+        ObserverScope::Group(group_id) => (None, Some(group_id.clone())),
+    };
+    let _ = sender.send(
+        json!({
+            "type": "observer-registered",
+            "observer_uid": observer_uid,
+            "conf_uid": conf_uid,
+            "group_id": group_id
+        })
+        .to_string(),
+    );
+    state.observers.insert(
+        observer_uid.clone(),
+        Observer {
+            scope,
+            sender: sender.clone(),
+        },
+    );
+
+    while let Some(msg) = receiver.next().await {
+        match msg {
+            Ok(Message::Text(text)) => {
+                let is_ping = serde_json::from_str::<Value>(&text)
+                    .is_ok_and(|msg| msg.get("type").and_then(|t| t.as_str()) == Some("ping"));
+                let reply = if is_ping {
+                    json!({ "type": "pong" })
+                } else {
+                    json!({
+                        "type": "error",
+                        "message": "Observers can't send messages"
+                    })
+                };
+                let _ = sender.send(reply.to_string());
+            }
+            Ok(Message::Close(_)) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    state.observers.remove(&observer_uid);
+    writer.abort();
+    info!("Observer {} disconnected", observer_uid);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    /// WebSocket URL of a server accepting at most `max_connections`
+    async fn serve(max_connections: usize) -> String {
+        let mut config = test_support::config();
+        config.system_config.max_connections = Some(max_connections);
+        let state = test_support::state(config).await;
+
+        let app = crate::routes::create_routes(state.clone()).with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("ws://{}/client-ws?client_type=text", addr)
+    }
+
+    type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// Connect, waiting a moment for the server to release a closed
+    /// connection's slot
+    async fn connect_when_free(url: &str) -> Option<Socket> {
+        for _ in 0..50 {
+            if let Ok((socket, _)) = tokio_tungstenite::connect_async(url).await {
+                return Some(socket);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn connection_past_the_limit_is_refused_until_one_closes() {
+        let url = serve(2).await;
+        let (first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (_second, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        match tokio_tungstenite::connect_async(&url).await {
+            Err(tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), 503);
+                let body: Value = serde_json::from_slice(response.body().as_deref().unwrap()).unwrap();
+                assert_eq!(body["max_connections"], 2);
+            }
+            other => panic!("third connection was not refused: {:?}", other.map(|_| ())),
+        }
+
+        // The slot is given back once the server has cleaned up
+        drop(first);
+        assert!(connect_when_free(&url).await.is_some(), "closing a connection didn't free its slot");
+    }
+
+    #[tokio::test]
+    async fn refused_duplicate_client_uid_frees_its_slot() {
+        let url = format!("{}&client_uid=dup-test", serve(2).await);
+        let (_first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        // Accepted, then closed for reusing the uid
+        for _ in 0..3 {
+            let mut duplicate = connect_when_free(&url).await.expect("refused duplicate kept its slot");
+            while let Some(Ok(_)) = duplicate.next().await {}
+        }
+
+        let other = url.replace("dup-test", "other-test");
+        assert!(connect_when_free(&other).await.is_some());
+    }
+}